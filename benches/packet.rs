@@ -0,0 +1,86 @@
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+
+use bkdns::packet::{self, DNSPacket, DNSQuestion, RecordType};
+use bkdns::packet_ref::DNSPacketRef;
+
+#[allow(dead_code)]
+#[path = "../src/testdata.rs"]
+mod testdata;
+
+// The glue for f.iana-servers.net, a pointer into the NS rdata which points on to the zone name
+const DNSSEC_GLUE_NAME: usize = 979;
+
+// Counts allocations so the parse benches can report how many each parser makes
+struct CountingAlloc;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAlloc {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAlloc = CountingAlloc;
+
+fn allocations<T>(parse: impl Fn() -> T) -> usize {
+    let before = ALLOCATIONS.load(Ordering::Relaxed);
+    black_box(parse());
+    ALLOCATIONS.load(Ordering::Relaxed) - before
+}
+
+fn query() -> DNSPacket {
+    let mut packet = DNSPacket::with_id(0x1234);
+    packet.header.flags.recurse_desired = true;
+    packet.add_question(DNSQuestion::new(String::from("www.example.com"), RecordType::A).unwrap());
+    packet
+}
+
+// serialize allocates the packet every call, serialize_into reuses one buffer
+fn serialize(c: &mut Criterion) {
+    let packet = query();
+    c.bench_function("serialize query", |b| b.iter(|| black_box(&packet).serialize().unwrap()));
+
+    let mut buf = [0; 512];
+    c.bench_function("serialize_into query", |b| {
+        b.iter(|| black_box(&packet).serialize_into(&mut buf).unwrap())
+    });
+}
+
+fn parse(c: &mut Criterion) {
+    for (name, bytes) in [("A", &testdata::A_RESPONSE[..]), ("DNSSEC", &testdata::DNSSEC_RESPONSE[..])] {
+        println!(
+            "{} response allocations: owned {}, borrowed {}",
+            name,
+            allocations(|| DNSPacket::deserialize(bytes).unwrap()),
+            allocations(|| DNSPacketRef::deserialize(bytes).unwrap()),
+        );
+        c.bench_function(&format!("parse {} response", name), |b| {
+            b.iter(|| DNSPacket::deserialize(black_box(bytes)).unwrap())
+        });
+        c.bench_function(&format!("parse {} response borrowed", name), |b| {
+            b.iter(|| DNSPacketRef::deserialize(black_box(bytes)).unwrap())
+        });
+    }
+}
+
+fn names(c: &mut Criterion) {
+    c.bench_function("encode name", |b| {
+        b.iter(|| packet::serialize_dns_str(black_box("www.example.com")).unwrap())
+    });
+    c.bench_function("decode compressed name", |b| {
+        b.iter(|| packet::deserialize_dns_str(black_box(&testdata::DNSSEC_RESPONSE), DNSSEC_GLUE_NAME).unwrap())
+    });
+}
+
+criterion_group!(benches, serialize, parse, names);
+criterion_main!(benches);
@@ -0,0 +1,21 @@
+#![no_main]
+
+use bkdns::packet::DNSPacket;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let packet = match DNSPacket::deserialize(data) {
+        Ok(packet) => packet,
+        Err(_) => return,
+    };
+
+    // Labels over 63 bytes are still read off the wire but can't be written back, skip those
+    let bytes = match packet.serialize() {
+        Ok(bytes) => bytes,
+        Err(_) => return,
+    };
+
+    // Whatever we emit must parse back to the same thing
+    let reparsed = DNSPacket::deserialize(&bytes).expect("re-serialized packet failed to parse");
+    assert_eq!(reparsed, packet);
+});
@@ -0,0 +1,13 @@
+#![no_main]
+
+use bkdns::packet::deserialize_dns_str;
+use libfuzzer_sys::fuzz_target;
+
+// Names are read at any offset of a packet (compression pointers land anywhere)
+fuzz_target!(|data: &[u8]| {
+    if data.is_empty() {
+        return;
+    }
+    let offset = data[0] as usize % data.len();
+    let _ = deserialize_dns_str(data, offset);
+});
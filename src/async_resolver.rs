@@ -0,0 +1,476 @@
+/* Async counterpart of `Resolver`, on tokio. The packet types are shared, only the transport differs.
+    UDP queries all go out over one socket: a background task reads every reply and hands it
+    to whichever in-flight query has its transaction id and question, so any number can be
+    outstanding at once.
+ */
+use std::io::ErrorKind;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::{TcpStream, UdpSocket};
+#[cfg(unix)]
+use tokio::net::UnixStream;
+use tokio::sync::{oneshot, Mutex};
+use tokio::task::JoinHandle;
+use tracing::Instrument;
+
+use crate::demux::Demux;
+use crate::error::DnsError;
+use crate::hosts::HostsFile;
+use crate::names;
+use crate::packet::{DNSPacket, QueryBuilder, RecordType, DEFAULT_EDNS_PAYLOAD, UDP_MESSAGE_SIZE};
+use crate::resolver::{self, AddressSource, IpPreference, QueryOutcome, ResolveResult, TransportKind};
+
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(5);
+
+// Queries waiting on a UDP reply
+type Pending = Arc<Demux<oneshot::Sender<std::io::Result<Vec<u8>>>>>;
+
+struct SharedSocket {
+    socket: Arc<UdpSocket>,
+    reader: JoinHandle<()>,
+}
+
+pub struct AsyncResolver {
+    // Read when the shared UDP socket is created, on the first UDP query
+    pub nameserver: SocketAddr,
+    pub transport: TransportKind,
+    pub timeout: Duration,
+    pub ip_preference: IpPreference,
+    /* Send an OPT record advertising this UDP payload size, and read UDP responses up to it,
+        DEFAULT_EDNS_PAYLOAD to begin with and 512 bytes without EDNS when unset. Like
+        `nameserver`, read for the reader's buffer when the shared UDP socket is created.
+     */
+    pub edns_payload: Option<u16>,
+    // Answers `lookup_host` before any query is sent, the system's own by default
+    pub hosts: Option<HostsFile>,
+    // Bound on the first UDP query, and again for the next one after its reader stops
+    udp: Mutex<Option<SharedSocket>>,
+    pending: Pending,
+}
+
+impl AsyncResolver {
+    pub fn new(nameserver: SocketAddr) -> Self {
+        AsyncResolver {
+            nameserver,
+            transport: TransportKind::Udp,
+            timeout: DEFAULT_TIMEOUT,
+            ip_preference: IpPreference::Ipv4First,
+            edns_payload: Some(DEFAULT_EDNS_PAYLOAD),
+            hosts: Some(HostsFile::system()),
+            udp: Mutex::new(None),
+            pending: Arc::new(Demux::new()),
+        }
+    }
+
+    /* Sends a single recursive query and returns the parsed response, whatever its reply code,
+        along with the time between sending the query and receiving the response.
+     */
+    pub async fn query(&self, name: &str, rtype: RecordType) -> Result<(DNSPacket, Duration), DnsError> {
+        if rtype == RecordType::AXFR && self.transport == TransportKind::Udp {
+            return Err(DnsError::RequiresTcp(rtype));
+        }
+
+        let name = &names::validate_name(name)?;
+        let span = tracing::info_span!("dns.query", name, "type" = ?rtype, server = %self.nameserver);
+        tracing::info!(parent: &span, transport = self.transport.name(), "sending query");
+        let start = Instant::now();
+        let exchange = async {
+            match &self.transport {
+                TransportKind::Udp => self.exchange_udp(name, rtype).await,
+                TransportKind::Tcp => {
+                    let stream = TcpStream::connect(self.nameserver).await?;
+                    exchange_stream(stream, self.builder().question(name, rtype).build()?).await
+                },
+                #[cfg(unix)]
+                TransportKind::Unix(path) => {
+                    let stream = UnixStream::connect(path).await?;
+                    exchange_stream(stream, self.builder().question(name, rtype).build()?).await
+                },
+                #[cfg(feature = "doh")]
+                TransportKind::Doh(_) => Err(DnsError::Io(std::io::Error::new(
+                    std::io::ErrorKind::Unsupported, "DNS over HTTPS isn't supported by AsyncResolver"
+                ))),
+                #[cfg(feature = "dot")]
+                TransportKind::Tls { .. } => Err(DnsError::Io(std::io::Error::new(
+                    std::io::ErrorKind::Unsupported, "DNS over TLS isn't supported by AsyncResolver"
+                ))),
+            }
+        };
+        let (query, reply) = tokio::time::timeout(self.timeout, exchange.instrument(span.clone())).await
+            .map_err(|_| std::io::Error::new(std::io::ErrorKind::TimedOut, "query timed out"))??;
+        let elapsed = start.elapsed();
+        tracing::info!(parent: &span, bytes = reply.len(), ms = elapsed.as_millis() as u64, "response received");
+
+        let response = DNSPacket::deserialize(&reply)?;
+        resolver::validate_response(&query, &response)?;
+        Ok((response, elapsed))
+    }
+
+    // Outcome of asking for `name`, there is no cache or CNAME following here
+    pub async fn resolve(&self, name: &str, rtype: RecordType) -> Result<ResolveResult, DnsError> {
+        let (response, elapsed) = self.query(name, rtype).await?;
+        Ok(ResolveResult {
+            outcome: QueryOutcome::from_response(&response),
+            elapsed,
+            server: self.transport.uses_nameservers().then_some(self.nameserver),
+            attempts: 1,
+            question: response.questions().first().cloned(),
+            age: None,
+        })
+    }
+
+    /* Resolves both A and AAAA records for `name` concurrently, ordered by `ip_preference`, unless
+        it's in the hosts file, and says which it was
+     */
+    pub async fn lookup_host(&self, name: &str) -> Result<(Vec<IpAddr>, AddressSource), DnsError> {
+        let from_hosts = resolver::from_hosts(self.hosts.as_ref(), name, self.ip_preference);
+        if !from_hosts.is_empty() {
+            return Ok((from_hosts, AddressSource::HostsFile));
+        }
+        let lookup = |rtype: RecordType| async move {
+            match self.resolve(name, rtype).await?.outcome {
+                QueryOutcome::Answers(answers) => Ok(resolver::addresses(&answers, name)),
+                QueryOutcome::NoData { .. } => Ok(Vec::new()),
+                QueryOutcome::NxDomain { .. } => Err(DnsError::NxDomain(String::from(name))),
+                QueryOutcome::Failure(code) => Err(DnsError::ReplyCode(code)),
+            }
+        };
+        let (v4, v6) = tokio::join!(lookup(RecordType::A), lookup(RecordType::AAAA));
+
+        let merged = match self.ip_preference {
+            IpPreference::Ipv4First => resolver::merge(v4, v6),
+            IpPreference::Ipv6First => resolver::merge(v6, v4),
+        };
+        merged.map(|addrs| (addrs, AddressSource::Dns))
+    }
+
+    fn builder(&self) -> QueryBuilder {
+        match self.edns_payload {
+            Some(payload) => QueryBuilder::new().edns(payload),
+            None => QueryBuilder::new(),
+        }
+    }
+
+    async fn exchange_udp(&self, name: &str, rtype: RecordType) -> Result<(DNSPacket, Vec<u8>), DnsError> {
+        let socket = self.udp_socket().await?;
+
+        // the id is only handed out once it can't clash with another query in flight
+        let (sender, receiver) = oneshot::channel();
+        let id = self.pending.register(name, rtype, sender)?;
+        // forget the id however this ends, timeouts included
+        let _waiting = Waiting { pending: &self.pending, id };
+
+        let query = self.builder().id(id).question(name, rtype).build()?;
+        socket.send(&query.serialize()?).await?;
+        let reply = receiver.await
+            .map_err(|_| std::io::Error::new(std::io::ErrorKind::BrokenPipe, "UDP reader stopped"))??;
+        Ok((query, reply))
+    }
+
+    // The shared socket, bound anew when there's none yet or its reader has stopped
+    async fn udp_socket(&self) -> Result<Arc<UdpSocket>, DnsError> {
+        let mut udp = self.udp.lock().await;
+        match udp.as_ref() {
+            Some(shared) if !shared.reader.is_finished() => return Ok(shared.socket.clone()),
+            Some(_) => tracing::info!("UDP reader stopped, binding a new socket"),
+            None => {},
+        }
+        let shared = self.bind_udp().await?;
+        let socket = shared.socket.clone();
+        *udp = Some(shared);
+        Ok(socket)
+    }
+
+    async fn bind_udp(&self) -> Result<SharedSocket, DnsError> {
+        let bind_addr = match self.nameserver {
+            SocketAddr::V4(_) => "0.0.0.0:0",
+            SocketAddr::V6(_) => "[::]:0",
+        };
+        let socket = Arc::new(UdpSocket::bind(bind_addr).await?);
+        socket.connect(self.nameserver).await?;
+        // the largest reply read is what the OPT record advertises, if there is one
+        let buffer_size = self.edns_payload.map_or(UDP_MESSAGE_SIZE, |x| x as usize);
+        let reader = tokio::spawn(read_replies(socket.clone(), self.pending.clone(), buffer_size));
+        Ok(SharedSocket { socket, reader })
+    }
+}
+
+impl Drop for AsyncResolver {
+    fn drop(&mut self) {
+        if let Some(shared) = self.udp.get_mut() {
+            shared.reader.abort();
+        }
+    }
+}
+
+// Releases a query's id when the query finishes or is dropped, the one place it is released
+struct Waiting<'a> {
+    pending: &'a Pending,
+    id: u16,
+}
+
+impl Drop for Waiting<'_> {
+    fn drop(&mut self) {
+        self.pending.release(self.id);
+    }
+}
+
+/* Replies no one is waiting on (late, duplicate, spoofed or unparseable) are dropped. An ICMP
+    error from the nameserver comes back as a refused or reset recv on a connected socket and
+    is skipped, any other error stops the reader and fails every query still waiting with it.
+    The next query binds a new socket.
+ */
+async fn read_replies(socket: Arc<UdpSocket>, pending: Pending, buffer_size: usize) {
+    let mut buf = vec![0; buffer_size];
+    loop {
+        let size = match socket.recv(&mut buf).await {
+            Ok(size) => size,
+            Err(err) if matches!(err.kind(), ErrorKind::ConnectionRefused | ErrorKind::ConnectionReset) => continue,
+            Err(err) => {
+                tracing::warn!(%err, "UDP reader stopped");
+                for sender in pending.drain() {
+                    let _ = sender.send(Err(std::io::Error::new(err.kind(), err.to_string())));
+                }
+                return;
+            },
+        };
+        let sender = DNSPacket::deserialize(&buf[..size]).ok().and_then(|reply| pending.route(&reply));
+        if let Some(sender) = sender {
+            let _ = sender.send(Ok(buf[..size].to_vec()));
+        }
+    }
+}
+
+// One query per connection, framed like the sync resolver's TCP messages
+async fn exchange_stream<S>(mut stream: S, query: DNSPacket) -> Result<(DNSPacket, Vec<u8>), DnsError>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    write_tcp_message(&mut stream, &query.serialize()?).await?;
+    let reply = read_tcp_message(&mut stream).await?;
+    Ok((query, reply))
+}
+
+async fn write_tcp_message<W: AsyncWrite + Unpin>(stream: &mut W, message: &[u8]) -> Result<(), DnsError> {
+    let length = u16::try_from(message.len())
+        .map_err(|_| std::io::Error::new(std::io::ErrorKind::InvalidInput, "message too long for TCP"))?;
+    let mut bytes = Vec::with_capacity(2 + message.len());
+    bytes.extend_from_slice(&length.to_be_bytes());
+    bytes.extend_from_slice(message);
+    stream.write_all(&bytes).await?;
+    Ok(())
+}
+
+async fn read_tcp_message<R: AsyncRead + Unpin>(stream: &mut R) -> Result<Vec<u8>, DnsError> {
+    let mut length = [0; 2];
+    stream.read_exact(&mut length).await?;
+    let mut message = vec![0; u16::from_be_bytes(length) as usize];
+    stream.read_exact(&mut message).await?;
+    Ok(message)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{Ipv4Addr, SocketAddr};
+    use std::time::Duration;
+
+    use tokio::net::UdpSocket;
+
+    use crate::async_resolver::AsyncResolver;
+    use crate::packet::{DNSRecord, DNSPacket, RecordData, RecordType, RECORD_CLASS};
+
+    // Answers `query` with an A record whose last octet is the length of the name asked for
+    fn answer(query: &DNSPacket) -> Vec<u8> {
+        let question = &query.questions()[0];
+        let mut response = DNSPacket::with_id(query.header.id());
+        response.header.flags.is_response = true;
+        response.add_question(crate::packet::DNSQuestion::new(String::from(question.name()), question.qtype()).unwrap());
+        response.add_answer(DNSRecord {
+            name: String::from(question.name()),
+            rtype: RecordType::A,
+            class: RECORD_CLASS,
+            ttl: 60,
+            data: RecordData::A(Ipv4Addr::new(192, 0, 2, question.name().len() as u8)),
+        });
+        response.serialize().unwrap()
+    }
+
+    async fn mock_server() -> (UdpSocket, SocketAddr) {
+        let server = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let addr = server.local_addr().unwrap();
+        (server, addr)
+    }
+
+    async fn receive(server: &UdpSocket) -> (DNSPacket, SocketAddr) {
+        let mut buf = [0; 512];
+        let (size, peer) = server.recv_from(&mut buf).await.unwrap();
+        (DNSPacket::deserialize(&buf[..size]).unwrap(), peer)
+    }
+
+    fn address(result: Result<(DNSPacket, Duration), crate::error::DnsError>) -> Ipv4Addr {
+        match result.unwrap().0.answers()[0].data {
+            RecordData::A(addr) => addr,
+            ref data => panic!("expected an A record, got {:?}", data),
+        }
+    }
+
+    #[tokio::test]
+    async fn concurrent_queries_interleaved() {
+        let (server, addr) = mock_server().await;
+        // wait for all three queries, then answer them newest first
+        let mock = tokio::spawn(async move {
+            let mut queries = Vec::new();
+            for _ in 0..3 {
+                queries.push(receive(&server).await);
+            }
+            for (query, peer) in queries.iter().rev() {
+                server.send_to(&answer(query), peer).await.unwrap();
+            }
+        });
+
+        let resolver = AsyncResolver::new(addr);
+        let (a, b, c) = tokio::join!(
+            resolver.query("a.example", RecordType::A),
+            resolver.query("bb.example", RecordType::A),
+            resolver.query("ccc.example", RecordType::A),
+        );
+        mock.await.unwrap();
+
+        assert_eq!(address(a), Ipv4Addr::new(192, 0, 2, 9));
+        assert_eq!(address(b), Ipv4Addr::new(192, 0, 2, 10));
+        assert_eq!(address(c), Ipv4Addr::new(192, 0, 2, 11));
+        assert!(resolver.pending.is_empty());
+    }
+
+    #[tokio::test]
+    async fn thousand_in_flight_none_misrouted() {
+        const QUERIES: usize = 1000;
+        let (server, addr) = mock_server().await;
+        // hold every query, then answer newest first along with a decoy for another name
+        let mock = tokio::spawn(async move {
+            let mut queries = Vec::new();
+            for _ in 0..QUERIES {
+                queries.push(receive(&server).await);
+            }
+            for (index, (query, peer)) in queries.iter().rev().enumerate() {
+                let mut decoy = DNSPacket::with_id(query.header.id());
+                decoy.add_question(crate::packet::DNSQuestion::new(String::from("decoy.example"), RecordType::A).unwrap());
+                server.send_to(&answer(&decoy), peer).await.unwrap();
+                server.send_to(&answer(query), peer).await.unwrap();
+                // let the reader keep up, a full socket buffer drops replies
+                if index % 16 == 0 {
+                    tokio::time::sleep(Duration::from_millis(1)).await;
+                }
+            }
+        });
+
+        let resolver = std::sync::Arc::new(AsyncResolver::new(addr));
+        let mut queries = tokio::task::JoinSet::new();
+        for index in 0..QUERIES {
+            let resolver = resolver.clone();
+            let name = format!("q{}.example", index);
+            queries.spawn(async move { (resolver.query(&name, RecordType::A).await, name) });
+            // every query is still pending, this only keeps the mock's socket buffer from overflowing
+            if index % 16 == 0 {
+                tokio::time::sleep(Duration::from_millis(1)).await;
+            }
+        }
+        let mut answered = 0;
+        while let Some(joined) = queries.join_next().await {
+            let (result, name) = joined.unwrap();
+            let (response, _) = result.unwrap();
+            assert_eq!(response.questions()[0].name(), name);
+            assert_eq!(address(Ok((response, Duration::ZERO))), Ipv4Addr::new(192, 0, 2, name.len() as u8));
+            answered += 1;
+        }
+        mock.await.unwrap();
+        assert_eq!(answered, QUERIES);
+        assert!(resolver.pending.is_empty());
+    }
+
+    #[tokio::test]
+    async fn stray_reply_ignored() {
+        let (server, addr) = mock_server().await;
+        let mock = tokio::spawn(async move {
+            let (query, peer) = receive(&server).await;
+            let mut stray = answer(&query);
+            stray[..2].copy_from_slice(&query.header.id().wrapping_add(1).to_be_bytes());
+            server.send_to(&stray, peer).await.unwrap();
+            server.send_to(&answer(&query), peer).await.unwrap();
+        });
+
+        let resolver = AsyncResolver::new(addr);
+        let result = resolver.query("example.com", RecordType::A).await;
+        mock.await.unwrap();
+        assert_eq!(address(result), Ipv4Addr::new(192, 0, 2, 11));
+    }
+
+    #[tokio::test]
+    async fn edns_payload_sizes_the_reader() {
+        let (server, addr) = mock_server().await;
+        // 40 A records, about 1100 bytes and well past the 512 of a query without EDNS
+        let mock = tokio::spawn(async move {
+            let (query, peer) = receive(&server).await;
+            let mut response = DNSPacket::deserialize(&answer(&query)).unwrap();
+            for _ in 1..40 {
+                response.add_answer(response.answers()[0].clone());
+            }
+            server.send_to(&response.serialize().unwrap(), peer).await.unwrap();
+            query
+        });
+
+        let mut resolver = AsyncResolver::new(addr);
+        resolver.edns_payload = Some(1232);
+        let (response, _) = resolver.query("example.com", RecordType::A).await.unwrap();
+        let query = mock.await.unwrap();
+        assert_eq!((query.additional()[0].rtype, query.additional()[0].class), (RecordType::OPT, 1232));
+        assert_eq!(response.answers().len(), 40);
+    }
+
+    #[tokio::test]
+    async fn rebinds_after_the_reader_stops() {
+        let (server, addr) = mock_server().await;
+        let mock = tokio::spawn(async move {
+            for _ in 0..2 {
+                let (query, peer) = receive(&server).await;
+                server.send_to(&answer(&query), peer).await.unwrap();
+            }
+        });
+
+        let resolver = AsyncResolver::new(addr);
+        assert_eq!(address(resolver.query("example.com", RecordType::A).await), Ipv4Addr::new(192, 0, 2, 11));
+        // as if the socket had failed
+        resolver.udp.lock().await.as_ref().unwrap().reader.abort();
+        while !resolver.udp.lock().await.as_ref().unwrap().reader.is_finished() {
+            tokio::task::yield_now().await;
+        }
+        assert_eq!(address(resolver.query("example.com", RecordType::A).await), Ipv4Addr::new(192, 0, 2, 11));
+        mock.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn timeout_forgets_query() {
+        // a server that never answers
+        let (_server, addr) = mock_server().await;
+        let mut resolver = AsyncResolver::new(addr);
+        resolver.timeout = Duration::from_millis(50);
+
+        match resolver.query("example.com", RecordType::A).await {
+            Err(crate::error::DnsError::Io(err)) => assert_eq!(err.kind(), std::io::ErrorKind::TimedOut),
+            result => panic!("expected a timeout, got {:?}", result.map(|x| x.0)),
+        }
+        assert!(resolver.pending.is_empty());
+    }
+
+    #[tokio::test]
+    async fn axfr_over_udp_rejected() {
+        let resolver = AsyncResolver::new("127.0.0.1:53".parse().unwrap());
+        assert!(matches!(
+            resolver.query("example.com", RecordType::AXFR).await,
+            Err(crate::error::DnsError::RequiresTcp(RecordType::AXFR))
+        ));
+    }
+}
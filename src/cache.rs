@@ -0,0 +1,144 @@
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use crate::packet::{DNSRecord, DNSPacket, RecordType, ReplyCode, RECORD_CLASS};
+
+// Source of the current time, swappable so expiry can be tested without sleeping
+pub trait Clock {
+    fn now(&self) -> Instant;
+}
+
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+}
+
+struct CacheEntry {
+    answers: Vec<DNSRecord>,
+    stored: Instant,
+    expires: Instant,
+}
+
+// Answers keyed on (lowercased name, type, class), valid for the smallest TTL in the set
+pub struct Cache<C: Clock = SystemClock> {
+    clock: C,
+    entries: HashMap<(String, RecordType, u16), CacheEntry>,
+}
+
+impl Cache {
+    pub fn new() -> Self {
+        Cache::with_clock(SystemClock)
+    }
+}
+
+impl Default for Cache {
+    fn default() -> Self {
+        Cache::new()
+    }
+}
+
+impl<C: Clock> Cache<C> {
+    pub fn with_clock(clock: C) -> Self {
+        Cache { clock, entries: HashMap::new() }
+    }
+
+    pub fn get(&self, name: &str, rtype: RecordType, class: u16) -> Option<&[DNSRecord]> {
+        self.get_with_age(name, rtype, class).map(|(answers, _)| answers)
+    }
+
+    // The answers as they came, TTLs and all, and how long they've been held
+    pub fn get_with_age(&self, name: &str, rtype: RecordType, class: u16) -> Option<(&[DNSRecord], Duration)> {
+        let now = self.clock.now();
+        self.entries.get(&(name.to_ascii_lowercase(), rtype, class))
+            .filter(|entry| entry.expires > now)
+            .map(|entry| (entry.answers.as_slice(), now - entry.stored))
+    }
+
+    // Only successful responses with answers are cached
+    pub fn insert(&mut self, response: &DNSPacket) {
+        let (question, ttl) = match (
+            response.questions().first(),
+            response.answers().iter().map(|x| x.ttl).min(),
+        ) {
+            (Some(question), Some(ttl)) if response.reply_code() == ReplyCode::NoError => (question, ttl),
+            _ => return,
+        };
+
+        let now = self.clock.now();
+        self.entries.insert(
+            (question.name().to_ascii_lowercase(), question.qtype(), RECORD_CLASS),
+            CacheEntry {
+                answers: response.answers().to_vec(),
+                stored: now,
+                expires: now + Duration::from_secs(ttl as u64),
+            },
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+    use std::rc::Rc;
+    use std::time::{Duration, Instant};
+
+    // Clock that only moves when the test advances it
+    struct MockClock {
+        start: Instant,
+        elapsed: Rc<Cell<Duration>>,
+    }
+
+    impl crate::cache::Clock for MockClock {
+        fn now(&self) -> Instant {
+            self.start + self.elapsed.get()
+        }
+    }
+
+    #[test]
+    fn cache_expires_after_min_ttl() {
+        // example.com A 93.184.216.34 (TTL 300), example.com A 93.184.216.35 (TTL 60)
+        let response = crate::packet::DNSPacket::deserialize(&hex_literal::hex!(
+            """
+            12 34 81 80 00 01 00 02 00 00 00 00
+            07 65 78 61 6d 70 6c 65 03 63 6f 6d 00 00 01 00 01
+            c0 0c 00 01 00 01 00 00 01 2c 00 04 5d b8 d8 22
+            c0 0c 00 01 00 01 00 00 00 3c 00 04 5d b8 d8 23
+            """
+        )).unwrap();
+
+        let elapsed = Rc::new(Cell::new(Duration::ZERO));
+        let mut cache = crate::cache::Cache::with_clock(MockClock {
+            start: Instant::now(),
+            elapsed: elapsed.clone(),
+        });
+        cache.insert(&response);
+
+        let rtype = crate::packet::RecordType::A;
+        let class = crate::packet::RECORD_CLASS;
+        assert_eq!(cache.get("EXAMPLE.com", rtype, class).unwrap().len(), 2);
+        assert!(cache.get("example.com", crate::packet::RecordType::AAAA, class).is_none());
+
+        elapsed.set(Duration::from_secs(59));
+        let (answers, age) = cache.get_with_age("example.com", rtype, class).unwrap();
+        assert_eq!(age, Duration::from_secs(59));
+        assert_eq!(answers.iter().map(|x| x.remaining_ttl(age)).collect::<Vec<_>>(), [241, 1]);
+
+        elapsed.set(Duration::from_secs(61));
+        assert!(cache.get("example.com", rtype, class).is_none());
+    }
+
+    #[test]
+    fn cache_skips_errors() {
+        let mut cache = crate::cache::Cache::new();
+        cache.insert(&crate::packet::DNSPacket::deserialize(&hex_literal::hex!(
+            """
+            12 34 81 83 00 01 00 00 00 00 00 00
+            07 65 78 61 6d 70 6c 65 03 63 6f 6d 00 00 01 00 01
+            """
+        )).unwrap());
+        assert!(cache.get("example.com", crate::packet::RecordType::A, crate::packet::RECORD_CLASS).is_none());
+    }
+}
@@ -0,0 +1,954 @@
+/* Command line of the bkdns binary. A lookup is the default, run when no subcommand
+    is given, so `bkdns example.com 9.9.9.9` keeps working the way it always has.
+ */
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::path::PathBuf;
+
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use tracing::level_filters::LevelFilter;
+
+use bkdns::error::DnsError;
+use bkdns::names;
+use bkdns::packet::{RecordType, ReplyCode, DEFAULT_EDNS_PAYLOAD};
+use bkdns::resolver::QueryOutcome;
+use bkdns::tsig::Tsig;
+
+// Use Quad9 if no nameserver specified and the system has none configured
+pub const DEFAULT_NAMESERVER: IpAddr = IpAddr::V4(Ipv4Addr::new(9, 9, 9, 9));
+
+// The system's nameservers where it lists any, otherwise Quad9
+pub fn default_nameservers() -> Vec<IpAddr> {
+    #[cfg(unix)]
+    {
+        let system = bkdns::resolver::system_nameservers();
+        if !system.is_empty() {
+            return system;
+        }
+    }
+    vec![DEFAULT_NAMESERVER]
+}
+
+// Names the record type lookups ask for when they aren't given one
+pub const DEFAULT_TYPE_VAR: &str = "BKDNS_DEFAULT_TYPE";
+
+// Names a hosts file lookups read instead of the system's
+pub const HOSTS_FILE_VAR: &str = "BKDNS_HOSTS";
+
+/* The record type used when neither --type nor a positional gives one: the value of
+    BKDNS_DEFAULT_TYPE (`var`) when it is set, otherwise A. An explicit type always wins.
+ */
+pub fn default_type(var: Option<&str>) -> Result<RecordType, String> {
+    match var.map(str::trim).filter(|x| !x.is_empty()) {
+        Some(name) => name.parse()
+            .map_err(|_| format!("{} is set to {}, which is not a record type", DEFAULT_TYPE_VAR, name)),
+        None => Ok(RecordType::A),
+    }
+}
+
+const EXIT_STATUS_HELP: &str = "\
+Exit status:
+  0  an answer, or a name that has no records of the type asked for
+  1  usage errors, bad arguments or files that can't be read or written
+  2  network failures and timeouts
+  3  NXDOMAIN, the name doesn't exist
+  4  SERVFAIL or any other error reply code, and CNAME loops
+  5  a response that doesn't parse or doesn't match the query
+--compare, --propagation and the serial subcommand exit 1 when the servers disagree.";
+
+// dig-style +options accepted among the positionals, each the same as a --flag
+const QUERY_OPTIONS: &[&str] = &["dnssec", "cd", "noedns"];
+
+#[derive(Debug, Parser)]
+#[command(
+    name = "bkdns",
+    version,
+    about = "Look up DNS records",
+    args_conflicts_with_subcommands = true,
+    arg_required_else_help = true,
+    after_help = EXIT_STATUS_HELP
+)]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+    #[command(flatten)]
+    pub query: QueryArgs,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    #[command(about = "Dissect a packet from hex, a file or stdin, nothing is sent")]
+    Decode(DecodeArgs),
+    #[command(about = "Print the query a lookup would send, without sending it")]
+    Encode(EncodeArgs),
+    #[command(about = "Answer queries over UDP and TCP from the records in a zone file")]
+    Serve(ServeArgs),
+    #[command(about = "Forward queries over UDP and TCP to upstream nameservers, logging each one")]
+    Proxy(ProxyArgs),
+    #[command(about = "Transfer a whole zone over TCP and print it in zone-file format")]
+    Axfr(AxfrArgs),
+    #[command(about = "Compare a zone's SOA serial across its nameservers, exiting 1 unless all are in sync")]
+    Serial(SerialArgs),
+    #[command(about = "Add and delete records in a zone with a dynamic update (RFC 2136)")]
+    Update(UpdateArgs),
+    #[command(about = "Tell a secondary that a zone changed with a NOTIFY (RFC 1996) and wait for it to acknowledge")]
+    Notify(NotifyArgs),
+    #[command(about = "List the instances of a DNS-SD service on the local link over mDNS, with their hosts, ports and TXT")]
+    Browse(BrowseArgs),
+}
+
+#[derive(Debug, Args)]
+pub struct DecodeArgs {
+    #[arg(long, conflicts_with = "file", help = "The packet as hex, whitespace and colons between bytes are ignored")]
+    pub hex: Option<String>,
+    #[arg(long, help = "Read the packet from a file, raw or as hex")]
+    pub file: Option<PathBuf>,
+    #[arg(long, help = "Also print the packet serialized again")]
+    pub reencode: bool,
+}
+
+#[derive(Debug, Args)]
+pub struct EncodeArgs {
+    pub domain: String,
+    #[arg(value_parser = parse_type, default_value = "A", help = "Record type, a mnemonic or TYPEnnn")]
+    pub rtype: RecordType,
+    #[arg(long, value_parser = parse_id, help = "Transaction id, decimal or 0x hex, for reproducible output")]
+    pub id: Option<u16>,
+    #[arg(short = 'o', help = "Write the raw bytes to a file instead of printing hex")]
+    pub output: Option<PathBuf>,
+}
+
+#[derive(Debug, Args)]
+pub struct ServeArgs {
+    #[arg(long, help = "Records to serve, one `name TTL [class] type rdata` per line")]
+    pub zone: PathBuf,
+    #[arg(long, default_value = "127.0.0.1:5300", help = "Address and port to listen on")]
+    pub listen: SocketAddr,
+    #[arg(
+        long,
+        value_name = "ADDR",
+        help = "Also take NOTIFY messages for the zone on this UDP address, reloading the zone file when its serial is newer"
+    )]
+    pub listen_notify: Option<SocketAddr>,
+    #[arg(
+        long,
+        value_name = "IP,IP,...",
+        value_delimiter = ',',
+        requires = "listen_notify",
+        help = "Refuse NOTIFY messages from anyone else"
+    )]
+    pub allow_notify: Vec<IpAddr>,
+}
+
+#[derive(Debug, Args)]
+pub struct AxfrArgs {
+    #[arg(help = "The zone to transfer")]
+    pub zone: String,
+    #[arg(
+        value_name = "@NAMESERVER",
+        value_parser = parse_at_server,
+        help = "Nameserver to transfer from: an address, optionally with a port, or a hostname; the @ is optional"
+    )]
+    pub nameserver: String,
+    #[arg(short, long, default_value_t = 53, help = "Port to connect to unless the nameserver gives one")]
+    pub port: u16,
+    #[arg(
+        long,
+        value_name = "NAME:ALG:SECRET",
+        value_parser = parse_tsig,
+        help = "Sign the transfer with a TSIG key and check every message is signed back, the secret in base64"
+    )]
+    pub tsig: Option<Tsig>,
+}
+
+#[derive(Debug, Args)]
+pub struct SerialArgs {
+    #[arg(help = "The zone to check")]
+    pub zone: String,
+    #[arg(
+        value_name = "@NAMESERVER",
+        required = true,
+        value_parser = parse_at_server,
+        help = "Nameservers to ask: addresses, optionally with ports, or hostnames; the @ is optional"
+    )]
+    pub nameservers: Vec<String>,
+    #[arg(short, long, default_value_t = 53, help = "Port to send queries to unless a nameserver gives one")]
+    pub port: u16,
+    #[arg(long, value_name = "N", help = "Also require every serial to be at least N")]
+    pub expect_serial: Option<u32>,
+}
+
+/* Names in the record arguments are relative to the zone unless they end in a dot or are
+    already in it, and `@` is the zone itself. Deletions go before additions, so deleting an
+    RRset and adding to it replaces it.
+ */
+#[derive(Debug, Args)]
+pub struct UpdateArgs {
+    #[arg(help = "The zone to update")]
+    pub zone: String,
+    #[arg(
+        value_name = "@NAMESERVER",
+        value_parser = parse_at_server,
+        help = "The zone's primary: an address, optionally with a port, or a hostname; the @ is optional"
+    )]
+    pub nameserver: String,
+    #[arg(long, value_name = "RECORD", help = "Add a record, `name TTL [class] type rdata`, repeatable")]
+    pub add: Vec<String>,
+    #[arg(
+        long,
+        value_name = "NAME [TYPE [RDATA]]",
+        help = "Delete every record of the name, its records of one type, or the one record with that rdata, repeatable"
+    )]
+    pub delete: Vec<String>,
+    #[arg(long, value_name = "NAME [TYPE]", help = "Only update when the name has records (of the type), repeatable")]
+    pub require: Vec<String>,
+    #[arg(long, value_name = "NAME [TYPE]", help = "Only update when the name has no records (of the type), repeatable")]
+    pub require_absent: Vec<String>,
+    #[arg(short, long, default_value_t = 53, help = "Port to send the update to unless the nameserver gives one")]
+    pub port: u16,
+    #[arg(long, help = "Send the update over TCP instead of UDP")]
+    pub tcp: bool,
+    #[arg(
+        long,
+        value_name = "NAME:ALG:SECRET",
+        value_parser = parse_tsig,
+        help = "Sign the update with a TSIG key and require a signed response, the secret in base64"
+    )]
+    pub tsig: Option<Tsig>,
+}
+
+#[derive(Debug, Args)]
+pub struct NotifyArgs {
+    #[arg(help = "The zone that changed")]
+    pub zone: String,
+    #[arg(
+        value_name = "@SECONDARY",
+        value_parser = parse_at_server,
+        help = "Nameserver to notify: an address, optionally with a port, or a hostname; the @ is optional"
+    )]
+    pub nameserver: String,
+    #[arg(
+        long,
+        value_name = "NAMESERVER",
+        help = "Where to look up the zone's current SOA, sent along as a hint [default: the system's nameservers]"
+    )]
+    pub primary: Option<String>,
+    #[arg(short, long, default_value_t = 53, help = "Port to send the NOTIFY to unless the nameserver gives one")]
+    pub port: u16,
+}
+
+#[derive(Debug, Args)]
+pub struct BrowseArgs {
+    #[arg(help = "The service type, like _http._tcp.local")]
+    pub service: String,
+    #[arg(
+        long,
+        value_name = "SECONDS",
+        default_value_t = 2,
+        value_parser = clap::value_parser!(u64).range(1..),
+        help = "How long to collect replies for, and again for each instance missing its SRV or TXT"
+    )]
+    pub timeout: u64,
+}
+
+#[derive(Debug, Args)]
+pub struct ProxyArgs {
+    #[arg(long, default_value = "127.0.0.1:5353", help = "Address and port to listen on")]
+    pub listen: SocketAddr,
+    #[arg(
+        long,
+        required = true,
+        value_parser = parse_upstream,
+        help = "Nameserver to forward to, port 53 unless given, repeat for failover"
+    )]
+    pub upstream: Vec<SocketAddr>,
+    #[arg(long, help = "Answer NXDOMAIN for the names or *.domain wildcards in a hosts-style file, repeatable")]
+    pub blocklist: Vec<PathBuf>,
+    #[arg(long, requires = "blocklist", help = "Answer blocked names with 0.0.0.0 and :: instead of NXDOMAIN")]
+    pub block_with_zero: bool,
+    #[arg(
+        long = "override",
+        value_name = "NAME=ADDR",
+        value_parser = parse_override,
+        help = "Answer NAME (or *.domain) with ADDR locally, repeat for more names or addresses"
+    )]
+    pub overrides: Vec<(String, IpAddr)>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum OutputFormat {
+    // The whole response in master-file format
+    Zonefile,
+    // The whole response as JSON, through the serde feature
+    Json,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Family {
+    Ipv4,
+    Ipv6,
+}
+
+#[derive(Debug, Args)]
+pub struct QueryArgs {
+    #[arg(
+        value_name = "ARGS",
+        help = "domain_name (or an address to look up its PTR), an optional record type and nameservers (ip or @ip), in any order"
+    )]
+    pub args: Vec<String>,
+    #[arg(long, value_name = "NAME", help = "The name to look up, for one that reads as a record type or address")]
+    pub name: Option<String>,
+    #[arg(
+        long = "type",
+        value_name = "TYPE",
+        value_parser = parse_type,
+        help = "The record type, leaving positionals that read as one free to be the name [default: A, or $BKDNS_DEFAULT_TYPE]"
+    )]
+    pub rtype: Option<RecordType>,
+    #[arg(long, value_name = "IP", help = "Ask this nameserver, after any @nameservers (repeatable)")]
+    pub server: Vec<IpAddr>,
+    #[arg(short, long, default_value_t = 53, help = "Port to send queries to on each nameserver")]
+    pub port: u16,
+    #[arg(long, help = "Print every A and AAAA address for the name")]
+    pub both: bool,
+    #[arg(
+        long,
+        conflicts_with_all = ["both", "dual", "rtype"],
+        help = "Ask for A, AAAA, MX, NS, TXT and SOA records in turn and report each"
+    )]
+    pub all: bool,
+    #[arg(
+        long,
+        conflicts_with = "both",
+        help = "Look up A and AAAA at once, Happy Eyeballs style, and print the addresses interleaved"
+    )]
+    pub dual: bool,
+    #[arg(
+        long,
+        value_enum,
+        value_name = "FAMILY",
+        help = "Which address family comes first with --both and --dual [default: ipv4]"
+    )]
+    pub prefer: Option<Family>,
+    #[arg(
+        long,
+        value_name = "MS",
+        requires = "dual",
+        help = "How long --dual waits for the preferred family once the other has answered [default: 50]"
+    )]
+    pub resolution_delay: Option<u64>,
+    #[arg(long, help = "Keep answers for their TTL instead of re-asking the nameserver")]
+    pub cache: bool,
+    #[arg(
+        long,
+        help = "Don't answer A, AAAA, --both and --dual lookups from the hosts file ($BKDNS_HOSTS or the system's), \
+            ask the nameservers (implied by any nameserver given, --tcp, --tls, --doh or --unix)"
+    )]
+    pub no_hosts: bool,
+    #[arg(long, help = "Re-query CNAME targets until the records are found")]
+    pub follow_cname: bool,
+    #[arg(long, help = "Randomize the query name's case and reject replies that don't echo it")]
+    pub dns0x20: bool,
+    #[arg(long, value_parser = parse_id, help = "Transaction id, decimal or 0x hex, instead of a random one")]
+    pub id: Option<u16>,
+    #[arg(long, help = "Show xn-- names decoded back to Unicode")]
+    pub unicode: bool,
+    #[arg(long, help = "Only print the record data of each answer, like dig +short")]
+    pub short: bool,
+    #[arg(long, help = "Transfer the whole zone over TCP and print it in zone-file style")]
+    pub axfr: bool,
+    #[arg(long, help = "Set the DO bit, asking for RRSIG records with the answers (also +dnssec)")]
+    pub dnssec: bool,
+    #[arg(long, help = "Set the CD bit, asking the server to answer without validating DNSSEC (also +cd)")]
+    pub cd: bool,
+    #[arg(
+        long,
+        value_name = "BYTES",
+        value_parser = clap::value_parser!(u16).range(512..),
+        help = "Advertise this UDP payload size in an EDNS OPT record and read responses up to it [default: 1232]"
+    )]
+    pub bufsize: Option<u16>,
+    #[arg(
+        long,
+        conflicts_with = "bufsize",
+        help = "Send queries without an EDNS OPT record, holding UDP responses to 512 bytes (also +noedns)"
+    )]
+    pub no_edns: bool,
+    #[arg(long, help = "Send queries over TCP instead of UDP")]
+    pub tcp: bool,
+    #[arg(
+        long,
+        conflicts_with_all = ["tcp", "tls", "doh", "unix", "race", "axfr", "compare", "propagation", "watch", "count"],
+        help = "Ask the link's mDNS group (224.0.0.251:5353) and list every device that answers within 2 seconds"
+    )]
+    pub mdns: bool,
+    #[arg(
+        long,
+        conflicts_with_all = ["tcp", "tls", "doh", "unix", "race", "axfr", "compare", "propagation", "watch", "count", "mdns"],
+        help = "Ask the link's LLMNR groups (224.0.0.252 and ff02::1:3, port 5355) and list every host that answers within a second"
+    )]
+    pub llmnr: bool,
+    #[arg(
+        long,
+        value_name = "NAME:ALG:SECRET",
+        value_parser = parse_tsig,
+        help = "Sign queries with a TSIG key (hmac-sha256, -sha384 or -sha512, secret in base64), require signed responses"
+    )]
+    pub tsig: Option<Tsig>,
+    #[arg(long, help = "Take a SERVFAIL as the answer instead of asking the next nameserver")]
+    pub stop_on_servfail: bool,
+    #[arg(long, help = "Ask again over TCP when a UDP query gets SERVFAIL, before trying the next nameserver")]
+    pub retry_tcp_on_servfail: bool,
+    #[arg(long, help = "Start each query at the next nameserver in turn, like resolv.conf's options rotate")]
+    pub rotate: bool,
+    #[arg(
+        long,
+        conflicts_with = "rotate",
+        help = "Send UDP queries to every nameserver at once and take the first valid response"
+    )]
+    pub race: bool,
+    #[arg(long, help = "Hex dump the query and response bytes to stderr, marking each section")]
+    pub debug_wire: bool,
+    #[arg(
+        short,
+        long,
+        action = clap::ArgAction::Count,
+        help = "Log to stderr: -v each step of the exchange, -vv flags and cache decisions, -vvv the wire bytes"
+    )]
+    pub verbose: u8,
+    #[arg(short, long, conflicts_with = "verbose", help = "Only log errors, not warnings such as failing over")]
+    pub quiet: bool,
+    #[arg(long, value_name = "PATH", help = "Send queries framed over a local stub's Unix stream socket")]
+    pub unix: Option<PathBuf>,
+    #[arg(long, value_name = "URL", help = "Send queries to a DNS over HTTPS endpoint (needs the doh feature)")]
+    pub doh: Option<String>,
+    #[arg(
+        long,
+        value_name = "HOSTNAME",
+        help = "Send queries over DNS over TLS, checking the certificate is for HOSTNAME (needs the dot feature)"
+    )]
+    pub tls: Option<String>,
+    #[arg(long, value_name = "PORT", requires = "tls", help = "Connect to this port instead of 853")]
+    pub tls_port: Option<u16>,
+    #[arg(long, value_enum, help = "Print the response in another format")]
+    pub output: Option<OutputFormat>,
+    #[arg(long, value_name = "PATH", help = "Parse a raw packet dump instead of querying anything")]
+    pub parse_file: Option<PathBuf>,
+    #[arg(long, value_name = "PATH", help = "Save the serialized query to a file instead of sending it")]
+    pub write_query: Option<PathBuf>,
+    #[arg(long, value_name = "SECONDS", help = "Repeat the query on an interval and report changes in the answers")]
+    pub watch: Option<u64>,
+    #[arg(long, requires = "watch", help = "Stop once the answers differ from the first response")]
+    pub until_changed: bool,
+    #[arg(
+        long,
+        value_name = "N",
+        value_parser = clap::value_parser!(u32).range(1..),
+        conflicts_with_all = ["watch", "cache", "id"],
+        help = "Send the query N times, like ping, and report min/avg/max response time and failures"
+    )]
+    pub count: Option<u32>,
+    #[arg(
+        long,
+        value_name = "IP,IP,...",
+        value_delimiter = ',',
+        help = "Ask every listed nameserver and check that their answers agree"
+    )]
+    pub compare: Option<Vec<IpAddr>>,
+    #[arg(long, requires = "compare", help = "Answers whose TTLs differ don't agree either")]
+    pub strict_ttl: bool,
+    #[arg(long, help = "Ask a list of well-known public resolvers which have the current A records")]
+    pub propagation: bool,
+    #[arg(long, value_name = "PATH", requires = "propagation", help = "Ask the resolvers listed in this file instead")]
+    pub resolvers: Option<PathBuf>,
+    #[arg(
+        long,
+        value_name = "IP,IP,...",
+        value_delimiter = ',',
+        requires = "propagation",
+        help = "The A records the zone now has"
+    )]
+    pub expect: Option<Vec<Ipv4Addr>>,
+}
+
+// What the positional arguments of a lookup ask for
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Target {
+    pub domain: String,
+    pub rtype: RecordType,
+    // Tried in the order given, @nameservers first
+    pub nameservers: Vec<SocketAddr>,
+}
+
+impl QueryArgs {
+    pub fn dnssec(&self) -> bool {
+        self.dnssec || self.args.iter().any(|x| x == "+dnssec")
+    }
+
+    pub fn checking_disabled(&self) -> bool {
+        self.cd || self.args.iter().any(|x| x == "+cd")
+    }
+
+    // The UDP payload size to advertise, none with --no-edns
+    pub fn edns_payload(&self) -> Option<u16> {
+        match self.no_edns || self.args.iter().any(|x| x == "+noedns") {
+            true => None,
+            false => Some(self.bufsize.unwrap_or(DEFAULT_EDNS_PAYLOAD)),
+        }
+    }
+
+    /* --no-hosts, a nameserver or transport given outright, or anything asked of the answer
+        the hosts file can't give (DNSSEC, CD, a signature, an EDNS size): whoever was asked for
+        should answer, not the hosts file. A lone address is the name rather than a nameserver,
+        but that asks for its PTR, which the hosts file doesn't answer anyway.
+     */
+    pub fn skips_hosts(&self) -> bool {
+        self.no_hosts
+            || !self.server.is_empty()
+            || self.args.iter().any(|x| x.starts_with('@') || x.parse::<IpAddr>().is_ok())
+            || self.tcp
+            || self.tls.is_some()
+            || self.doh.is_some()
+            || self.unix.is_some()
+            || self.dnssec()
+            || self.checking_disabled()
+            || self.tsig.is_some()
+            || self.bufsize.is_some()
+    }
+
+    // How much of the library's tracing output reaches stderr
+    pub fn log_level(&self) -> LevelFilter {
+        match (self.quiet, self.verbose) {
+            (true, _) => LevelFilter::ERROR,
+            (false, 0) => LevelFilter::WARN,
+            (false, 1) => LevelFilter::INFO,
+            (false, 2) => LevelFilter::DEBUG,
+            (false, _) => LevelFilter::TRACE,
+        }
+    }
+
+    /* Sorts out the positionals like dig, in any order: an @nameserver is a nameserver, a
+        record type mnemonic the type, an address another nameserver and whatever is left the
+        name. --name, --type and --server settle anything that reads more than one way.
+        Without any nameserver, `defaults` are asked, and without a type the `default_type` of
+        `type_var`, BKDNS_DEFAULT_TYPE's value, which isn't read otherwise. An address as the
+        name asks for its PTR.
+     */
+    pub fn target(&self, defaults: &[IpAddr], type_var: Option<&str>) -> Result<Target, String> {
+        let mut servers: Vec<SocketAddr> = Vec::new();
+        let mut addresses: Vec<(&String, IpAddr)> = Vec::new();
+        let mut types: Vec<(&String, RecordType)> = Vec::new();
+        let mut names: Vec<&String> = Vec::new();
+        for arg in &self.args {
+            if let Some(option) = arg.strip_prefix('+') {
+                if !QUERY_OPTIONS.contains(&option) {
+                    return Err(format!("unknown query option {}", arg));
+                }
+            } else if let Some(server) = arg.strip_prefix('@') {
+                let addr: IpAddr = server.parse().map_err(|_| format!("invalid nameserver address {}", server))?;
+                servers.push(SocketAddr::new(addr, self.port));
+            } else if let Ok(addr) = arg.parse::<IpAddr>() {
+                addresses.push((arg, addr));
+            } else if let Some(rtype) = arg.parse::<RecordType>().ok().filter(|_| self.rtype.is_none()) {
+                types.push((arg, rtype));
+            } else {
+                names.push(arg);
+            }
+        }
+        servers.extend(self.server.iter().map(|x| SocketAddr::new(*x, self.port)));
+
+        let domain = match (&self.name, names.as_slice()) {
+            (Some(name), []) => name.clone(),
+            (Some(name), [other, ..]) => {
+                return Err(format!("the name is already {} from --name, {} is not a record type or address", name, other));
+            },
+            (None, [name]) => (*name).clone(),
+            (None, [first, second, ..]) => return Err(format!("only one name can be given, {} and {} both look like one", first, second)),
+            // nothing else can be the name, so a lone type or address is it
+            (None, []) => match (types.as_slice(), addresses.as_slice()) {
+                ([(arg, _)], []) => {
+                    let arg = (*arg).clone();
+                    types.clear();
+                    arg
+                },
+                ([], [(arg, _)]) => {
+                    let arg = (*arg).clone();
+                    addresses.clear();
+                    arg
+                },
+                ([], []) => return Err(String::from("a domain name is required")),
+                _ => {
+                    let ambiguous: Vec<&str> = types.iter().map(|x| x.0.as_str())
+                        .chain(addresses.iter().map(|x| x.0.as_str()))
+                        .collect();
+                    return Err(format!(
+                        "any of {} could be the name, say which with --name (or give the type with --type)",
+                        ambiguous.join(", ")
+                    ));
+                },
+            },
+        };
+
+        // an address as the name is a reverse lookup, like dig -x
+        let reverse = domain.parse::<IpAddr>().ok();
+        let domain = reverse.map_or(domain, names::reverse_name);
+
+        let rtype = match (self.rtype, types.as_slice()) {
+            (Some(rtype), _) => rtype,
+            (None, []) if reverse.is_some() => RecordType::PTR,
+            (None, []) => default_type(type_var)?,
+            (None, [(_, rtype)]) => *rtype,
+            (None, [_, (arg, _), ..]) => return Err(format!("only one record type can be given, {} is a second", arg)),
+        };
+
+        servers.extend(addresses.iter().map(|(_, addr)| SocketAddr::new(*addr, self.port)));
+        if servers.is_empty() {
+            servers.extend(defaults.iter().map(|x| SocketAddr::new(*x, self.port)));
+        }
+        Ok(Target { domain, rtype, nameservers: servers })
+    }
+}
+
+/* The exit status contract in EXIT_STATUS_HELP. Variants are ordered from best to worst,
+    so when several lookups make up one run the worst is the max.
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Status {
+    Answer,
+    Usage,
+    Network,
+    NxDomain,
+    ServerError,
+    BadResponse,
+}
+
+impl Status {
+    pub fn code(self) -> i32 {
+        match self {
+            Status::Answer => 0,
+            Status::Usage => 1,
+            Status::Network => 2,
+            Status::NxDomain => 3,
+            Status::ServerError => 4,
+            Status::BadResponse => 5,
+        }
+    }
+
+    pub fn from_outcome(outcome: &QueryOutcome) -> Self {
+        match outcome {
+            QueryOutcome::Answers(_) | QueryOutcome::NoData { .. } => Status::Answer,
+            QueryOutcome::NxDomain { .. } => Status::NxDomain,
+            QueryOutcome::Failure(_) => Status::ServerError,
+        }
+    }
+
+    pub fn from_error(err: &DnsError) -> Self {
+        match err {
+            DnsError::Io(_) | DnsError::NoNameservers | DnsError::IdsExhausted => Status::Network,
+            #[cfg(feature = "doh")]
+            DnsError::Http(_) => Status::Network,
+            #[cfg(feature = "dot")]
+            DnsError::Tls(_) => Status::Network,
+            DnsError::NxDomain(_) | DnsError::ReplyCode(ReplyCode::NxDomain) => Status::NxDomain,
+            DnsError::ReplyCode(_)
+            | DnsError::CnameLoop(_)
+            | DnsError::TransferMissingSoa
+            | DnsError::TransferRefused(_)
+            | DnsError::TsigRejected(_) => Status::ServerError,
+            DnsError::TooShort { .. }
+            | DnsError::BadPointer { .. }
+            | DnsError::BadLabelType { .. }
+            | DnsError::BadRecordLength { .. }
+            | DnsError::TooManyRecords(_)
+            | DnsError::Malformed { .. }
+            | DnsError::IdMismatch { .. }
+            | DnsError::NotAResponse
+            | DnsError::CaseMismatch { .. }
+            | DnsError::TsigInvalid(_) => Status::BadResponse,
+            #[cfg(feature = "bincode")]
+            DnsError::Bincode(_) => Status::BadResponse,
+            // the name or query asked for can't be sent at all
+            DnsError::BufferTooSmall(_)
+            | DnsError::RequiresTcp(_)
+            | DnsError::EmptyLabel(_)
+            | DnsError::LabelTooLong(_)
+            | DnsError::NameTooLong(_)
+            | DnsError::NonAsciiName(_)
+            | DnsError::InvalidEscape(_)
+            | DnsError::InvalidIdn(_)
+            | DnsError::UnknownType(_)
+            | DnsError::UnknownClass(_)
+            | DnsError::BadTsigKey(_)
+            | DnsError::NotInZone { .. }
+            | DnsError::ZoneSyntax { .. } => Status::Usage,
+        }
+    }
+}
+
+fn parse_type(name: &str) -> Result<RecordType, String> {
+    name.parse().map_err(|_| format!("unknown record type {}", name))
+}
+
+fn parse_at_server(server: &str) -> Result<String, String> {
+    match server.strip_prefix('@').unwrap_or(server) {
+        "" => Err(String::from("the nameserver is empty")),
+        server => Ok(String::from(server)),
+    }
+}
+
+fn parse_upstream(upstream: &str) -> Result<SocketAddr, String> {
+    upstream.parse::<IpAddr>()
+        .map(|x| SocketAddr::new(x, 53))
+        .or_else(|_| upstream.parse::<SocketAddr>())
+        .map_err(|_| format!("invalid nameserver address {}", upstream))
+}
+
+fn parse_override(entry: &str) -> Result<(String, IpAddr), String> {
+    let (name, addr) = entry.split_once('=').ok_or_else(|| String::from("expected NAME=ADDR"))?;
+    let addr = addr.parse().map_err(|_| format!("invalid address {}", addr))?;
+    Ok((String::from(name), addr))
+}
+
+fn parse_tsig(spec: &str) -> Result<Tsig, String> {
+    spec.parse().map_err(|err: DnsError| err.to_string())
+}
+
+fn parse_id(id: &str) -> Result<u16, String> {
+    let parsed = match id.strip_prefix("0x").or_else(|| id.strip_prefix("0X")) {
+        Some(hex) => u16::from_str_radix(hex, 16),
+        None => id.parse(),
+    };
+    parsed.map_err(|_| String::from("expected a number up to 65535"))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddr;
+
+    use clap::Parser;
+
+    use bkdns::packet::RecordType;
+
+    use crate::cli::{Cli, Command, Target, DEFAULT_NAMESERVER};
+
+    fn target(args: &[&str]) -> Result<Target, String> {
+        let cli = Cli::try_parse_from(std::iter::once("bkdns").chain(args.iter().copied())).unwrap();
+        cli.query.target(&[DEFAULT_NAMESERVER], None)
+    }
+
+    fn server(addr: &str) -> SocketAddr {
+        SocketAddr::new(addr.parse().unwrap(), 53)
+    }
+
+    #[test]
+    fn positional_nameserver() {
+        assert_eq!(
+            target(&["example.com", "1.1.1.1"]),
+            Ok(Target { domain: String::from("example.com"), rtype: RecordType::A, nameservers: vec![server("1.1.1.1")] })
+        );
+        assert_eq!(target(&["example.com"]).unwrap().nameservers, [server("9.9.9.9")]);
+    }
+
+    #[test]
+    fn default_nameservers() {
+        let cli = Cli::try_parse_from(["bkdns", "example.com", "-p", "5353"]).unwrap();
+        let defaults = ["192.0.2.53".parse().unwrap(), "2001:db8::53".parse().unwrap()];
+        assert_eq!(
+            cli.query.target(&defaults, None).unwrap().nameservers,
+            ["192.0.2.53:5353".parse::<SocketAddr>().unwrap(), "[2001:db8::53]:5353".parse().unwrap()]
+        );
+
+        // only stand in when no nameserver was given
+        let cli = Cli::try_parse_from(["bkdns", "example.com", "@1.1.1.1"]).unwrap();
+        assert_eq!(cli.query.target(&defaults, None).unwrap().nameservers, [server("1.1.1.1")]);
+    }
+
+    #[test]
+    fn skips_hosts() {
+        let skips = |args: &[&str]| {
+            Cli::try_parse_from(std::iter::once("bkdns").chain(args.iter().copied())).unwrap().query.skips_hosts()
+        };
+        assert!(!skips(&["localhost"]));
+        assert!(!skips(&["localhost", "-p", "5353", "--both"]));
+        for args in [
+            &["localhost", "--no-hosts"][..],
+            &["localhost", "@127.0.0.1"],
+            &["localhost", "127.0.0.1"],
+            &["localhost", "--server", "127.0.0.1"],
+            &["localhost", "--tcp"],
+            &["localhost", "--unix", "/run/stub.sock"],
+            &["localhost", "--dnssec"],
+            &["localhost", "+dnssec"],
+            &["localhost", "--cd"],
+            &["localhost", "+cd"],
+            &["localhost", "--tsig", "key.:hmac-sha256:c2VjcmV0"],
+            &["localhost", "--bufsize", "1232"],
+        ] {
+            assert!(skips(args), "{:?}", args);
+        }
+    }
+
+    #[test]
+    fn type_and_at_servers_in_any_order() {
+        let parsed = target(&["example.com", "8.8.8.8", "mx", "@1.1.1.1"]).unwrap();
+        assert_eq!(parsed.rtype, RecordType::MX);
+        // @nameservers come first
+        assert_eq!(parsed.nameservers, [server("1.1.1.1"), server("8.8.8.8")]);
+        assert_eq!(target(&["@1.1.1.1", "example.com"]).unwrap().domain, "example.com");
+
+        let parsed = target(&["AAAA", "@1.1.1.1", "example.com"]).unwrap();
+        assert_eq!((parsed.domain.as_str(), parsed.rtype), ("example.com", RecordType::AAAA));
+    }
+
+    #[test]
+    fn ambiguous_names() {
+        // a lone token has to be the name, whatever it looks like
+        assert_eq!(target(&["mx"]).unwrap(), Target {
+            domain: String::from("mx"), rtype: RecordType::A, nameservers: vec![server("9.9.9.9")],
+        });
+        assert_eq!(target(&["192.0.2.1", "@1.1.1.1"]).unwrap(), Target {
+            domain: String::from("1.2.0.192.in-addr.arpa"), rtype: RecordType::PTR, nameservers: vec![server("1.1.1.1")],
+        });
+        let parsed = target(&["--name", "2001:db8::1", "TXT"]).unwrap();
+        assert_eq!((parsed.domain.as_str(), parsed.rtype), (bkdns::names::reverse_name("2001:db8::1".parse().unwrap()).as_str(), RecordType::TXT));
+
+        assert_eq!(target(&["--name", "mx", "MX"]).unwrap().rtype, RecordType::MX);
+        let parsed = target(&["mx", "--type", "TXT"]).unwrap();
+        assert_eq!((parsed.domain.as_str(), parsed.rtype), ("mx", RecordType::TXT));
+        let parsed = target(&["--server", "8.8.8.8", "example.com", "@1.1.1.1"]).unwrap();
+        assert_eq!(parsed.nameservers, [server("1.1.1.1"), server("8.8.8.8")]);
+        assert!(target(&["--name", "example.com", "example.org"]).is_err());
+    }
+
+    #[test]
+    fn default_type() {
+        use crate::cli::default_type;
+
+        assert_eq!(default_type(None), Ok(RecordType::A));
+        assert_eq!(default_type(Some("")), Ok(RecordType::A));
+        assert_eq!(default_type(Some("aaaa")), Ok(RecordType::AAAA));
+        assert!(default_type(Some("bogus")).is_err());
+
+        // --type and a positional type beat the environment, which beats A
+        let with_env = |args: &[&str]| {
+            let cli = Cli::try_parse_from(std::iter::once("bkdns").chain(args.iter().copied())).unwrap();
+            cli.query.target(&[DEFAULT_NAMESERVER], Some("MX")).unwrap().rtype
+        };
+        assert_eq!(with_env(&["example.com"]), RecordType::MX);
+        assert_eq!(with_env(&["example.com", "--type", "TXT"]), RecordType::TXT);
+        assert_eq!(with_env(&["example.com", "AAAA"]), RecordType::AAAA);
+        assert_eq!(with_env(&["192.0.2.1"]), RecordType::PTR);
+
+        // a bad value only matters when it would be used
+        let cli = Cli::try_parse_from(["bkdns", "example.com", "AAAA"]).unwrap();
+        assert_eq!(cli.query.target(&[DEFAULT_NAMESERVER], Some("bogus")).unwrap().rtype, RecordType::AAAA);
+        let cli = Cli::try_parse_from(["bkdns", "example.com"]).unwrap();
+        assert!(cli.query.target(&[DEFAULT_NAMESERVER], Some("bogus")).is_err());
+    }
+
+    #[test]
+    fn bad_positionals() {
+        // with no arguments at all the help is shown instead
+        assert!(Cli::try_parse_from(["bkdns"]).is_err());
+        assert!(target(&["@1.1.1.1"]).is_err());
+        assert!(target(&["example.com", "bogus"]).is_err());
+        assert!(target(&["MX", "A"]).is_err());
+        assert!(target(&["example.com", "A", "MX"]).is_err());
+        assert!(target(&["example.com", "@not-an-ip"]).is_err());
+    }
+
+    #[test]
+    fn flags() {
+        let cli = Cli::try_parse_from([
+            "bkdns", "--tcp", "-v", "--compare", "8.8.8.8,1.1.1.1", "--strict-ttl", "example.com",
+        ]).unwrap();
+        assert!(cli.query.tcp && cli.query.verbose == 1 && cli.query.strict_ttl);
+        assert_eq!(cli.query.compare.unwrap().len(), 2);
+
+        // options that only make sense with another one
+        assert!(Cli::try_parse_from(["bkdns", "--strict-ttl", "example.com"]).is_err());
+        assert!(Cli::try_parse_from(["bkdns", "--tls-port", "853", "example.com"]).is_err());
+        assert!(Cli::try_parse_from(["bkdns", "--watch", "soon", "example.com"]).is_err());
+        assert!(Cli::try_parse_from(["bkdns", "--count", "0", "example.com"]).is_err());
+        assert!(Cli::try_parse_from(["bkdns", "--mdns", "--tcp", "printer.local"]).is_err());
+        assert!(Cli::try_parse_from(["bkdns", "--llmnr", "--mdns", "fileserver"]).is_err());
+        assert!(Cli::try_parse_from(["bkdns", "serve", "--zone", "zone", "--allow-notify", "192.0.2.1"]).is_err());
+        assert!(Cli::try_parse_from(["bkdns", "--count", "3", "--id", "1", "example.com"]).is_err());
+        assert!(Cli::try_parse_from(["bkdns", "--bufsize", "511", "example.com"]).is_err());
+        assert!(Cli::try_parse_from(["bkdns", "--bufsize", "65536", "example.com"]).is_err());
+        assert_eq!(Cli::try_parse_from(["bkdns", "--bufsize", "65535", "example.com"]).unwrap().query.bufsize, Some(65535));
+        assert!(Cli::try_parse_from(["bkdns", "--bufsize", "4096", "--no-edns", "example.com"]).is_err());
+    }
+
+    #[test]
+    fn edns_payload() {
+        let payload = |args: &[&str]| {
+            Cli::try_parse_from(std::iter::once("bkdns").chain(args.iter().copied())).unwrap().query.edns_payload()
+        };
+        assert_eq!(payload(&["example.com"]), Some(1232));
+        assert_eq!(payload(&["example.com", "--bufsize", "4096"]), Some(4096));
+        assert_eq!(payload(&["example.com", "--no-edns"]), None);
+        assert_eq!(payload(&["example.com", "+noedns"]), None);
+    }
+
+    #[test]
+    fn dnssec_option() {
+        let cli = Cli::try_parse_from(["bkdns", "example.com", "+dnssec", "AAAA"]).unwrap();
+        assert!(cli.query.dnssec());
+        assert_eq!(cli.query.target(&[DEFAULT_NAMESERVER], None).unwrap().rtype, RecordType::AAAA);
+        assert!(Cli::try_parse_from(["bkdns", "--dnssec", "example.com"]).unwrap().query.dnssec());
+        assert!(!Cli::try_parse_from(["bkdns", "example.com"]).unwrap().query.dnssec());
+        assert!(target(&["example.com", "+bogus"]).is_err());
+
+        assert!(Cli::try_parse_from(["bkdns", "example.com", "+cd"]).unwrap().query.checking_disabled());
+        assert!(Cli::try_parse_from(["bkdns", "--cd", "example.com"]).unwrap().query.checking_disabled());
+        assert!(!Cli::try_parse_from(["bkdns", "example.com", "+dnssec"]).unwrap().query.checking_disabled());
+    }
+
+    #[test]
+    fn port() {
+        let parsed = target(&["-p", "5353", "example.com", "@127.0.0.1", "--server", "::1"]).unwrap();
+        assert_eq!(parsed.nameservers, [
+            SocketAddr::new("127.0.0.1".parse().unwrap(), 5353),
+            SocketAddr::new("::1".parse().unwrap(), 5353),
+        ]);
+    }
+
+    #[test]
+    fn worst_status_wins() {
+        use crate::cli::Status;
+
+        let statuses = [Status::Answer, Status::ServerError, Status::NxDomain];
+        assert_eq!(statuses.into_iter().max(), Some(Status::ServerError));
+        assert_eq!(Status::from_error(&bkdns::error::DnsError::NoNameservers).code(), 2);
+    }
+
+    #[test]
+    fn verbosity() {
+        use tracing::level_filters::LevelFilter;
+
+        let level = |args: &[&str]| {
+            Cli::try_parse_from(std::iter::once("bkdns").chain(args.iter().copied())).unwrap().query.log_level()
+        };
+        assert_eq!(level(&["example.com"]), LevelFilter::WARN);
+        assert_eq!(level(&["-v", "example.com"]), LevelFilter::INFO);
+        assert_eq!(level(&["-vv", "example.com"]), LevelFilter::DEBUG);
+        assert_eq!(level(&["-vvvv", "example.com"]), LevelFilter::TRACE);
+        assert_eq!(level(&["-q", "example.com"]), LevelFilter::ERROR);
+        assert!(Cli::try_parse_from(["bkdns", "-q", "-v", "example.com"]).is_err());
+    }
+
+    #[test]
+    fn subcommands() {
+        let cli = Cli::try_parse_from(["bkdns", "encode", "example.com", "AAAA", "--id", "0x1234"]).unwrap();
+        match cli.command {
+            Some(Command::Encode(args)) => {
+                assert_eq!((args.domain.as_str(), args.rtype, args.id), ("example.com", RecordType::AAAA, Some(0x1234)));
+            },
+            command => panic!("expected encode, got {:?}", command),
+        }
+
+        let cli = Cli::try_parse_from(["bkdns", "decode", "--hex", "1234", "--reencode"]).unwrap();
+        assert!(matches!(cli.command, Some(Command::Decode(args)) if args.reencode && args.hex.as_deref() == Some("1234")));
+        assert!(Cli::try_parse_from(["bkdns", "decode", "--hex", "12", "--file", "x"]).is_err());
+    }
+}
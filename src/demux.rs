@@ -0,0 +1,242 @@
+/* Many queries in flight on one socket. `IdAllocator` hands out transaction ids no other
+    pending query holds, and `Demux` gives each reply to the query with its id and question,
+    so a late reply to a finished query can't be taken for the answer to a new one.
+ */
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use crate::error::DnsError;
+use crate::names;
+use crate::packet::{DNSPacket, RecordType};
+
+const ID_COUNT: usize = 1 << 16;
+
+// Transaction ids in use, one bit each
+pub struct IdAllocator {
+    used: Vec<u64>,
+    count: usize,
+}
+
+impl IdAllocator {
+    pub fn new() -> Self {
+        IdAllocator { used: vec![0; ID_COUNT / 64], count: 0 }
+    }
+
+    /* A random id, or the next free one after it when that's taken, so the choice stays
+        unpredictable until the space is nearly full. All 65536 in use is an error.
+     */
+    pub fn allocate(&mut self) -> Result<u16, DnsError> {
+        if self.count == ID_COUNT {
+            return Err(DnsError::IdsExhausted);
+        }
+        let start = rand::random::<u16>();
+        let id = (0..=u16::MAX).map(|x| start.wrapping_add(x)).find(|x| !self.in_use(*x)).unwrap();
+        self.used[id as usize / 64] |= 1 << (id % 64);
+        self.count += 1;
+        Ok(id)
+    }
+
+    // Frees an id for reuse, releasing one twice is harmless
+    pub fn release(&mut self, id: u16) {
+        if self.in_use(id) {
+            self.used[id as usize / 64] &= !(1 << (id % 64));
+            self.count -= 1;
+        }
+    }
+
+    pub fn in_use(&self, id: u16) -> bool {
+        self.used[id as usize / 64] & (1 << (id % 64)) != 0
+    }
+
+    pub fn len(&self) -> usize {
+        self.count
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+}
+
+impl Default for IdAllocator {
+    fn default() -> Self {
+        IdAllocator::new()
+    }
+}
+
+struct PendingQuery<T> {
+    name: String,
+    rtype: RecordType,
+    waiter: T,
+}
+
+// Pending queries by id, each with whatever is waiting on its reply
+pub struct Demux<T> {
+    inner: Mutex<(IdAllocator, HashMap<u16, PendingQuery<T>>)>,
+}
+
+impl<T> Demux<T> {
+    pub fn new() -> Self {
+        Demux { inner: Mutex::new((IdAllocator::new(), HashMap::new())) }
+    }
+
+    // Picks an id for a query asking for `name` and `rtype`, to send it with
+    pub fn register(&self, name: &str, rtype: RecordType, waiter: T) -> Result<u16, DnsError> {
+        let mut inner = self.inner.lock().unwrap();
+        let id = inner.0.allocate()?;
+        inner.1.insert(id, PendingQuery { name: String::from(name), rtype, waiter });
+        Ok(id)
+    }
+
+    /* Forgets a query that finished or timed out, its id can go to the next one. The only place
+        ids are freed, so whoever registered a query releases it exactly once.
+     */
+    pub fn release(&self, id: u16) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.1.remove(&id);
+        inner.0.release(id);
+    }
+
+    /* The waiter of the query `reply` answers, taken out so nothing else is routed to it.
+        The id and the question both have to match, the name ignoring case since 0x20 is
+        checked later. Anything else is left pending and None returned. The id stays held
+        until `release`, a new query given it in between would be released along with the old.
+     */
+    pub fn route(&self, reply: &DNSPacket) -> Option<T> {
+        let question = reply.questions().first()?;
+        let mut inner = self.inner.lock().unwrap();
+        let id = reply.header.id();
+        let pending = inner.1.get(&id)?;
+        if pending.rtype != question.qtype() || !names::eq_ignore_case(&pending.name, question.name()) {
+            return None;
+        }
+        inner.1.remove(&id).map(|x| x.waiter)
+    }
+
+    // Every waiter still pending, taken out for when no reply can come, their ids held like `route`'s
+    pub fn drain(&self) -> Vec<T> {
+        self.inner.lock().unwrap().1.drain().map(|(_, x)| x.waiter).collect()
+    }
+
+    // Queries registered and not yet released, answered or not
+    pub fn len(&self) -> usize {
+        self.inner.lock().unwrap().0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl<T> Default for Demux<T> {
+    fn default() -> Self {
+        Demux::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use crate::packet::{DNSPacket, DNSQuestion, RecordType};
+
+    fn reply(id: u16, name: &str, rtype: RecordType) -> DNSPacket {
+        let mut packet = DNSPacket::with_id(id);
+        packet.header.flags.is_response = true;
+        packet.add_question(DNSQuestion::new(String::from(name), rtype).unwrap());
+        packet
+    }
+
+    #[test]
+    fn ids_exhausted() {
+        let mut ids = crate::demux::IdAllocator::new();
+        let mut taken: Vec<u16> = (0..=u16::MAX).map(|_| ids.allocate().unwrap()).collect();
+        assert!(matches!(ids.allocate(), Err(crate::error::DnsError::IdsExhausted)));
+
+        // every id came out once
+        taken.sort();
+        assert!(taken.iter().copied().eq(0..=u16::MAX));
+
+        ids.release(1234);
+        ids.release(1234);
+        assert_eq!(ids.len(), 65535);
+        assert_eq!(ids.allocate().unwrap(), 1234);
+    }
+
+    #[test]
+    fn route_needs_the_question() {
+        let demux = crate::demux::Demux::new();
+        let id = demux.register("example.com", RecordType::A, "example.com A").unwrap();
+
+        assert_eq!(demux.route(&reply(id, "example.org", RecordType::A)), None);
+        assert_eq!(demux.route(&reply(id, "example.com", RecordType::AAAA)), None);
+        assert_eq!(demux.route(&reply(id.wrapping_add(1), "example.com", RecordType::A)), None);
+        assert_eq!(demux.route(&DNSPacket::with_id(id)), None);
+        assert_eq!(demux.len(), 1);
+
+        assert_eq!(demux.route(&reply(id, "EXAMPLE.com", RecordType::A)), Some("example.com A"));
+        // a duplicate finds no one waiting
+        assert_eq!(demux.route(&reply(id, "example.com", RecordType::A)), None);
+        // the id is only free once the query is released, so it can't go to another meanwhile
+        assert_eq!(demux.len(), 1);
+        demux.release(id);
+        assert!(demux.is_empty());
+    }
+
+    #[test]
+    fn drain() {
+        let demux = crate::demux::Demux::new();
+        let first = demux.register("example.com", RecordType::A, "first").unwrap();
+        let second = demux.register("example.org", RecordType::A, "second").unwrap();
+
+        let mut drained = demux.drain();
+        drained.sort();
+        assert_eq!(drained, ["first", "second"]);
+        assert_eq!(demux.route(&reply(first, "example.com", RecordType::A)), None);
+        demux.release(first);
+        demux.release(second);
+        assert!(demux.is_empty());
+    }
+
+    #[test]
+    fn thousands_in_flight() {
+        const THREADS: usize = 8;
+        const PER_THREAD: usize = 2000;
+        let demux = Arc::new(crate::demux::Demux::new());
+
+        // register from every thread at once, then answer what another thread registered
+        let registered: Vec<Vec<(u16, String)>> = (0..THREADS)
+            .map(|thread| {
+                let demux = demux.clone();
+                std::thread::spawn(move || {
+                    (0..PER_THREAD)
+                        .map(|index| {
+                            let name = format!("q{}.t{}.example", index, thread);
+                            (demux.register(&name, RecordType::A, name.clone()).unwrap(), name)
+                        })
+                        .collect::<Vec<_>>()
+                })
+            })
+            .collect::<Vec<_>>()
+            .into_iter()
+            .map(|x| x.join().unwrap())
+            .collect();
+        assert_eq!(demux.len(), THREADS * PER_THREAD);
+
+        let answering: Vec<_> = registered.into_iter()
+            .map(|mut queries| {
+                let demux = demux.clone();
+                std::thread::spawn(move || {
+                    queries.reverse();
+                    for (id, name) in queries {
+                        assert_eq!(demux.route(&reply(id, &name, RecordType::A)).as_deref(), Some(name.as_str()));
+                        demux.release(id);
+                    }
+                })
+            })
+            .collect();
+        for thread in answering {
+            thread.join().unwrap();
+        }
+        assert!(demux.is_empty());
+    }
+}
@@ -0,0 +1,232 @@
+/* DNS-SD browsing (RFC 6763) over mDNS. A PTR query for a service type like `_http._tcp.local`
+    lists its instances, each instance's SRV record says which host and port it's on and its TXT
+    record carries `key=value` attributes. Responders usually send the SRV and TXT along with
+    the PTR in the additional section, they're only asked for when one didn't.
+ */
+use std::net::SocketAddr;
+
+use crate::error::DnsError;
+use crate::mdns::{Mdns, Responder};
+use crate::names;
+use crate::packet::{self, DNSRecord, RecordData, RecordType};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Instance {
+    // The full name, `Printer._ipp._tcp.local`
+    pub name: String,
+    // Who announced it
+    pub responder: SocketAddr,
+    // From the SRV record, None when no responder sent one
+    pub host: Option<String>,
+    pub port: Option<u16>,
+    // In the order of the TXT record, an attribute with no `=` is a boolean one and has no value
+    pub txt: Vec<(String, Option<String>)>,
+}
+
+impl Instance {
+    // The instance label alone, unescaped for display: `My Printer` rather than `My\032Printer`
+    pub fn label(&self) -> String {
+        let bytes = packet::serialize_dns_str(&self.name).unwrap_or_default();
+        match bytes.first() {
+            Some(&length) if length > 0 => {
+                String::from_utf8_lossy(bytes.get(1..1 + length as usize).unwrap_or_default()).into_owned()
+            },
+            _ => self.name.clone(),
+        }
+    }
+}
+
+/* The instances of `service` that answer within the mDNS window, in the order they were
+    announced. Any instance missing its SRV or TXT gets a query of its own for it, which takes
+    another window.
+ */
+pub fn browse(mdns: &Mdns, service: &str) -> Result<Vec<Instance>, DnsError> {
+    let service = names::validate_mdns_name(service)?;
+    let mut instances = instances(&mdns.query(&service, RecordType::PTR)?, &service);
+    for instance in &mut instances {
+        if instance.host.is_none() {
+            let responders = mdns.query(&instance.name, RecordType::SRV)?;
+            fill_in(instance, responders.iter().flat_map(records));
+        }
+        if instance.txt.is_empty() {
+            let responders = mdns.query(&instance.name, RecordType::TXT)?;
+            fill_in(instance, responders.iter().flat_map(records));
+        }
+    }
+    Ok(instances)
+}
+
+// Every PTR of `service` the responders answered with, filled in from what came with it
+fn instances(responders: &[Responder], service: &str) -> Vec<Instance> {
+    let mut instances: Vec<Instance> = Vec::new();
+    for responder in responders {
+        for record in &responder.answers {
+            let RecordData::Ptr(name) = &record.data else { continue };
+            let seen = instances.iter().any(|x| names::eq_ignore_case(&x.name, name));
+            if seen || !names::eq_ignore_case(&record.name, service) {
+                continue;
+            }
+            let mut instance = Instance {
+                name: name.clone(),
+                responder: responder.addr,
+                host: None,
+                port: None,
+                txt: Vec::new(),
+            };
+            fill_in(&mut instance, records(responder));
+            instances.push(instance);
+        }
+    }
+    instances
+}
+
+fn records(responder: &Responder) -> impl Iterator<Item = &DNSRecord> {
+    responder.answers.iter().chain(&responder.additional)
+}
+
+// Takes the first SRV and TXT for the instance among `records`, unless it has them already
+fn fill_in<'a>(instance: &mut Instance, records: impl Iterator<Item = &'a DNSRecord>) {
+    for record in records.filter(|x| names::eq_ignore_case(&x.name, &instance.name)) {
+        match &record.data {
+            RecordData::Srv { port, target, .. } if instance.host.is_none() => {
+                instance.host = Some(target.clone());
+                instance.port = Some(*port);
+            },
+            RecordData::Txt(strings) if instance.txt.is_empty() => instance.txt = txt_attributes(strings),
+            _ => {},
+        }
+    }
+}
+
+/* Each character-string split on its first `=` (RFC 6763 section 6.4). Keys are kept as sent,
+    a string with no key is skipped, and so is the single empty string of a TXT with nothing in it.
+    Keys are ASCII and values are usually UTF-8 text (section 6.5), both are decoded lossily.
+ */
+pub fn txt_attributes(strings: &[Vec<u8>]) -> Vec<(String, Option<String>)> {
+    let text = |bytes: &[u8]| String::from_utf8_lossy(bytes).into_owned();
+    strings.iter()
+        .filter(|x| !x.is_empty() && !x.starts_with(b"="))
+        .map(|x| match x.iter().position(|byte| *byte == b'=') {
+            Some(split) => (text(&x[..split]), Some(text(&x[split + 1..]))),
+            None => (text(x), None),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{SocketAddr, UdpSocket};
+    use std::time::Duration;
+
+    use crate::dnssd::Instance;
+    use crate::mdns::{Mdns, Responder};
+    use crate::packet::{DNSPacket, DNSRecord, RecordData, RecordType, RECORD_CLASS};
+
+    const SERVICE: &str = "_ipp._tcp.local";
+    const INSTANCE: &str = "My\\032Printer._ipp._tcp.local";
+
+    fn record(name: &str, rtype: RecordType, data: RecordData) -> DNSRecord {
+        DNSRecord { name: String::from(name), rtype, class: RECORD_CLASS, ttl: 120, data }
+    }
+
+    fn ptr(instance: &str) -> DNSRecord {
+        record(SERVICE, RecordType::PTR, RecordData::Ptr(String::from(instance)))
+    }
+
+    fn srv(instance: &str, port: u16) -> DNSRecord {
+        let data = RecordData::Srv { priority: 0, weight: 0, port, target: String::from("printer.local") };
+        record(instance, RecordType::SRV, data)
+    }
+
+    fn txt(instance: &str, strings: &[&str]) -> DNSRecord {
+        record(instance, RecordType::TXT, RecordData::Txt(strings.iter().map(|x| x.as_bytes().to_vec()).collect()))
+    }
+
+    #[test]
+    fn txt_attributes() {
+        let strings: Vec<Vec<u8>> = ["txtvers=1", "rp=printers/x=y", "Color", "empty=", "=novalue", ""]
+            .into_iter()
+            .map(|x| x.as_bytes().to_vec())
+            .collect();
+        assert_eq!(crate::dnssd::txt_attributes(&strings), [
+            (String::from("txtvers"), Some(String::from("1"))),
+            (String::from("rp"), Some(String::from("printers/x=y"))),
+            (String::from("Color"), None),
+            (String::from("empty"), Some(String::new())),
+        ]);
+    }
+
+    #[test]
+    fn instances() {
+        let first: SocketAddr = "192.0.2.1:5353".parse().unwrap();
+        let second: SocketAddr = "192.0.2.2:5353".parse().unwrap();
+        let responders = [
+            Responder {
+                addr: first,
+                answers: vec![ptr(INSTANCE)],
+                additional: vec![srv(INSTANCE, 631), txt(INSTANCE, &["rp=ipp/print"])],
+            },
+            // the same instance again, and one that left its SRV and TXT out
+            Responder { addr: second, answers: vec![ptr(INSTANCE), ptr("Other._ipp._tcp.local")], additional: vec![] },
+        ];
+        let instances = crate::dnssd::instances(&responders, SERVICE);
+        assert_eq!(instances, [
+            Instance {
+                name: String::from(INSTANCE),
+                responder: first,
+                host: Some(String::from("printer.local")),
+                port: Some(631),
+                txt: vec![(String::from("rp"), Some(String::from("ipp/print")))],
+            },
+            Instance {
+                name: String::from("Other._ipp._tcp.local"),
+                responder: second,
+                host: None,
+                port: None,
+                txt: vec![],
+            },
+        ]);
+        assert_eq!(instances[0].label(), "My Printer");
+    }
+
+    #[test]
+    fn browse() {
+        let responder = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let group = match responder.local_addr().unwrap() {
+            SocketAddr::V4(addr) => addr,
+            SocketAddr::V6(_) => unreachable!(),
+        };
+        // the PTR comes alone, so the SRV and TXT are asked for
+        let asked = std::thread::spawn(move || {
+            let mut asked = Vec::new();
+            for _ in 0..3 {
+                let mut buf = [0; 512];
+                let (size, client) = responder.recv_from(&mut buf).unwrap();
+                let query = DNSPacket::deserialize(&buf[..size]).unwrap();
+                let question = query.questions()[0].clone();
+                let mut response = DNSPacket::with_id(0);
+                response.header.flags.is_response = true;
+                response.add_answer(match question.qtype() {
+                    RecordType::PTR => ptr(INSTANCE),
+                    RecordType::SRV => srv(INSTANCE, 8080),
+                    _ => txt(INSTANCE, &["path=/"]),
+                });
+                responder.send_to(&response.serialize().unwrap(), client).unwrap();
+                asked.push((String::from(question.name()), question.qtype()));
+            }
+            asked
+        });
+
+        let mdns = Mdns { group, window: Duration::from_millis(200) };
+        let instances = crate::dnssd::browse(&mdns, SERVICE).unwrap();
+        // names go out lowercased, like any other query's
+        assert_eq!(asked.join().unwrap(), [
+            (String::from(SERVICE), RecordType::PTR),
+            (INSTANCE.to_lowercase(), RecordType::SRV),
+            (INSTANCE.to_lowercase(), RecordType::TXT),
+        ]);
+        assert_eq!(instances.len(), 1);
+        assert_eq!((instances[0].host.as_deref(), instances[0].port), (Some("printer.local"), Some(8080)));
+        assert_eq!(instances[0].txt, [(String::from("path"), Some(String::from("/")))]);
+    }
+}
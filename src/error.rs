@@ -0,0 +1,191 @@
+use alloc::boxed::Box;
+use alloc::string::String;
+use core::fmt;
+
+use crate::packet::{RecordType, ReplyCode};
+
+// The record sections of a packet, in wire order
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Section {
+    Answer,
+    Authority,
+    Additional,
+}
+
+// What was being read when parsing a packet failed. Questions and records count from 0.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ParsePart {
+    Header,
+    Question(usize),
+    // The owner name and the fixed fields up to and including rdlength
+    Record { section: Section, index: usize },
+    Rdata { section: Section, index: usize },
+}
+
+impl fmt::Display for ParsePart {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let section_name = |section: &Section| match section {
+            Section::Answer => "answer",
+            Section::Authority => "authority record",
+            Section::Additional => "additional record",
+        };
+        match self {
+            ParsePart::Header => write!(f, "the header"),
+            ParsePart::Question(index) => write!(f, "question #{}", index + 1),
+            ParsePart::Record { section, index } => write!(f, "{} #{}", section_name(section), index + 1),
+            ParsePart::Rdata { section, index } => write!(f, "the rdata of {} #{}", section_name(section), index + 1),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum DnsError {
+    #[cfg(feature = "std")]
+    Io(std::io::Error),
+    TooShort { offset: usize, expected: usize },
+    BadPointer { offset: usize, pointer: usize },
+    // A length byte with only one of the top two bits set, a reserved or obsolete label type
+    BadLabelType { offset: usize, length: u8 },
+    BadRecordLength { rtype: u16, length: usize },
+    TooManyRecords(usize),
+    // `error` came up while parsing `part`, which starts at `offset` of the packet
+    Malformed { part: ParsePart, offset: usize, error: Box<DnsError> },
+    BufferTooSmall(usize),
+    NoNameservers,
+    // Every transaction id is taken by a query still in flight
+    IdsExhausted,
+    #[cfg(feature = "doh")]
+    Http(String),
+    #[cfg(feature = "dot")]
+    Tls(String),
+    #[cfg(feature = "bincode")]
+    Bincode(String),
+    IdMismatch { expected: u16, received: u16 },
+    NotAResponse,
+    CaseMismatch { expected: String, received: String },
+    NxDomain(String),
+    ReplyCode(ReplyCode),
+    CnameLoop(String),
+    RequiresTcp(RecordType),
+    TransferMissingSoa,
+    // The nameserver answered the AXFR query with REFUSED, it doesn't transfer the zone to us
+    TransferRefused(String),
+    // The TSIG error the nameserver answered a signed query with, BADSIG, BADKEY or BADTIME mostly
+    #[cfg(feature = "tsig")]
+    TsigRejected(u16),
+    // A response to a signed query whose own signature is missing or doesn't hold up
+    #[cfg(feature = "tsig")]
+    TsigInvalid(&'static str),
+    #[cfg(feature = "tsig")]
+    BadTsigKey(String),
+    EmptyLabel(String),
+    LabelTooLong(String),
+    NameTooLong(usize),
+    NonAsciiName(String),
+    InvalidEscape(String),
+    InvalidIdn(String),
+    // Not a mnemonic or TYPEnnn (CLASSnnn) form
+    UnknownType(String),
+    UnknownClass(String),
+    // A record in an UPDATE for a name outside the zone being updated
+    NotInZone { name: String, zone: String },
+    // A zone file entry that couldn't be read, lines count from 1
+    ZoneSyntax { line: usize, message: String },
+}
+
+impl fmt::Display for DnsError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            #[cfg(feature = "std")]
+            DnsError::Io(err) => write!(f, "Network error: {}", err),
+            DnsError::TooShort { offset, expected } => write!(
+                f, "Packet ended before the {} bytes expected at offset {}", expected, offset
+            ),
+            DnsError::BadPointer { offset, pointer } => write!(
+                f, "Invalid compression pointer to {} at offset {}", pointer, offset
+            ),
+            DnsError::BadLabelType { offset, length } => write!(
+                f, "Invalid label type {:#04x} at offset {}", length & 0xC0, offset
+            ),
+            DnsError::BadRecordLength { rtype, length } => write!(
+                f, "Invalid record data length {} for type {}", length, rtype
+            ),
+            DnsError::TooManyRecords(count) => write!(f, "Packet claims too many records: {}", count),
+            DnsError::Malformed { part, error, .. } => write!(f, "{} in {}", error, part),
+            DnsError::BufferTooSmall(size) => write!(f, "Packet does not fit in a {} byte buffer", size),
+            DnsError::NoNameservers => write!(f, "No nameservers to ask"),
+            DnsError::IdsExhausted => write!(f, "All 65536 transaction ids are in use by queries in flight"),
+            #[cfg(feature = "doh")]
+            DnsError::Http(err) => write!(f, "DNS over HTTPS request failed: {}", err),
+            #[cfg(feature = "dot")]
+            DnsError::Tls(err) => write!(f, "DNS over TLS setup failed: {}", err),
+            #[cfg(feature = "bincode")]
+            DnsError::Bincode(err) => write!(f, "Invalid bincode packet: {}", err),
+            DnsError::IdMismatch { expected, received } => write!(
+                f, "Response id {:#06x} does not match query id {:#06x}", received, expected
+            ),
+            DnsError::NotAResponse => write!(f, "Received a query, not a response"),
+            DnsError::CaseMismatch { expected, received } => write!(
+                f, "Response echoed question {} instead of {}", received, expected
+            ),
+            DnsError::NxDomain(name) => write!(f, "Domain does not exist: {}", name),
+            DnsError::ReplyCode(code) => write!(f, "Server returned error reply code {:?}", code),
+            DnsError::CnameLoop(name) => write!(f, "CNAME chain for {} loops or is too long", name),
+            DnsError::RequiresTcp(rtype) => write!(f, "{:?} queries can only be sent over TCP", rtype),
+            DnsError::TransferMissingSoa => write!(f, "Zone transfer did not start with an SOA record"),
+            DnsError::TransferRefused(zone) => write!(
+                f, "The nameserver refused to transfer {}, it may only allow its secondaries", zone
+            ),
+            #[cfg(feature = "tsig")]
+            DnsError::TsigRejected(code) => match crate::tsig::error_name(*code) {
+                Some(name) => write!(f, "The nameserver rejected the query's TSIG signature: {}", name),
+                None => write!(f, "The nameserver rejected the query's TSIG signature: error {}", code),
+            },
+            #[cfg(feature = "tsig")]
+            DnsError::TsigInvalid(reason) => write!(f, "The response's TSIG signature is invalid: {}", reason),
+            #[cfg(feature = "tsig")]
+            DnsError::BadTsigKey(reason) => write!(f, "Invalid TSIG key: {}", reason),
+            DnsError::EmptyLabel(name) => write!(f, "Domain name has an empty label: {}", name),
+            DnsError::LabelTooLong(label) => write!(f, "Label is longer than 63 bytes: {}", label),
+            DnsError::NameTooLong(size) => write!(f, "Domain name is {} bytes, more than 255", size),
+            DnsError::NonAsciiName(name) => write!(f, "Domain name is not ASCII: {}", name),
+            DnsError::InvalidEscape(name) => write!(f, "Domain name has an invalid escape: {}", name),
+            DnsError::InvalidIdn(name) => write!(f, "Invalid internationalized domain name: {}", name),
+            DnsError::UnknownType(name) => write!(f, "Unknown record type: {}", name),
+            DnsError::UnknownClass(name) => write!(f, "Unknown record class: {}", name),
+            DnsError::NotInZone { name, zone } => write!(f, "{} is not in the zone {}", name, zone),
+            DnsError::ZoneSyntax { line, message } => write!(f, "Zone file line {}: {}", line, message),
+        }
+    }
+}
+
+impl DnsError {
+    // Where in the packet a parse error is, as precisely as the error knows
+    pub fn offset(&self) -> Option<usize> {
+        match self {
+            DnsError::TooShort { offset, .. }
+            | DnsError::BadPointer { offset, .. }
+            | DnsError::BadLabelType { offset, .. } => Some(*offset),
+            DnsError::Malformed { offset, error, .. } => Some(error.offset().unwrap_or(*offset)),
+            _ => None,
+        }
+    }
+
+    // Tags an error from parsing `part` with where it was, done once at the outermost level
+    pub(crate) fn within(self, part: ParsePart, offset: usize) -> Self {
+        match self {
+            DnsError::Malformed { .. } => self,
+            error => DnsError::Malformed { part, offset, error: Box::new(error) },
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for DnsError {}
+
+#[cfg(feature = "std")]
+impl From<std::io::Error> for DnsError {
+    fn from(err: std::io::Error) -> Self {
+        DnsError::Io(err)
+    }
+}
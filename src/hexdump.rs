@@ -0,0 +1,109 @@
+/* Classic hex dumps of packets: offset, 16 bytes in hex and the same bytes as
+    ASCII, with optional labelled marks where a part of the packet begins.
+ */
+use alloc::format;
+use alloc::string::String;
+
+use crate::packet_ref::Layout;
+
+const ROW_SIZE: usize = 16;
+
+pub fn hexdump(bytes: &[u8]) -> String {
+    hexdump_with_marks(bytes, &[])
+}
+
+/* Each mark is an offset and a label. The row holding a mark is cut short before it, and
+    the label gets a line of its own above the bytes it names. Marks must be in order.
+ */
+pub fn hexdump_with_marks(bytes: &[u8], marks: &[(usize, &str)]) -> String {
+    let mut dump = String::new();
+    let mut marks = marks.iter().peekable();
+    let mut start = 0;
+    while start < bytes.len() {
+        while let Some((_, label)) = marks.next_if(|(offset, _)| *offset <= start) {
+            dump += &format!("; {}\n", label);
+        }
+        let mut end = (start + ROW_SIZE).min(bytes.len());
+        if let Some((offset, _)) = marks.peek() {
+            end = end.min(*offset);
+        }
+        dump += &row(start, &bytes[start..end]);
+        start = end;
+    }
+    dump
+}
+
+// A dump marking where the header ends and each non-empty section of the packet begins
+pub fn hexdump_packet(bytes: &[u8], layout: &Layout) -> String {
+    let sections = [
+        (&layout.header, "header"),
+        (&layout.questions, "question section"),
+        (&layout.answers, "answer section"),
+        (&layout.authority, "authority section"),
+        (&layout.additional, "additional section"),
+    ];
+    let mut marks: alloc::vec::Vec<(usize, &str)> = sections.iter()
+        .filter(|(range, _)| !range.is_empty())
+        .map(|(range, label)| (range.start, *label))
+        .collect();
+    if layout.additional.end < bytes.len() {
+        marks.push((layout.additional.end, "trailing bytes"));
+    }
+    hexdump_with_marks(bytes, &marks)
+}
+
+fn row(offset: usize, bytes: &[u8]) -> String {
+    let mut hex = String::new();
+    for index in 0..ROW_SIZE {
+        // an extra space between the two halves of the row
+        if index == ROW_SIZE / 2 {
+            hex.push(' ');
+        }
+        match bytes.get(index) {
+            Some(byte) => hex += &format!("{:02x} ", byte),
+            None => hex += "   ",
+        }
+    }
+    let ascii: String = bytes.iter()
+        .map(|x| if x.is_ascii_graphic() || *x == b' ' { *x as char } else { '.' })
+        .collect();
+    format!("{:08x}  {} |{}|\n", offset, hex, ascii)
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn hexdump() {
+        let bytes: Vec<u8> = (0x5e..0x72).collect();
+        assert_eq!(
+            crate::hexdump::hexdump(&bytes),
+            "00000000  5e 5f 60 61 62 63 64 65  66 67 68 69 6a 6b 6c 6d  |^_`abcdefghijklm|\n\
+             00000010  6e 6f 70 71                                       |nopq|\n"
+        );
+        assert_eq!(crate::hexdump::hexdump(&[]), "");
+    }
+
+    #[test]
+    fn marks_split_rows() {
+        let dump = crate::hexdump::hexdump_with_marks(&[0, 0x41, 0x42, 0x7f], &[(0, "start"), (2, "rest")]);
+        assert_eq!(
+            dump,
+            "; start\n\
+             00000000  00 41                                             |.A|\n\
+             ; rest\n\
+             00000002  42 7f                                             |B.|\n"
+        );
+    }
+
+    #[test]
+    fn packet_sections() {
+        let bytes = crate::testdata::A_RESPONSE;
+        let view = crate::packet_ref::DNSPacketRef::deserialize(&bytes).unwrap();
+        let labels: Vec<String> = crate::hexdump::hexdump_packet(&bytes, view.layout())
+            .lines()
+            .filter(|x| x.starts_with(';'))
+            .map(String::from)
+            .collect();
+        assert_eq!(labels, ["; header", "; question section", "; answer section"]);
+    }
+}
@@ -0,0 +1,166 @@
+/* Hosts file lookups, answered the way getaddrinfo answers them before any DNS: /etc/hosts, or
+    %SystemRoot%\System32\drivers\etc\hosts on Windows. Each line is an address followed by the
+    names it has, the canonical name first and then any aliases.
+ */
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+// Addresses by lowercased name, in the order the file lists them
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Hosts {
+    names: HashMap<String, Vec<IpAddr>>,
+}
+
+impl Hosts {
+    /* `#` starts a comment anywhere on a line and fields are split on any run of spaces and
+        tabs. A line whose first field isn't an address is skipped whole, and so is one with an
+        IPv6 %zone that IpAddr can't hold. A name listed twice for an address keeps it once.
+     */
+    pub fn parse(text: &str) -> Self {
+        let mut hosts = Hosts::default();
+        for line in text.lines() {
+            let line = line.split('#').next().unwrap_or_default();
+            let mut fields = line.split_whitespace();
+            let Some(addr) = fields.next().and_then(|x| x.parse::<IpAddr>().ok()) else { continue };
+            for name in fields {
+                let addrs = hosts.names.entry(normalize(name)).or_default();
+                if !addrs.contains(&addr) {
+                    addrs.push(addr);
+                }
+            }
+        }
+        hosts
+    }
+
+    pub fn lookup(&self, name: &str) -> &[IpAddr] {
+        self.names.get(&normalize(name)).map_or(&[], |x| x.as_slice())
+    }
+}
+
+fn normalize(name: &str) -> String {
+    name.trim_end_matches('.').to_ascii_lowercase()
+}
+
+/* A hosts file, parsed the first time it's asked and again whenever its modification time
+    changes, so a long-running caller sees edits without paying for a read on every lookup
+ */
+#[derive(Debug)]
+pub struct HostsFile {
+    path: PathBuf,
+    loaded: Mutex<Option<(SystemTime, Hosts)>>,
+}
+
+impl HostsFile {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        HostsFile { path: path.into(), loaded: Mutex::new(None) }
+    }
+
+    // The operating system's own
+    pub fn system() -> Self {
+        #[cfg(windows)]
+        let path = std::env::var_os("SystemRoot")
+            .map_or_else(|| PathBuf::from(r"C:\Windows"), PathBuf::from)
+            .join(r"System32\drivers\etc\hosts");
+        #[cfg(not(windows))]
+        let path = PathBuf::from("/etc/hosts");
+        HostsFile::new(path)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    // The addresses the file has for `name`, none when it's missing or can't be read
+    pub fn lookup(&self, name: &str) -> Vec<IpAddr> {
+        let mut loaded = self.loaded.lock().unwrap();
+        let modified = match std::fs::metadata(&self.path).and_then(|x| x.modified()) {
+            Ok(modified) => modified,
+            Err(err) => {
+                tracing::debug!(path = %self.path.display(), %err, "no hosts file");
+                *loaded = None;
+                return Vec::new();
+            },
+        };
+        if loaded.as_ref().is_none_or(|(read, _)| *read != modified) {
+            match std::fs::read_to_string(&self.path) {
+                Ok(text) => {
+                    tracing::debug!(path = %self.path.display(), "reading the hosts file");
+                    *loaded = Some((modified, Hosts::parse(&text)));
+                },
+                Err(err) => {
+                    tracing::debug!(path = %self.path.display(), %err, "couldn't read the hosts file");
+                    *loaded = None;
+                },
+            }
+        }
+        loaded.as_ref().map(|(_, hosts)| hosts.lookup(name).to_vec()).unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::IpAddr;
+    use std::time::{Duration, SystemTime};
+
+    use crate::hosts::{Hosts, HostsFile};
+
+    /* Legal if unusual: tabs and runs of spaces, trailing comments, a comment with no space
+        before it, CRLF endings, a name repeated on a line and one on three
+     */
+    const FIXTURE: &str = "\
+        # The loopback\t\taddresses\n\
+        127.0.0.1\tlocalhost  localhost.localdomain\t loopback# call it what you like\n\
+        ::1     localhost ip6-localhost ip6-loopback\r\n\
+        \n\
+        \t   \n\
+        192.0.2.10 Files.Example.com. files files\n\
+        192.0.2.11 files.example.com\n\
+        2001:db8::10 files.example.com\n\
+        fe80::1%eth0 router\n\
+        not-an-address stray\n\
+        192.0.2.12\n\
+        192.0.2.13 printer #printer.example.com\n";
+
+    fn addrs(addrs: &[&str]) -> Vec<IpAddr> {
+        addrs.iter().map(|x| x.parse().unwrap()).collect()
+    }
+
+    #[test]
+    fn parse() {
+        let hosts = Hosts::parse(FIXTURE);
+        assert_eq!(hosts.lookup("localhost"), addrs(&["127.0.0.1", "::1"]));
+        assert_eq!(hosts.lookup("LOOPBACK"), addrs(&["127.0.0.1"]));
+        assert_eq!(hosts.lookup("ip6-loopback"), addrs(&["::1"]));
+        assert_eq!(hosts.lookup("files.example.com."), addrs(&["192.0.2.10", "192.0.2.11", "2001:db8::10"]));
+        assert_eq!(hosts.lookup("files"), addrs(&["192.0.2.10"]));
+        assert_eq!(hosts.lookup("printer"), addrs(&["192.0.2.13"]));
+        for missing in ["router", "stray", "printer.example.com", "call", "addresses"] {
+            assert!(hosts.lookup(missing).is_empty(), "{}", missing);
+        }
+        assert_eq!(Hosts::parse(""), Hosts::default());
+    }
+
+    #[test]
+    fn reloads_when_modified() {
+        let path = std::env::temp_dir().join(format!("bkdns-test-{}.hosts", std::process::id()));
+        let hosts = HostsFile::new(&path);
+        assert!(hosts.lookup("files").is_empty());
+
+        let write = |text: &str, age: u64| {
+            std::fs::write(&path, text).unwrap();
+            // set outright, the two writes can land within the filesystem's timestamp granularity
+            let file = std::fs::File::options().write(true).open(&path).unwrap();
+            file.set_modified(SystemTime::now() - Duration::from_secs(age)).unwrap();
+        };
+        write("192.0.2.10 files\n", 60);
+        assert_eq!(hosts.lookup("files"), addrs(&["192.0.2.10"]));
+        write("192.0.2.11 files\n", 30);
+        assert_eq!(hosts.lookup("files"), addrs(&["192.0.2.11"]));
+
+        std::fs::remove_file(&path).unwrap();
+        assert!(hosts.lookup("files").is_empty());
+    }
+}
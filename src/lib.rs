@@ -0,0 +1,48 @@
+#![cfg_attr(not(any(feature = "std", test)), no_std)]
+#![allow(clippy::upper_case_acronyms)]
+
+// The wire format only needs alloc, sockets and clocks come with the std feature
+extern crate alloc;
+
+#[cfg(feature = "tokio")]
+pub mod async_resolver;
+#[cfg(feature = "std")]
+pub mod cache;
+#[cfg(feature = "std")]
+pub mod demux;
+#[cfg(feature = "std")]
+pub mod dnssd;
+pub mod error;
+pub mod hexdump;
+#[cfg(feature = "std")]
+pub mod hosts;
+#[cfg(feature = "std")]
+pub mod llmnr;
+#[cfg(feature = "std")]
+pub mod mdns;
+pub mod names;
+pub mod notify;
+pub mod packet;
+pub mod packet_ref;
+#[cfg(feature = "std")]
+pub mod proxy;
+#[cfg(feature = "std")]
+pub mod resolver;
+pub mod rrset;
+#[cfg(feature = "serde")]
+mod serde_support;
+#[cfg(feature = "std")]
+pub mod server;
+#[cfg(any(test, feature = "test-util"))]
+pub mod testutil;
+#[cfg(feature = "std")]
+pub mod transport;
+#[cfg(feature = "tsig")]
+pub mod tsig;
+pub mod update;
+pub mod zonefile;
+
+#[cfg(test)]
+mod test_support;
+#[cfg(test)]
+mod testdata;
@@ -0,0 +1,221 @@
+/* Link-Local Multicast Name Resolution (RFC 4795), what Windows hosts ask the local link when
+    DNS doesn't know a name. The header is DNS's with three bits given other meanings: C where
+    AA is, T where RD is, and RA reserved, all clear in a query. The query goes to the group of
+    each family and every host that owns the name answers unicast, so the responses that arrive
+    within a window are collected instead of taking the first.
+ */
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr, UdpSocket};
+use std::time::Duration;
+
+use crate::error::DnsError;
+use crate::mdns;
+use crate::names;
+use crate::packet::{DNSPacket, DNSQuestion, DNSRecord, RecordType, ReplyCode};
+
+pub const LLMNR_GROUP_V4: Ipv4Addr = Ipv4Addr::new(224, 0, 0, 252);
+pub const LLMNR_GROUP_V6: Ipv6Addr = Ipv6Addr::new(0xff02, 0, 0, 0, 0, 0, 1, 3);
+pub const LLMNR_PORT: u16 = 5355;
+
+// LLMNR_TIMEOUT, how long a querier waits for responses (RFC 4795 section 7)
+pub const DEFAULT_WINDOW: Duration = Duration::from_secs(1);
+
+// One host's response, as it was sent
+#[derive(Debug, Clone, PartialEq)]
+pub struct Response {
+    pub addr: SocketAddr,
+    pub reply_code: ReplyCode,
+    pub answers: Vec<DNSRecord>,
+    // C, the responder has seen another host claim the name too (RFC 4795 section 4)
+    pub conflict: bool,
+    // T, the responder hasn't finished checking that the name is its alone
+    pub tentative: bool,
+}
+
+#[derive(Debug, Clone)]
+pub struct Llmnr {
+    // Each is asked at once, a unicast address directly
+    pub groups: Vec<SocketAddr>,
+    pub window: Duration,
+}
+
+impl Default for Llmnr {
+    fn default() -> Self {
+        Llmnr {
+            groups: vec![
+                SocketAddr::new(LLMNR_GROUP_V4.into(), LLMNR_PORT),
+                SocketAddr::new(LLMNR_GROUP_V6.into(), LLMNR_PORT),
+            ],
+            window: DEFAULT_WINDOW,
+        }
+    }
+}
+
+impl Llmnr {
+    /* Asks every group for `name` and returns the responses that came within the window, one
+        per host in the order they arrived. None answering is an empty list, not an error. A group
+        the query can't be sent to, like IPv6 on a host without it, is skipped unless they all are.
+     */
+    pub fn query(&self, name: &str, rtype: RecordType) -> Result<Vec<Response>, DnsError> {
+        // UTF-8 on the wire like mDNS (RFC 4795 section 2.1), not xn-- labels
+        let name = names::validate_mdns_name(name)?;
+        // RD and the rest of the flags stay clear
+        let mut query = DNSPacket::with_id(rand::random::<u16>());
+        query.add_question(DNSQuestion::new(name.clone(), rtype)?);
+        let bytes = query.serialize()?;
+
+        let mut sockets = Vec::new();
+        let mut failure = None;
+        for group in &self.groups {
+            let sent = socket(*group).and_then(|socket| {
+                socket.send_to(&bytes, group)?;
+                Ok(socket)
+            });
+            match sent {
+                Ok(socket) => sockets.push(socket),
+                Err(err) => {
+                    tracing::debug!(%group, %err, "couldn't send the LLMNR query");
+                    failure = Some(err);
+                },
+            }
+        }
+        if sockets.is_empty() {
+            if let Some(err) = failure {
+                return Err(err);
+            }
+        }
+
+        let received = std::thread::scope(|scope| {
+            let receivers: Vec<_> = sockets.iter()
+                .map(|socket| scope.spawn(|| mdns::receive(socket, self.window)))
+                .collect();
+            receivers.into_iter().map(|x| x.join().unwrap()).collect::<Result<Vec<_>, _>>()
+        })?;
+        Ok(responses(received.into_iter().flatten(), &query))
+    }
+}
+
+// Any port of the group's family, with the multicast TTL of RFC 4795 section 2.5 for a group
+fn socket(group: SocketAddr) -> Result<UdpSocket, DnsError> {
+    let socket = match group {
+        SocketAddr::V4(group) => {
+            let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0))?;
+            if group.ip().is_multicast() {
+                socket.set_multicast_ttl_v4(255)?;
+            }
+            socket
+        },
+        SocketAddr::V6(group) => {
+            let socket = UdpSocket::bind((Ipv6Addr::UNSPECIFIED, 0))?;
+            if group.ip().is_multicast() {
+                socket2::SockRef::from(&socket).set_multicast_hops_v6(255)?;
+            }
+            socket
+        },
+    };
+    Ok(socket)
+}
+
+/* The responses to `query` among what arrived: the same id, and the question echoed back, as
+    RFC 4795 section 2.1.1 has queriers check. A host that sent more than one is kept once.
+ */
+fn responses(received: impl Iterator<Item = (SocketAddr, DNSPacket)>, query: &DNSPacket) -> Vec<Response> {
+    let mut responses: Vec<Response> = Vec::new();
+    for (addr, packet) in received {
+        let flags = &packet.header.flags;
+        let echoed = match (packet.questions(), query.questions()) {
+            ([echoed], [asked]) => {
+                echoed.qtype() == asked.qtype() && names::eq_ignore_case(echoed.name(), asked.name())
+            },
+            _ => false,
+        };
+        if !flags.is_response || packet.header.id() != query.header.id() || !echoed {
+            tracing::debug!(%addr, "ignoring a message that isn't a response to the LLMNR query");
+            continue;
+        }
+        if responses.iter().any(|x| x.addr == addr) {
+            continue;
+        }
+        responses.push(Response {
+            addr,
+            reply_code: packet.reply_code(),
+            answers: packet.answers().iter().filter(|x| x.rtype != RecordType::OPT).cloned().collect(),
+            conflict: flags.is_authoritative,
+            tentative: flags.recurse_desired,
+        });
+    }
+    responses
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{SocketAddr, UdpSocket};
+    use std::time::Duration;
+
+    use crate::llmnr::{Llmnr, Response};
+    use crate::packet::{DNSPacket, DNSRecord, RecordType, ReplyCode};
+    use crate::testutil::a;
+
+    fn response(query: &DNSPacket, answers: Vec<DNSRecord>) -> DNSPacket {
+        let mut packet = DNSPacket::with_id(query.header.id());
+        packet.header.flags.is_response = true;
+        packet.add_question(query.questions()[0].clone());
+        for answer in answers {
+            packet.add_answer(answer);
+        }
+        packet
+    }
+
+    #[test]
+    fn responses() {
+        let first: SocketAddr = "192.0.2.1:5355".parse().unwrap();
+        let second: SocketAddr = "192.0.2.2:5355".parse().unwrap();
+        let query = DNSPacket::query("fileserver", RecordType::A).unwrap();
+
+        let mut tentative = response(&query, vec![a("fileserver", 2)]);
+        tentative.header.flags.recurse_desired = true;
+        tentative.header.flags.is_authoritative = true;
+        let mut other_id = response(&query, vec![a("fileserver", 3)]);
+        other_id.header.id = query.header.id().wrapping_add(1);
+        let mut other_name = response(&DNSPacket::query("printer", RecordType::A).unwrap(), vec![a("fileserver", 4)]);
+        other_name.header.id = query.header.id();
+        let received = vec![
+            (first, response(&query, vec![a("fileserver", 1)])),
+            (second, tentative),
+            // sent again, a stale response, another host's query and an answer for another name
+            (first, response(&query, vec![a("fileserver", 1)])),
+            (second, other_id),
+            (second, query.clone()),
+            (second, other_name),
+        ];
+        assert_eq!(crate::llmnr::responses(received.into_iter(), &query), [
+            Response {
+                addr: first, reply_code: ReplyCode::NoError, answers: vec![a("fileserver", 1)], conflict: false, tentative: false,
+            },
+            Response {
+                addr: second, reply_code: ReplyCode::NoError, answers: vec![a("fileserver", 2)], conflict: true, tentative: true,
+            },
+        ]);
+    }
+
+    #[test]
+    fn query() {
+        let responder = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let group = responder.local_addr().unwrap();
+        let answered = std::thread::spawn(move || {
+            let mut buf = [0; 512];
+            let (size, client) = responder.recv_from(&mut buf).unwrap();
+            let query = DNSPacket::deserialize(&buf[..size]).unwrap();
+            responder.send_to(&response(&query, vec![a("fileserver", 1)]).serialize().unwrap(), client).unwrap();
+            query
+        });
+
+        let llmnr = Llmnr { groups: vec![group], window: Duration::from_millis(300) };
+        let responses = llmnr.query("fileserver", RecordType::A).unwrap();
+        let query = answered.join().unwrap();
+        assert!(!query.header.flags.recurse_desired);
+        assert_eq!(responses.len(), 1);
+        assert_eq!((responses[0].addr, &responses[0].answers), (group, &vec![a("fileserver", 1)]));
+
+        assert_eq!(llmnr.query("fileserver", RecordType::A).unwrap(), []);
+    }
+}
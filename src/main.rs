@@ -1,45 +1,1359 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
 
-mod packet;
+use clap::{CommandFactory, Parser};
+use tracing::level_filters::LevelFilter;
 
-// Use Quad9 if no nameserver specified
-const DEFAULT_NAMESERVER: &str = "9.9.9.9";
+use bkdns::cache::Cache;
+use bkdns::error::DnsError;
+use bkdns::hosts::HostsFile;
+use bkdns::names;
+use bkdns::notify::{self, Notify};
+use bkdns::packet::{self, DNSRecord, RecordData, RecordType, Soa};
+use bkdns::proxy;
+use bkdns::resolver::{AddressSource, IpPreference, QueryOutcome, Resolver, TransportKind};
+use bkdns::rrset;
+use bkdns::server;
+use bkdns::update;
+use bkdns::zonefile;
+
+use cli::{
+    AxfrArgs, BrowseArgs, Cli, Command, DecodeArgs, EncodeArgs, Family, NotifyArgs, OutputFormat, ProxyArgs, SerialArgs,
+    ServeArgs, Status, UpdateArgs,
+};
+
+mod cli;
+
+// Asked for by --all, in the order reported
+const ALL_TYPES: [RecordType; 6] = [
+    RecordType::A,
+    RecordType::AAAA,
+    RecordType::MX,
+    RecordType::NS,
+    RecordType::TXT,
+    RecordType::SOA,
+];
+
+// Asked by --propagation unless --resolvers names a file of others
+const PUBLIC_RESOLVERS: &[(&str, &str)] = &[
+    ("Google", "8.8.8.8"),
+    ("Google", "8.8.4.4"),
+    ("Cloudflare", "1.1.1.1"),
+    ("Cloudflare", "1.0.0.1"),
+    ("Quad9", "9.9.9.9"),
+    ("Quad9", "149.112.112.112"),
+    ("OpenDNS", "208.67.222.222"),
+    ("OpenDNS", "208.67.220.220"),
+    ("AdGuard", "94.140.14.14"),
+    ("CleanBrowsing", "185.228.168.9"),
+    ("Comodo", "8.26.56.26"),
+    ("Verisign", "64.6.64.6"),
+];
 
 fn main() {
-   if !(2..=3).contains(&std::env::args().len()) {
-        println!("usage: {} domain_name [nameserver_ip]", std::env::args().nth(0).unwrap());
+    // clap would exit 2 on a usage error, which here means a network failure
+    let cli = Cli::try_parse().unwrap_or_else(|err| {
+        let _ = err.print();
+        std::process::exit(if err.use_stderr() { Status::Usage.code() } else { Status::Answer.code() });
+    });
+    match cli.command {
+        Some(Command::Decode(args)) => return decode(args),
+        Some(Command::Encode(args)) => return encode(args),
+        Some(Command::Serve(args)) => return serve(args),
+        Some(Command::Proxy(args)) => return proxy(args),
+        Some(Command::Axfr(args)) => return axfr(args),
+        Some(Command::Serial(args)) => return serial(args),
+        Some(Command::Update(args)) => return update(args),
+        Some(Command::Notify(args)) => return notify(args),
+        Some(Command::Browse(args)) => return browse(args),
+        None => {},
+    }
+    let args = cli.query;
+    init_logging(args.log_level(), args.debug_wire);
+
+    if let Some(path) = &args.parse_file {
+        let bytes = match std::fs::read(path) {
+            Ok(bytes) => bytes,
+            Err(err) => {
+                println!("Failed to read {}: {}", path.display(), err);
+                std::process::exit(1);
+            },
+        };
+        match packet::DNSPacket::deserialize(&bytes) {
+            Ok(packet) => print!("{}", dissect(&packet)),
+            Err(err) => {
+                println!("Failed to parse {} ({} bytes): {}", path.display(), bytes.len(), err);
+                if let Some(offset) = err.offset() {
+                    print!("{}", hex_context(&bytes, offset));
+                }
+                std::process::exit(Status::from_error(&err).code());
+            },
+        }
+        return;
+    }
+
+    let type_var = std::env::var(cli::DEFAULT_TYPE_VAR).ok();
+    let target = args.target(&cli::default_nameservers(), type_var.as_deref()).unwrap_or_else(|err| {
+        let _ = Cli::command().error(clap::error::ErrorKind::ValueValidation, err).print();
+        std::process::exit(Status::Usage.code());
+    });
+    let domain = &target.domain;
+    let nameservers = target.nameservers;
+    if args.mdns {
+        std::process::exit(mdns(domain, target.rtype, args.unicode).code());
+    }
+    if args.llmnr {
+        std::process::exit(llmnr(domain, target.rtype, args.unicode).code());
+    }
+
+    let mut resolver = Resolver::new(nameservers[0]);
+    resolver.nameservers = nameservers;
+    resolver.next_on_servfail = !args.stop_on_servfail;
+    resolver.rotate = args.rotate;
+    resolver.race = args.race;
+    if let Some(family) = args.prefer {
+        resolver.ip_preference = match family {
+            Family::Ipv4 => IpPreference::Ipv4First,
+            Family::Ipv6 => IpPreference::Ipv6First,
+        };
+    }
+    if let Some(ms) = args.resolution_delay {
+        resolver.resolution_delay = Duration::from_millis(ms);
+    }
+    resolver.retry_tcp_on_servfail = args.retry_tcp_on_servfail;
+    if args.cache {
+        resolver.cache = Some(Mutex::new(Cache::new()));
+    }
+    if args.skips_hosts() {
+        resolver.hosts = None;
+    } else if let Some(path) = std::env::var_os(cli::HOSTS_FILE_VAR) {
+        resolver.hosts = Some(HostsFile::new(path));
+    }
+    resolver.follow_cname = args.follow_cname;
+    resolver.dns0x20 = args.dns0x20;
+    resolver.dnssec_ok = args.dnssec();
+    resolver.edns_payload = args.edns_payload();
+    resolver.checking_disabled = args.checking_disabled();
+    resolver.tsig = args.tsig.clone();
+    resolver.id = args.id;
+    resolver.transport = match (args.doh.clone(), args.tls.clone(), args.unix.clone(), args.tcp) {
+        #[cfg(feature = "doh")]
+        (Some(url), _, _, _) => TransportKind::Doh(url),
+        #[cfg(not(feature = "doh"))]
+        (Some(_), _, _, _) => {
+            println!("--doh needs bkdns built with the doh feature");
+            std::process::exit(Status::Usage.code());
+        },
+        #[cfg(feature = "dot")]
+        (None, Some(server_name), _, _) => TransportKind::Tls {
+            server_name,
+            port: args.tls_port.unwrap_or(bkdns::resolver::DOT_PORT),
+        },
+        #[cfg(not(feature = "dot"))]
+        (None, Some(_), _, _) => {
+            println!("--tls needs bkdns built with the dot feature");
+            std::process::exit(Status::Usage.code());
+        },
+        #[cfg(unix)]
+        (None, None, Some(path), _) => TransportKind::Unix(path),
+        #[cfg(not(unix))]
+        (None, None, Some(_), _) => {
+            println!("--unix is only supported on Unix platforms");
+            std::process::exit(Status::Usage.code());
+        },
+        (None, None, None, true) => TransportKind::Tcp,
+        (None, None, None, false) => TransportKind::Udp,
+    };
+    if let Some(path) = &args.write_query {
+        let bytes = names::validate_name(domain)
+            .and_then(|x| resolver.build_query(&x, target.rtype))
+            .and_then(|x| x.serialize())
+            .unwrap_or_else(|err| fail("Failed to build query", err));
+        if let Err(err) = std::fs::write(path, &bytes) {
+            println!("Failed to write {}: {}", path.display(), err);
+            std::process::exit(1);
+        }
+        println!("Wrote {} bytes to {}", bytes.len(), path.display());
+        return;
+    }
+
+    if let Some(servers) = &args.compare {
+        let servers: Vec<std::net::SocketAddr> = servers.iter().map(|x| std::net::SocketAddr::new(*x, args.port)).collect();
+        let agree = compare(&resolver, &servers, domain, args.strict_ttl);
+        std::process::exit(if agree { 0 } else { 1 });
+    }
+
+    if args.propagation {
+        let resolvers = match &args.resolvers {
+            Some(path) => read_resolvers(path).unwrap_or_else(|err| {
+                println!("Failed to read {}: {}", path.display(), err);
+                std::process::exit(1);
+            }),
+            None => PUBLIC_RESOLVERS.iter()
+                .map(|(name, addr)| (String::from(*name), addr.parse().unwrap()))
+                .collect(),
+        };
+        let agree = check_propagation(&resolver, &resolvers, domain, args.expect.clone());
+        std::process::exit(if agree { 0 } else { 1 });
+    }
+
+    if args.axfr {
+        for record in resolver.axfr(domain).unwrap_or_else(|err| fail("Zone transfer failed", err)) {
+            println!("{}", zonefile::record(&record));
+        }
+        return;
+    }
+
+    if let Some(format) = args.output {
+        let (response, _) = resolver.query(domain, target.rtype)
+            .unwrap_or_else(|err| fail("Failed to resolve domain", err));
+        match format {
+            OutputFormat::Zonefile => print!("{}", response.to_zonefile()),
+            OutputFormat::Json => println!("{}", serde_json::to_string_pretty(&response).unwrap()),
+        }
+        std::process::exit(Status::from_outcome(&QueryOutcome::from_response(&response)).code());
+    }
+
+    if let Some(interval) = args.watch {
+        watch(&resolver, domain, Duration::from_secs(interval), args.until_changed);
+        return;
+    }
+
+    if let Some(count) = args.count {
+        std::process::exit(repeat(&resolver, domain, target.rtype, count).code());
+    }
+
+    if args.both {
+        let (addrs, source) = resolver.lookup_host(domain).unwrap_or_else(|err| fail("Failed to resolve host", err));
+        print_addresses(&addrs, source);
+        return;
+    }
+
+    if args.dual {
+        let resolver = Arc::new(resolver);
+        let (addrs, source) = resolver.lookup_host_dual(domain).unwrap_or_else(|err| fail("Failed to resolve host", err));
+        print_addresses(&addrs, source);
         return;
-   }
-   
-   let sock = std::net::UdpSocket::bind("0.0.0.0:0")
-     .expect("Failed to bind UDP source socket");
+    }
+
+    let unicode = args.unicode;
+    if args.all {
+        std::process::exit(all_types(&resolver, domain, unicode).code());
+    }
+
+    let from_hosts = hosts_records(&resolver, domain, target.rtype);
+    if !from_hosts.is_empty() {
+        for record in from_hosts {
+            match args.short {
+                true => println!("{}", format_rdata(&record.data, unicode)),
+                false => println!("{}\t; from hosts file", format_answer(&record, unicode)),
+            }
+        }
+        std::process::exit(Status::Answer.code());
+    }
+
+    let result = resolver.resolve(domain, target.rtype)
+        .unwrap_or_else(|err| fail("Failed to resolve domain", err));
+    let status = Status::from_outcome(&result.outcome);
+
+    if args.short {
+        if let QueryOutcome::Answers(answers) = result.outcome {
+            for answer in answers {
+                println!("{}", format_rdata(&answer.data, unicode));
+            }
+        }
+        std::process::exit(status.code());
+    }
+
+    // as dig shows it, so a server that changed the case of the name stands out
+    if let Some(question) = &result.question {
+        println!(";; QUESTION SECTION:");
+        let class = packet::RecordClass::from_u16(question.qclass());
+        println!(";{}\t\t{}\t{}", zonefile::fqdn(question.name()), class, question.qtype());
+        println!();
+    }
+    print_outcome(result.outcome, domain, unicode);
+    println!("Query time: {} ms", result.elapsed.as_millis());
+    match (result.server, result.attempts) {
+        (Some(server), 1) => println!("Server: {}", server),
+        (Some(server), attempts) => println!("Server: {} ({} nameservers asked)", server, attempts),
+        (None, _) => {},
+    }
+    std::process::exit(status.code());
+}
+
+// The addresses of --both and --dual, each marked when they came from the hosts file, not DNS
+fn print_addresses(addrs: &[std::net::IpAddr], source: AddressSource) {
+    let marker = match source {
+        AddressSource::HostsFile => "\t; from hosts file",
+        AddressSource::Dns => "",
+    };
+    for addr in addrs {
+        println!("{}{}", addr, marker);
+    }
+}
+
+// The hosts file entries for an A or AAAA query, as records with a TTL of 0
+fn hosts_records(resolver: &Resolver, domain: &str, rtype: RecordType) -> Vec<DNSRecord> {
+    resolver.from_hosts(domain)
+        .into_iter()
+        .filter_map(|addr| match (addr, rtype) {
+            (std::net::IpAddr::V4(addr), RecordType::A) => Some(RecordData::A(addr)),
+            (std::net::IpAddr::V6(addr), RecordType::AAAA) => Some(RecordData::Aaaa(addr)),
+            _ => None,
+        })
+        .map(|data| DNSRecord { name: String::from(domain), rtype, class: packet::RECORD_CLASS, ttl: 0, data })
+        .collect()
+}
+
+fn print_outcome(outcome: QueryOutcome, domain: &str, unicode: bool) {
+    match outcome {
+        QueryOutcome::Answers(answers) => {
+            for answer in answers {
+                println!("{}", format_answer(&answer, unicode));
+            }
+        },
+        QueryOutcome::NoData { soa } => {
+            println!("No records found for {}", domain);
+            if let Some(soa) = soa {
+                println!("{}", format_soa(&soa));
+            }
+        },
+        QueryOutcome::NxDomain { soa } => {
+            println!("Domain does not exist: {}", domain);
+            if let Some(soa) = soa {
+                println!("{}", format_soa(&soa));
+            }
+        },
+        QueryOutcome::Failure(code) => println!("Server returned error reply code {:?}", code),
+    }
+}
+
+/* --mdns: the answers of each device that replied under a heading of its address, in the
+    order they replied. Nobody replying is reported like a query that timed out.
+ */
+fn mdns(domain: &str, rtype: RecordType, unicode: bool) -> Status {
+    let mdns = bkdns::mdns::Mdns::default();
+    let responders = mdns.query(domain, rtype).unwrap_or_else(|err| fail("Failed to send the mDNS query", err));
+    if responders.is_empty() {
+        println!("No responses for {} within {} s", domain, mdns.window.as_secs());
+        return Status::Network;
+    }
+    for (index, responder) in responders.iter().enumerate() {
+        if index > 0 {
+            println!();
+        }
+        println!(";; {}", responder.addr);
+        for answer in &responder.answers {
+            println!("{}", format_answer(answer, unicode));
+        }
+    }
+    Status::Answer
+}
+
+/* --llmnr, a paragraph per host that answered. The C and T bits are shown when set, they
+    mean the host may not be the only one with the name.
+ */
+fn llmnr(domain: &str, rtype: RecordType, unicode: bool) -> Status {
+    let llmnr = bkdns::llmnr::Llmnr::default();
+    let responses = llmnr.query(domain, rtype).unwrap_or_else(|err| fail("Failed to send the LLMNR query", err));
+    if responses.is_empty() {
+        println!("No responses for {} within {} s", domain, llmnr.window.as_secs());
+        return Status::Network;
+    }
+    for (index, response) in responses.iter().enumerate() {
+        if index > 0 {
+            println!();
+        }
+        let mut heading = format!(";; {}", response.addr);
+        if response.reply_code != packet::ReplyCode::NoError {
+            heading += &format!(", {}", response.reply_code);
+        }
+        if response.conflict {
+            heading += ", conflict";
+        }
+        if response.tentative {
+            heading += ", tentative";
+        }
+        println!("{}", heading);
+        if response.answers.is_empty() {
+            println!("no {} records", rtype);
+        }
+        for answer in &response.answers {
+            println!("{}", format_answer(answer, unicode));
+        }
+    }
+    Status::Answer
+}
+
+// The `browse` subcommand, an instance to a paragraph
+fn browse(args: BrowseArgs) {
+    let mdns = bkdns::mdns::Mdns { window: Duration::from_secs(args.timeout), ..Default::default() };
+    let instances = bkdns::dnssd::browse(&mdns, &args.service)
+        .unwrap_or_else(|err| fail("Failed to browse", err));
+    if instances.is_empty() {
+        println!("No instances of {} within {} s", args.service, args.timeout);
+        std::process::exit(Status::Network.code());
+    }
+    for (index, instance) in instances.iter().enumerate() {
+        if index > 0 {
+            println!();
+        }
+        println!("{}", instance.label());
+        match (&instance.host, instance.port) {
+            (Some(host), Some(port)) => println!("  host {}, port {}", zonefile::fqdn(host), port),
+            _ => println!("  no SRV record"),
+        }
+        for (key, value) in &instance.txt {
+            match value {
+                Some(value) => println!("  {}={}", key, value),
+                None => println!("  {}", key),
+            }
+        }
+        println!("  from {}", instance.responder);
+    }
+}
+
+/* --all: each of ALL_TYPES in turn, under a heading of its own. A type that fails gets its
+    error printed and the rest are still asked. The status is the worst of them.
+ */
+fn all_types(resolver: &Resolver, domain: &str, unicode: bool) -> Status {
+    let mut worst = Status::Answer;
+    for (index, rtype) in ALL_TYPES.into_iter().enumerate() {
+        if index > 0 {
+            println!();
+        }
+        println!(";; {}", rtype);
+        let status = match resolver.resolve(domain, rtype) {
+            Ok(result) => {
+                let status = Status::from_outcome(&result.outcome);
+                print_outcome(result.outcome, domain, unicode);
+                status
+            },
+            Err(err) => {
+                println!("Failed to resolve domain: {}", err);
+                Status::from_error(&err)
+            },
+        };
+        worst = worst.max(status);
+    }
+    worst
+}
+
+// Reports the error that ended the run and exits with the status it maps to
+fn fail(context: &str, err: DnsError) -> ! {
+    println!("{}: {}", context, err);
+    std::process::exit(Status::from_error(&err).code());
+}
+
+/* Library events go to stderr at the level -v/-q ask for. --debug-wire turns on just the
+    wire dumps, which otherwise need -vvv.
+ */
+fn init_logging(level: LevelFilter, debug_wire: bool) {
+    use tracing_subscriber::layer::SubscriberExt;
+
+    let mut filter = tracing_subscriber::filter::Targets::new().with_default(level);
+    if debug_wire {
+        filter = filter.with_target(bkdns::resolver::WIRE_TARGET, tracing::Level::TRACE);
+    }
+    let subscriber = tracing_subscriber::fmt()
+        .with_writer(std::io::stderr)
+        .with_max_level(tracing::Level::TRACE)
+        .without_time()
+        .with_target(false)
+        .finish()
+        .with(filter);
+    tracing::subscriber::set_global_default(subscriber).expect("Failed to set up logging");
+}
+
+/* The `decode` subcommand. Reads the packet from --hex, --file or stdin and prints every
+    section; --reencode also prints it serialized again. A file or stdin holding only hex
+    digits, whitespace and colons is taken as hex, anything else as the raw packet.
+ */
+fn decode(args: DecodeArgs) {
+    let input = match (args.hex, args.file) {
+        (Some(text), _) => text.into_bytes(),
+        (None, Some(path)) => std::fs::read(&path).unwrap_or_else(|err| {
+            println!("Failed to read {}: {}", path.display(), err);
+            std::process::exit(1);
+        }),
+        (None, None) => {
+            let mut input: Vec<u8> = Vec::new();
+            if let Err(err) = std::io::Read::read_to_end(&mut std::io::stdin(), &mut input) {
+                println!("Failed to read stdin: {}", err);
+                std::process::exit(1);
+            }
+            input
+        },
+    };
+    let is_hex = !input.is_empty()
+        && input.iter().all(|x| x.is_ascii_hexdigit() || x.is_ascii_whitespace() || *x == b':');
+    let bytes = match is_hex {
+        true => parse_hex(&input).unwrap_or_else(|err| {
+            println!("Invalid hex: {}", err);
+            std::process::exit(1);
+        }),
+        false => input,
+    };
+
+    let packet = match packet::DNSPacket::deserialize(&bytes) {
+        Ok(packet) => packet,
+        Err(err) => {
+            println!("Failed to parse packet ({} bytes): {}", bytes.len(), err);
+            if let Some(offset) = err.offset() {
+                print!("{}", hex_context(&bytes, offset));
+            }
+            std::process::exit(Status::from_error(&err).code());
+        },
+    };
+    print!("{}", dissect(&packet));
+    if args.reencode {
+        match packet.serialize() {
+            Ok(reencoded) => {
+                println!(";; REENCODED ({} bytes)", reencoded.len());
+                println!("{}", to_hex(&reencoded));
+                let compressed = packet.serialize_with(&packet::SerializeOptions { compress: true });
+                if reencoded == bytes {
+                    println!(";; matches the input");
+                } else if compressed.is_ok_and(|x| x == bytes) {
+                    println!(";; matches the input once names are compressed");
+                } else {
+                    println!(";; differs from the input");
+                }
+            },
+            Err(err) => fail("Failed to reencode packet", err),
+        }
+    }
+}
 
-    let domain = std::env::args().nth(1).unwrap();
+/* The `encode` subcommand. Builds the query for the name and type (A by default) as a
+    normal lookup would, then prints it as hex or with -o writes the raw bytes to a file.
+    --id takes decimal or 0x hex, so the output can be reproduced.
+ */
+fn encode(args: EncodeArgs) {
+    let query = names::validate_name(&args.domain)
+        .and_then(|x| {
+            let builder = packet::QueryBuilder::new().question(&x, args.rtype);
+            match args.id {
+                Some(id) => builder.id(id).build(),
+                None => builder.build(),
+            }
+        })
+        .unwrap_or_else(|err| fail("Failed to build query", err));
+    let bytes = query.serialize().unwrap_or_else(|err| fail("Failed to serialize query", err));
 
-    let nameserver = format!(
-        "{}:53",
-        match std::env::args().len() {
-            3 => std::env::args().nth(2).unwrap(),
-            _ => String::from(DEFAULT_NAMESERVER),
+    match args.output {
+        Some(path) => {
+            if let Err(err) = std::fs::write(&path, &bytes) {
+                println!("Failed to write {}: {}", path.display(), err);
+                std::process::exit(1);
+            }
+            println!("Wrote {} bytes to {}", bytes.len(), path.display());
+        },
+        None => println!("{}", to_hex(&bytes)),
+    }
+}
+
+// The `serve` subcommand, which runs until killed
+fn serve(args: ServeArgs) {
+    let text = match std::fs::read_to_string(&args.zone) {
+        Ok(text) => text,
+        Err(err) => {
+            println!("Failed to read {}: {}", args.zone.display(), err);
+            std::process::exit(1);
+        },
+    };
+    let zone = server::Zone::new(zonefile::parse(&text).unwrap_or_else(|err| fail("Failed to load zone", err)));
+    let records = zone.records().len();
+    let listener = args.listen_notify.map(|addr| {
+        let Some(origin) = zone.origin() else {
+            println!("--listen-notify needs an SOA in {} to know which zone it is", args.zone.display());
+            std::process::exit(Status::Usage.code());
+        };
+        let listener = server::NotifyListener::bind(addr, origin)
+            .unwrap_or_else(|err| fail("Failed to listen for NOTIFY", err))
+            .with_allowed(args.allow_notify.clone());
+        println!("Taking NOTIFY for {} on {}", origin, listener.local_addr().unwrap_or(addr));
+        listener
+    });
+    let server = server::Server::bind(args.listen, zone).unwrap_or_else(|err| fail("Failed to listen", err));
+    let server = Arc::new(server);
+    println!("Serving {} records on {}", records, server.local_addr().unwrap_or(args.listen));
+
+    if let Some(listener) = listener {
+        init_logging(LevelFilter::INFO, false);
+        // not joined, the zone is still served without it and the server failing ends both
+        let (server, path) = (server.clone(), args.zone.clone());
+        std::thread::spawn(move || {
+            if let Err(err) = listener.run(|notify, peer| reload(&server, &path, notify, peer)) {
+                tracing::error!(%err, "NOTIFY listener stopped, the zone won't be reloaded");
+            }
+        });
+    }
+    if let Err(err) = server.run() {
+        fail("Server stopped", err);
+    }
+}
+
+/* A NOTIFY for the zone being served re-checks the serial: the zone file is read again and
+    swapped in when its serial is newer, by RFC 1982 arithmetic, than the one being served. A
+    file that no longer loads leaves the zone as it was.
+ */
+fn reload(server: &server::Server, path: &std::path::Path, notify: Notify, peer: std::net::SocketAddr) {
+    tracing::info!(%peer, zone = notify.zone, serial = ?notify.serial, "NOTIFY received");
+    let records = std::fs::read_to_string(path)
+        .map_err(|err| err.to_string())
+        .and_then(|text| zonefile::parse(&text).map_err(|err| err.to_string()));
+    let zone = match records {
+        Ok(records) => server::Zone::new(records),
+        Err(err) => {
+            tracing::warn!(path = %path.display(), %err, "couldn't reload the zone file");
+            return;
+        },
+    };
+
+    let is_newer = |a: Option<u32>, b: Option<u32>| match (a, b) {
+        (Some(a), Some(b)) => Soa::compare_serials(a, b) == Some(std::cmp::Ordering::Greater),
+        _ => false,
+    };
+    let (serving, loaded) = (server.serial(), zone.serial());
+    if is_newer(loaded, serving) {
+        server.set_zone(zone);
+        tracing::info!(from = ?serving, to = ?loaded, "zone reloaded");
+    } else {
+        tracing::info!(serial = ?serving, "zone file unchanged");
+    }
+    if is_newer(notify.serial, loaded) {
+        tracing::warn!(notified = ?notify.serial, file = ?loaded, "the zone file is older than the notified serial");
+    }
+}
+
+// The `proxy` subcommand, logging a line per query to stderr until killed
+fn proxy(args: ProxyArgs) {
+    init_logging(LevelFilter::INFO, false);
+    let mut resolver = Resolver::new(args.upstream[0]);
+    resolver.nameservers = args.upstream.clone();
+    let mut rules = proxy::Rules::new();
+    rules.block_with_zero = args.block_with_zero;
+    for path in &args.blocklist {
+        match std::fs::read_to_string(path) {
+            Ok(text) => tracing::info!(path = %path.display(), names = rules.load_blocklist(&text), "blocklist loaded"),
+            Err(err) => {
+                println!("Failed to read {}: {}", path.display(), err);
+                std::process::exit(1);
+            },
+        }
+    }
+    for (name, addr) in &args.overrides {
+        rules.add_override(name, *addr);
+    }
+    let proxy = proxy::Proxy::bind(args.listen, resolver)
+        .unwrap_or_else(|err| fail("Failed to listen", err))
+        .with_rules(rules);
+    println!("Forwarding {} to {:?}", proxy.local_addr().unwrap_or(args.listen), args.upstream);
+    if let Err(err) = proxy.run() {
+        fail("Proxy stopped", err);
+    }
+}
+
+// The `axfr` subcommand, transferring over TCP from the nameserver's first address
+fn axfr(args: AxfrArgs) {
+    let server = nameserver_address(&args.nameserver, args.port).unwrap_or_else(|err| {
+        println!("{}", err);
+        std::process::exit(Status::Usage.code());
+    });
+    let mut resolver = Resolver::new(server);
+    resolver.tsig = args.tsig;
+    for record in resolver.axfr(&args.zone).unwrap_or_else(|err| fail("Zone transfer failed", err)) {
+        println!("{}", zonefile::record(&record));
+    }
+}
+
+// The `update` subcommand, reporting the reply code the primary answered with
+fn update(args: UpdateArgs) {
+    let usage = |err: String| -> ! {
+        println!("{}", err);
+        std::process::exit(Status::Usage.code());
+    };
+    let server = nameserver_address(&args.nameserver, args.port).unwrap_or_else(|err| usage(err));
+    let message = update_message(&args).unwrap_or_else(|err| usage(err));
+
+    let mut resolver = Resolver::new(server);
+    resolver.tsig = args.tsig;
+    if args.tcp {
+        resolver.transport = TransportKind::Tcp;
+    }
+    let (response, _) = resolver.send(&message).unwrap_or_else(|err| fail("Update failed", err));
+    let code = response.reply_code();
+    println!("Update of {}: {}", names::validate_name(&args.zone).unwrap_or(args.zone), code);
+    let status = match code {
+        packet::ReplyCode::NoError => Status::Answer,
+        packet::ReplyCode::NxDomain => Status::NxDomain,
+        _ => Status::ServerError,
+    };
+    std::process::exit(status.code());
+}
+
+// The UPDATE message --require, --require-absent, --delete and --add make, in that order
+fn update_message(args: &UpdateArgs) -> Result<packet::DNSPacket, String> {
+    let zone = &args.zone;
+    // a name and an optional type, what prerequisites and deletions start with
+    let name_and_type = |spec: &str| -> Result<(String, Option<RecordType>, Vec<String>), String> {
+        let mut words = spec.split_whitespace();
+        let name = words.next().ok_or_else(|| String::from("a record argument is empty"))?;
+        let rtype = words.next().map(str::parse::<RecordType>).transpose().map_err(|err| err.to_string())?;
+        Ok((update::qualify(name, zone), rtype, words.map(String::from).collect()))
+    };
+    let record = |line: String| -> Result<DNSRecord, String> {
+        match zonefile::parse(&line).map_err(|err| format!("{}: {}", line, err))?.as_slice() {
+            [record] => Ok(record.clone()),
+            _ => Err(format!("{}: expected one record", line)),
+        }
+    };
+
+    let mut builder = update::UpdateBuilder::new(zone);
+    for spec in &args.require {
+        builder = match name_and_type(spec)? {
+            (name, Some(rtype), rest) if rest.is_empty() => builder.require_rrset(&name, rtype),
+            (name, None, _) => builder.require_name(&name),
+            _ => return Err(format!("--require {}: expected a name and an optional type", spec)),
+        };
+    }
+    for spec in &args.require_absent {
+        builder = match name_and_type(spec)? {
+            (name, Some(rtype), rest) if rest.is_empty() => builder.require_absent_rrset(&name, rtype),
+            (name, None, _) => builder.require_absent_name(&name),
+            _ => return Err(format!("--require-absent {}: expected a name and an optional type", spec)),
+        };
+    }
+    for spec in &args.delete {
+        builder = match name_and_type(spec)? {
+            (name, None, _) => builder.delete_name(&name),
+            (name, Some(rtype), rest) if rest.is_empty() => builder.delete_rrset(&name, rtype),
+            // the TTL is only there for the zone file syntax
+            (name, Some(rtype), rdata) => {
+                builder.delete_record(record(format!("{} 0 {} {}", name, rtype, rdata.join(" ")))?)
+            },
+        };
+    }
+    for spec in &args.add {
+        let (name, rest) = spec.trim().split_once(char::is_whitespace).unwrap_or((spec.trim(), ""));
+        builder = builder.add_record(record(format!("{} {}", update::qualify(name, zone), rest))?);
+    }
+    builder.build().map_err(|err| err.to_string())
+}
+
+/* The `notify` subcommand. The zone's SOA is looked up first and sent along, the secondary
+    checks the primary for itself anyway (RFC 1996 section 3.11), so a zone without one is
+    notified without a serial.
+ */
+fn notify(args: NotifyArgs) {
+    let usage = |err: String| -> ! {
+        println!("{}", err);
+        std::process::exit(Status::Usage.code());
+    };
+    let zone = names::validate_name(&args.zone).unwrap_or_else(|err| usage(err.to_string()));
+    let server = nameserver_address(&args.nameserver, args.port).unwrap_or_else(|err| usage(err));
+    let primaries: Vec<std::net::SocketAddr> = match &args.primary {
+        Some(primary) => vec![nameserver_address(primary, 53).unwrap_or_else(|err| usage(err))],
+        None => cli::default_nameservers().iter().map(|x| std::net::SocketAddr::new(*x, 53)).collect(),
+    };
+
+    let mut lookup = Resolver::new(primaries[0]);
+    lookup.nameservers = primaries;
+    let (response, _) = lookup.query(&zone, RecordType::SOA)
+        .unwrap_or_else(|err| fail("Failed to look up the zone's SOA", err));
+    let soa = response.answers().iter()
+        .find(|x| x.rtype == RecordType::SOA && names::eq_ignore_case(&x.name, &zone))
+        .cloned();
+    let serial = match &soa {
+        Some(DNSRecord { data: RecordData::Soa(soa), .. }) => format!("serial {}", soa.serial),
+        _ => String::from("no SOA found"),
+    };
+
+    let message = notify::message(&zone, soa).unwrap_or_else(|err| fail("Failed to build the NOTIFY", err));
+    let (response, elapsed) = Resolver::new(server).send(&message).unwrap_or_else(|err| fail("NOTIFY failed", err));
+    if response.header.flags.opcode != notify::OPCODE_NOTIFY {
+        println!("{} answered with opcode {}, not a NOTIFY acknowledgment", server, response.header.flags.opcode);
+        std::process::exit(Status::BadResponse.code());
+    }
+    let code = response.reply_code();
+    println!("NOTIFY for {} ({}) to {}: {}, {} ms", zone, serial, server, code, elapsed.as_millis());
+    std::process::exit(if code == packet::ReplyCode::NoError { Status::Answer } else { Status::ServerError }.code());
+}
+
+/* The `serial` subcommand. Every nameserver is asked for the zone's SOA at once and the
+    newest serial, by RFC 1982 arithmetic, is the one the others are held to. Nameservers that
+    don't answer are reported in the table rather than ending the run.
+ */
+fn serial(args: SerialArgs) {
+    let (zone, port) = (&args.zone, args.port);
+    let results: Vec<(String, Result<u32, String>)> = std::thread::scope(|scope| {
+        let handles: Vec<_> = args.nameservers.iter()
+            .map(|name| scope.spawn(move || match nameserver_address(name, port) {
+                Ok(server) => {
+                    // a hostname with the address it was found at
+                    let hostname = name.parse::<std::net::IpAddr>().is_err()
+                        && name.parse::<std::net::SocketAddr>().is_err();
+                    let label = match hostname {
+                        true => format!("{} ({})", name, server),
+                        false => server.to_string(),
+                    };
+                    (label, soa_serial(server, zone))
+                },
+                Err(err) => (name.clone(), Err(err)),
+            }))
+            .collect();
+        handles.into_iter().map(|x| x.join().unwrap()).collect()
+    });
+
+    let is_newer = |a: u32, b: u32| Soa::compare_serials(a, b) == Some(std::cmp::Ordering::Greater);
+    let newest = results.iter()
+        .filter_map(|(_, x)| x.as_ref().ok().copied())
+        .reduce(|newest, x| if is_newer(x, newest) { x } else { newest });
+
+    let width = results.iter().map(|(label, _)| label.len() + 2).max().unwrap_or(0).max(24);
+    println!("{:<width$}{:<12}STATUS", "SERVER", "SERIAL");
+    let mut in_sync = 0;
+    for (label, result) in &results {
+        let (serial, status) = match (result, newest) {
+            (Err(err), _) => (String::from("-"), err.clone()),
+            (Ok(serial), _) if args.expect_serial.is_some_and(|x| is_newer(x, *serial)) => {
+                (serial.to_string(), format!("older than {}", args.expect_serial.unwrap()))
+            },
+            (Ok(serial), Some(newest)) if *serial == newest => {
+                in_sync += 1;
+                (serial.to_string(), String::from("in sync"))
+            },
+            (Ok(serial), Some(newest)) => (serial.to_string(), format!("behind by {}", newest.wrapping_sub(*serial))),
+            (Ok(serial), None) => (serial.to_string(), String::new()),
+        };
+        println!("{:<width$}{:<12}{}", label, serial, status);
+    }
+
+    match newest {
+        Some(newest) => println!("{} of {} servers have serial {}", in_sync, results.len(), newest),
+        None => println!("No server answered"),
+    }
+    std::process::exit(if in_sync == results.len() { 0 } else { 1 });
+}
+
+// The serial of the SOA `server` answers for `zone`, or what went wrong in a few words
+fn soa_serial(server: std::net::SocketAddr, zone: &str) -> Result<u32, String> {
+    let response = match Resolver::new(server).query(zone, packet::RecordType::SOA) {
+        Ok((response, _)) => response,
+        Err(DnsError::Io(_)) => return Err(String::from("unreachable")),
+        Err(err) => return Err(err.to_string()),
+    };
+    if response.reply_code() != packet::ReplyCode::NoError {
+        return Err(response.reply_code().to_string());
+    }
+    response.answers().iter()
+        .find_map(|x| match &x.data {
+            RecordData::Soa(soa) if names::eq_ignore_case(&x.name, zone) => Some(soa.serial),
+            _ => None,
+        })
+        .ok_or_else(|| String::from("no SOA in the answer"))
+}
+
+/* A nameserver given on the command line as an address, with or without a port, or a
+    hostname looked up with the default nameservers, taking its first address. `port` is
+    used unless one is given.
+ */
+fn nameserver_address(name: &str, port: u16) -> Result<std::net::SocketAddr, String> {
+    if let Ok(server) = name.parse() {
+        return Ok(server);
+    }
+    if let Ok(addr) = name.parse() {
+        return Ok(std::net::SocketAddr::new(addr, port));
+    }
+    let defaults: Vec<std::net::SocketAddr> = cli::default_nameservers().iter()
+        .map(|x| std::net::SocketAddr::new(*x, 53))
+        .collect();
+    let mut resolver = Resolver::new(defaults[0]);
+    resolver.nameservers = defaults;
+    match resolver.lookup_host(name) {
+        Ok((addrs, _)) => addrs.first()
+            .map(|x| std::net::SocketAddr::new(*x, port))
+            .ok_or_else(|| format!("Failed to look up {}: it has no addresses", name)),
+        Err(err) => Err(format!("Failed to look up {}: {}", name, err)),
+    }
+}
+
+// Hex digits two to a byte, whitespace and colons between them are ignored
+fn parse_hex(text: &[u8]) -> Result<Vec<u8>, String> {
+    let digits: Vec<u8> = text.iter()
+        .copied()
+        .filter(|x| !x.is_ascii_whitespace() && *x != b':')
+        .collect();
+    if !digits.len().is_multiple_of(2) {
+        return Err(format!("odd number of hex digits ({})", digits.len()));
+    }
+    digits.chunks(2)
+        .map(|x| {
+            let pair = std::str::from_utf8(x).map_err(|err| err.to_string())?;
+            u8::from_str_radix(pair, 16).map_err(|_| format!("not a hex byte: {}", pair))
+        })
+        .collect()
+}
+
+/* Up to 16 bytes of `bytes` around `offset` with a caret under the byte there, or just past
+    the last byte when the error is the packet ending early.
+ */
+fn hex_context(bytes: &[u8], offset: usize) -> String {
+    let at = offset.min(bytes.len());
+    let start = at.saturating_sub(8);
+    let end = (start + 16).min(bytes.len());
+    let hex: Vec<String> = bytes[start..end].iter().map(|x| format!("{:02x}", x)).collect();
+    format!("{:04x}  {}\n      {}^^\n", start, hex.join(" "), " ".repeat(3 * (at - start)))
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|x| format!("{:02x}", x)).collect()
+}
+
+// Every section of `packet`, with an OPT record shown as the EDNS data it carries
+fn dissect(packet: &packet::DNSPacket) -> String {
+    let mut formatted = format!(
+        ";; HEADER\n;; id {:#06x}, status {}, {:?}\n",
+        packet.header.id(), packet.reply_code(), packet.header.flags
+    );
+    formatted += &format!(
+        ";; {} questions, {} answers, {} authority, {} additional\n",
+        packet.questions().len(), packet.answers().len(), packet.authority().len(), packet.additional().len()
+    );
+    formatted += ";; QUESTION\n";
+    for question in packet.questions() {
+        let class = packet::RecordClass::from_u16(question.qclass());
+        formatted += &format!("{}\t{}\t{}\n", zonefile::fqdn(question.name()), class, question.qtype());
+    }
+    for (title, records) in [("ANSWER", packet.answers()), ("AUTHORITY", packet.authority())] {
+        formatted += &format!(";; {}\n", title);
+        for record in records {
+            formatted += &(zonefile::record(record) + "\n");
         }
+    }
+    formatted += ";; ADDITIONAL\n";
+    for record in packet.additional() {
+        formatted += &match (record.rtype, &record.data) {
+            (packet::RecordType::OPT, RecordData::Unknown { data, .. }) => format_opt(record, data),
+            _ => zonefile::record(record) + "\n",
+        };
+    }
+    formatted
+}
+
+/* An OPT pseudo-record (RFC 6891): the class is the sender's UDP payload size and the TTL
+    holds the extended rcode, version and flags. Options are listed as code and hex data.
+ */
+fn format_opt(record: &DNSRecord, data: &[u8]) -> String {
+    let [extended_rcode, version, flags_high, _] = record.ttl.to_be_bytes();
+    let mut formatted = format!(
+        ";; EDNS version {}, udp payload {}, extended rcode {}{}\n",
+        version,
+        record.class,
+        extended_rcode,
+        if flags_high & 0x80 != 0 { ", do" } else { "" }
     );
+    let mut options = data;
+    while options.len() >= 4 {
+        let code = u16::from_be_bytes([options[0], options[1]]);
+        let length = (u16::from_be_bytes([options[2], options[3]]) as usize).min(options.len() - 4);
+        formatted += &format!(";; option {} ({} bytes) {}\n", code, length, to_hex(&options[4..4 + length]));
+        options = &options[4 + length..];
+    }
+    if !options.is_empty() {
+        formatted += &format!(";; {} trailing bytes in OPT data\n", options.len());
+    }
+    formatted
+}
 
-   println!("Asking {} to resolve {}", nameserver, domain);
-   sock.connect(nameserver)
-     .expect("Upstream UDP connection failed to nameserver");
+// What one server said to --compare: its reply code and answers, or why it didn't answer
+type ServerAnswer = Result<(packet::ReplyCode, Vec<DNSRecord>), String>;
 
-   let mut packet = packet::DNSPacket::new();
-   packet.add_question(packet::DNSQuestion::new(domain, packet::RecordType::A));
-   packet.header.flags.recurse_desired = true;
+/* Asks each of `servers` the same question, with a resolver of its own set up like `template`,
+    and prints a table of what each said. The first server to answer is the reference the
+    others are held to: same reply code and the same records, in any order.
+ */
+fn compare(template: &Resolver, servers: &[std::net::SocketAddr], domain: &str, strict_ttl: bool) -> bool {
+    let results: Vec<(std::net::SocketAddr, ServerAnswer)> = servers.iter()
+        .map(|server| {
+            let result = resolver_like(template, *server).query(domain, packet::RecordType::A)
+                .map(|(response, _)| (
+                    response.reply_code(),
+                    response.answers().to_vec(),
+                ))
+                .map_err(|err| err.to_string());
+            (*server, result)
+        })
+        .collect();
 
-   sock.send(&packet.serialize())
-        .expect("Failed to send DNS Packet");
+    println!("{:<24}{:<12}ANSWERS", "SERVER", "RCODE");
+    for (server, result) in &results {
+        match result {
+            Ok((code, answers)) => {
+                let answers: Vec<String> = answers.iter()
+                    .map(|x| format!("{} ({})", format_rdata(&x.data, false), x.ttl))
+                    .collect();
+                println!("{:<24}{:<12}{}", server.to_string(), format!("{:?}", code), answers.join(", "));
+            },
+            Err(err) => println!("{:<24}{:<12}{}", server.to_string(), "-", err),
+        }
+    }
+
+    let reference = match results.iter().find_map(|(server, x)| x.as_ref().ok().map(|x| (server, x))) {
+        Some(reference) => reference,
+        None => {
+            println!("No server answered");
+            return false;
+        },
+    };
+    let (reference_server, (reference_code, reference_answers)) = reference;
+    let mut agree = true;
+    for (server, result) in &results {
+        let (code, answers) = match result {
+            Ok(answer) => answer,
+            Err(_) => {
+                println!("Mismatch: {} did not answer", server);
+                agree = false;
+                continue;
+            },
+        };
+        if code != reference_code {
+            println!("Mismatch: {} returned {:?}, {} returned {:?}", server, code, reference_server, reference_code);
+            agree = false;
+            continue;
+        }
+        let diff = rrset::diff(reference_answers, answers);
+        for record in &diff.removed {
+            println!("Mismatch: {} is missing {}", server, zonefile::record(record));
+        }
+        for record in &diff.added {
+            println!("Mismatch: {} has extra {}", server, zonefile::record(record));
+        }
+        if diff.changed() {
+            agree = false;
+        } else if strict_ttl && !rrset::same_rrset_with_ttl(reference_answers, answers) {
+            println!("Mismatch: {} has different TTLs than {}", server, reference_server);
+            agree = false;
+        }
+    }
+
+    if agree {
+        println!("All {} servers agree", results.len());
+    }
+    agree
+}
+
+// A resolver for just `server`, otherwise set up like `template`
+fn resolver_like(template: &Resolver, server: std::net::SocketAddr) -> Resolver {
+    let mut resolver = Resolver::new(server);
+    resolver.transport = template.transport.clone();
+    resolver.timeout = template.timeout;
+    resolver.dns0x20 = template.dns0x20;
+    resolver.dnssec_ok = template.dnssec_ok;
+    resolver.edns_payload = template.edns_payload;
+    resolver.checking_disabled = template.checking_disabled;
+    resolver.tsig = template.tsig.clone();
+    resolver.id = template.id;
+    resolver
+}
+
+/* Reads a --resolvers file: an address per line, optionally followed by a label.
+    Blank lines and lines starting with # are skipped.
+ */
+fn read_resolvers(path: &std::path::Path) -> Result<Vec<(String, std::net::IpAddr)>, String> {
+    let text = std::fs::read_to_string(path).map_err(|err| err.to_string())?;
+    let mut resolvers: Vec<(String, std::net::IpAddr)> = Vec::new();
+    for (number, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let (addr, label) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+        let addr: std::net::IpAddr = addr.parse()
+            .map_err(|_| format!("line {}: invalid address {}", number + 1, addr))?;
+        let label = if label.trim().is_empty() { addr.to_string() } else { String::from(label.trim()) };
+        resolvers.push((label, addr));
+    }
+    if resolvers.is_empty() {
+        return Err(String::from("no resolvers listed"));
+    }
+    Ok(resolvers)
+}
+
+/* Asks every resolver at once for the A records of `domain` and prints which have the
+    addresses in `expect`, or without it the set most of them returned. A resolver that
+    doesn't answer in time is listed as "no response" and counts as not having them.
+ */
+fn check_propagation(
+    template: &Resolver,
+    resolvers: &[(String, std::net::IpAddr)],
+    domain: &str,
+    expect: Option<Vec<std::net::Ipv4Addr>>,
+) -> bool {
+    let results: Vec<Result<(packet::ReplyCode, Vec<std::net::Ipv4Addr>), bkdns::error::DnsError>> =
+        std::thread::scope(|scope| {
+            let handles: Vec<_> = resolvers.iter()
+                .map(|(_, addr)| scope.spawn(move || {
+                    let resolver = resolver_like(template, std::net::SocketAddr::new(*addr, 53));
+                    resolver.query(domain, packet::RecordType::A).map(|(response, _)| {
+                        let mut addresses: Vec<std::net::Ipv4Addr> = response.answers().iter()
+                            .filter_map(|x| match x.data {
+                                RecordData::A(addr) => Some(addr),
+                                _ => None,
+                            })
+                            .collect();
+                        addresses.sort();
+                        addresses.dedup();
+                        (response.reply_code(), addresses)
+                    })
+                }))
+                .collect();
+            handles.into_iter().map(|x| x.join().unwrap()).collect()
+        });
+
+    let expect = match expect {
+        Some(mut expect) => {
+            expect.sort();
+            expect.dedup();
+            expect
+        },
+        None => {
+            // the answer given by the most resolvers, the first one seen on a tie
+            let answers: Vec<&Vec<std::net::Ipv4Addr>> = results.iter()
+                .filter_map(|x| x.as_ref().ok())
+                .filter(|(code, addresses)| *code == packet::ReplyCode::NoError && !addresses.is_empty())
+                .map(|(_, addresses)| addresses)
+                .collect();
+            let mut most_common: Option<(&Vec<std::net::Ipv4Addr>, usize)> = None;
+            for addresses in &answers {
+                let count = answers.iter().filter(|x| *x == addresses).count();
+                if most_common.is_none_or(|(_, most)| count > most) {
+                    most_common = Some((addresses, count));
+                }
+            }
+            match most_common {
+                Some((addresses, _)) => addresses.clone(),
+                None => {
+                    println!("No resolver returned A records for {}", domain);
+                    return false;
+                },
+            }
+        },
+    };
+    let format_addresses = |addresses: &[std::net::Ipv4Addr]| {
+        addresses.iter().map(|x| x.to_string()).collect::<Vec<String>>().join(", ")
+    };
+
+    println!("{:<16}{:<18}STATUS", "RESOLVER", "SERVER");
+    let mut updated = 0;
+    for ((label, addr), result) in resolvers.iter().zip(&results) {
+        let status = match result {
+            Ok((_, addresses)) if *addresses == expect => {
+                updated += 1;
+                String::from("updated")
+            },
+            Ok((packet::ReplyCode::NoError, addresses)) if addresses.is_empty() => String::from("no A records"),
+            Ok((packet::ReplyCode::NoError, addresses)) => format!("stale ({})", format_addresses(addresses)),
+            Ok((code, _)) => format!("{:?}", code),
+            Err(bkdns::error::DnsError::Io(_)) => String::from("no response"),
+            Err(err) => err.to_string(),
+        };
+        println!("{:<16}{:<18}{}", label, addr.to_string(), status);
+    }
+    println!("{} of {} resolvers have {}", updated, resolvers.len(), format_addresses(&expect));
+    updated == resolvers.len()
+}
+
+/* Re-queries `domain` every `interval`, printing a line per query and the records that
+    came or went. Runs until Ctrl-C, or with `until_changed` until the answers first differ
+    from the first response, then prints how many changes were seen.
+ */
+fn watch(resolver: &Resolver, domain: &str, interval: Duration, until_changed: bool) {
+    let interrupted = Arc::new(AtomicBool::new(false));
+    {
+        let interrupted = interrupted.clone();
+        ctrlc::set_handler(move || interrupted.store(true, Ordering::SeqCst))
+            .expect("Failed to set the Ctrl-C handler");
+    }
 
-   let mut buf =  [0; 1024];
-   sock.recv(&mut buf)
-     .expect("No response from DNS Server");
+    let mut first: Option<Vec<DNSRecord>> = None;
+    let mut last: Option<Vec<DNSRecord>> = None;
+    let (mut queries, mut changes) = (0, 0);
+    while !interrupted.load(Ordering::SeqCst) {
+        let started = Instant::now();
+        queries += 1;
+        let (answers, age) = match resolver.resolve(domain, packet::RecordType::A) {
+            Ok(result) => match result.outcome {
+                QueryOutcome::Answers(answers) => (answers, result.age),
+                QueryOutcome::NoData { .. } | QueryOutcome::NxDomain { .. } => (Vec::new(), result.age),
+                QueryOutcome::Failure(code) => {
+                    println!("[{}] server returned error reply code {:?}", timestamp(), code);
+                    wait(&interrupted, interval.saturating_sub(started.elapsed()));
+                    continue;
+                },
+            },
+            Err(err) => {
+                println!("[{}] query failed: {}", timestamp(), err);
+                wait(&interrupted, interval.saturating_sub(started.elapsed()));
+                continue;
+            },
+        };
 
-   let response = packet::DNSPacket::deserialize(&buf)
-     .expect("Failed to parse response");
+        match &last {
+            None => {
+                println!("[{}] {} records, first response", timestamp(), answers.len());
+                for record in &answers {
+                    println!("    {}", zonefile::record(record));
+                }
+            },
+            Some(previous) => {
+                let diff = rrset::diff(previous, &answers);
+                let status = match (diff.changed(), diff.refreshed.is_empty()) {
+                    (true, _) => "changed",
+                    (false, false) => "unchanged, TTL reset (cache refreshed)",
+                    (false, true) if age.is_some() => "unchanged, from the cache",
+                    (false, true) => "unchanged",
+                };
+                println!("[{}] {} records, {}", timestamp(), answers.len(), status);
+                // cached answers keep the TTLs they came with, what's left of them is shown instead
+                if let Some(age) = age {
+                    for record in &answers {
+                        println!("    {}", cached_record(record, age));
+                    }
+                }
+                if diff.changed() {
+                    changes += 1;
+                }
+                for record in &diff.added {
+                    println!("  + {}", zonefile::record(record));
+                }
+                for record in &diff.removed {
+                    println!("  - {}", zonefile::record(record));
+                }
+            },
+        }
+
+        let first = first.get_or_insert_with(|| answers.clone());
+        if until_changed && !rrset::same_rrset(first, &answers) {
+            break;
+        }
+        last = Some(answers);
+        wait(&interrupted, interval.saturating_sub(started.elapsed()));
+    }
+    println!("{} queries, {} changes in the answers", queries, changes);
+}
+
+// A cached record with its TTL counted down: `example.com.	A	192.0.2.1	ttl=42 (expires in 42s)`
+fn cached_record(record: &DNSRecord, age: Duration) -> String {
+    let ttl = record.remaining_ttl(age);
+    format!(
+        "{}\t{}\t{}\tttl={} (expires in {}s)",
+        zonefile::fqdn(&record.name),
+        record.rtype,
+        zonefile::rdata(&record.data),
+        ttl,
+        ttl
+    )
+}
+
+/* --count: the same question `count` times in a row, each with a new id, one line per query
+    and the spread of response times at the end. Failed queries don't count towards the times.
+    The status is the worst of them, so any failure shows.
+ */
+fn repeat(resolver: &Resolver, domain: &str, rtype: packet::RecordType, count: u32) -> Status {
+    let mut worst = Status::Answer;
+    let mut times: Vec<Duration> = Vec::new();
+    for seq in 1..=count {
+        match resolver.resolve(domain, rtype) {
+            Ok(result) => {
+                let outcome = match &result.outcome {
+                    QueryOutcome::Answers(answers) => format!("{} records", answers.len()),
+                    QueryOutcome::NoData { .. } => String::from("no records"),
+                    QueryOutcome::NxDomain { .. } => String::from("NXDOMAIN"),
+                    QueryOutcome::Failure(code) => code.to_string(),
+                };
+                println!("{}: {}, {} ms", seq, outcome, result.elapsed.as_millis());
+                worst = worst.max(Status::from_outcome(&result.outcome));
+                times.push(result.elapsed);
+            },
+            Err(err) => {
+                println!("{}: failed: {}", seq, err);
+                worst = worst.max(Status::from_error(&err));
+            },
+        }
+    }
+
+    println!();
+    println!("{} queries, {} answered, {} failed", count, times.len(), count as usize - times.len());
+    if let (Some(min), Some(max)) = (times.iter().min(), times.iter().max()) {
+        let avg = times.iter().sum::<Duration>() / times.len() as u32;
+        println!("min/avg/max = {:.1}/{:.1}/{:.1} ms", millis(*min), millis(avg), millis(*max));
+    }
+    worst
+}
 
-    println!("{:?}", response.header);
-}
\ No newline at end of file
+fn millis(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1000.0
+}
+
+// Sleeps for `duration`, cut short by Ctrl-C
+fn wait(interrupted: &AtomicBool, duration: Duration) {
+    let until = Instant::now() + duration;
+    while !interrupted.load(Ordering::SeqCst) && Instant::now() < until {
+        std::thread::sleep(Duration::from_millis(100).min(until.saturating_duration_since(Instant::now())));
+    }
+}
+
+// Wall clock time of day in UTC, HH:MM:SS
+fn timestamp() -> String {
+    let seconds = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map(|x| x.as_secs())
+        .unwrap_or(0);
+    format!("{:02}:{:02}:{:02} UTC", seconds / 3600 % 24, seconds / 60 % 60, seconds % 60)
+}
+
+fn display_name(name: &str, unicode: bool) -> String {
+    match unicode {
+        true => names::to_unicode(name),
+        false => String::from(name),
+    }
+}
+
+fn format_soa(soa: &Soa) -> String {
+    format!(
+        "SOA {} {} serial={} refresh={} retry={} expire={} minimum={}",
+        soa.mname, soa.rname, soa.serial, soa.refresh, soa.retry, soa.expire, soa.minimum
+    )
+}
+
+// Names as given, Unicode with --unicode, and the SOA on one line. The rest is as zone files write it.
+fn format_rdata(data: &RecordData, unicode: bool) -> String {
+    match data {
+        RecordData::Cname(name) | RecordData::Ns(name) | RecordData::Ptr(name) => display_name(name, unicode),
+        RecordData::Mx { pref, exchange } => format!("{} {}", pref, display_name(exchange, unicode)),
+        RecordData::Srv { priority, weight, port, target } => {
+            format!("{} {} {} {}", priority, weight, port, display_name(target, unicode))
+        },
+        RecordData::Soa(soa) => format_soa(soa),
+        _ => zonefile::rdata(data),
+    }
+}
+
+fn format_answer(answer: &DNSRecord, unicode: bool) -> String {
+    let formatted = format!(
+        "{}\t{}\tIN\t{}\t{}",
+        display_name(&answer.name, unicode),
+        answer.ttl,
+        answer.rtype,
+        format_rdata(&answer.data, unicode)
+    );
+    match &answer.data {
+        RecordData::Rrsig(_) => formatted + "\t; not validated",
+        RecordData::Dnskey(key) => formatted + &format!("\t; key tag {}, not validated", key.key_tag()),
+        _ => formatted,
+    }
+}
@@ -0,0 +1,239 @@
+/* One-shot Multicast DNS queries (RFC 6762 section 5.1). The query goes to the mDNS group with
+    the QU bit set, and every device that has the name can answer, so replies are collected
+    for a while instead of taking the first. Responders answer the QU query unicast, or
+    multicast to the whole group if they've recently done so, and both are listened for.
+ */
+use std::io::ErrorKind;
+use std::net::{Ipv4Addr, SocketAddr, SocketAddrV4, UdpSocket};
+use std::time::{Duration, Instant};
+
+use socket2::{Domain, Protocol, Socket, Type};
+
+use crate::error::DnsError;
+use crate::names;
+use crate::packet::{DNSPacket, DNSQuestion, DNSRecord, RecordType};
+
+pub const MDNS_GROUP: Ipv4Addr = Ipv4Addr::new(224, 0, 0, 251);
+pub const MDNS_PORT: u16 = 5353;
+
+// The top bit of an answer's class, telling caches to replace what they had (RFC 6762 section 10.2)
+pub const CACHE_FLUSH: u16 = 0x8000;
+
+// Most an mDNS message can be, even over UDP (RFC 6762 section 17)
+const MAX_MDNS_MESSAGE: usize = 9000; // bytes
+
+// How long replies are collected for
+pub const DEFAULT_WINDOW: Duration = Duration::from_secs(2);
+
+// Every record one device gave, across however many responses it sent
+#[derive(Debug, Clone, PartialEq)]
+pub struct Responder {
+    pub addr: SocketAddr,
+    pub answers: Vec<DNSRecord>,
+    // What the responder guessed would be asked next, like a service's SRV and TXT
+    pub additional: Vec<DNSRecord>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Mdns {
+    // Where the query goes, a unicast address is asked directly and no group is joined
+    pub group: SocketAddrV4,
+    pub window: Duration,
+}
+
+impl Default for Mdns {
+    fn default() -> Self {
+        Mdns { group: SocketAddrV4::new(MDNS_GROUP, MDNS_PORT), window: DEFAULT_WINDOW }
+    }
+}
+
+impl Mdns {
+    /* Asks for `name` and returns the responders that answered within the window, in the order
+        they first did. None answering is an empty list, not an error.
+     */
+    pub fn query(&self, name: &str, rtype: RecordType) -> Result<Vec<Responder>, DnsError> {
+        let name = names::validate_mdns_name(name)?;
+        // multicast queries have id 0 (RFC 6762 section 18.1)
+        let mut query = DNSPacket::with_id(0);
+        query.add_question(DNSQuestion::new(name.clone(), rtype)?.with_unicast_response(true));
+
+        let socket = self.socket()?;
+        socket.send_to(&query.serialize()?, self.group)?;
+        Ok(group(receive(&socket, self.window)?, &name))
+    }
+
+    /* Bound to the group's port with SO_REUSEADDR beside any mDNS responder on this host, so
+        multicast replies reach it, or to any port when that fails. Replies to QU queries come
+        unicast to wherever the query came from either way.
+     */
+    fn socket(&self) -> Result<UdpSocket, DnsError> {
+        let socket = Socket::new(Domain::IPV4, Type::DGRAM, Some(Protocol::UDP))?;
+        if self.group.ip().is_multicast() {
+            socket.set_reuse_address(true)?;
+            let port = SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, self.group.port());
+            if let Err(err) = socket.bind(&port.into()) {
+                tracing::debug!(%err, "couldn't bind the mDNS port, only unicast replies will be seen");
+                socket.bind(&SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, 0).into())?;
+            }
+            socket.join_multicast_v4(self.group.ip(), &Ipv4Addr::UNSPECIFIED)?;
+            // mDNS stays on the local link, every packet has TTL 255 (RFC 6762 section 11)
+            socket.set_multicast_ttl_v4(255)?;
+        } else {
+            socket.bind(&SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, 0).into())?;
+        }
+        Ok(socket.into())
+    }
+}
+
+// Every message that arrives on `socket` within `window` and parses, whoever it's from
+pub(crate) fn receive(socket: &UdpSocket, window: Duration) -> Result<Vec<(SocketAddr, DNSPacket)>, DnsError> {
+    let mut messages = Vec::new();
+    let mut buf = vec![0; MAX_MDNS_MESSAGE];
+    let until = Instant::now() + window;
+    loop {
+        let left = until.saturating_duration_since(Instant::now());
+        if left.is_zero() {
+            return Ok(messages);
+        }
+        socket.set_read_timeout(Some(left))?;
+        let (size, peer) = match socket.recv_from(&mut buf) {
+            Ok(received) => received,
+            Err(err) if matches!(err.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => return Ok(messages),
+            Err(err) => return Err(err.into()),
+        };
+        match DNSPacket::deserialize(&buf[..size]) {
+            Ok(packet) => messages.push((peer, packet)),
+            Err(err) => tracing::debug!(%peer, %err, "ignoring a multicast message that doesn't parse"),
+        }
+    }
+}
+
+/* Sorts received messages into responders. Only responses count, the queries of other hosts
+    on the group are ignored, and so are responses with no answer for `name`, like announcements
+    of something else. The cache-flush bit is masked off every class, a record sent again is
+    kept once, and OPT records are dropped.
+ */
+fn group(responses: Vec<(SocketAddr, DNSPacket)>, name: &str) -> Vec<Responder> {
+    let mut responders: Vec<Responder> = Vec::new();
+    for (addr, packet) in responses {
+        if !packet.header.flags.is_response
+            || !packet.answers().iter().any(|x| names::eq_ignore_case(&x.name, name))
+        {
+            continue;
+        }
+        let index = match responders.iter().position(|x| x.addr == addr) {
+            Some(index) => index,
+            None => {
+                responders.push(Responder { addr, answers: Vec::new(), additional: Vec::new() });
+                responders.len() - 1
+            },
+        };
+        let responder = &mut responders[index];
+        add_records(&mut responder.answers, packet.answers());
+        add_records(&mut responder.additional, packet.additional());
+    }
+    responders
+}
+
+fn add_records(records: &mut Vec<DNSRecord>, received: &[DNSRecord]) {
+    for record in received.iter().filter(|x| x.rtype != RecordType::OPT) {
+        let record = DNSRecord { class: record.class & !CACHE_FLUSH, ..record.clone() };
+        if !records.contains(&record) {
+            records.push(record);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{SocketAddr, UdpSocket};
+    use std::time::Duration;
+
+    use crate::mdns::{Mdns, Responder, CACHE_FLUSH};
+    use crate::packet::{DNSPacket, DNSRecord, RecordType, RECORD_CLASS};
+    use crate::testutil::a;
+
+    fn response(answers: Vec<DNSRecord>) -> DNSPacket {
+        let mut packet = DNSPacket::with_id(0);
+        packet.header.flags.is_response = true;
+        packet.header.flags.is_authoritative = true;
+        for answer in answers {
+            packet.add_answer(answer);
+        }
+        packet
+    }
+
+    #[test]
+    fn group() {
+        let first: SocketAddr = "192.0.2.1:5353".parse().unwrap();
+        let second: SocketAddr = "192.0.2.2:5353".parse().unwrap();
+        let printer = DNSRecord { class: RECORD_CLASS | CACHE_FLUSH, ..a("printer.local", 1) };
+        let mut query = DNSPacket::query("printer.local", RecordType::A).unwrap();
+        query.header.id = 0;
+        let responses = vec![
+            (first, response(vec![printer.clone()])),
+            (second, response(vec![a("printer.local", 2)])),
+            // sent again, another host's query, and an announcement of something else
+            (first, response(vec![printer])),
+            (second, query),
+            (second, response(vec![a("laptop.local", 3)])),
+        ];
+        assert_eq!(crate::mdns::group(responses, "Printer.local"), [
+            Responder { addr: first, answers: vec![a("printer.local", 1)], additional: vec![] },
+            Responder { addr: second, answers: vec![a("printer.local", 2)], additional: vec![] },
+        ]);
+    }
+
+    #[test]
+    fn query() {
+        let responder = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let group = match responder.local_addr().unwrap() {
+            SocketAddr::V4(addr) => addr,
+            SocketAddr::V6(_) => unreachable!(),
+        };
+        let answered = std::thread::spawn(move || {
+            let mut buf = [0; 512];
+            let (size, client) = responder.recv_from(&mut buf).unwrap();
+            let query = DNSPacket::deserialize(&buf[..size]).unwrap();
+            let flushed = DNSRecord { class: RECORD_CLASS | CACHE_FLUSH, ..a("printer.local", 1) };
+            let bytes = response(vec![flushed]).serialize().unwrap();
+            responder.send_to(&bytes, client).unwrap();
+            query
+        });
+
+        let mdns = Mdns { group, window: Duration::from_millis(300) };
+        let responders = mdns.query("printer.local", RecordType::A).unwrap();
+        let query = answered.join().unwrap();
+        assert_eq!(query.header.id(), 0);
+        assert!(query.questions()[0].unicast_response());
+        assert_eq!(responders.len(), 1);
+        assert_eq!(responders[0].addr, SocketAddr::from(group));
+        assert_eq!(responders[0].answers, [a("printer.local", 1)]);
+
+        // once it's gone there are no responders, which isn't an error
+        assert_eq!(mdns.query("printer.local", RecordType::A).unwrap(), []);
+    }
+
+    #[test]
+    fn query_utf8_name() {
+        let responder = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let group = match responder.local_addr().unwrap() {
+            SocketAddr::V4(addr) => addr,
+            SocketAddr::V6(_) => unreachable!(),
+        };
+        let asked = std::thread::spawn(move || {
+            let mut buf = [0; 512];
+            let (size, client) = responder.recv_from(&mut buf).unwrap();
+            let bytes = response(vec![a("B\\195\\188ro.local", 1)]).serialize().unwrap();
+            responder.send_to(&bytes, client).unwrap();
+            buf[12..size - 4].to_vec()
+        });
+
+        let mdns = Mdns { group, window: Duration::from_millis(300) };
+        let responders = mdns.query("Büro.local", RecordType::A).unwrap();
+        // the label goes out as UTF-8, not as xn--bro-hoa
+        assert_eq!(asked.join().unwrap(), b"\x05b\xc3\xbcro\x05local\x00");
+        assert_eq!(responders.len(), 1);
+        assert_eq!(responders[0].answers[0].name, "B\\195\\188ro.local");
+    }
+}
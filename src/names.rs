@@ -0,0 +1,234 @@
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::net::IpAddr;
+
+use rand::Rng;
+
+use crate::error::DnsError;
+use crate::packet;
+
+// Prefix marking a punycode encoded label (RFC 3490)
+const ACE_PREFIX: &str = "xn--";
+
+/* Converts any Unicode labels in `name` to xn-- A-labels.
+    ASCII labels are left untouched so their case and encoding survive as given.
+ */
+pub fn to_ascii(name: &str) -> Result<String, DnsError> {
+    if name.is_ascii() {
+        return Ok(String::from(name));
+    }
+
+    let labels: Result<Vec<String>, DnsError> = name.split('.')
+        .map(|label| match label.is_ascii() {
+            true => Ok(String::from(label)),
+            false => idna::domain_to_ascii(label)
+                .map_err(|_| DnsError::InvalidIdn(String::from(name))),
+        })
+        .collect();
+    Ok(labels?.join("."))
+}
+
+/* Cleans up a name typed by a user before it goes into a query: surrounding whitespace
+    and the trailing dot are dropped, Unicode labels converted and everything lowercased.
+    Empty labels (leading or doubled dots) and labels or names over the RFC 1035 limits are errors.
+ */
+pub fn validate_name(name: &str) -> Result<String, DnsError> {
+    let name = to_ascii(name.trim())?;
+    packet::serialize_dns_str(&name)?;
+    Ok(packet::strip_root(&name).to_ascii_lowercase())
+}
+
+/* `validate_name` for mDNS, DNS-SD and LLMNR, whose names go on the wire as UTF-8 (RFC 6762
+    section 16, RFC 4795 section 2.1): nothing is converted to xn-- labels, the UTF-8 bytes
+    become the \DDD escapes names read off the wire have, and only ASCII letters are lowercased.
+ */
+pub fn validate_mdns_name(name: &str) -> Result<String, DnsError> {
+    let mut escaped = String::with_capacity(name.len());
+    for chr in name.trim().chars() {
+        match chr.is_ascii() {
+            true => escaped.push(chr.to_ascii_lowercase()),
+            false => chr.encode_utf8(&mut [0; 4]).bytes().for_each(|x| escaped.push_str(&format!("\\{:03}", x))),
+        }
+    }
+    packet::serialize_dns_str(&escaped)?;
+    Ok(String::from(packet::strip_root(&escaped)))
+}
+
+// Names compare case-insensitively (RFC 4343), with or without the trailing dot
+pub fn eq_ignore_case(a: &str, b: &str) -> bool {
+    let a = a.strip_suffix('.').unwrap_or(a);
+    let b = b.strip_suffix('.').unwrap_or(b);
+    a.eq_ignore_ascii_case(b)
+}
+
+// `name` is `zone` or somewhere under it
+pub fn in_zone(name: &str, zone: &str) -> bool {
+    let (name, zone) = (name.trim_end_matches('.'), zone.trim_end_matches('.'));
+    zone.is_empty()
+        || eq_ignore_case(name, zone)
+        || name.len() > zone.len()
+            && name.as_bytes()[name.len() - zone.len() - 1] == b'.'
+            && eq_ignore_case(&name[name.len() - zone.len()..], zone)
+}
+
+// Randomly flips the case of each ASCII letter (DNS 0x20 encoding)
+pub fn randomize_case<R: Rng + ?Sized>(name: &str, rng: &mut R) -> String {
+    name.chars()
+        .map(|chr| match rng.gen::<bool>() {
+            true => chr.to_ascii_uppercase(),
+            false => chr.to_ascii_lowercase(),
+        })
+        .collect()
+}
+
+// Decodes xn-- labels in `name` back to Unicode for display, leaving invalid ones as-is
+pub fn to_unicode(name: &str) -> String {
+    name.split('.')
+        .map(|label| {
+            if !label.get(..ACE_PREFIX.len()).is_some_and(|x| x.eq_ignore_ascii_case(ACE_PREFIX)) {
+                return String::from(label);
+            }
+            match idna::domain_to_unicode(label) {
+                (decoded, Ok(())) => decoded,
+                _ => String::from(label),
+            }
+        })
+        .collect::<Vec<String>>()
+        .join(".")
+}
+
+/* The name a PTR query for `addr` asks about: the IPv4 octets reversed under in-addr.arpa
+    (RFC 1035 section 3.5), the IPv6 nibbles reversed under ip6.arpa (RFC 3596 section 2.5).
+ */
+pub fn reverse_name(addr: IpAddr) -> String {
+    match addr {
+        IpAddr::V4(addr) => {
+            let [a, b, c, d] = addr.octets();
+            format!("{}.{}.{}.{}.in-addr.arpa", d, c, b, a)
+        },
+        IpAddr::V6(addr) => {
+            let mut name = String::new();
+            for byte in addr.octets().iter().rev() {
+                name.push_str(&format!("{:x}.{:x}.", byte & 0xf, byte >> 4));
+            }
+            name.push_str("ip6.arpa");
+            name
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    #[test]
+    fn validate_name_normalizes() {
+        assert_eq!(crate::names::validate_name("WWW.Example.COM").unwrap(), "www.example.com");
+        assert_eq!(crate::names::validate_name(" example.com. \n").unwrap(), "example.com");
+        assert_eq!(crate::names::validate_name("Bücher.example").unwrap(), "xn--bcher-kva.example");
+        assert_eq!(crate::names::validate_name(".").unwrap(), "");
+    }
+
+    #[test]
+    fn validate_mdns_name() {
+        use crate::names::validate_mdns_name;
+
+        assert_eq!(validate_mdns_name(" Büro._IPP._tcp.local. ").unwrap(), "b\\195\\188ro._ipp._tcp.local");
+        assert!(validate_mdns_name("a..local").is_err());
+        // 21 characters of 3 bytes each is 63 bytes, the most a label holds
+        assert!(validate_mdns_name(&format!("{}.local", "€".repeat(21))).is_ok());
+        assert!(validate_mdns_name(&format!("{}.local", "€".repeat(22))).is_err());
+    }
+
+    #[test]
+    fn validate_name_empty_labels() {
+        for name in ["EXAMPLE..COM", ".example.com", "example.com..", ".."] {
+            assert!(
+                matches!(crate::names::validate_name(name), Err(crate::error::DnsError::EmptyLabel(_))),
+                "{} was accepted", name
+            );
+        }
+    }
+
+    #[test]
+    fn validate_name_label_too_long() {
+        let name = format!("{}.com", "a".repeat(64));
+        assert!(matches!(crate::names::validate_name(&name), Err(crate::error::DnsError::LabelTooLong(_))));
+        assert!(crate::names::validate_name(&format!("{}.com", "a".repeat(63))).is_ok());
+    }
+
+    #[test]
+    fn validate_name_too_long() {
+        // 4 labels of 63 bytes is 256 bytes on the wire
+        let name = vec!["a".repeat(63); 4].join(".");
+        assert!(matches!(crate::names::validate_name(&name), Err(crate::error::DnsError::NameTooLong(_))));
+        let name = ["a".repeat(63), "a".repeat(63), "a".repeat(63), "a".repeat(61)].join(".");
+        assert!(crate::names::validate_name(&name).is_ok());
+    }
+
+    #[test]
+    fn reverse_name() {
+        assert_eq!(crate::names::reverse_name("9.9.9.9".parse().unwrap()), "9.9.9.9.in-addr.arpa");
+        assert_eq!(crate::names::reverse_name("192.0.2.1".parse().unwrap()), "1.2.0.192.in-addr.arpa");
+        assert_eq!(
+            crate::names::reverse_name("2001:db8::567:89ab".parse().unwrap()),
+            "b.a.9.8.7.6.5.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.8.b.d.0.1.0.0.2.ip6.arpa"
+        );
+    }
+
+    #[test]
+    fn eq_ignore_case() {
+        assert!(crate::names::eq_ignore_case("WWW.Example.com", "www.example.COM"));
+        assert!(crate::names::eq_ignore_case("example.com.", "example.com"));
+        assert!(!crate::names::eq_ignore_case("example.com", "example.org"));
+    }
+
+    #[test]
+    fn randomize_case() {
+        use rand::SeedableRng;
+
+        let name = "www.example-domain.com";
+        let randomized = crate::names::randomize_case(name, &mut rand::rngs::StdRng::seed_from_u64(0x20));
+        assert!(crate::names::eq_ignore_case(&randomized, name));
+        assert_ne!(randomized, name);
+        assert_ne!(randomized, name.to_ascii_uppercase());
+        // non-letters are untouched
+        assert_eq!(randomized.find('-'), name.find('-'));
+        assert_eq!(
+            randomized,
+            crate::names::randomize_case(name, &mut rand::rngs::StdRng::seed_from_u64(0x20))
+        );
+    }
+
+    #[test]
+    fn to_ascii_german() {
+        assert_eq!(crate::names::to_ascii("bücher.example").unwrap(), "xn--bcher-kva.example");
+        assert_eq!(crate::names::to_ascii("münchen.de").unwrap(), "xn--mnchen-3ya.de");
+    }
+
+    #[test]
+    fn to_ascii_cyrillic() {
+        assert_eq!(crate::names::to_ascii("пример.рф").unwrap(), "xn--e1afmkfd.xn--p1ai");
+    }
+
+    #[test]
+    fn to_ascii_emoji() {
+        assert_eq!(crate::names::to_ascii("💩.la").unwrap(), "xn--ls8h.la");
+    }
+
+    #[test]
+    fn to_ascii_passthrough() {
+        assert_eq!(crate::names::to_ascii("WwW.Example.COM").unwrap(), "WwW.Example.COM");
+        assert_eq!(crate::names::to_ascii("xn--bcher-kva.example").unwrap(), "xn--bcher-kva.example");
+        // only the Unicode label is touched
+        assert_eq!(crate::names::to_ascii("WWW.bücher.example").unwrap(), "WWW.xn--bcher-kva.example");
+    }
+
+    #[test]
+    fn to_unicode() {
+        assert_eq!(crate::names::to_unicode("www.xn--bcher-kva.example"), "www.bücher.example");
+        assert_eq!(crate::names::to_unicode("xn--e1afmkfd.xn--p1ai"), "пример.рф");
+        assert_eq!(crate::names::to_unicode("XN--ls8h.la"), "💩.la");
+        assert_eq!(crate::names::to_unicode("Example.COM"), "Example.COM");
+    }
+}
@@ -0,0 +1,130 @@
+/* NOTIFY (RFC 1996), a primary telling its secondaries that a zone changed so they check its
+    serial now rather than at the next refresh. The message is a query for the zone's SOA with
+    AA set, the SOA as the primary has it in the answer section as a hint of the new serial.
+ */
+use alloc::string::String;
+
+use crate::error::DnsError;
+use crate::names;
+use crate::packet::{DNSPacket, DNSQuestion, DNSRecord, RecordData, RecordType};
+
+// The opcode of a NOTIFY message, where queries have 0
+pub const OPCODE_NOTIFY: u8 = 4;
+
+// What a NOTIFY announced, the serial only when the primary sent its SOA along
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Notify {
+    pub zone: String,
+    pub serial: Option<u32>,
+}
+
+// The NOTIFY for `zone`, with its SOA in the answer section unless there's none to hand
+pub fn message(zone: &str, soa: Option<DNSRecord>) -> Result<DNSPacket, DnsError> {
+    #[cfg(feature = "std")]
+    let id = rand::random::<u16>();
+    #[cfg(not(feature = "std"))]
+    let id = 0;
+
+    let zone = names::validate_name(zone)?;
+    let mut packet = DNSPacket::with_id(id);
+    packet.header.flags.opcode = OPCODE_NOTIFY;
+    packet.header.flags.is_authoritative = true;
+    packet.add_question(DNSQuestion::new(zone.clone(), RecordType::SOA)?);
+    if let Some(soa) = soa {
+        if !names::eq_ignore_case(&soa.name, &zone) {
+            return Err(DnsError::NotInZone { name: soa.name, zone });
+        }
+        packet.add_answer(soa);
+    }
+    Ok(packet)
+}
+
+/* The zone and serial a NOTIFY announces, or why it isn't one: exactly one question, for the
+    SOA of the zone, and at most that SOA in the answer section
+ */
+pub fn parse(packet: &DNSPacket) -> Result<Notify, &'static str> {
+    if packet.header.flags.is_response || packet.header.flags.opcode != OPCODE_NOTIFY {
+        return Err("not a NOTIFY");
+    }
+    let question = match packet.questions() {
+        [question] => question,
+        _ => return Err("not exactly one question"),
+    };
+    if question.qtype() != RecordType::SOA {
+        return Err("the question isn't for an SOA");
+    }
+    let serial = match packet.answers() {
+        [] => None,
+        [DNSRecord { name, data: RecordData::Soa(soa), .. }] if names::eq_ignore_case(name, question.name()) => {
+            Some(soa.serial)
+        },
+        _ => return Err("the answer section holds something other than the zone's SOA"),
+    };
+    Ok(Notify { zone: String::from(question.name()), serial })
+}
+
+#[cfg(test)]
+mod tests {
+    use hex_literal::hex;
+
+    use crate::notify::{Notify, OPCODE_NOTIFY};
+    use crate::packet::{DNSPacket, DNSRecord, RecordData, RecordType, Soa, RECORD_CLASS};
+
+    fn soa(name: &str, serial: u32) -> DNSRecord {
+        let soa = Soa {
+            mname: String::from("ns.example.com"),
+            rname: String::from("admin.example.com"),
+            serial,
+            refresh: 7200,
+            retry: 3600,
+            expire: 1209600,
+            minimum: 300,
+        };
+        let data = RecordData::Soa(soa);
+        DNSRecord { name: String::from(name), rtype: RecordType::SOA, class: RECORD_CLASS, ttl: 3600, data }
+    }
+
+    #[test]
+    fn message() {
+        let mut notify = crate::notify::message("example.com.", None).unwrap();
+        notify.header.id = 0x1234;
+        assert_eq!(notify.serialize().unwrap(), hex!(
+            // id, opcode 4 and AA, 1 question
+            "1234 2400 0001 0000 0000 0000"
+            "076578616d706c6503636f6d00 0006 0001"
+        ));
+
+        let notify = crate::notify::message("example.com", Some(soa("example.com", 7))).unwrap();
+        assert_eq!(notify.header.flags.opcode, OPCODE_NOTIFY);
+        let parsed = DNSPacket::deserialize(&notify.serialize().unwrap()).unwrap();
+        assert_eq!(crate::notify::parse(&parsed), Ok(Notify { zone: String::from("example.com"), serial: Some(7) }));
+
+        assert!(crate::notify::message("example.com", Some(soa("example.org", 7))).is_err());
+    }
+
+    #[test]
+    fn parse() {
+        use crate::notify::parse;
+
+        let notify = crate::notify::message("example.com", None).unwrap();
+        assert_eq!(parse(&notify).unwrap().serial, None);
+
+        assert!(parse(&DNSPacket::query("example.com", RecordType::SOA).unwrap()).is_err());
+        let mut response = notify.clone();
+        response.header.flags.is_response = true;
+        assert!(parse(&response).is_err());
+
+        let mut wrong_type = DNSPacket::query("example.com", RecordType::A).unwrap();
+        wrong_type.header.flags.opcode = OPCODE_NOTIFY;
+        assert!(parse(&wrong_type).is_err());
+
+        // another zone's SOA, or more than the one
+        let mut other = notify.clone();
+        other.add_answer(soa("example.org", 7));
+        assert!(parse(&other).is_err());
+        let mut two = notify.clone();
+        two.add_answer(soa("example.com", 7));
+        two.add_answer(soa("example.com", 8));
+        assert!(parse(&two).is_err());
+    }
+}
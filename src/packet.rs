@@ -1,9 +1,46 @@
+use alloc::collections::BTreeMap;
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec;
+use alloc::vec::Vec;
+use core::fmt;
+use core::net::{Ipv4Addr, Ipv6Addr};
+
+use crate::error::DnsError;
+use crate::names;
+use crate::packet_ref::{DNSPacketRef, NameRef, QuestionRef, RecordRef};
+
 // Record Class will always be Internet/IN/1
-const RECORD_CLASS: u16 = 1;
+pub const RECORD_CLASS: u16 = 1;
+
+// QU, the top bit of an mDNS question's class, asking for a unicast response (RFC 6762 section 5.4)
+pub const UNICAST_RESPONSE: u16 = 0x8000;
+
+// Flag in an OPT record's TTL asking for DNSSEC records with the answer (RFC 3225)
+pub const EDNS_DNSSEC_OK: u32 = 0x8000;
+
+pub(crate) const HEADER_SIZE: usize = core::mem::size_of::<u16>() * 6; // bytes
+
+// RFC 1035 2.3.4 size limits
+const MAX_LABEL_SIZE: usize = 63; // bytes
+pub(crate) const MAX_NAME_SIZE: usize = 255; // bytes, on the wire
+
+// Smallest possible question (root name, type, class) and record (adds ttl, rdlength)
+pub(crate) const MIN_QUESTION_SIZE: usize = 1 + 2 * 2; // bytes
+pub(crate) const MIN_RECORD_SIZE: usize = 1 + 2 * 2 + 4 + 2; // bytes
+
+// Upper bound on records across all sections of one packet
+pub(crate) const MAX_RECORDS: usize = 8192;
 
-const HEADER_SIZE: usize = std::mem::size_of::<u16>() * 6; // bytes
+// Compression pointers have 14 bits of offset
+const MAX_POINTER_OFFSET: usize = 0x3FFF;
 
-#[derive(Debug, PartialEq)]
+// Classic UDP limit, and the most any message can hold (TCP's u16 length prefix)
+pub(crate) const UDP_MESSAGE_SIZE: usize = 512; // bytes
+pub(crate) const MAX_MESSAGE_SIZE: usize = 65535; // bytes
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct DNSFlags {
     pub is_response: bool,
     pub opcode: u8,
@@ -11,27 +48,15 @@ pub struct DNSFlags {
     pub is_truncated: bool,
      pub recurse_desired: bool,
      pub recurse_available: bool,
-     pub answer_authed: bool,
-     pub unauth_ok: bool,
+     // Reserved, zero in anything sent but kept as received so packets round-trip
+     pub z: bool,
+     // AD, the upstream resolver validated the answer and authority with DNSSEC (RFC 4035 section 3.2.3)
+     pub authentic_data: bool,
+     // CD, the server is to leave DNSSEC validation to us (RFC 4035 section 3.2.2)
+     pub checking_disabled: bool,
      pub reply_code: u8,
 }
 
-impl DNSFlags {
-    pub fn default() -> Self {
-        DNSFlags {
-            is_response: false,
-            opcode: 0,
-            is_authoritative: false,
-            is_truncated: false,
-            recurse_desired: false,
-            recurse_available: false,
-            answer_authed: false,
-            unauth_ok: false,
-            reply_code: 0,
-        }
-    }
-}
-
 /*  Example 
 Flags: 0x8580 Standard query response, No error
         1... .... .... .... = Response: Message is a response
@@ -55,9 +80,9 @@ impl DNSFlags {
         flags |= (self.is_truncated as u16) << 9;  
         flags |= (self.recurse_desired as u16) << 8;  
         flags |= (self.recurse_available as u16) << 7;  
-        //reserved at 6
-        flags |= (self.answer_authed as u16) << 5;  
-        flags |= (self.unauth_ok as u16) << 4;  
+        flags |= (self.z as u16) << 6;
+        flags |= (self.authentic_data as u16) << 5;  
+        flags |= (self.checking_disabled as u16) << 4;
         flags |= (self.reply_code & 0xF) as u16; // keep lower 4 bits
         flags
     }
@@ -70,13 +95,199 @@ impl DNSFlags {
             is_truncated: (uint16 & 0x200 ) > 0,
             recurse_desired: (uint16 & 0x100 ) > 0,
             recurse_available: (uint16 & 0x80 ) > 0,
-            answer_authed: (uint16 & 0x20 ) > 0,
-            unauth_ok: (uint16 & 0x10 ) > 0,
+            z: (uint16 & 0x40) > 0,
+            authentic_data: (uint16 & 0x20 ) > 0,
+            checking_disabled: (uint16 & 0x10 ) > 0,
             reply_code: (uint16 & 0xF) as u8,
         }
     }
+
+    /* The numeric codes as on the wire, kept stable for tools that log or compare them,
+        whatever the fields become
+     */
+    pub fn raw_opcode(&self) -> u8 {
+        self.opcode & 0xF
+    }
+
+    pub fn raw_reply_code(&self) -> u8 {
+        self.reply_code & 0xF
+    }
+
+    fn opcode_name(&self) -> String {
+        match self.opcode {
+            0 => String::from("Standard query"),
+            1 => String::from("Inverse query"),
+            2 => String::from("Server status request"),
+            4 => String::from("Notify"),
+            5 => String::from("Dynamic update"),
+            opcode => format!("Unknown operation ({})", opcode),
+        }
+    }
+
+    fn reply_code_name(&self) -> String {
+        match ReplyCode::from_u8(self.reply_code) {
+            ReplyCode::NoError => String::from("No error"),
+            ReplyCode::FormErr => String::from("Format error"),
+            ReplyCode::ServFail => String::from("Server failure"),
+            ReplyCode::NxDomain => String::from("No such name"),
+            ReplyCode::NotImp => String::from("Not implemented"),
+            ReplyCode::Refused => String::from("Refused"),
+            ReplyCode::YxDomain => String::from("Name exists when it should not"),
+            ReplyCode::YxRrset => String::from("RRset exists when it should not"),
+            ReplyCode::NxRrset => String::from("RRset that should exist does not"),
+            ReplyCode::NotAuth => String::from("Server not authoritative for zone"),
+            ReplyCode::NotZone => String::from("Name not contained in zone"),
+            ReplyCode::BadVers => String::from("Bad EDNS version"),
+            ReplyCode::Unknown(code) => format!("Unknown error ({})", code),
+        }
+    }
+}
+
+// The Wireshark-style breakdown in the example above, a line per field
+impl fmt::Display for DNSFlags {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let bits = self.serialize();
+        match self.is_response {
+            true => write!(f, "Flags: {:#06x} {} response, {}", bits, self.opcode_name(), self.reply_code_name())?,
+            false => write!(f, "Flags: {:#06x} {}", bits, self.opcode_name())?,
+        }
+        // whether the resolver vouched for the answer is worth seeing without reading the bits
+        if self.authentic_data {
+            f.write_str(", DNSSEC validated")?;
+        }
+
+        let choose = |set: bool, yes: &'static str, no: &'static str| if set { yes } else { no };
+        let fields: [(u16, &str, String); 10] = [
+            (0x8000, "Response", String::from(choose(self.is_response, "Message is a response", "Message is a query"))),
+            (0x7800, "Opcode", format!("{} ({})", self.opcode_name(), self.opcode)),
+            (0x0400, "Authoritative", String::from(choose(
+                self.is_authoritative, "Server is an authority for domain", "Server is not an authority for domain"
+            ))),
+            (0x0200, "Truncated", String::from(choose(self.is_truncated, "Message is truncated", "Message is not truncated"))),
+            (0x0100, "Recursion desired", String::from(choose(
+                self.recurse_desired, "Do query recursively", "Don't do query recursively"
+            ))),
+            (0x0080, "Recursion available", String::from(choose(
+                self.recurse_available, "Server can do recursive queries", "Server can't do recursive queries"
+            ))),
+            (0x0040, "Z", format!("reserved ({})", self.z as u8)),
+            (0x0020, "Answer authenticated", String::from(choose(
+                self.authentic_data,
+                "Answer/authority portion was authenticated by the server",
+                "Answer/authority portion was not authenticated by the server",
+            ))),
+            (0x0010, "Non-authenticated data", String::from(choose(self.checking_disabled, "Acceptable", "Unacceptable"))),
+            (0x000f, "Reply code", format!("{} ({})", self.reply_code_name(), self.reply_code)),
+        ];
+        for (mask, label, meaning) in fields {
+            write!(f, "\n        {} = {}: {}", bit_pattern(bits, mask), label, meaning)?;
+        }
+        Ok(())
+    }
 }
 
+// The bits of `value` under `mask` as 0s and 1s, the rest as dots, in groups of four
+fn bit_pattern(value: u16, mask: u16) -> String {
+    let mut pattern = String::new();
+    for bit in (0..16).rev() {
+        if bit % 4 == 3 && bit != 15 {
+            pattern.push(' ');
+        }
+        pattern.push(match (mask >> bit & 1, value >> bit & 1) {
+            (0, _) => '.',
+            (_, 0) => '0',
+            _ => '1',
+        });
+    }
+    pattern
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum ReplyCode {
+    NoError,
+    FormErr,
+    ServFail,
+    NxDomain,
+    NotImp,
+    Refused,
+    // The EDNS version asked for isn't supported (RFC 6891), only possible with an OPT record
+    BadVers,
+    // The rest of RFC 2136's, answers to an UPDATE whose prerequisites failed or that went to the wrong server
+    YxDomain,
+    YxRrset,
+    NxRrset,
+    NotAuth,
+    NotZone,
+    Unknown(u16),
+}
+
+impl ReplyCode {
+    // Codes past 15 have their upper 8 bits in the OPT record, the header holds only the lower 4
+    pub fn value(&self) -> u16 {
+        match self {
+            ReplyCode::NoError => 0,
+            ReplyCode::FormErr => 1,
+            ReplyCode::ServFail => 2,
+            ReplyCode::NxDomain => 3,
+            ReplyCode::NotImp => 4,
+            ReplyCode::Refused => 5,
+            ReplyCode::YxDomain => 6,
+            ReplyCode::YxRrset => 7,
+            ReplyCode::NxRrset => 8,
+            ReplyCode::NotAuth => 9,
+            ReplyCode::NotZone => 10,
+            ReplyCode::BadVers => 16,
+            ReplyCode::Unknown(value) => *value,
+        }
+    }
+
+    pub fn from_u8(value: u8) -> Self {
+        ReplyCode::from_u16(value as u16)
+    }
+
+    pub fn from_u16(value: u16) -> Self {
+        match value {
+            0 => ReplyCode::NoError,
+            1 => ReplyCode::FormErr,
+            2 => ReplyCode::ServFail,
+            3 => ReplyCode::NxDomain,
+            4 => ReplyCode::NotImp,
+            5 => ReplyCode::Refused,
+            6 => ReplyCode::YxDomain,
+            7 => ReplyCode::YxRrset,
+            8 => ReplyCode::NxRrset,
+            9 => ReplyCode::NotAuth,
+            10 => ReplyCode::NotZone,
+            16 => ReplyCode::BadVers,
+            _ => ReplyCode::Unknown(value),
+        }
+    }
+}
+
+// The mnemonic of RFC 6895, or RCODE and the number as dig shows codes it doesn't know
+impl fmt::Display for ReplyCode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ReplyCode::NoError => write!(f, "NOERROR"),
+            ReplyCode::FormErr => write!(f, "FORMERR"),
+            ReplyCode::ServFail => write!(f, "SERVFAIL"),
+            ReplyCode::NxDomain => write!(f, "NXDOMAIN"),
+            ReplyCode::NotImp => write!(f, "NOTIMP"),
+            ReplyCode::Refused => write!(f, "REFUSED"),
+            ReplyCode::YxDomain => write!(f, "YXDOMAIN"),
+            ReplyCode::YxRrset => write!(f, "YXRRSET"),
+            ReplyCode::NxRrset => write!(f, "NXRRSET"),
+            ReplyCode::NotAuth => write!(f, "NOTAUTH"),
+            ReplyCode::NotZone => write!(f, "NOTZONE"),
+            ReplyCode::BadVers => write!(f, "BADVERS"),
+            ReplyCode::Unknown(value) => write!(f, "RCODE{}", value),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum RecordType {
     A,
     NS,
@@ -85,6 +296,21 @@ pub enum RecordType {
     PTR,
     MX,
     TXT,
+    AAAA,
+    // Where a service is, host and port (RFC 2782)
+    SRV,
+    // The EDNS pseudo-record (RFC 6891), only ever in the additional section
+    OPT,
+    // DNSSEC signatures and zone keys (RFC 4034)
+    RRSIG,
+    DNSKEY,
+    // Transaction signature (RFC 8945), the last additional record of a signed message
+    TSIG,
+    // Zone transfer, only valid as a question and only over TCP
+    AXFR,
+    // Every type, in a question or an UPDATE deleting or requiring a whole name
+    ANY,
+    Unknown(u16),
 }
 
 impl RecordType {
@@ -97,217 +323,2330 @@ impl RecordType {
             RecordType::PTR => 12,
             RecordType::MX => 15,
             RecordType::TXT => 16,
+            RecordType::AAAA => 28,
+            RecordType::SRV => 33,
+            RecordType::OPT => 41,
+            RecordType::RRSIG => 46,
+            RecordType::DNSKEY => 48,
+            RecordType::TSIG => 250,
+            RecordType::AXFR => 252,
+            RecordType::ANY => 255,
+            RecordType::Unknown(value) => *value,
        } 
     }
+
+    pub fn from_u16(value: u16) -> Self {
+        match value {
+            1 => RecordType::A,
+            2 => RecordType::NS,
+            5 => RecordType::CNAME,
+            6 => RecordType::SOA,
+            12 => RecordType::PTR,
+            15 => RecordType::MX,
+            16 => RecordType::TXT,
+            28 => RecordType::AAAA,
+            33 => RecordType::SRV,
+            41 => RecordType::OPT,
+            46 => RecordType::RRSIG,
+            48 => RecordType::DNSKEY,
+            250 => RecordType::TSIG,
+            252 => RecordType::AXFR,
+            255 => RecordType::ANY,
+            _ => RecordType::Unknown(value),
+        }
+    }
+}
+
+const KNOWN_TYPES: [RecordType; 15] = [
+    RecordType::A, RecordType::NS, RecordType::CNAME, RecordType::SOA, RecordType::PTR,
+    RecordType::MX, RecordType::TXT, RecordType::AAAA, RecordType::SRV, RecordType::OPT,
+    RecordType::RRSIG, RecordType::DNSKEY, RecordType::TSIG, RecordType::AXFR, RecordType::ANY,
+];
+
+// Mnemonic for the type, or the RFC 3597 TYPEnnn form for ones we don't know
+impl fmt::Display for RecordType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RecordType::Unknown(value) => write!(f, "TYPE{}", value),
+            rtype => write!(f, "{:?}", rtype),
+        }
+    }
+}
+
+// Reads a mnemonic or TYPEnnn back into its type, ignoring case
+impl core::str::FromStr for RecordType {
+    type Err = DnsError;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        let unknown = || DnsError::UnknownType(String::from(name));
+        if let Some(value) = generic_value(name, "TYPE") {
+            return value.parse().map(RecordType::from_u16).map_err(|_| unknown());
+        }
+        KNOWN_TYPES.into_iter().find(|x| x.to_string().eq_ignore_ascii_case(name)).ok_or_else(unknown)
+    }
+}
+
+// The digits after a TYPE or CLASS prefix, which may be in any case
+fn generic_value<'a>(name: &'a str, prefix: &str) -> Option<&'a str> {
+    name.get(..prefix.len()).filter(|x| x.eq_ignore_ascii_case(prefix)).and_then(|_| name.get(prefix.len()..))
+}
+
+// Records here are all IN, the others only turn up in packets from elsewhere
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum RecordClass {
+    IN,
+    // Chaos, where servers answer version.bind and the like
+    CH,
+    HS,
+    // Any class, valid in a question, and in an UPDATE for whole RRsets
+    ANY,
+    // Only in an UPDATE, for single records deleted and RRsets that mustn't exist (RFC 2136)
+    NONE,
+    Unknown(u16),
+}
+
+impl RecordClass {
+    pub fn value(&self) -> u16 {
+        match self {
+            RecordClass::IN => RECORD_CLASS,
+            RecordClass::CH => 3,
+            RecordClass::HS => 4,
+            RecordClass::ANY => 255,
+            RecordClass::NONE => 254,
+            RecordClass::Unknown(value) => *value,
+        }
+    }
+
+    pub fn from_u16(value: u16) -> Self {
+        match value {
+            RECORD_CLASS => RecordClass::IN,
+            3 => RecordClass::CH,
+            4 => RecordClass::HS,
+            255 => RecordClass::ANY,
+            254 => RecordClass::NONE,
+            _ => RecordClass::Unknown(value),
+        }
+    }
+}
+
+// Mnemonic for the class, or CLASSnnn like types
+impl fmt::Display for RecordClass {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RecordClass::Unknown(value) => write!(f, "CLASS{}", value),
+            class => write!(f, "{:?}", class),
+        }
+    }
+}
+
+impl core::str::FromStr for RecordClass {
+    type Err = DnsError;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        let unknown = || DnsError::UnknownClass(String::from(name));
+        if let Some(value) = generic_value(name, "CLASS") {
+            return value.parse().map(RecordClass::from_u16).map_err(|_| unknown());
+        }
+        [RecordClass::IN, RecordClass::CH, RecordClass::HS, RecordClass::ANY, RecordClass::NONE]
+            .into_iter()
+            .find(|x| x.to_string().eq_ignore_ascii_case(name))
+            .ok_or_else(unknown)
+    }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct DNSHeader {
-    id: u16,
+    pub(crate) id: u16,
     pub flags: DNSFlags,
-    question_count: u16,
-    answer_count: u16,
-    authority_count: u16,
-    additional_count: u16,
+    pub(crate) question_count: u16,
+    pub(crate) answer_count: u16,
+    pub(crate) authority_count: u16,
+    pub(crate) additional_count: u16,
 }
 
+// The counts are read-only, they follow the packet's sections through its `add_` methods
 impl DNSHeader {
+    /// What a response claimed, read straight from the wire:
+    ///
+    /// ```
+    /// use bkdns::packet::DNSHeader;
+    ///
+    /// let header = DNSHeader::deserialize(&[0x4d, 0x2a, 0x81, 0x80, 0, 1, 0, 2, 0, 0, 0, 1]).unwrap();
+    /// assert_eq!(header.id(), 0x4d2a);
+    /// assert_eq!(header.question_count(), 1);
+    /// assert_eq!(header.answer_count(), 2);
+    /// assert_eq!(header.authority_count(), 0);
+    /// assert_eq!(header.additional_count(), 1);
+    /// ```
+    pub fn id(&self) -> u16 {
+        self.id
+    }
+
+    pub fn set_id(&mut self, id: u16) {
+        self.id = id;
+    }
+
+    pub fn question_count(&self) -> u16 {
+        self.question_count
+    }
+
+    pub fn answer_count(&self) -> u16 {
+        self.answer_count
+    }
+
+    pub fn authority_count(&self) -> u16 {
+        self.authority_count
+    }
+
+    pub fn additional_count(&self) -> u16 {
+        self.additional_count
+    }
+
     pub fn serialize(&self) -> Vec<u8> {
-        let mut bytes: Vec<u8> = Vec::with_capacity(HEADER_SIZE);
-        bytes.extend_from_slice(&self.id.to_be_bytes());
-        bytes.extend_from_slice(&self.flags.serialize().to_be_bytes());
-        bytes.extend_from_slice(&self.question_count.to_be_bytes());
-        bytes.extend_from_slice(&self.answer_count.to_be_bytes());
-        bytes.extend_from_slice(&self.authority_count.to_be_bytes());
-        bytes.extend_from_slice(&self.additional_count.to_be_bytes());
+        self.to_bytes().to_vec()
+    }
+
+    // Returns the number of bytes written, always HEADER_SIZE
+    pub fn serialize_into(&self, buf: &mut [u8]) -> Result<usize, DnsError> {
+        let mut out = Writer::new(buf);
+        out.put(&self.to_bytes())?;
+        Ok(out.len)
+    }
+
+    fn to_bytes(&self) -> [u8; HEADER_SIZE] {
+        let fields = [
+            self.id,
+            self.flags.serialize(),
+            self.question_count,
+            self.answer_count,
+            self.authority_count,
+            self.additional_count,
+        ];
+        let mut bytes = [0; HEADER_SIZE];
+        for (chunk, field) in bytes.chunks_exact_mut(2).zip(fields) {
+            chunk.copy_from_slice(&field.to_be_bytes());
+        }
         bytes
     }
 
-    pub fn deserialize(bytes: &[u8]) -> Result<Self, String> {
+    pub fn deserialize(bytes: &[u8]) -> Result<Self, DnsError> {
         if bytes.len() < HEADER_SIZE {
-            return Err(format!(
-                "Failed to parse header. Expected {} bytes, got: {}", HEADER_SIZE, bytes.len()
-            ));
+            return Err(DnsError::TooShort { offset: 0, expected: HEADER_SIZE });
         }
 
         Ok(DNSHeader {
-            id: u16::from_be_bytes(bytes[0..2].try_into().unwrap()),
-            flags: DNSFlags::from(u16::from_be_bytes(bytes[2..4].try_into().unwrap())),
-            question_count: u16::from_be_bytes(bytes[4..6].try_into().unwrap()),
-            answer_count: u16::from_be_bytes(bytes[6..8].try_into().unwrap()),
-            authority_count: u16::from_be_bytes(bytes[8..10].try_into().unwrap()),
-            additional_count: u16::from_be_bytes(bytes[10..12].try_into().unwrap()),
+            id: read_u16(bytes, 0)?,
+            flags: DNSFlags::from(read_u16(bytes, 2)?),
+            question_count: read_u16(bytes, 4)?,
+            answer_count: read_u16(bytes, 6)?,
+            authority_count: read_u16(bytes, 8)?,
+            additional_count: read_u16(bytes, 10)?,
         })
     }
 }
 
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct DNSQuestion {
-    name: String,
-    qtype: RecordType,
+    pub(crate) name: String,
+    pub(crate) qtype: RecordType,
+    // IN unless set, without the QU bit
+    #[cfg_attr(feature = "serde", serde(default = "in_class"))]
+    pub(crate) qclass: u16,
+    // The QU bit of mDNS, serialized on top of the class
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub(crate) unicast_response: bool,
+}
+
+// Questions serialized before they had a class were all IN
+#[cfg(feature = "serde")]
+fn in_class() -> u16 {
+    RECORD_CLASS
 }
 
 impl DNSQuestion {
-    pub fn new(name: String, qtype: RecordType) -> Self {
-        DNSQuestion { name, qtype }
+    /* A trailing dot (fully qualified name) is accepted and stripped,
+        and Unicode labels are converted to their xn-- form.
+     */
+    pub fn new(name: String, qtype: RecordType) -> Result<Self, DnsError> {
+        let name = names::to_ascii(&name)?;
+        visit_labels(&name, |_| Ok(()))?;
+        let name = String::from(strip_root(&name));
+        Ok(DNSQuestion { name, qtype, qclass: RECORD_CLASS, unicast_response: false })
     }
 
-    pub fn serialize(&self) -> Vec<u8> {
-        // 6 u16 fields (2 bytes)
-        let name_bytes = serialize_dns_str(self.name.as_str());
-        let mut bytes: Vec<u8> = Vec::with_capacity(2 * 2 + name_bytes.len());
-        bytes.extend_from_slice(name_bytes.as_slice());
-        bytes.extend_from_slice(&self.qtype.value().to_be_bytes());
-        bytes.extend_from_slice(&RECORD_CLASS.to_be_bytes());
-        bytes
+    pub fn with_qclass(mut self, qclass: u16) -> Self {
+        self.qclass = qclass & !UNICAST_RESPONSE;
+        self
+    }
+
+    pub fn with_unicast_response(mut self, unicast_response: bool) -> Self {
+        self.unicast_response = unicast_response;
+        self
+    }
+
+    pub fn unicast_response(&self) -> bool {
+        self.unicast_response
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn qtype(&self) -> RecordType {
+        self.qtype
+    }
+
+    pub fn qclass(&self) -> u16 {
+        self.qclass
+    }
+
+    // Bytes the question takes on the wire with its name written out in full
+    pub fn serialized_len(&self) -> usize {
+        // the encoded name, then the qtype and qclass u16 fields
+        name_len(&self.name) + 2 * 2
+    }
+
+    pub fn serialize(&self) -> Result<Vec<u8>, DnsError> {
+        let mut bytes = vec![0; self.serialized_len()];
+        let size = self.serialize_into(&mut bytes)?;
+        bytes.truncate(size);
+        Ok(bytes)
+    }
+
+    // Returns the number of bytes written to the start of `buf`
+    pub fn serialize_into(&self, buf: &mut [u8]) -> Result<usize, DnsError> {
+        let mut out = Writer::new(buf);
+        self.write(&mut out, &mut NameTable::new(false))?;
+        Ok(out.len)
+    }
+
+    fn write(&self, out: &mut Writer, names: &mut NameTable) -> Result<(), DnsError> {
+        names.write(out, &self.name)?;
+        out.put(&self.qtype.value().to_be_bytes())?;
+        let unicast = if self.unicast_response { UNICAST_RESPONSE } else { 0 };
+        out.put(&(self.qclass | unicast).to_be_bytes())
+    }
+
+    // Returns the question and the number of bytes it occupied at `offset`
+    pub fn deserialize(bytes: &[u8], offset: usize) -> Result<(Self, usize), DnsError> {
+        let (question, size) = QuestionRef::parse(bytes, offset)?;
+        Ok((question.to_owned(), size))
+    }
+}
+
+// Removes the trailing dot of a fully qualified name, unless it is an escaped "\."
+pub(crate) fn strip_root(name: &str) -> &str {
+    match name.strip_suffix('.') {
+        Some(stripped) => {
+            let escapes = stripped.bytes().rev().take_while(|x| *x == b'\\').count();
+            match escapes % 2 {
+                0 => stripped,
+                _ => name,
+            }
+        },
+        None => name,
+    }
+}
+
+/* Hands the raw bytes of each label of a name to `visit`, rejecting anything that can't be encoded.
+    A trailing dot is allowed, and the root ("." or "") has no labels.
+    Labels use the presentation format escapes: "\." and "\\" for a literal
+    dot or backslash, and "\DDD" for any byte by its decimal value.
+    Nothing is allocated, so serialize_into can write names straight out.
+ */
+fn visit_labels<F>(dns_str: &str, mut visit: F) -> Result<(), DnsError>
+where
+    F: FnMut(&[u8]) -> Result<(), DnsError>,
+{
+    if !dns_str.is_ascii() {
+        return Err(DnsError::NonAsciiName(String::from(dns_str)));
+    }
+    let name = strip_root(dns_str).as_bytes();
+    if name.is_empty() {
+        return Ok(());
+    }
+
+    let mut label = [0; MAX_LABEL_SIZE];
+    let mut label_len = 0;
+    let mut label_start = 0;
+    // a length byte per label, the label itself, and the null terminator
+    let mut size = 1;
+    let mut position = 0;
+    loop {
+        let byte = match name.get(position) {
+            None | Some(b'.') => {
+                if label_len == 0 {
+                    return Err(DnsError::EmptyLabel(String::from(dns_str)));
+                }
+                size += label_len + 1;
+                if size > MAX_NAME_SIZE {
+                    return Err(DnsError::NameTooLong(size));
+                }
+                visit(&label[..label_len])?;
+                if position == name.len() {
+                    return Ok(());
+                }
+                label_len = 0;
+                position += 1;
+                label_start = position;
+                continue;
+            },
+            Some(b'\\') => match name.get(position + 1) {
+                Some(digit) if digit.is_ascii_digit() => {
+                    let value = name.get(position + 1..position + 4)
+                        .filter(|x| x.iter().all(u8::is_ascii_digit))
+                        .map(|x| x.iter().fold(0u16, |value, x| value * 10 + (x - b'0') as u16))
+                        .filter(|x| *x <= u8::MAX as u16)
+                        .ok_or_else(|| DnsError::InvalidEscape(String::from(dns_str)))?;
+                    position += 4;
+                    value as u8
+                },
+                Some(escaped) => {
+                    position += 2;
+                    *escaped
+                },
+                None => return Err(DnsError::InvalidEscape(String::from(dns_str))),
+            },
+            Some(byte) => {
+                position += 1;
+                *byte
+            },
+        };
+
+        if label_len == MAX_LABEL_SIZE {
+            let rest = name[label_start..].split(|x| *x == b'.').next().unwrap_or_default();
+            return Err(DnsError::LabelTooLong(String::from_utf8_lossy(rest).into_owned()));
+        }
+        label[label_len] = byte;
+        label_len += 1;
+    }
+}
+
+// The raw bytes of each label of a name, see visit_labels
+fn dns_labels(dns_str: &str) -> Result<Vec<Vec<u8>>, DnsError> {
+    let mut labels: Vec<Vec<u8>> = Vec::new();
+    visit_labels(dns_str, |label| {
+        labels.push(label.to_vec());
+        Ok(())
+    })?;
+    Ok(labels)
+}
+
+/* Writes a label in presentation format, the reverse of visit_labels.
+    Dots and backslashes are escaped, and bytes that aren't printable ASCII
+    become "\DDD", so every name read off the wire serializes back to the same bytes.
+ */
+pub(crate) fn write_label<W: fmt::Write>(out: &mut W, label: &[u8]) -> fmt::Result {
+    for byte in label {
+        match byte {
+            b'.' | b'\\' => {
+                out.write_char('\\')?;
+                out.write_char(*byte as char)?;
+            },
+            0x21..=0x7E => out.write_char(*byte as char)?,
+            _ => write!(out, "\\{:03}", byte)?,
+        }
+    }
+    Ok(())
+}
+
+/* Bytes `dns_str` takes on the wire uncompressed. Names that don't encode get the most
+    their text could need, a length byte and terminator on top of it.
+ */
+fn name_len(dns_str: &str) -> usize {
+    let mut size = 1;
+    match visit_labels(dns_str, |label| {
+        size += label.len() + 1;
+        Ok(())
+    }) {
+        Ok(()) => size,
+        Err(_) => dns_str.len() + 2,
     }
 }
 
-pub fn serialize_dns_str(dns_str: &str) -> Vec<u8> {
-    let parts: Vec<String> = dns_str.split(".")
-                                .map(|x| x.to_owned())
-                                .collect();
+pub fn serialize_dns_str(dns_str: &str) -> Result<Vec<u8>, DnsError> {
+    let parts = dns_labels(dns_str)?;
     let parts_len: usize = parts.iter()
                         .map(|x| x.len())
                         .sum();
     /* Size is:
         * 1 byte per "part" (anything period separated)
         * 1 byte for the null terminator
-        * The number of characters in each part (ASCII encodes to 1 byte each)
+        * The number of bytes in each part, after escapes are decoded
      */ 
     let mut bytes: Vec<u8> = Vec::with_capacity(parts.len() + 1 + parts_len);
     for part in parts.iter() {
         bytes.push(part.len() as u8);
-        for chr in part.chars() {
-            bytes.push(chr.to_ascii_lowercase() as u8);
-        }
+        // case is kept as given, compare names with names::eq_ignore_case
+        bytes.extend_from_slice(part);
     }
     bytes.push(0); // null terminator
-    bytes
+    Ok(bytes)
 }
 
-pub struct DNSPacket {
-    pub header: DNSHeader,
-    questions: Vec<DNSQuestion>,
+/* Writes names into a packet being built, remembering where each suffix landed.
+    With compression on, a suffix already in the packet becomes a pointer back to it.
+    Suffixes are matched case-sensitively so the casing of every name survives.
+ */
+struct NameTable {
+    compress: bool,
+    offsets: BTreeMap<Vec<Vec<u8>>, usize>,
 }
 
-impl DNSPacket {
+impl NameTable {
+    fn new(compress: bool) -> Self {
+        NameTable { compress, offsets: BTreeMap::new() }
+    }
 
-    pub fn new() -> Self {
-        DNSPacket {
-            header: DNSHeader {
-                id: rand::random::<u16>(),
-                flags: DNSFlags::default(),
-                question_count: 0,
-                answer_count: 0,
-                authority_count: 0,
-                additional_count: 0,
-            },
-            questions: Vec::new(),
+    // `out` must hold the packet from its first byte, offsets are taken from its length
+    fn write(&mut self, out: &mut Writer, name: &str) -> Result<(), DnsError> {
+        if !self.compress {
+            visit_labels(name, |label| {
+                out.put(&[label.len() as u8])?;
+                out.put(label)
+            })?;
+            return out.put(&[0]); // null terminator
         }
+
+        let labels = dns_labels(name)?;
+        for index in 0..labels.len() {
+            let suffix = &labels[index..];
+            if let Some(offset) = self.offsets.get(suffix) {
+                return out.put(&(0xC000 | *offset as u16).to_be_bytes());
+            }
+            if out.len <= MAX_POINTER_OFFSET {
+                self.offsets.insert(suffix.to_vec(), out.len);
+            }
+            out.put(&[labels[index].len() as u8])?;
+            out.put(&labels[index])?;
+        }
+        out.put(&[0]) // null terminator
     }
+}
 
-    pub fn add_question(&mut self, question: DNSQuestion) {
-        self.questions.push(question);
-        self.header.question_count += 1;
+// Writes into a caller-provided buffer, running out of room is an error rather than a panic
+struct Writer<'a> {
+    buf: &'a mut [u8],
+    len: usize,
+}
+
+impl<'a> Writer<'a> {
+    fn new(buf: &'a mut [u8]) -> Self {
+        Writer { buf, len: 0 }
     }
 
-    pub fn serialize(&self) -> Vec<u8> {
-        let mut bytes: Vec<u8> = Vec::from(self.header.serialize());
-        for question in self.questions.iter() {
-            bytes.extend_from_slice(&question.serialize().as_slice());
+    fn put(&mut self, bytes: &[u8]) -> Result<(), DnsError> {
+        let end = self.len + bytes.len();
+        let size = self.buf.len();
+        self.buf.get_mut(self.len..end)
+            .ok_or(DnsError::BufferTooSmall(size))?
+            .copy_from_slice(bytes);
+        self.len = end;
+        Ok(())
+    }
+}
+
+/* Runs `write` over a buffer of `size` bytes and keeps what was written.
+    Anything that doesn't fit is retried once with room for the largest possible message.
+ */
+fn to_vec<F>(size: usize, write: F) -> Result<Vec<u8>, DnsError>
+where
+    F: Fn(&mut Writer) -> Result<(), DnsError>,
+{
+    let mut bytes = vec![0; size];
+    let mut out = Writer::new(&mut bytes);
+    match write(&mut out) {
+        Err(DnsError::BufferTooSmall(_)) if size < MAX_MESSAGE_SIZE => to_vec(MAX_MESSAGE_SIZE, write),
+        result => {
+            result?;
+            let length = out.len;
+            bytes.truncate(length);
+            Ok(bytes)
+        },
+    }
+}
+
+// Resource record data, parsed according to the record type
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum RecordData {
+    A(Ipv4Addr),
+    Aaaa(Ipv6Addr),
+    Cname(String),
+    Ns(String),
+    Mx { pref: u16, exchange: String },
+    // Each character-string of the record as sent, bytes that needn't be UTF-8
+    Txt(#[cfg_attr(feature = "serde", serde(with = "crate::serde_support::character_strings"))] Vec<Vec<u8>>),
+    Soa(Soa),
+    Ptr(String),
+    Srv { priority: u16, weight: u16, port: u16, target: String },
+    Rrsig(Rrsig),
+    Dnskey(Dnskey),
+    Unknown {
+        rtype: u16,
+        #[cfg_attr(feature = "serde", serde(with = "crate::serde_support::hex"))]
+        data: Vec<u8>,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Soa {
+    pub mname: String,
+    pub rname: String,
+    pub serial: u32,
+    pub refresh: u32,
+    pub retry: u32,
+    pub expire: u32,
+    pub minimum: u32,
+}
+
+impl Soa {
+    /* How serial `a` compares to `b` in RFC 1982 sequence space arithmetic, which lets the
+        serial wrap past u32::MAX. Serials exactly 2^31 apart have no order, None.
+     */
+    pub fn compare_serials(a: u32, b: u32) -> Option<core::cmp::Ordering> {
+        match a.wrapping_sub(b) {
+            0 => Some(core::cmp::Ordering::Equal),
+            0x8000_0000 => None,
+            ahead if ahead < 0x8000_0000 => Some(core::cmp::Ordering::Greater),
+            _ => Some(core::cmp::Ordering::Less),
         }
-       bytes
     }
 
-    pub fn deserialize(bytes: &[u8]) -> Result<Self, String> {
-        let mut read_count: usize = 0;
-        if read_count + bytes.len() < HEADER_SIZE {
-            return Err(String::from("Packet size is too small. Expected: Header"));
+    pub fn serialize(&self) -> Result<Vec<u8>, DnsError> {
+        to_vec(UDP_MESSAGE_SIZE, |out| self.write(out, &mut NameTable::new(false)))
+    }
+
+    fn write(&self, out: &mut Writer, names: &mut NameTable) -> Result<(), DnsError> {
+        names.write(out, &self.mname)?;
+        names.write(out, &self.rname)?;
+        for field in [self.serial, self.refresh, self.retry, self.expire, self.minimum] {
+            out.put(&field.to_be_bytes())?;
         }
+        Ok(())
+    }
 
-        let header = DNSHeader::deserialize(&bytes[read_count..HEADER_SIZE])?;
-        read_count += HEADER_SIZE;
+    pub fn deserialize(bytes: &[u8], offset: usize, length: usize) -> Result<Self, DnsError> {
+        let (mname, mname_size) = deserialize_dns_str(bytes, offset)?;
+        let (rname, rname_size) = deserialize_dns_str(bytes, offset + mname_size)?;
+        let read_count = offset + mname_size + rname_size;
+        // 5 u32 fields follow the names
+        if read_count + 5 * 4 > offset + length {
+            return Err(DnsError::BadRecordLength { rtype: RecordType::SOA.value(), length });
+        }
 
-        let mut questions: Vec<DNSQuestion> = Vec::new();
-        
-        Ok(DNSPacket { header, questions})
+        Ok(Soa {
+            mname,
+            rname,
+            serial: read_u32(bytes, read_count)?,
+            refresh: read_u32(bytes, read_count + 4)?,
+            retry: read_u32(bytes, read_count + 8)?,
+            expire: read_u32(bytes, read_count + 12)?,
+            minimum: read_u32(bytes, read_count + 16)?,
+        })
     }
 }
 
-#[cfg(test)]
-mod tests {
+/* A signature over the records of one name and type (RFC 4034 section 3). Times are seconds
+    since the epoch, and `labels` counts those of the owner name without any wildcard.
+ */
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Rrsig {
+    pub type_covered: RecordType,
+    pub algorithm: u8,
+    pub labels: u8,
+    pub original_ttl: u32,
+    pub expiration: u32,
+    pub inception: u32,
+    pub key_tag: u16,
+    pub signer: String,
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_support::base64"))]
+    pub signature: Vec<u8>,
+}
 
-    #[test]
-    fn serialize_dns_str() {
-        assert_eq!(
-            crate::packet::serialize_dns_str(String::from("test.domain.com").as_str()),
-            hex_literal::hex!("04 74 65 73 74 06 64 6f 6d 61 69 6e 03 63 6f 6d 00")
-        );
-    }
-    #[test]
-    fn serialize_header() {
-        assert_eq!(
-            crate::packet::DNSHeader {
-                id: 0x1314,
-                flags: DNSFlags::default(),
-                question_count: 1, 
-                answer_count: 2, 
-                authority_count: 3, 
-                additional_count: 4, 
-            }.serialize(),
-            hex_literal::hex!("13 14 00 00 00 01 00 02 00 03 00 04")
-        );
+// Fields before the signer's name
+const RRSIG_FIXED_SIZE: usize = 18; // bytes
+
+impl Rrsig {
+    // The signer's name is never compressed (RFC 4034 section 3.1.7)
+    fn write(&self, out: &mut Writer) -> Result<(), DnsError> {
+        out.put(&self.type_covered.value().to_be_bytes())?;
+        out.put(&[self.algorithm, self.labels])?;
+        for field in [self.original_ttl, self.expiration, self.inception] {
+            out.put(&field.to_be_bytes())?;
+        }
+        out.put(&self.key_tag.to_be_bytes())?;
+        NameTable::new(false).write(out, &self.signer)?;
+        out.put(&self.signature)
     }
 
-    #[test]
-    fn serialize_question() {
-        assert_eq!(
-            crate::packet::DNSQuestion {
-                name: String::from("example.com"),
-                qtype: crate::packet::RecordType::A,
-            }.serialize(),
-            hex_literal::hex!("07 65 78 61 6d 70 6c 65 03 63 6f 6d 00 00 01 00 01")
-        );
+    pub fn deserialize(bytes: &[u8], offset: usize, length: usize) -> Result<Self, DnsError> {
+        let bad_length = || DnsError::BadRecordLength { rtype: RecordType::RRSIG.value(), length };
+        let data = read_bytes(bytes, offset, length)?;
+        if data.len() < RRSIG_FIXED_SIZE {
+            return Err(bad_length());
+        }
+        let (signer, signer_size) = deserialize_dns_str(bytes, offset + RRSIG_FIXED_SIZE)?;
+        let signature_size = length.checked_sub(RRSIG_FIXED_SIZE + signer_size).ok_or_else(bad_length)?;
+
+        Ok(Rrsig {
+            type_covered: RecordType::from_u16(read_u16(bytes, offset)?),
+            algorithm: data[2],
+            labels: data[3],
+            original_ttl: read_u32(bytes, offset + 4)?,
+            expiration: read_u32(bytes, offset + 8)?,
+            inception: read_u32(bytes, offset + 12)?,
+            key_tag: read_u16(bytes, offset + 16)?,
+            signer,
+            signature: read_bytes(bytes, offset + RRSIG_FIXED_SIZE + signer_size, signature_size)?.to_vec(),
+        })
     }
+}
 
-    #[test]
-    fn serialize_packet() {
-        let mut packet = crate::packet::DNSPacket::new();
-        
-        // set static packet ID for test
-        packet.header.id = 0xFFFF;
-        packet.add_question(crate::packet::DNSQuestion {
-            name: String::from("example.com"),
-            qtype: crate::packet::RecordType::A,
-        });
+// A public key signatures in the zone are checked against (RFC 4034 section 2)
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Dnskey {
+    // 256 for a zone key, 257 when it is also a secure entry point (KSK)
+    pub flags: u16,
+    // Always 3
+    pub protocol: u8,
+    pub algorithm: u8,
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_support::base64"))]
+    pub public_key: Vec<u8>,
+}
 
-        assert_eq!(
-            packet.serialize(),
-            hex_literal::hex!(
-                """ 
-                FF FF 00 00 00 01 00 00 00 00 00 00
-                07 65 78 61 6d 70 6c 65 03 63 6f 6d 00 00 01 00 01
-                """
-            )
-        );
+impl Dnskey {
+    fn write(&self, out: &mut Writer) -> Result<(), DnsError> {
+        out.put(&self.flags.to_be_bytes())?;
+        out.put(&[self.protocol, self.algorithm])?;
+        out.put(&self.public_key)
     }
 
-    #[test]
-    fn serialize_flags() {
-        let mut flags = crate::packet::DNSFlags::default();
-        flags.is_response = true;
-        flags.is_authoritative = true;
-        flags.recurse_available = true;
-        assert_eq!(flags.serialize(), 0x8480);
+    pub fn deserialize(bytes: &[u8], offset: usize, length: usize) -> Result<Self, DnsError> {
+        let data = read_bytes(bytes, offset, length)?;
+        if data.len() < 4 {
+            return Err(DnsError::BadRecordLength { rtype: RecordType::DNSKEY.value(), length });
+        }
+        Ok(Dnskey {
+            flags: u16::from_be_bytes([data[0], data[1]]),
+            protocol: data[2],
+            algorithm: data[3],
+            public_key: data[4..].to_vec(),
+        })
     }
 
-    #[test]
-    fn deserialize_flags() {
-        let mut flags = crate::packet::DNSFlags::default();
-        flags.is_response = true;
-        flags.is_authoritative = true;
-        flags.recurse_available = true;
-        assert_eq!(crate::packet::DNSFlags::from(0x8480), flags);
+    /* The tag RRSIGs name this key by (RFC 4034 appendix B), a checksum over the rdata.
+        Algorithm 1 (RSA/MD5) keys use another scheme that isn't implemented.
+     */
+    pub fn key_tag(&self) -> u16 {
+        let mut rdata: Vec<u8> = Vec::with_capacity(4 + self.public_key.len());
+        rdata.extend_from_slice(&self.flags.to_be_bytes());
+        rdata.extend_from_slice(&[self.protocol, self.algorithm]);
+        rdata.extend_from_slice(&self.public_key);
 
+        let mut sum: u32 = rdata.iter()
+            .enumerate()
+            .map(|(index, byte)| if index % 2 == 0 { (*byte as u32) << 8 } else { *byte as u32 })
+            .sum();
+        sum += (sum >> 16) & 0xFFFF;
+        (sum & 0xFFFF) as u16
     }
-}
\ No newline at end of file
+}
+
+impl RecordData {
+    // The rdata on the wire, names are written out in full (no compression)
+    pub fn serialize(&self) -> Result<Vec<u8>, DnsError> {
+        to_vec(UDP_MESSAGE_SIZE, |out| self.write(out, &mut NameTable::new(false)))
+    }
+
+    // Bytes `serialize` writes
+    pub fn serialized_len(&self) -> usize {
+        match self {
+            RecordData::A(_) => 4,
+            RecordData::Aaaa(_) => 16,
+            RecordData::Cname(name) | RecordData::Ns(name) | RecordData::Ptr(name) => name_len(name),
+            RecordData::Mx { exchange, .. } => 2 + name_len(exchange),
+            RecordData::Srv { target, .. } => 3 * 2 + name_len(target),
+            RecordData::Txt(strings) => strings.iter().map(|x| 1 + x.len()).sum(),
+            // 5 u32 fields follow the names
+            RecordData::Soa(soa) => name_len(&soa.mname) + name_len(&soa.rname) + 5 * 4,
+            RecordData::Rrsig(rrsig) => RRSIG_FIXED_SIZE + name_len(&rrsig.signer) + rrsig.signature.len(),
+            RecordData::Dnskey(dnskey) => 4 + dnskey.public_key.len(),
+            RecordData::Unknown { data, .. } => data.len(),
+        }
+    }
+
+    // Only the RFC 1035 types may have their rdata names compressed (RFC 3597 section 4)
+    fn write(&self, out: &mut Writer, names: &mut NameTable) -> Result<(), DnsError> {
+        match self {
+            RecordData::A(addr) => out.put(&addr.octets()),
+            RecordData::Aaaa(addr) => out.put(&addr.octets()),
+            RecordData::Cname(name) | RecordData::Ns(name) | RecordData::Ptr(name) => names.write(out, name),
+            RecordData::Mx { pref, exchange } => {
+                out.put(&pref.to_be_bytes())?;
+                names.write(out, exchange)
+            },
+            // SRV came after RFC 1035, its target is never compressed (RFC 2782)
+            RecordData::Srv { priority, weight, port, target } => {
+                for field in [priority, weight, port] {
+                    out.put(&field.to_be_bytes())?;
+                }
+                NameTable::new(false).write(out, target)
+            },
+            RecordData::Txt(strings) => {
+                for string in strings {
+                    // each string has a single length byte
+                    let length = u8::try_from(string.len())
+                        .map_err(|_| DnsError::BadRecordLength { rtype: RecordType::TXT.value(), length: string.len() })?;
+                    out.put(&[length])?;
+                    out.put(string)?;
+                }
+                Ok(())
+            },
+            RecordData::Soa(soa) => soa.write(out, names),
+            RecordData::Rrsig(rrsig) => rrsig.write(out),
+            RecordData::Dnskey(dnskey) => dnskey.write(out),
+            RecordData::Unknown { data, .. } => out.put(data),
+        }
+    }
+
+    // `bytes` is the whole packet so names in the rdata can be decompressed
+    pub fn deserialize(rtype: RecordType, bytes: &[u8], offset: usize, length: usize) -> Result<Self, DnsError> {
+        let data = read_bytes(bytes, offset, length)?;
+        let bad_length = || DnsError::BadRecordLength { rtype: rtype.value(), length };
+
+        match rtype {
+            RecordType::A => {
+                let octets: [u8; 4] = data.try_into().map_err(|_| bad_length())?;
+                Ok(RecordData::A(Ipv4Addr::from(octets)))
+            },
+            RecordType::AAAA => {
+                let octets: [u8; 16] = data.try_into().map_err(|_| bad_length())?;
+                Ok(RecordData::Aaaa(Ipv6Addr::from(octets)))
+            },
+            RecordType::CNAME => Ok(RecordData::Cname(deserialize_dns_str(bytes, offset)?.0)),
+            RecordType::NS => Ok(RecordData::Ns(deserialize_dns_str(bytes, offset)?.0)),
+            RecordType::PTR => Ok(RecordData::Ptr(deserialize_dns_str(bytes, offset)?.0)),
+            RecordType::MX => {
+                if length < 2 {
+                    return Err(bad_length());
+                }
+                let pref = read_u16(bytes, offset)?;
+                let (exchange, _) = deserialize_dns_str(bytes, offset + 2)?;
+                Ok(RecordData::Mx { pref, exchange })
+            },
+            RecordType::SRV => {
+                if length < 3 * 2 {
+                    return Err(bad_length());
+                }
+                let (target, _) = deserialize_dns_str(bytes, offset + 6)?;
+                Ok(RecordData::Srv {
+                    priority: read_u16(bytes, offset)?,
+                    weight: read_u16(bytes, offset + 2)?,
+                    port: read_u16(bytes, offset + 4)?,
+                    target,
+                })
+            },
+            RecordType::TXT => {
+                let mut strings: Vec<Vec<u8>> = Vec::new();
+                let mut position = 0;
+                while position < data.len() {
+                    let size = data[position] as usize;
+                    let string = data.get(position + 1..position + 1 + size).ok_or_else(bad_length)?;
+                    strings.push(string.to_vec());
+                    position += 1 + size;
+                }
+                Ok(RecordData::Txt(strings))
+            },
+            RecordType::SOA => Ok(RecordData::Soa(Soa::deserialize(bytes, offset, length)?)),
+            RecordType::RRSIG => Ok(RecordData::Rrsig(Rrsig::deserialize(bytes, offset, length)?)),
+            RecordType::DNSKEY => Ok(RecordData::Dnskey(Dnskey::deserialize(bytes, offset, length)?)),
+            _ => Ok(RecordData::Unknown { rtype: rtype.value(), data: data.to_vec() }),
+        }
+    }
+}
+
+// A resource record, from any of the answer, authority and additional sections
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct DNSRecord {
+    pub name: String,
+    pub rtype: RecordType,
+    pub class: u16,
+    pub ttl: u32,
+    pub data: RecordData,
+}
+
+// The name from when only answers were parsed
+pub type DNSAnswer = DNSRecord;
+
+impl DNSRecord {
+    /* An OPT pseudo-record for a query's additional section. It belongs to the root, its class
+        is the largest UDP response the sender accepts and its TTL carries the EDNS flags.
+     */
+    pub fn opt(udp_payload: u16, dnssec_ok: bool) -> Self {
+        DNSRecord {
+            name: String::new(),
+            rtype: RecordType::OPT,
+            class: udp_payload,
+            ttl: if dnssec_ok { EDNS_DNSSEC_OK } else { 0 },
+            data: RecordData::Unknown { rtype: RecordType::OPT.value(), data: Vec::new() },
+        }
+    }
+
+    pub fn serialize(&self) -> Result<Vec<u8>, DnsError> {
+        to_vec(UDP_MESSAGE_SIZE, |out| self.write(out, &mut NameTable::new(false)))
+    }
+
+    // Bytes the record takes on the wire with every name written out in full
+    pub fn serialized_len(&self) -> usize {
+        // type, class, ttl and rdlength sit between the name and the rdata
+        name_len(&self.name) + 2 + 2 + 4 + 2 + self.data.serialized_len()
+    }
+
+    // The TTL left once the record has been held for `elapsed`, counted in whole seconds down to 0
+    pub fn remaining_ttl(&self, elapsed: core::time::Duration) -> u32 {
+        self.ttl.saturating_sub(u32::try_from(elapsed.as_secs()).unwrap_or(u32::MAX))
+    }
+
+    fn write(&self, out: &mut Writer, names: &mut NameTable) -> Result<(), DnsError> {
+        names.write(out, &self.name)?;
+        // type, class, ttl, rdlength, then the rdata itself
+        out.put(&self.rtype.value().to_be_bytes())?;
+        out.put(&self.class.to_be_bytes())?;
+        out.put(&self.ttl.to_be_bytes())?;
+        let length_at = out.len;
+        out.put(&[0, 0])?;
+
+        // rdlength is only known once the (possibly compressed) rdata is written
+        self.data.write(out, names)?;
+        let length = out.len - length_at - 2;
+        let length = u16::try_from(length)
+            .map_err(|_| DnsError::BadRecordLength { rtype: self.rtype.value(), length })?;
+        out.buf[length_at..length_at + 2].copy_from_slice(&length.to_be_bytes());
+        Ok(())
+    }
+
+    // Returns the record and the number of bytes it occupied at `offset`
+    pub fn deserialize(bytes: &[u8], offset: usize) -> Result<(Self, usize), DnsError> {
+        let (record, size) = RecordRef::parse(bytes, offset)?;
+        Ok((record.to_owned()?, size))
+    }
+}
+
+// Checked reads: running off the end of the packet is an error, never a panic
+pub(crate) fn read_bytes(bytes: &[u8], offset: usize, length: usize) -> Result<&[u8], DnsError> {
+    offset.checked_add(length)
+        .and_then(|end| bytes.get(offset..end))
+        .ok_or(DnsError::TooShort { offset, expected: length })
+}
+
+pub(crate) fn read_u8(bytes: &[u8], offset: usize) -> Result<u8, DnsError> {
+    Ok(read_bytes(bytes, offset, 1)?[0])
+}
+
+pub(crate) fn read_u16(bytes: &[u8], offset: usize) -> Result<u16, DnsError> {
+    let field: [u8; 2] = read_bytes(bytes, offset, 2)?.try_into()
+        .map_err(|_| DnsError::TooShort { offset, expected: 2 })?;
+    Ok(u16::from_be_bytes(field))
+}
+
+pub(crate) fn read_u32(bytes: &[u8], offset: usize) -> Result<u32, DnsError> {
+    let field: [u8; 4] = read_bytes(bytes, offset, 4)?.try_into()
+        .map_err(|_| DnsError::TooShort { offset, expected: 4 })?;
+    Ok(u32::from_be_bytes(field))
+}
+
+/* Reads a (possibly compressed) name starting at `offset` of the full packet.
+    Returns the dotted name and the number of bytes it occupied at `offset`,
+    which stops at the first compression pointer.
+ */
+pub fn deserialize_dns_str(bytes: &[u8], offset: usize) -> Result<(String, usize), DnsError> {
+    let (name, size) = NameRef::parse(bytes, offset)?;
+    Ok((name.to_string(), size))
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct SerializeOptions {
+    // Replace repeated name suffixes with pointers to where they were first written
+    pub compress: bool,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct DNSPacket {
+    pub header: DNSHeader,
+    pub(crate) questions: Vec<DNSQuestion>,
+    pub(crate) answers: Vec<DNSRecord>,
+    pub(crate) authority: Vec<DNSRecord>,
+    pub(crate) additional: Vec<DNSRecord>,
+}
+
+#[cfg(feature = "std")]
+impl Default for DNSPacket {
+    fn default() -> Self {
+        DNSPacket::new()
+    }
+}
+
+impl DNSPacket {
+    // Random transaction id, without std use `with_id` with an id from the platform's RNG
+    #[cfg(feature = "std")]
+    pub fn new() -> Self {
+        DNSPacket::with_id(rand::random::<u16>())
+    }
+
+    // Caller-supplied transaction id (deterministic tests, reproducible captures)
+    pub fn with_id(id: u16) -> Self {
+        DNSPacket {
+            header: DNSHeader {
+                id,
+                flags: DNSFlags::default(),
+                question_count: 0,
+                answer_count: 0,
+                authority_count: 0,
+                additional_count: 0,
+            },
+            questions: Vec::new(),
+            answers: Vec::new(),
+            authority: Vec::new(),
+            additional: Vec::new(),
+        }
+    }
+
+    /// Sections only grow through these, which keeps the header's counts in step. A count is
+    /// 16 bits, so adding a 65536th entry to a section panics rather than wrapping it to 0:
+    ///
+    /// ```
+    /// use std::net::Ipv4Addr;
+    ///
+    /// use bkdns::packet::{DNSPacket, DNSQuestion, DNSRecord, RecordData, RecordType, RECORD_CLASS};
+    ///
+    /// let mut response = DNSPacket::with_id(0x4d2a);
+    /// response.add_question(DNSQuestion::new(String::from("example.com"), RecordType::A).unwrap());
+    /// response.add_answer(DNSRecord {
+    ///     name: String::from("example.com"),
+    ///     rtype: RecordType::A,
+    ///     class: RECORD_CLASS,
+    ///     ttl: 300,
+    ///     data: RecordData::A(Ipv4Addr::new(192, 0, 2, 1)),
+    /// });
+    ///
+    /// let parsed = DNSPacket::deserialize(&response.serialize().unwrap()).unwrap();
+    /// assert_eq!(parsed.header.id(), 0x4d2a);
+    /// assert_eq!(parsed.header.answer_count(), 1);
+    /// assert_eq!(parsed.questions()[0].name(), "example.com");
+    /// assert_eq!(parsed.answers()[0].data, RecordData::A(Ipv4Addr::new(192, 0, 2, 1)));
+    /// assert!(parsed.authority().is_empty() && parsed.additional().is_empty());
+    /// ```
+    pub fn add_question(&mut self, question: DNSQuestion) {
+        increment(&mut self.header.question_count, "questions");
+        self.questions.push(question);
+    }
+
+    pub fn add_answer(&mut self, record: DNSRecord) {
+        increment(&mut self.header.answer_count, "answers");
+        self.answers.push(record);
+    }
+
+    pub fn add_authority(&mut self, record: DNSRecord) {
+        increment(&mut self.header.authority_count, "authority records");
+        self.authority.push(record);
+    }
+
+    pub fn add_additional(&mut self, record: DNSRecord) {
+        increment(&mut self.header.additional_count, "additional records");
+        self.additional.push(record);
+    }
+
+    pub fn questions(&self) -> &[DNSQuestion] {
+        &self.questions
+    }
+
+    pub fn answers(&self) -> &[DNSRecord] {
+        &self.answers
+    }
+
+    pub fn authority(&self) -> &[DNSRecord] {
+        &self.authority
+    }
+
+    pub fn additional(&self) -> &[DNSRecord] {
+        &self.additional
+    }
+
+    /* The full 12-bit reply code: the header's 4 bits with the 8 above them from the top of
+        the OPT record's TTL (RFC 6891 section 6.1.3), when the response has one
+     */
+    pub fn reply_code(&self) -> ReplyCode {
+        let extended = self.additional.iter()
+            .find(|x| x.rtype == RecordType::OPT)
+            .map_or(0, |x| (x.ttl >> 24) as u16);
+        ReplyCode::from_u16(extended << 4 | self.header.flags.raw_reply_code() as u16)
+    }
+
+    // Splits `code` the same way, adding an OPT record for the upper bits when there's none
+    pub fn set_reply_code(&mut self, code: ReplyCode) {
+        let value = code.value();
+        self.header.flags.reply_code = (value & 0xF) as u8;
+        let extended = (value >> 4) as u32;
+        match self.additional.iter_mut().find(|x| x.rtype == RecordType::OPT) {
+            Some(opt) => opt.ttl = opt.ttl & 0x00FF_FFFF | extended << 24,
+            None if extended != 0 => {
+                let mut opt = DNSRecord::opt(UDP_MESSAGE_SIZE as u16, false);
+                opt.ttl |= extended << 24;
+                self.add_additional(opt);
+            },
+            None => {},
+        }
+    }
+
+    /* Drops whole records from the end until `serialize_with(options)` fits in `limit` bytes:
+        additional records first, apart from the OPT, then authority and then answers. Losing any
+        answer or authority record sets TC so the client asks again over TCP (RFC 2181 section 9),
+        the additional section can go without it. The header's counts follow the sections.
+     */
+    pub fn truncate_to(&mut self, limit: usize, options: &SerializeOptions) {
+        // what compression saves depends on the records left, so it's measured by writing them
+        let size = |packet: &DNSPacket| match options.compress {
+            true => packet.serialize_with(options).map_or_else(|_| packet.wire_len(), |x| x.len()),
+            false => packet.wire_len(),
+        };
+        while size(self) > limit {
+            if let Some(index) = self.additional.iter().rposition(|x| x.rtype != RecordType::OPT) {
+                self.header.additional_count = self.header.additional_count.saturating_sub(1);
+                self.additional.remove(index);
+            } else if self.authority.pop().is_some() {
+                self.header.authority_count = self.header.authority_count.saturating_sub(1);
+                self.header.flags.is_truncated = true;
+            } else if self.answers.pop().is_some() {
+                self.header.answer_count = self.header.answer_count.saturating_sub(1);
+                self.header.flags.is_truncated = true;
+            } else {
+                break;
+            }
+        }
+    }
+
+    pub fn serialize(&self) -> Result<Vec<u8>, DnsError> {
+        self.serialize_with(&SerializeOptions::default())
+    }
+
+    /* Bytes `serialize` writes. Compression only ever shortens a packet, so this is also
+        the most `serialize_with` can need.
+     */
+    pub fn wire_len(&self) -> usize {
+        HEADER_SIZE
+            + self.questions.iter().map(|x| x.serialized_len()).sum::<usize>()
+            + self.answers.iter()
+                .chain(&self.authority)
+                .chain(&self.additional)
+                .map(|x| x.serialized_len())
+                .sum::<usize>()
+    }
+
+    pub fn serialize_with(&self, options: &SerializeOptions) -> Result<Vec<u8>, DnsError> {
+        to_vec(self.wire_len().min(MAX_MESSAGE_SIZE), |out| self.write(out, options))
+    }
+
+    // Returns the number of bytes written to the start of `buf`, nothing is allocated
+    pub fn serialize_into(&self, buf: &mut [u8]) -> Result<usize, DnsError> {
+        self.serialize_into_with(buf, &SerializeOptions::default())
+    }
+
+    pub fn serialize_into_with(&self, buf: &mut [u8], options: &SerializeOptions) -> Result<usize, DnsError> {
+        let mut out = Writer::new(buf);
+        self.write(&mut out, options)?;
+        Ok(out.len)
+    }
+
+    // The header counts written are always the section sizes, whatever `header` holds
+    fn write(&self, out: &mut Writer, options: &SerializeOptions) -> Result<(), DnsError> {
+        let count = |size: usize| u16::try_from(size).map_err(|_| DnsError::TooManyRecords(size));
+        let header = DNSHeader {
+            id: self.header.id,
+            flags: self.header.flags.clone(),
+            question_count: count(self.questions.len())?,
+            answer_count: count(self.answers.len())?,
+            authority_count: count(self.authority.len())?,
+            additional_count: count(self.additional.len())?,
+        };
+
+        let mut names = NameTable::new(options.compress);
+        out.put(&header.to_bytes())?;
+        for question in self.questions.iter() {
+            question.write(out, &mut names)?;
+        }
+        for record in self.answers.iter().chain(&self.authority).chain(&self.additional) {
+            record.write(out, &mut names)?;
+        }
+        Ok(())
+    }
+
+    // Every section is parsed whatever the reply code, error responses still carry the authority SOA
+    pub fn deserialize(bytes: &[u8]) -> Result<Self, DnsError> {
+        DNSPacketRef::deserialize(bytes)?.to_owned()
+    }
+
+    // A recursive query for one name and type, for anything more see `QueryBuilder`
+    pub fn query(name: &str, rtype: RecordType) -> Result<Self, DnsError> {
+        QueryBuilder::new().question(name, rtype).build()
+    }
+}
+
+// One more in a header count, which has to stay within 16 bits like the wire format
+fn increment(count: &mut u16, section: &str) {
+    *count = count.checked_add(1)
+        .unwrap_or_else(|| panic!("a packet can't hold more than {} {}", u16::MAX, section));
+}
+
+/* UDP payload the resolvers offer unless told otherwise, and the one offered when DNSSEC records
+    are asked for without an EDNS size of its own. 1232 bytes fits in one packet on any IPv6 path,
+    so big answers come back whole without fragmenting or falling back to TCP.
+ */
+pub const DEFAULT_EDNS_PAYLOAD: u16 = 1232; // bytes
+
+/// Queries a step at a time. Recursion is on unless turned off, and names are only checked
+/// in `build`, where the first bad one is the error:
+///
+/// ```
+/// use bkdns::packet::{QueryBuilder, RecordType};
+///
+/// let query = QueryBuilder::new()
+///     .id(0x1234)
+///     .question("example.com", RecordType::A)
+///     .question("example.com", RecordType::AAAA)
+///     .build()
+///     .unwrap();
+/// assert_eq!(query.header.id(), 0x1234);
+/// assert_eq!(query.header.question_count(), 2);
+/// assert!(query.header.flags.recurse_desired);
+///
+/// assert!(QueryBuilder::new().question("bad..name", RecordType::A).build().is_err());
+/// ```
+///
+/// A DNSSEC query carries an OPT record with the DO bit set:
+///
+/// ```
+/// use bkdns::packet::{QueryBuilder, RecordType, EDNS_DNSSEC_OK};
+///
+/// let query = QueryBuilder::new()
+///     .question("example.com", RecordType::DNSKEY)
+///     .edns(4096)
+///     .dnssec(true)
+///     .build()
+///     .unwrap();
+/// let opt = &query.additional()[0];
+/// assert_eq!((opt.rtype, opt.class), (RecordType::OPT, 4096));
+/// assert_eq!(opt.ttl & EDNS_DNSSEC_OK, EDNS_DNSSEC_OK);
+/// ```
+#[derive(Debug, Clone)]
+pub struct QueryBuilder {
+    id: Option<u16>,
+    recursion: bool,
+    questions: Vec<(String, RecordType)>,
+    edns: Option<u16>,
+    dnssec: bool,
+    checking_disabled: bool,
+}
+
+impl QueryBuilder {
+    pub fn new() -> Self {
+        QueryBuilder {
+            id: None,
+            recursion: true,
+            questions: Vec::new(),
+            edns: None,
+            dnssec: false,
+            checking_disabled: false,
+        }
+    }
+
+    // Random when unset, and 0 without std, which has no RNG to draw it from
+    pub fn id(mut self, id: u16) -> Self {
+        self.id = Some(id);
+        self
+    }
+
+    pub fn recursion(mut self, recursion: bool) -> Self {
+        self.recursion = recursion;
+        self
+    }
+
+    // Questions go in the order added, most servers only answer one
+    pub fn question(mut self, name: &str, rtype: RecordType) -> Self {
+        self.questions.push((String::from(name), rtype));
+        self
+    }
+
+    // The largest UDP response accepted, sent in an OPT record
+    pub fn edns(mut self, payload: u16) -> Self {
+        self.edns = Some(payload);
+        self
+    }
+
+    // Asks for signatures with the DO bit, with EDNS at DEFAULT_EDNS_PAYLOAD unless set
+    pub fn dnssec(mut self, dnssec: bool) -> Self {
+        self.dnssec = dnssec;
+        self
+    }
+
+    // Sets CD, so a validating resolver answers even what fails validation
+    pub fn checking_disabled(mut self, checking_disabled: bool) -> Self {
+        self.checking_disabled = checking_disabled;
+        self
+    }
+
+    pub fn build(self) -> Result<DNSPacket, DnsError> {
+        #[cfg(feature = "std")]
+        let id = self.id.unwrap_or_else(rand::random::<u16>);
+        #[cfg(not(feature = "std"))]
+        let id = self.id.unwrap_or(0);
+
+        let mut packet = DNSPacket::with_id(id);
+        packet.header.flags.recurse_desired = self.recursion;
+        packet.header.flags.checking_disabled = self.checking_disabled;
+        for (name, rtype) in self.questions {
+            packet.add_question(DNSQuestion::new(name, rtype)?);
+        }
+        match (self.edns, self.dnssec) {
+            (Some(payload), dnssec) => packet.add_additional(DNSRecord::opt(payload, dnssec)),
+            (None, true) => packet.add_additional(DNSRecord::opt(DEFAULT_EDNS_PAYLOAD, true)),
+            (None, false) => {},
+        }
+        Ok(packet)
+    }
+}
+
+impl Default for QueryBuilder {
+    fn default() -> Self {
+        QueryBuilder::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    #[test]
+    fn serialize_dns_str() {
+        assert_eq!(
+            crate::packet::serialize_dns_str(String::from("test.domain.com").as_str()).unwrap(),
+            hex_literal::hex!("04 74 65 73 74 06 64 6f 6d 61 69 6e 03 63 6f 6d 00")
+        );
+    }
+    #[test]
+    fn serialize_dns_str_preserves_case() {
+        assert_eq!(
+            crate::packet::serialize_dns_str("ExAmPle.COM").unwrap(),
+            hex_literal::hex!("07 45 78 41 6d 50 6c 65 03 43 4f 4d 00")
+        );
+    }
+
+    #[test]
+    fn question_case_round_trip() {
+        let bytes = crate::packet::DNSQuestion::new(
+            String::from("wWw.ExAmPle.CoM"), crate::packet::RecordType::A
+        ).unwrap().serialize().unwrap();
+        let (question, size) = crate::packet::DNSQuestion::deserialize(&bytes, 0).unwrap();
+        assert_eq!(size, bytes.len());
+        assert_eq!(question.name(), "wWw.ExAmPle.CoM");
+        assert_eq!(question.serialize().unwrap(), bytes);
+    }
+
+    #[test]
+    fn serialize_dns_str_root() {
+        assert_eq!(crate::packet::serialize_dns_str(".").unwrap(), vec![0]);
+        assert_eq!(crate::packet::serialize_dns_str("").unwrap(), vec![0]);
+    }
+
+    #[test]
+    fn serialize_dns_str_trailing_dot() {
+        assert_eq!(
+            crate::packet::serialize_dns_str("example.com.").unwrap(),
+            crate::packet::serialize_dns_str("example.com").unwrap()
+        );
+    }
+
+    #[test]
+    fn question_trailing_dot_stripped() {
+        let question = crate::packet::DNSQuestion::new(
+            String::from("example.com."), crate::packet::RecordType::A
+        ).unwrap();
+        assert_eq!(question.name(), "example.com");
+    }
+
+    #[test]
+    fn question_empty_label() {
+        for name in ["foo..com", ".com", "..", "com.."] {
+            assert!(matches!(
+                crate::packet::DNSQuestion::new(String::from(name), crate::packet::RecordType::A),
+                Err(crate::error::DnsError::EmptyLabel(_))
+            ), "{}", name);
+        }
+    }
+
+    #[test]
+    fn question_label_too_long() {
+        let longest = "a".repeat(63);
+        assert!(crate::packet::DNSQuestion::new(
+            format!("{}.com", longest), crate::packet::RecordType::A
+        ).is_ok());
+        assert!(matches!(
+            crate::packet::DNSQuestion::new(format!("a{}.com", longest), crate::packet::RecordType::A),
+            Err(crate::error::DnsError::LabelTooLong(_))
+        ));
+        // the length byte can't silently wrap either
+        assert!(crate::packet::serialize_dns_str(&"a".repeat(300)).is_err());
+    }
+
+    #[test]
+    fn question_name_too_long() {
+        // 4 * (1 + 62) + 1 = 253 bytes on the wire, one more label pushes it past 255
+        let label = "a".repeat(62);
+        let longest = [label.as_str(); 4].join(".");
+        assert!(crate::packet::DNSQuestion::new(longest.clone(), crate::packet::RecordType::A).is_ok());
+        assert!(matches!(
+            crate::packet::DNSQuestion::new(format!("{}.ab", longest), crate::packet::RecordType::A),
+            Err(crate::error::DnsError::NameTooLong(256))
+        ));
+    }
+
+    #[test]
+    fn question_non_ascii() {
+        let question = crate::packet::DNSQuestion::new(
+            String::from("bücher.example"), crate::packet::RecordType::A
+        ).unwrap();
+        assert_eq!(question.name(), "xn--bcher-kva.example");
+        assert!(matches!(
+            crate::packet::serialize_dns_str("bücher.example"),
+            Err(crate::error::DnsError::NonAsciiName(_))
+        ));
+    }
+
+    #[test]
+    fn serialize_dns_str_rejects_non_ascii() {
+        // the wire form is only for names already converted to A-labels
+        assert!(matches!(
+            crate::packet::serialize_dns_str("münchen.de"),
+            Err(crate::error::DnsError::NonAsciiName(name)) if name == "münchen.de"
+        ));
+        assert_eq!(
+            crate::packet::serialize_dns_str(&crate::names::to_ascii("münchen.de").unwrap()).unwrap(),
+            crate::packet::serialize_dns_str("xn--mnchen-3ya.de").unwrap()
+        );
+    }
+
+    #[test]
+    fn serialize_header() {
+        assert_eq!(
+            crate::packet::DNSHeader {
+                id: 0x1314,
+                flags: crate::packet::DNSFlags::default(),
+                question_count: 1, 
+                answer_count: 2, 
+                authority_count: 3, 
+                additional_count: 4, 
+            }.serialize(),
+            hex_literal::hex!("13 14 00 00 00 01 00 02 00 03 00 04")
+        );
+    }
+
+    #[test]
+    fn serialize_question() {
+        assert_eq!(
+            crate::packet::DNSQuestion {
+                name: String::from("example.com"),
+                qtype: crate::packet::RecordType::A,
+                qclass: crate::packet::RECORD_CLASS,
+                unicast_response: false,
+            }.serialize().unwrap(),
+            hex_literal::hex!("07 65 78 61 6d 70 6c 65 03 63 6f 6d 00 00 01 00 01")
+        );
+
+        // other classes are kept, like CH for version.bind
+        let question = crate::packet::DNSQuestion::new(String::from("version.bind"), crate::packet::RecordType::TXT)
+            .unwrap()
+            .with_qclass(3);
+        let bytes = question.serialize().unwrap();
+        assert_eq!(&bytes[bytes.len() - 4..], hex_literal::hex!("00 10 00 03"));
+        assert_eq!(crate::packet::DNSQuestion::deserialize(&bytes, 0).unwrap(), (question, bytes.len()));
+
+        // the QU bit goes on top of the class, and comes back when parsed
+        let question = crate::packet::DNSQuestion::new(String::from("printer.local"), crate::packet::RecordType::A)
+            .unwrap()
+            .with_unicast_response(true);
+        let bytes = question.serialize().unwrap();
+        assert_eq!(bytes, hex_literal::hex!("07 70 72 69 6e 74 65 72 05 6c 6f 63 61 6c 00 00 01 80 01"));
+        assert_eq!(crate::packet::DNSQuestion::deserialize(&bytes, 0).unwrap(), (question, bytes.len()));
+    }
+
+    #[test]
+    fn question_serialized_len() {
+        for name in ["example.com", "example.com.", "", "a\\.b.example", "\\065bc.example"] {
+            let question = crate::packet::DNSQuestion::new(String::from(name), crate::packet::RecordType::A).unwrap();
+            assert_eq!(question.serialized_len(), question.serialize().unwrap().len(), "{}", name);
+        }
+    }
+
+    #[test]
+    fn wire_len() {
+        let mut query = crate::packet::DNSPacket::with_id(0x1234);
+        assert_eq!(query.wire_len(), query.serialize().unwrap().len());
+        query.add_question(crate::packet::DNSQuestion::new(String::from("example.com"), crate::packet::RecordType::A).unwrap());
+        assert_eq!(query.wire_len(), query.serialize().unwrap().len());
+
+        for bytes in [&crate::testdata::CNAME_RESPONSE[..], &crate::testdata::NXDOMAIN_RESPONSE, &crate::testdata::DNSSEC_RESPONSE] {
+            let packet = crate::packet::DNSPacket::deserialize(bytes).unwrap();
+            assert_eq!(packet.wire_len(), packet.serialize().unwrap().len());
+            let compressed = packet.serialize_with(&crate::packet::SerializeOptions { compress: true }).unwrap();
+            assert!(compressed.len() <= packet.wire_len());
+        }
+    }
+
+    #[test]
+    fn serialize_packet() {
+        let mut packet = crate::packet::DNSPacket::with_id(0xFFFF);
+        packet.add_question(crate::packet::DNSQuestion {
+            name: String::from("example.com"),
+            qtype: crate::packet::RecordType::A,
+            qclass: crate::packet::RECORD_CLASS,
+            unicast_response: false,
+        });
+
+        assert_eq!(
+            packet.serialize().unwrap(),
+            hex_literal::hex!(
+                """ 
+                FF FF 00 00 00 01 00 00 00 00 00 00
+                07 65 78 61 6d 70 6c 65 03 63 6f 6d 00 00 01 00 01
+                """
+            )
+        );
+    }
+
+    #[test]
+    fn serialize_response() {
+        let expected = crate::testdata::A_RESPONSE;
+
+        let mut packet = crate::packet::DNSPacket::with_id(0x4d2a);
+        packet.header.flags.is_response = true;
+        packet.header.flags.recurse_desired = true;
+        packet.header.flags.recurse_available = true;
+        packet.add_question(crate::packet::DNSQuestion::new(
+            String::from("example.com"), crate::packet::RecordType::A
+        ).unwrap());
+        packet.add_answer(crate::packet::DNSRecord {
+            name: String::from("example.com"),
+            rtype: crate::packet::RecordType::A,
+            class: crate::packet::RECORD_CLASS,
+            ttl: 3600,
+            data: crate::packet::RecordData::A(std::net::Ipv4Addr::new(93, 184, 216, 34)),
+        });
+        assert_eq!(crate::packet::DNSPacket::deserialize(&expected).unwrap(), packet);
+        let compressed = crate::packet::SerializeOptions { compress: true };
+        assert_eq!(packet.serialize_with(&compressed).unwrap(), expected);
+
+        // the same bytes, except the answer name is written out instead of pointing at the question
+        assert_eq!(
+            packet.serialize().unwrap(),
+            hex_literal::hex!(
+                """
+                4d 2a 81 80 00 01 00 01 00 00 00 00
+                07 65 78 61 6d 70 6c 65 03 63 6f 6d 00 00 01 00 01
+                07 65 78 61 6d 70 6c 65 03 63 6f 6d 00 00 01 00 01 00 00 0e 10 00 04 5d b8 d8 22
+                """
+            )
+        );
+    }
+
+    #[test]
+    fn serialize_compressed() {
+        use crate::packet::{DNSRecord, RecordData, RecordType, RECORD_CLASS};
+
+        let record = |name: &str, rtype: RecordType, data: RecordData| DNSRecord {
+            name: String::from(name), rtype, class: RECORD_CLASS, ttl: 300, data,
+        };
+        let mut packet = crate::packet::DNSPacket::with_id(0x0001);
+        packet.add_answer(record("www.example.com", RecordType::A, RecordData::A(std::net::Ipv4Addr::LOCALHOST)));
+        packet.add_answer(record("mail.example.com", RecordType::A, RecordData::A(std::net::Ipv4Addr::LOCALHOST)));
+        packet.add_answer(record("example.com", RecordType::MX, RecordData::Mx {
+            pref: 10, exchange: String::from("mail.example.com"),
+        }));
+        packet.add_answer(record("ftp.EXAMPLE.com", RecordType::CNAME, RecordData::Cname(String::from("www.example.com"))));
+
+        let plain = packet.serialize().unwrap();
+        let compressed = packet.serialize_with(&crate::packet::SerializeOptions { compress: true }).unwrap();
+        assert!(compressed.len() < plain.len());
+        // the parser refuses pointers that don't go backwards
+        assert_eq!(crate::packet::DNSPacket::deserialize(&compressed).unwrap(), packet);
+        // everything after the first record points back into it, case differences stop at "com"
+        assert_eq!(compressed[12 + 31..], hex_literal::hex!(
+            """
+            04 6d 61 69 6c c0 10 00 01 00 01 00 00 01 2c 00 04 7f 00 00 01
+            c0 10 00 0f 00 01 00 00 01 2c 00 04 00 0a c0 2b
+            03 66 74 70 07 45 58 41 4d 50 4c 45 c0 18 00 05 00 01 00 00 01 2c 00 02 c0 0c
+            """
+        ));
+    }
+
+    #[test]
+    fn srv_rdata() {
+        use crate::packet::{RecordData, RecordType};
+
+        let srv = RecordData::Srv { priority: 0, weight: 5, port: 631, target: String::from("printer.local") };
+        let bytes = hex_literal::hex!("0000 0005 0277 07 7072696e746572 05 6c6f63616c 00");
+        assert_eq!(srv.serialize().unwrap(), bytes);
+        assert_eq!(srv.serialized_len(), bytes.len());
+        assert_eq!(RecordData::deserialize(RecordType::SRV, &bytes, 0, bytes.len()).unwrap(), srv);
+        assert!(RecordData::deserialize(RecordType::SRV, &bytes, 0, 5).is_err());
+
+        // left uncompressed even when the owner name could be pointed at
+        let mut packet = crate::packet::DNSPacket::with_id(0x0001);
+        packet.add_answer(crate::packet::DNSRecord {
+            name: String::from("printer.local"),
+            rtype: RecordType::SRV,
+            class: crate::packet::RECORD_CLASS,
+            ttl: 120,
+            data: srv,
+        });
+        let compressed = packet.serialize_with(&crate::packet::SerializeOptions { compress: true }).unwrap();
+        assert!(compressed.ends_with(&bytes));
+        assert_eq!(crate::packet::DNSPacket::deserialize(&compressed).unwrap(), packet);
+    }
+
+    #[test]
+    fn serialize_compressed_pointer_range() {
+        use crate::packet::{DNSRecord, RecordData, RecordType, RECORD_CLASS};
+
+        let record = |name: &str, data: RecordData| DNSRecord {
+            name: String::from(name), rtype: RecordType::TXT, class: RECORD_CLASS, ttl: 300, data,
+        };
+        let mut packet = crate::packet::DNSPacket::with_id(0x0001);
+        // pad the packet past the 14 bit pointer range
+        for _ in 0..70 {
+            packet.add_answer(record("pad.example.com", RecordData::Txt(vec![vec![b'x'; 255]])));
+        }
+        packet.add_answer(record("late.example.org", RecordData::Txt(vec![])));
+        packet.add_answer(record("late.example.org", RecordData::Txt(vec![])));
+
+        let bytes = packet.serialize_with(&crate::packet::SerializeOptions { compress: true }).unwrap();
+        assert!(bytes.len() > 0x4000);
+        // the second name can't point at the first, it's out of reach
+        let tail = hex_literal::hex!("04 6c 61 74 65 07 65 78 61 6d 70 6c 65 03 6f 72 67 00 00 10 00 01 00 00 01 2c 00 00");
+        assert_eq!(bytes[bytes.len() - 2 * tail.len()..], [tail, tail].concat());
+        assert_eq!(crate::packet::DNSPacket::deserialize(&bytes).unwrap(), packet);
+    }
+
+    #[test]
+    fn serialize_counts_follow_sections() {
+        let mut packet = crate::packet::DNSPacket::with_id(0x0001);
+        packet.answers.push(crate::packet::DNSRecord {
+            name: String::from("example.com"),
+            rtype: crate::packet::RecordType::A,
+            class: crate::packet::RECORD_CLASS,
+            ttl: 60,
+            data: crate::packet::RecordData::A(std::net::Ipv4Addr::LOCALHOST),
+        });
+        packet.header.additional_count = 7;
+        assert_eq!(packet.serialize().unwrap()[..12], hex_literal::hex!("00 01 00 00 00 00 00 01 00 00 00 00"));
+    }
+
+    #[test]
+    fn full_response_round_trip() {
+        // two answers, seven authority and thirteen additional records
+        let packet = crate::packet::DNSPacket::deserialize(&crate::testdata::DNSSEC_RESPONSE).unwrap();
+        let bytes = packet.serialize().unwrap();
+        assert_eq!(crate::packet::DNSPacket::deserialize(&bytes).unwrap(), packet);
+
+        // sections go out in wire order: questions, answers, authority, additional
+        let layout = crate::packet_ref::DNSPacketRef::deserialize(&bytes).unwrap().layout().clone();
+        assert!(layout.questions.end == layout.answers.start && layout.answers.end == layout.authority.start);
+        assert!(layout.authority.end == layout.additional.start && layout.additional.end == bytes.len());
+    }
+
+    #[test]
+    fn serialize_into() {
+        let mut packet = crate::packet::DNSPacket::with_id(0xFFFF);
+        packet.add_question(crate::packet::DNSQuestion::new(
+            String::from("example.com"), crate::packet::RecordType::A
+        ).unwrap());
+        let expected = packet.serialize().unwrap();
+
+        let mut buf = [0xAA; 64];
+        assert_eq!(packet.serialize_into(&mut buf).unwrap(), expected.len());
+        assert_eq!(buf[..expected.len()], expected);
+        assert!(buf[expected.len()..].iter().all(|x| *x == 0xAA));
+
+        let question = &packet.questions()[0];
+        assert_eq!(question.serialize_into(&mut buf).unwrap(), 17);
+        assert_eq!(packet.header.serialize_into(&mut buf).unwrap(), 12);
+
+        // every buffer that's too small is refused, none of them panic
+        for size in 0..expected.len() {
+            assert!(matches!(
+                packet.serialize_into(&mut buf[..size]),
+                Err(crate::error::DnsError::BufferTooSmall(x)) if x == size
+            ));
+        }
+        assert!(question.serialize_into(&mut buf[..16]).is_err());
+        assert!(packet.header.serialize_into(&mut buf[..11]).is_err());
+    }
+
+    #[test]
+    #[should_panic(expected = "a packet can't hold more than 65535 questions")]
+    fn section_counts_fill_up() {
+        let question = crate::packet::DNSQuestion::new(String::from("a"), crate::packet::RecordType::A).unwrap();
+        let mut packet = crate::packet::DNSPacket::with_id(0);
+        for _ in 0..u16::MAX {
+            packet.add_question(question.clone());
+        }
+        assert_eq!(packet.header.question_count(), u16::MAX);
+        assert_eq!(packet.questions().len(), 65535);
+
+        // one more would wrap the count to 0
+        packet.add_question(question);
+    }
+
+    #[test]
+    fn truncate_to() {
+        use crate::packet::{DNSPacket, DNSRecord, RecordData, RecordType, SerializeOptions, RECORD_CLASS};
+
+        let uncompressed = SerializeOptions::default();
+        let txt = |text: &str| DNSRecord {
+            name: String::from("example.com"),
+            rtype: RecordType::TXT,
+            class: RECORD_CLASS,
+            ttl: 300,
+            data: RecordData::Txt(vec![text.as_bytes().to_vec()]),
+        };
+        let mut packet = DNSPacket::query("example.com", RecordType::TXT).unwrap();
+        for _ in 0..4 {
+            packet.add_answer(txt(&"a".repeat(200)));
+        }
+        packet.add_authority(txt("authority"));
+        packet.add_additional(txt("glue"));
+        packet.add_additional(DNSRecord::opt(1232, false));
+
+        // nothing to do when it fits already
+        let mut fits = packet.clone();
+        fits.truncate_to(packet.wire_len(), &uncompressed);
+        assert_eq!(fits, packet);
+
+        // dropping additional records doesn't need TC
+        let mut small = packet.clone();
+        small.truncate_to(packet.wire_len() - 1, &uncompressed);
+        assert!(!small.header.flags.is_truncated);
+        assert_eq!(small.additional().iter().map(|x| x.rtype).collect::<Vec<_>>(), [RecordType::OPT]);
+
+        packet.truncate_to(512, &uncompressed);
+        assert!(packet.header.flags.is_truncated);
+        assert_eq!((packet.answers().len(), packet.authority().len(), packet.additional().len()), (2, 0, 1));
+        let bytes = packet.serialize().unwrap();
+        assert!(bytes.len() <= 512);
+        assert_eq!(DNSPacket::deserialize(&bytes).unwrap(), packet);
+
+        // 25 A records take 675 bytes written out in full, and fit in 512 with their names compressed
+        let mut addresses = DNSPacket::query("example.com", RecordType::A).unwrap();
+        for last in 0..25 {
+            addresses.add_answer(DNSRecord {
+                name: String::from("example.com"),
+                rtype: RecordType::A,
+                class: RECORD_CLASS,
+                ttl: 300,
+                data: RecordData::A(core::net::Ipv4Addr::new(192, 0, 2, last)),
+            });
+        }
+        let compressed = SerializeOptions { compress: true };
+        let mut fits = addresses.clone();
+        fits.truncate_to(512, &compressed);
+        assert_eq!(fits, addresses);
+        assert!(fits.serialize_with(&compressed).unwrap().len() <= 512);
+        addresses.truncate_to(512, &uncompressed);
+        assert!(addresses.header.flags.is_truncated && addresses.answers().len() < 25);
+    }
+
+    #[test]
+    fn serialize_flags() {
+        let mut flags = crate::packet::DNSFlags {
+            is_response: true,
+            is_authoritative: true,
+            recurse_available: true,
+            ..Default::default()
+        };
+        assert_eq!(flags.serialize(), 0x8480);
+        flags.z = true;
+        assert_eq!(flags.serialize(), 0x84c0);
+    }
+
+    #[test]
+    fn deserialize_flags() {
+        let mut flags = crate::packet::DNSFlags {
+            is_response: true,
+            is_authoritative: true,
+            recurse_available: true,
+            ..Default::default()
+        };
+        assert_eq!(crate::packet::DNSFlags::from(0x8480), flags);
+
+        flags.z = true;
+        assert_eq!(crate::packet::DNSFlags::from(0x84c0), flags);
+        flags.checking_disabled = true;
+        assert_eq!(crate::packet::DNSFlags::from(0x84d0), flags);
+        flags.authentic_data = true;
+        assert_eq!(crate::packet::DNSFlags::from(0x84f0), flags);
+    }
+
+    #[test]
+    fn flags_round_trip() {
+        for bits in 0..=u16::MAX {
+            assert_eq!(crate::packet::DNSFlags::from(bits).serialize(), bits, "{:#06x}", bits);
+        }
+        assert!(!crate::packet::DNSFlags::default().z);
+    }
+
+    #[test]
+    fn raw_codes() {
+        let flags = crate::packet::DNSFlags::from(0x2943);
+        assert_eq!((flags.raw_opcode(), flags.raw_reply_code()), (5, 3));
+        // codes with no name yet come through as they are
+        let flags = crate::packet::DNSFlags::from(0x780f);
+        assert_eq!((flags.raw_opcode(), flags.raw_reply_code()), (15, 15));
+    }
+
+    #[test]
+    fn query_builder() {
+        use crate::packet::{DNSPacket, QueryBuilder, RecordType, DEFAULT_EDNS_PAYLOAD, EDNS_DNSSEC_OK};
+
+        let query = DNSPacket::query("Example.com.", RecordType::MX).unwrap();
+        assert!(query.header.flags.recurse_desired);
+        assert_eq!(query.questions()[0].name(), "Example.com");
+        assert!(query.additional().is_empty());
+
+        // DNSSEC without a payload of its own gets the default one
+        let query = QueryBuilder::new()
+            .recursion(false)
+            .dnssec(true)
+            .question("example.com", RecordType::A)
+            .build()
+            .unwrap();
+        assert!(!query.header.flags.recurse_desired);
+        assert_eq!(query.additional()[0].class, DEFAULT_EDNS_PAYLOAD);
+        assert_eq!(query.additional()[0].ttl, EDNS_DNSSEC_OK);
+        let query = QueryBuilder::new().edns(4096).question("example.com", RecordType::A).build().unwrap();
+        assert_eq!((query.additional()[0].class, query.additional()[0].ttl), (4096, 0));
+        assert!(!query.header.flags.checking_disabled);
+
+        // CD is bit 4 of the flags
+        let query = QueryBuilder::new().id(0).checking_disabled(true).question("example.com", RecordType::A).build();
+        assert_eq!(query.unwrap().serialize().unwrap()[2..4], [0x01, 0x10]);
+
+        assert!(matches!(
+            QueryBuilder::new().question("example.com", RecordType::A).question("a..b", RecordType::A).build(),
+            Err(crate::error::DnsError::EmptyLabel(_))
+        ));
+    }
+
+    #[test]
+    fn extended_reply_code() {
+        use crate::packet::{DNSPacket, DNSRecord, ReplyCode};
+
+        // BADVERS is 16: nothing in the header, 1 in the OPT record's extended bits
+        let mut response = DNSPacket::with_id(0x1234);
+        response.header.flags.is_response = true;
+        let mut opt = DNSRecord::opt(1232, true);
+        opt.ttl |= 1 << 24;
+        response.add_additional(opt);
+        let response = DNSPacket::deserialize(&response.serialize().unwrap()).unwrap();
+        assert_eq!(response.header.flags.raw_reply_code(), 0);
+        assert_eq!(response.reply_code(), ReplyCode::BadVers);
+        assert_eq!(response.reply_code().to_string(), "BADVERS");
+
+        let mut response = DNSPacket::with_id(0x1234);
+        response.set_reply_code(ReplyCode::Unknown(0x123));
+        assert_eq!(response.header.flags.raw_reply_code(), 3);
+        assert_eq!(response.additional()[0].ttl, 0x12 << 24);
+        assert_eq!(response.reply_code().to_string(), "RCODE291");
+        // an existing OPT record keeps its other fields
+        response.set_reply_code(ReplyCode::NxDomain);
+        assert_eq!(response.additional().len(), 1);
+        assert_eq!(response.reply_code(), ReplyCode::NxDomain);
+
+        // without EDNS the header's 4 bits are all there is
+        let mut response = DNSPacket::with_id(0x1234);
+        response.set_reply_code(ReplyCode::Refused);
+        assert!(response.additional().is_empty());
+        assert_eq!(response.reply_code(), ReplyCode::Refused);
+    }
+
+    #[test]
+    fn display_flags() {
+        assert_eq!(
+            crate::packet::DNSFlags::from(0x8580).to_string(),
+            "\
+Flags: 0x8580 Standard query response, No error
+        1... .... .... .... = Response: Message is a response
+        .000 0... .... .... = Opcode: Standard query (0)
+        .... .1.. .... .... = Authoritative: Server is an authority for domain
+        .... ..0. .... .... = Truncated: Message is not truncated
+        .... ...1 .... .... = Recursion desired: Do query recursively
+        .... .... 1... .... = Recursion available: Server can do recursive queries
+        .... .... .0.. .... = Z: reserved (0)
+        .... .... ..0. .... = Answer authenticated: Answer/authority portion was not authenticated by the server
+        .... .... ...0 .... = Non-authenticated data: Unacceptable
+        .... .... .... 0000 = Reply code: No error (0)"
+        );
+        assert_eq!(
+            crate::packet::DNSFlags::from(0x2943).to_string(),
+            "\
+Flags: 0x2943 Dynamic update
+        0... .... .... .... = Response: Message is a query
+        .010 1... .... .... = Opcode: Dynamic update (5)
+        .... .0.. .... .... = Authoritative: Server is not an authority for domain
+        .... ..0. .... .... = Truncated: Message is not truncated
+        .... ...1 .... .... = Recursion desired: Do query recursively
+        .... .... 0... .... = Recursion available: Server can't do recursive queries
+        .... .... .1.. .... = Z: reserved (1)
+        .... .... ..0. .... = Answer authenticated: Answer/authority portion was not authenticated by the server
+        .... .... ...0 .... = Non-authenticated data: Unacceptable
+        .... .... .... 0011 = Reply code: No such name (3)"
+        );
+
+        // AD from a validating resolver shows on the first line too
+        let flags = crate::packet::DNSFlags::from(0x81a0).to_string();
+        let lines: Vec<&str> = flags.lines().collect();
+        assert_eq!(lines[0], "Flags: 0x81a0 Standard query response, No error, DNSSEC validated");
+        assert_eq!(
+            lines[8],
+            "        .... .... ..1. .... = Answer authenticated: Answer/authority portion was authenticated by the server"
+        );
+    }
+
+    #[test]
+    fn record_type_from_str() {
+        use crate::packet::RecordType;
+
+        assert_eq!("MX".parse::<RecordType>().unwrap(), RecordType::MX);
+        assert_eq!("aaaa".parse::<RecordType>().unwrap(), RecordType::AAAA);
+        assert_eq!("TYPE99".parse::<RecordType>().unwrap(), RecordType::Unknown(99));
+        assert_eq!("type15".parse::<RecordType>().unwrap(), RecordType::MX);
+        assert!(matches!("TYPE".parse::<RecordType>(), Err(crate::error::DnsError::UnknownType(x)) if x == "TYPE"));
+        assert!("MXX".parse::<RecordType>().is_err());
+
+        // every type reads back from how it's shown
+        for value in [1, 2, 5, 6, 12, 15, 16, 28, 41, 46, 48, 252, 99, 65535] {
+            let rtype = RecordType::from_u16(value);
+            assert_eq!(rtype.to_string().parse::<RecordType>().unwrap(), rtype);
+        }
+        assert_eq!(RecordType::Unknown(99).to_string(), "TYPE99");
+    }
+
+    #[test]
+    fn record_class_from_str() {
+        use crate::packet::RecordClass;
+
+        assert_eq!("in".parse::<RecordClass>().unwrap(), RecordClass::IN);
+        assert_eq!("CH".parse::<RecordClass>().unwrap(), RecordClass::CH);
+        assert_eq!("CLASS1".parse::<RecordClass>().unwrap(), RecordClass::IN);
+        assert_eq!("class42".parse::<RecordClass>().unwrap(), RecordClass::Unknown(42));
+        assert!("INN".parse::<RecordClass>().is_err());
+        for value in [1, 3, 4, 255, 42] {
+            let class = RecordClass::from_u16(value);
+            assert_eq!(class.value(), value);
+            assert_eq!(class.to_string().parse::<RecordClass>().unwrap(), class);
+        }
+        assert_eq!(RecordClass::Unknown(42).to_string(), "CLASS42");
+    }
+
+    #[test]
+    fn deserialize_dns_str_compressed() {
+        let bytes = hex_literal::hex!(
+            "07 65 78 61 6d 70 6c 65 03 63 6f 6d 00 03 77 77 77 c0 00"
+        );
+        assert_eq!(
+            crate::packet::deserialize_dns_str(&bytes, 13).unwrap(),
+            (String::from("www.example.com"), 6)
+        );
+    }
+
+    #[test]
+    fn dns_str_escapes_round_trip() {
+        // labels "a.b", "\" and a space (0x20) followed by 0x03
+        let bytes = hex_literal::hex!("03 61 2e 62 01 5c 02 20 03 00");
+        let (name, _) = crate::packet::deserialize_dns_str(&bytes, 0).unwrap();
+        assert_eq!(name, "a\\.b.\\\\.\\032\\003");
+        assert_eq!(crate::packet::serialize_dns_str(&name).unwrap(), bytes);
+    }
+
+    #[test]
+    fn dns_str_escaped_trailing_dot() {
+        assert_eq!(crate::packet::serialize_dns_str("a\\.").unwrap(), hex_literal::hex!("02 61 2e 00"));
+        assert_eq!(crate::packet::serialize_dns_str("a\\\\.").unwrap(), hex_literal::hex!("02 61 5c 00"));
+        assert!(matches!(
+            crate::packet::serialize_dns_str("a\\256"),
+            Err(crate::error::DnsError::InvalidEscape(_))
+        ));
+        assert!(matches!(
+            crate::packet::serialize_dns_str("a\\"),
+            Err(crate::error::DnsError::InvalidEscape(_))
+        ));
+    }
+
+    #[test]
+    fn deserialize_dns_str_pointer_loop() {
+        // label at 0 followed by a pointer back to itself
+        let bytes = hex_literal::hex!("03 77 77 77 c0 00");
+        assert!(crate::packet::deserialize_dns_str(&bytes, 0).is_err());
+    }
+
+    #[test]
+    fn deserialize_dns_str_reserved_label_types() {
+        // 0x80 and 0x41 would otherwise read as labels of 128 and 65 bytes
+        let mut bytes = vec![0x03, b'w', b'w', b'w', 0x80];
+        bytes.extend([b'a'; 128]);
+        bytes.push(0);
+        assert!(matches!(
+            crate::packet::deserialize_dns_str(&bytes, 0),
+            Err(crate::error::DnsError::BadLabelType { offset: 4, length: 0x80 })
+        ));
+        bytes[4] = 0x41;
+        assert!(matches!(
+            crate::packet::deserialize_dns_str(&bytes, 0),
+            Err(crate::error::DnsError::BadLabelType { offset: 4, length: 0x41 })
+        ));
+    }
+
+    #[test]
+    fn deserialize_packet() {
+        let response = crate::packet::DNSPacket::deserialize(&crate::testdata::CNAME_RESPONSE).unwrap();
+
+        assert_eq!(response.header.id, 0x1234);
+        assert_eq!(response.questions[0].name, "www.example.com");
+        assert_eq!(response.questions[0].qtype, crate::packet::RecordType::A);
+
+        let answers = response.answers();
+        assert_eq!(answers.len(), 2);
+        assert_eq!(answers[0].name, "www.example.com");
+        assert_eq!(answers[0].data, crate::packet::RecordData::Cname(String::from("example.com")));
+        assert_eq!(answers[1].name, "example.com");
+        assert_eq!(answers[1].ttl, 3600);
+        assert_eq!(
+            answers[1].data,
+            crate::packet::RecordData::A(std::net::Ipv4Addr::new(93, 184, 216, 34))
+        );
+    }
+
+    #[test]
+    fn deserialize_short_header() {
+        // a broken server's 5 byte reply
+        assert!(matches!(
+            crate::packet::DNSPacket::deserialize(&hex_literal::hex!("12 34 81 80 00")),
+            Err(crate::error::DnsError::Malformed { part: crate::error::ParsePart::Header, error, .. })
+                if matches!(*error, crate::error::DnsError::TooShort { offset: 0, expected: 12 })
+        ));
+        assert!(crate::packet::DNSPacket::deserialize(&[]).is_err());
+    }
+
+    #[test]
+    fn deserialize_truncated_record() {
+        // answer count claims a record that isn't there
+        assert!(crate::packet::DNSPacket::deserialize(&hex_literal::hex!(
+            "12 34 81 80 00 00 00 01 00 00 00 00"
+        )).is_err());
+    }
+
+    #[test]
+    fn deserialize_error_offsets() {
+        use crate::error::{DnsError, ParsePart, Section};
+
+        // the answer's 4 byte address starts at 41, the packet stops 2 bytes into it
+        let truncated = &crate::testdata::A_RESPONSE[..43];
+        let err = crate::packet::DNSPacket::deserialize(truncated).unwrap_err();
+        assert_eq!(err.offset(), Some(41));
+        assert!(matches!(
+            &err,
+            DnsError::Malformed { part: ParsePart::Record { section: Section::Answer, index: 0 }, offset: 29, error }
+                if matches!(**error, DnsError::TooShort { offset: 41, expected: 4 })
+        ));
+        assert_eq!(
+            err.to_string(),
+            "Packet ended before the 4 bytes expected at offset 41 in answer #1"
+        );
+
+        // the same answer claiming a 3 byte address only fails once the rdata is decoded
+        let mut bad_length = crate::testdata::A_RESPONSE[..44].to_vec();
+        bad_length[40] = 3;
+        let err = crate::packet::DNSPacket::deserialize(&bad_length).unwrap_err();
+        assert_eq!(err.offset(), Some(41));
+        assert!(matches!(
+            err,
+            DnsError::Malformed { part: ParsePart::Rdata { section: Section::Answer, index: 0 }, .. }
+        ));
+    }
+
+    #[test]
+    fn deserialize_soa() {
+        let response = crate::packet::DNSPacket::deserialize(&crate::testdata::NXDOMAIN_RESPONSE).unwrap();
+
+        assert_eq!(
+            response.authority()[0].data,
+            crate::packet::RecordData::Soa(crate::packet::Soa {
+                mname: String::from("ns.icann.org"),
+                rname: String::from("noc.dns.icann.org"),
+                serial: 2024010101,
+                refresh: 7200,
+                retry: 3600,
+                expire: 1209600,
+                minimum: 3600,
+            })
+        );
+    }
+
+    #[test]
+    fn deserialize_ptr() {
+        // two answers for 9.9.9.9.in-addr.arpa, the second target pointing into the first
+        let response = crate::packet::DNSPacket::deserialize(&hex_literal::hex!(
+            """
+            12 34 81 80 00 01 00 02 00 00 00 00
+            01 39 01 39 01 39 01 39 07 69 6e 2d 61 64 64 72 04 61 72 70 61 00 00 0c 00 01
+            c0 0c 00 0c 00 01 00 00 0e 10 00 10 04 64 6e 73 39 05 71 75 61 64 39 03 6e 65 74 00
+            c0 0c 00 0c 00 01 00 00 0e 10 00 08 05 64 6e 73 31 30 c0 37
+            """
+        )).unwrap();
+
+        let targets: Vec<&crate::packet::RecordData> = response.answers().iter().map(|x| &x.data).collect();
+        assert_eq!(targets, [
+            &crate::packet::RecordData::Ptr(String::from("dns9.quad9.net")),
+            &crate::packet::RecordData::Ptr(String::from("dns10.quad9.net")),
+        ]);
+        assert_eq!(response.answers()[0].name, "9.9.9.9.in-addr.arpa");
+    }
+
+    #[test]
+    fn compare_serials() {
+        use core::cmp::Ordering;
+
+        use crate::packet::Soa;
+
+        assert_eq!(Soa::compare_serials(2024010102, 2024010101), Some(Ordering::Greater));
+        assert_eq!(Soa::compare_serials(7, 7), Some(Ordering::Equal));
+        // a serial that wrapped is still newer
+        assert_eq!(Soa::compare_serials(3, u32::MAX - 3), Some(Ordering::Greater));
+        assert_eq!(Soa::compare_serials(u32::MAX - 3, 3), Some(Ordering::Less));
+        assert_eq!(Soa::compare_serials(0, 0x8000_0000), None);
+    }
+
+    #[test]
+    fn deserialize_dnssec_response() {
+        let response = crate::packet::DNSPacket::deserialize(&crate::testdata::DNSSEC_RESPONSE).unwrap();
+        assert_eq!(response.answers().len(), 2);
+        assert_eq!(response.authority().len(), 7);
+        assert_eq!(response.additional().len(), 13);
+        assert_eq!(response.authority()[5].name, "example.com");
+        assert_eq!(response.additional()[11].name, "f.iana-servers.net");
+        let rrsig = match &response.answers()[1].data {
+            crate::packet::RecordData::Rrsig(rrsig) => rrsig,
+            data => panic!("expected an RRSIG, got {:?}", data),
+        };
+        assert_eq!(
+            (rrsig.type_covered, rrsig.algorithm, rrsig.labels, rrsig.original_ttl, rrsig.key_tag),
+            (crate::packet::RecordType::A, 8, 3, 300, 2371)
+        );
+        assert_eq!((rrsig.expiration, rrsig.inception), (1767225600, 1764633600));
+        assert_eq!(rrsig.signer, "example.com");
+        assert_eq!(rrsig.signature.len(), 256);
+        // the signer name was written out in full, and stays that way
+        let record = &response.answers()[1];
+        assert_eq!(record.data.serialized_len(), 18 + 13 + 256);
+        assert_eq!(crate::packet::DNSPacket::deserialize(&response.serialize().unwrap()).unwrap().answers()[1], *record);
+    }
+
+    #[test]
+    fn dnskey_key_tag() {
+        // the example key of RFC 4034 section 2.3, key id 2642
+        let key = crate::packet::Dnskey {
+            flags: 256,
+            protocol: 3,
+            algorithm: 5,
+            public_key: hex_literal::hex!(
+                "01 03 d2 2a 6c a7 7f 35 b8 93 20 6f d3 5e 4c 50 6d 83 78 84 37 09 b9 7e 04 16 47 e1 bf f4 3d 8d"
+                "64 c6 49 af 1e 37 19 73 c9 e8 91 fc e3 df 51 9a 8c 84 0a 63 ee 42 a6 d2 eb dd bb 97 03 5d 21 5a"
+                "a4 e4 17 b1 fa 45 fa 11 a9 74 1e a2 09 8c 1d fa 5f b5 fe b3 32 fd 4b c8 15 20 89 ae f3 6b a6 44"
+                "cc e2 41 3b 3b 72 be 18 cb ef 8d a2 53 f4 e9 3d 21 03 86 6d 92 34 a2 e2 8d f5 29 a6 7d 54 68 db"
+                "ef e3"
+            ).to_vec(),
+        };
+        assert_eq!(key.key_tag(), 2642);
+
+        let data = crate::packet::RecordData::Dnskey(key.clone());
+        let bytes = data.serialize().unwrap();
+        assert_eq!(bytes.len(), data.serialized_len());
+        assert_eq!(
+            crate::packet::RecordData::deserialize(crate::packet::RecordType::DNSKEY, &bytes, 0, bytes.len()).unwrap(),
+            data
+        );
+        assert!(crate::packet::RecordData::deserialize(crate::packet::RecordType::DNSKEY, &bytes[..3], 0, 3).is_err());
+        assert!(crate::packet::RecordData::deserialize(crate::packet::RecordType::RRSIG, &bytes, 0, 17).is_err());
+    }
+
+    #[test]
+    fn opt_record() {
+        let mut packet = crate::packet::DNSPacket::with_id(0x1234);
+        packet.add_question(crate::packet::DNSQuestion::new(String::from("example.com"), crate::packet::RecordType::A).unwrap());
+        packet.add_additional(crate::packet::DNSRecord::opt(1232, true));
+        let bytes = packet.serialize().unwrap();
+        // root name, type 41, payload 1232, DO set, no options
+        assert!(bytes.ends_with(&hex_literal::hex!("00 00 29 04 d0 00 00 80 00 00 00")));
+
+        let parsed = crate::packet::DNSPacket::deserialize(&bytes).unwrap();
+        assert_eq!(parsed.additional()[0].rtype, crate::packet::RecordType::OPT);
+        assert_eq!(parsed.additional()[0].ttl & crate::packet::EDNS_DNSSEC_OK, crate::packet::EDNS_DNSSEC_OK);
+    }
+
+    #[test]
+    fn remaining_ttl() {
+        use core::time::Duration;
+
+        let record = crate::packet::DNSRecord {
+            name: String::from("example.com"),
+            rtype: crate::packet::RecordType::A,
+            class: crate::packet::RECORD_CLASS,
+            ttl: 300,
+            data: crate::packet::RecordData::A(core::net::Ipv4Addr::new(192, 0, 2, 1)),
+        };
+        assert_eq!(record.remaining_ttl(Duration::ZERO), 300);
+        assert_eq!(record.remaining_ttl(Duration::from_millis(42_900)), 258);
+        assert_eq!(record.remaining_ttl(Duration::from_secs(301)), 0);
+        assert_eq!(record.remaining_ttl(Duration::MAX), 0);
+    }
+
+    #[test]
+    fn deserialize_nested_pointers() {
+        // a.b.example.com where b.example.com is itself a pointer into example.com
+        let bytes = hex_literal::hex!(
+            "07 65 78 61 6d 70 6c 65 03 63 6f 6d 00 01 62 c0 00 01 61 c0 0d"
+        );
+        assert_eq!(
+            crate::packet::deserialize_dns_str(&bytes, 17).unwrap(),
+            (String::from("a.b.example.com"), 4)
+        );
+    }
+
+    #[test]
+    fn deserialize_claimed_counts() {
+        // header claims 256 answers in a 14 byte packet
+        assert!(matches!(
+            crate::packet::DNSPacket::deserialize(&hex_literal::hex!(
+                "12 34 81 80 00 00 01 00 00 00 00 00 00 00"
+            )),
+            Err(crate::error::DnsError::Malformed { error, .. }) if matches!(*error, crate::error::DnsError::TooShort { .. })
+        ));
+        assert!(matches!(
+            crate::packet::DNSPacket::deserialize(&hex_literal::hex!(
+                "12 34 81 80 ff ff ff ff ff ff ff ff"
+            )),
+            Err(crate::error::DnsError::Malformed { error, .. }) if matches!(*error, crate::error::DnsError::TooManyRecords(_))
+        ));
+    }
+
+    #[test]
+    fn deserialize_name_expansion_cap() {
+        // 5 labels of 63 bytes expand past 255
+        let mut bytes: Vec<u8> = Vec::new();
+        for _ in 0..5 {
+            bytes.push(63);
+            bytes.extend_from_slice(&[b'a'; 63]);
+        }
+        bytes.push(0);
+        assert!(matches!(
+            crate::packet::deserialize_dns_str(&bytes, 0),
+            Err(crate::error::DnsError::NameTooLong(_))
+        ));
+    }
+
+    #[test]
+    fn deserialize_mutated_packets() {
+        use rand::{Rng, SeedableRng};
+
+        let corpus: [&[u8]; 4] = [
+            &crate::testdata::CNAME_RESPONSE,
+            &crate::testdata::NXDOMAIN_RESPONSE,
+            &crate::testdata::AAAA_RESPONSE,
+            &crate::testdata::DNSSEC_RESPONSE,
+        ];
+
+        let mut rng = rand::rngs::StdRng::seed_from_u64(0xD115);
+        for packet in corpus {
+            assert!(crate::packet::DNSPacket::deserialize(packet).is_ok());
+
+            for length in 0..packet.len() {
+                assert!(crate::packet::DNSPacket::deserialize(&packet[..length]).is_err());
+            }
+
+            for index in 0..packet.len() {
+                for value in [0x00, 0x01, 0x3f, 0x40, 0x80, 0xc0, 0xff] {
+                    let mut mutated = packet.to_vec();
+                    mutated[index] = value;
+                    let _ = crate::packet::DNSPacket::deserialize(&mutated);
+                }
+            }
+
+            for _ in 0..2000 {
+                let mut mutated = packet.to_vec();
+                for _ in 0..rng.gen_range(1..=4) {
+                    let index = rng.gen_range(0..mutated.len());
+                    mutated[index] = rng.gen();
+                }
+                mutated.truncate(rng.gen_range(0..=mutated.len()));
+                let _ = crate::packet::DNSPacket::deserialize(&mutated);
+            }
+        }
+    }
+
+    #[test]
+    fn deserialize_servfail_sections() {
+        // a SERVFAIL still has its question and authority parsed
+        let response = crate::packet::DNSPacket::deserialize(&hex_literal::hex!(
+            """
+            12 34 81 82 00 01 00 00 00 01 00 00
+            07 65 78 61 6d 70 6c 65 03 63 6f 6d 00 00 01 00 01
+            c0 0c 00 06 00 01 00 00 0e 10 00 2c
+            02 6e 73 05 69 63 61 6e 6e 03 6f 72 67 00
+            03 6e 6f 63 03 64 6e 73 c0 2c
+            78 a3 f1 75 00 00 1c 20 00 00 0e 10 00 12 75 00 00 00 0e 10
+            """
+        )).unwrap();
+        assert_eq!(response.header.flags.reply_code, 2);
+        assert_eq!(response.questions().len(), 1);
+        assert!(matches!(response.authority()[0].data, crate::packet::RecordData::Soa(_)));
+    }
+
+    proptest::proptest! {
+        #[test]
+        fn packet_round_trip(packet in crate::test_support::packet()) {
+            let bytes = packet.serialize().unwrap();
+            proptest::prop_assert_eq!(crate::packet::DNSPacket::deserialize(&bytes).unwrap(), packet);
+        }
+
+        #[test]
+        fn compressed_packet_round_trip(packet in crate::test_support::packet()) {
+            let options = crate::packet::SerializeOptions { compress: true };
+            let bytes = packet.serialize_with(&options).unwrap();
+            proptest::prop_assert!(bytes.len() <= packet.serialize().unwrap().len());
+            proptest::prop_assert_eq!(crate::packet::DNSPacket::deserialize(&bytes).unwrap(), packet);
+        }
+
+        #[test]
+        fn record_round_trip(record in crate::test_support::record()) {
+            let bytes = record.serialize().unwrap();
+            let (parsed, size) = crate::packet::DNSRecord::deserialize(&bytes, 0).unwrap();
+            proptest::prop_assert_eq!(size, bytes.len());
+            proptest::prop_assert_eq!(parsed, record);
+        }
+    }
+}
@@ -0,0 +1,396 @@
+/* Borrowed view of a packet: parsing checks the structure and records where
+    everything is, but copies nothing out of the buffer. Names are walked (and
+    decompressed) when their labels are iterated, rdata stays raw bytes until
+    `to_owned` turns the view into a `DNSPacket`.
+ */
+use alloc::string::ToString;
+use alloc::vec::Vec;
+use core::fmt::{self, Write};
+use core::ops::Range;
+
+use crate::error::{DnsError, ParsePart, Section};
+use crate::packet::{
+    self, DNSRecord, DNSHeader, DNSPacket, DNSQuestion, RecordClass, RecordData, RecordType, HEADER_SIZE,
+    MAX_NAME_SIZE, MAX_RECORDS, MIN_QUESTION_SIZE, MIN_RECORD_SIZE,
+};
+
+// A (possibly compressed) name somewhere in `packet`, already checked to be well formed
+#[derive(Clone, Copy)]
+pub struct NameRef<'a> {
+    packet: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> NameRef<'a> {
+    /* Checks the name at `offset` of the full packet, with the same rules as deserialize_dns_str.
+        Returns the name and the number of bytes it occupied at `offset`.
+     */
+    pub fn parse(bytes: &'a [u8], offset: usize) -> Result<(Self, usize), DnsError> {
+        let mut position = offset;
+        let mut read_count: Option<usize> = None;
+        // Every pointer must jump somewhere earlier than the last one, so cycles can't happen
+        let mut pointer_limit = offset;
+        // Expanded size on the wire, capped like any other name
+        let mut name_size: usize = 1;
+
+        loop {
+            let length = packet::read_u8(bytes, position)? as usize;
+
+            if length & 0xC0 == 0xC0 {
+                let pointer = (packet::read_u16(bytes, position)? & 0x3FFF) as usize;
+                if pointer >= pointer_limit {
+                    return Err(DnsError::BadPointer { offset: position, pointer });
+                }
+                if read_count.is_none() {
+                    read_count = Some(position + 2 - offset);
+                }
+                pointer_limit = pointer;
+                position = pointer;
+                continue;
+            }
+            // 0x40 was EDNS's extended label types (RFC 6891 section 5), 0x80 was never assigned
+            if length & 0xC0 != 0 {
+                return Err(DnsError::BadLabelType { offset: position, length: length as u8 });
+            }
+
+            position += 1;
+            if length == 0 {
+                break;
+            }
+
+            name_size += length + 1;
+            if name_size > MAX_NAME_SIZE {
+                return Err(DnsError::NameTooLong(name_size));
+            }
+
+            packet::read_bytes(bytes, position, length)?;
+            position += length;
+        }
+
+        let name = NameRef { packet: bytes, offset };
+        Ok((name, read_count.unwrap_or_else(|| position - offset)))
+    }
+
+    // The raw bytes of each label, following compression pointers as they come up
+    pub fn labels(&self) -> Labels<'a> {
+        Labels { packet: self.packet, position: Some(self.offset) }
+    }
+
+    pub fn is_root(&self) -> bool {
+        self.labels().next().is_none()
+    }
+}
+
+// Dotted presentation format, the same text deserialize_dns_str returns
+impl fmt::Display for NameRef<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (index, label) in self.labels().enumerate() {
+            if index > 0 {
+                f.write_char('.')?;
+            }
+            packet::write_label(f, label)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Debug for NameRef<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "\"{}\"", self)
+    }
+}
+
+pub struct Labels<'a> {
+    packet: &'a [u8],
+    // None once the root label has been reached
+    position: Option<usize>,
+}
+
+impl<'a> Iterator for Labels<'a> {
+    type Item = &'a [u8];
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let position = self.position?;
+            let length = *self.packet.get(position)? as usize;
+
+            if length & 0xC0 == 0xC0 {
+                let low = *self.packet.get(position + 1)? as usize;
+                self.position = Some((length & 0x3F) << 8 | low);
+                continue;
+            }
+            if length == 0 {
+                self.position = None;
+                return None;
+            }
+
+            self.position = Some(position + 1 + length);
+            return self.packet.get(position + 1..position + 1 + length);
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct QuestionRef<'a> {
+    pub name: NameRef<'a>,
+    pub qtype: RecordType,
+    pub qclass: u16,
+    pub unicast_response: bool,
+}
+
+impl<'a> QuestionRef<'a> {
+    // Returns the question and the number of bytes it occupied at `offset`
+    pub fn parse(bytes: &'a [u8], offset: usize) -> Result<(Self, usize), DnsError> {
+        let (name, name_size) = NameRef::parse(bytes, offset)?;
+        let qtype = RecordType::from_u16(packet::read_u16(bytes, offset + name_size)?);
+        // mDNS's QU bit goes on top of the class
+        let class = packet::read_u16(bytes, offset + name_size + 2)?;
+        let (qclass, unicast_response) = (class & !packet::UNICAST_RESPONSE, class & packet::UNICAST_RESPONSE != 0);
+        Ok((QuestionRef { name, qtype, qclass, unicast_response }, name_size + 2 * 2))
+    }
+
+    pub fn to_owned(&self) -> DNSQuestion {
+        DNSQuestion {
+            name: self.name.to_string(),
+            qtype: self.qtype,
+            qclass: self.qclass,
+            unicast_response: self.unicast_response,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct RecordRef<'a> {
+    pub name: NameRef<'a>,
+    pub rtype: RecordType,
+    pub class: u16,
+    pub ttl: u32,
+    // The rdata as it is on the wire, names in it may point elsewhere in the packet
+    pub data: &'a [u8],
+    packet: &'a [u8],
+    data_offset: usize,
+}
+
+impl<'a> RecordRef<'a> {
+    // Returns the record and the number of bytes it occupied at `offset`
+    pub fn parse(bytes: &'a [u8], offset: usize) -> Result<(Self, usize), DnsError> {
+        let (name, name_size) = NameRef::parse(bytes, offset)?;
+        let mut read_count = offset + name_size;
+
+        let rtype = RecordType::from_u16(packet::read_u16(bytes, read_count)?);
+        let class = packet::read_u16(bytes, read_count + 2)?;
+        let ttl = packet::read_u32(bytes, read_count + 4)?;
+        let length = packet::read_u16(bytes, read_count + 8)? as usize;
+        read_count += 10; // type, class, ttl, rdlength
+
+        let data = packet::read_bytes(bytes, read_count, length)?;
+        let record = RecordRef { name, rtype, class, ttl, data, packet: bytes, data_offset: read_count };
+        read_count += length;
+
+        Ok((record, read_count - offset))
+    }
+
+    // A name inside the rdata starting `at` bytes in, e.g. 2 for an MX exchange
+    pub fn rdata_name(&self, at: usize) -> Result<NameRef<'a>, DnsError> {
+        if at >= self.data.len() {
+            return Err(DnsError::BadRecordLength { rtype: self.rtype.value(), length: self.data.len() });
+        }
+        Ok(NameRef::parse(self.packet, self.data_offset + at)?.0)
+    }
+
+    /* Decodes the rdata, which is where errors in it first show up. UPDATE's prerequisites and
+        deletions of whole RRsets, class ANY or NONE with nothing in the rdata, stay empty.
+     */
+    pub fn to_owned(&self) -> Result<DNSRecord, DnsError> {
+        let data = match RecordClass::from_u16(self.class) {
+            RecordClass::ANY | RecordClass::NONE if self.data.is_empty() => {
+                RecordData::Unknown { rtype: self.rtype.value(), data: Vec::new() }
+            },
+            _ => RecordData::deserialize(self.rtype, self.packet, self.data_offset, self.data.len())?,
+        };
+        Ok(DNSRecord { name: self.name.to_string(), rtype: self.rtype, class: self.class, ttl: self.ttl, data })
+    }
+}
+
+// Where each part of a parsed packet sits in its buffer, any bytes after `additional` are unused
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Layout {
+    pub header: Range<usize>,
+    pub questions: Range<usize>,
+    pub answers: Range<usize>,
+    pub authority: Range<usize>,
+    pub additional: Range<usize>,
+}
+
+#[derive(Debug)]
+pub struct DNSPacketRef<'a> {
+    pub header: DNSHeader,
+    questions: Vec<QuestionRef<'a>>,
+    answers: Vec<RecordRef<'a>>,
+    authority: Vec<RecordRef<'a>>,
+    additional: Vec<RecordRef<'a>>,
+    layout: Layout,
+}
+
+impl<'a> DNSPacketRef<'a> {
+    // Every section is parsed whatever the reply code, error responses still carry the authority SOA
+    pub fn deserialize(bytes: &'a [u8]) -> Result<Self, DnsError> {
+        let header = DNSHeader::deserialize(bytes).map_err(|err| err.within(ParsePart::Header, 0))?;
+        let mut read_count: usize = HEADER_SIZE;
+
+        // Reject counts that can't possibly fit before parsing anything
+        let questions_claimed = header.question_count as usize;
+        let records_claimed = header.answer_count as usize
+            + header.authority_count as usize
+            + header.additional_count as usize;
+        if questions_claimed + records_claimed > MAX_RECORDS {
+            return Err(DnsError::TooManyRecords(questions_claimed + records_claimed).within(ParsePart::Header, 0));
+        }
+        let min_size = questions_claimed * MIN_QUESTION_SIZE + records_claimed * MIN_RECORD_SIZE;
+        if read_count + min_size > bytes.len() {
+            return Err(DnsError::TooShort { offset: read_count, expected: min_size }.within(ParsePart::Header, 0));
+        }
+
+        let mut questions: Vec<QuestionRef> = Vec::with_capacity(questions_claimed);
+        for index in 0..header.question_count as usize {
+            let (question, size) = QuestionRef::parse(bytes, read_count)
+                .map_err(|err| err.within(ParsePart::Question(index), read_count))?;
+            questions.push(question);
+            read_count += size;
+        }
+
+        let questions_range = HEADER_SIZE..read_count;
+
+        let mut records = |section: Section, count: u16| -> Result<(Vec<RecordRef<'a>>, Range<usize>), DnsError> {
+            let start = read_count;
+            let mut records: Vec<RecordRef> = Vec::with_capacity(count as usize);
+            for index in 0..count as usize {
+                let (record, size) = RecordRef::parse(bytes, read_count)
+                    .map_err(|err| err.within(ParsePart::Record { section, index }, read_count))?;
+                records.push(record);
+                read_count += size;
+            }
+            Ok((records, start..read_count))
+        };
+        let (answers, answers_range) = records(Section::Answer, header.answer_count)?;
+        let (authority, authority_range) = records(Section::Authority, header.authority_count)?;
+        let (additional, additional_range) = records(Section::Additional, header.additional_count)?;
+
+        let layout = Layout {
+            header: 0..HEADER_SIZE,
+            questions: questions_range,
+            answers: answers_range,
+            authority: authority_range,
+            additional: additional_range,
+        };
+        Ok(DNSPacketRef { header, questions, answers, authority, additional, layout })
+    }
+
+    pub fn layout(&self) -> &Layout {
+        &self.layout
+    }
+
+    pub fn questions(&self) -> &[QuestionRef<'a>] {
+        &self.questions
+    }
+
+    pub fn answers(&self) -> &[RecordRef<'a>] {
+        &self.answers
+    }
+
+    pub fn authority(&self) -> &[RecordRef<'a>] {
+        &self.authority
+    }
+
+    pub fn additional(&self) -> &[RecordRef<'a>] {
+        &self.additional
+    }
+
+    pub fn to_owned(&self) -> Result<DNSPacket, DnsError> {
+        let records = |section: Section, records: &[RecordRef]| records.iter()
+            .enumerate()
+            .map(|(index, x)| x.to_owned().map_err(|err| err.within(ParsePart::Rdata { section, index }, x.data_offset)))
+            .collect::<Result<Vec<DNSRecord>, DnsError>>();
+        Ok(DNSPacket {
+            header: self.header.clone(),
+            questions: self.questions.iter().map(|x| x.to_owned()).collect(),
+            answers: records(Section::Answer, &self.answers)?,
+            authority: records(Section::Authority, &self.authority)?,
+            additional: records(Section::Additional, &self.additional)?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::packet_ref::{DNSPacketRef, NameRef};
+
+    #[test]
+    fn labels_follow_pointers() {
+        // glue for f.iana-servers.net: "f" then a pointer to "iana-servers" then "net"
+        let (name, size) = NameRef::parse(&crate::testdata::DNSSEC_RESPONSE, 979).unwrap();
+        assert_eq!(size, 2);
+        let labels: Vec<&[u8]> = name.labels().collect();
+        assert_eq!(labels, [&b"f"[..], b"iana-servers", b"net"]);
+        assert_eq!(name.to_string(), "f.iana-servers.net");
+    }
+
+    #[test]
+    fn root_name() {
+        let (name, size) = NameRef::parse(&[0], 0).unwrap();
+        assert_eq!(size, 1);
+        assert!(name.is_root());
+        assert_eq!(name.to_string(), "");
+    }
+
+    #[test]
+    fn borrowed_matches_owned() {
+        let corpus: [&[u8]; 4] = [
+            &crate::testdata::CNAME_RESPONSE,
+            &crate::testdata::NXDOMAIN_RESPONSE,
+            &crate::testdata::AAAA_RESPONSE,
+            &crate::testdata::DNSSEC_RESPONSE,
+        ];
+        for bytes in corpus {
+            let view = DNSPacketRef::deserialize(bytes).unwrap();
+            assert_eq!(view.to_owned().unwrap(), crate::packet::DNSPacket::deserialize(bytes).unwrap());
+        }
+    }
+
+    #[test]
+    fn layout() {
+        let view = DNSPacketRef::deserialize(&crate::testdata::CNAME_RESPONSE).unwrap();
+        assert_eq!(
+            view.layout(),
+            &crate::packet_ref::Layout {
+                header: 0..12,
+                questions: 12..33,
+                answers: 33..63,
+                authority: 63..63,
+                additional: 63..63,
+            }
+        );
+    }
+
+    #[test]
+    fn rdata_left_raw() {
+        let view = DNSPacketRef::deserialize(&crate::testdata::CNAME_RESPONSE).unwrap();
+        let cname = &view.answers()[0];
+        assert_eq!(cname.name.to_string(), "www.example.com");
+        // the target is a single pointer back to the question's "example.com"
+        assert_eq!(cname.data.len(), 2);
+        assert_eq!(cname.rdata_name(0).unwrap().to_string(), "example.com");
+        assert!(cname.rdata_name(2).is_err());
+        assert_eq!(view.answers()[1].data, [93, 184, 216, 34]);
+    }
+
+    #[test]
+    fn bad_rdata_only_fails_to_owned() {
+        let mut bytes = crate::testdata::CNAME_RESPONSE;
+        // the A record's type becomes CNAME, its 4 address bytes aren't a name
+        let type_at = bytes.len() - 4 - 10;
+        bytes[type_at..type_at + 2].copy_from_slice(&5u16.to_be_bytes());
+        let view = DNSPacketRef::deserialize(&bytes).unwrap();
+        assert!(view.to_owned().is_err());
+    }
+}
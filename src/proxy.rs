@@ -0,0 +1,370 @@
+/* A forwarding proxy over UDP and TCP. Each client query is asked again upstream through a
+    Resolver, with the resolver's own ids, failover and timeouts, and the response relayed back
+    under the client's id and question. Every query gets a thread of its own so a slow upstream
+    answer doesn't hold up anyone else's, up to a limit past which UDP queries are dropped. Names
+    in the proxy's Rules are answered locally instead.
+ */
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, TcpListener, UdpSocket};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Instant;
+
+use crate::error::DnsError;
+use crate::packet::{
+    DNSHeader, DNSPacket, DNSQuestion, DNSRecord, RecordData, RecordType, ReplyCode, SerializeOptions, MAX_MESSAGE_SIZE,
+    RECORD_CLASS,
+};
+use crate::resolver::Resolver;
+use crate::server::{self, UDP_RECV_SIZE};
+
+// TTL of the records made up for blocked and overridden names
+const LOCAL_TTL: u32 = 60;
+
+// UDP queries being answered at once, a burst beyond it is dropped rather than given threads
+pub const DEFAULT_MAX_IN_FLIGHT: usize = 256;
+
+// What the proxy does with a name instead of asking upstream
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Action {
+    Block,
+    // A and AAAA queries get these addresses, other types no data
+    Override(Vec<IpAddr>),
+}
+
+/* Names answered locally, by exact name or `*.domain` for everything under the domain.
+    Lookups go through the name's suffixes a label at a time, so the cost doesn't grow with
+    the number of entries. An exact entry beats any wildcard, a longer wildcard a shorter one,
+    and an override beats a block of the same name.
+ */
+#[derive(Debug, Clone, Default)]
+pub struct Rules {
+    exact: HashMap<String, Action>,
+    // Keyed by the domain after `*.`
+    wildcards: HashMap<String, Action>,
+    // Answer blocked A and AAAA queries with 0.0.0.0 and :: instead of NXDOMAIN
+    pub block_with_zero: bool,
+}
+
+impl Rules {
+    pub fn new() -> Self {
+        Rules::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.exact.len() + self.wildcards.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn block(&mut self, pattern: &str) {
+        let (key, map) = self.entry(pattern);
+        map.entry(key).or_insert(Action::Block);
+    }
+
+    // Each call adds an address, so a name can have both an A and an AAAA answer
+    pub fn add_override(&mut self, pattern: &str, addr: IpAddr) {
+        let (key, map) = self.entry(pattern);
+        match map.entry(key).or_insert(Action::Override(Vec::new())) {
+            Action::Override(addrs) => addrs.push(addr),
+            block => *block = Action::Override(vec![addr]),
+        }
+    }
+
+    /* Blocks every name in a hosts-style list: a name or `*.domain` per line, or an address
+        followed by names as in /etc/hosts, the address ignored. `#` starts a comment.
+        Returns how many names were read.
+     */
+    pub fn load_blocklist(&mut self, text: &str) -> usize {
+        let mut count = 0;
+        for line in text.lines() {
+            let line = line.split('#').next().unwrap_or_default();
+            let mut words = line.split_whitespace().peekable();
+            if words.peek().is_some_and(|x| x.parse::<IpAddr>().is_ok()) {
+                words.next();
+            }
+            for name in words {
+                self.block(name);
+                count += 1;
+            }
+        }
+        count
+    }
+
+    pub fn lookup(&self, name: &str) -> Option<&Action> {
+        let name = normalize(name);
+        if let Some(action) = self.exact.get(&name) {
+            return Some(action);
+        }
+        let mut rest = name.as_str();
+        while let Some((_, parent)) = rest.split_once('.') {
+            if let Some(action) = self.wildcards.get(parent) {
+                return Some(action);
+            }
+            rest = parent;
+        }
+        None
+    }
+
+    fn entry(&mut self, pattern: &str) -> (String, &mut HashMap<String, Action>) {
+        match pattern.strip_prefix("*.") {
+            Some(domain) => (normalize(domain), &mut self.wildcards),
+            None => (normalize(pattern), &mut self.exact),
+        }
+    }
+}
+
+fn normalize(name: &str) -> String {
+    name.trim_end_matches('.').to_ascii_lowercase()
+}
+
+// Listens on the same address and port for both
+pub struct Proxy {
+    socket: UdpSocket,
+    listener: TcpListener,
+    resolver: Arc<Resolver>,
+    rules: Arc<Rules>,
+    max_in_flight: usize,
+    in_flight: Arc<AtomicUsize>,
+}
+
+impl Proxy {
+    pub fn bind(addr: SocketAddr, resolver: Resolver) -> Result<Self, DnsError> {
+        let socket = UdpSocket::bind(addr)?;
+        let listener = TcpListener::bind(socket.local_addr()?)?;
+        Ok(Proxy {
+            socket,
+            listener,
+            resolver: Arc::new(resolver),
+            rules: Arc::new(Rules::new()),
+            max_in_flight: DEFAULT_MAX_IN_FLIGHT,
+            in_flight: Arc::new(AtomicUsize::new(0)),
+        })
+    }
+
+    pub fn with_rules(mut self, rules: Rules) -> Self {
+        self.rules = Arc::new(rules);
+        self
+    }
+
+    pub fn with_max_in_flight(mut self, max_in_flight: usize) -> Self {
+        self.max_in_flight = max_in_flight;
+        self
+    }
+
+    pub fn local_addr(&self) -> Result<SocketAddr, DnsError> {
+        Ok(self.socket.local_addr()?)
+    }
+
+    // Relays queries until the UDP socket fails
+    pub fn run(&self) -> Result<(), DnsError> {
+        let (resolver, rules) = (self.resolver.clone(), self.rules.clone());
+        server::serve_tcp(self.listener.try_clone()?, move |message, client| {
+            respond(&resolver, &rules, message, client, MAX_MESSAGE_SIZE)
+        });
+
+        let mut buf = [0; UDP_RECV_SIZE];
+        loop {
+            let (size, client) = self.socket.recv_from(&mut buf)?;
+            if self.in_flight.fetch_add(1, Ordering::SeqCst) >= self.max_in_flight {
+                self.in_flight.fetch_sub(1, Ordering::SeqCst);
+                tracing::warn!(%client, "dropping the query, too many in flight");
+                continue;
+            }
+            let in_flight = InFlight(self.in_flight.clone());
+            let (socket, resolver, rules) = (self.socket.try_clone()?, self.resolver.clone(), self.rules.clone());
+            let message = buf[..size].to_vec();
+            std::thread::spawn(move || {
+                let _in_flight = in_flight;
+                if let Some(bytes) = respond(&resolver, &rules, &message, client, server::udp_limit(&message)) {
+                    if let Err(err) = socket.send_to(&bytes, client) {
+                        tracing::warn!(%client, %err, "couldn't send the response");
+                    }
+                }
+            });
+        }
+    }
+}
+
+// One query counted in `Proxy::in_flight`, until its thread is done with it
+struct InFlight(Arc<AtomicUsize>);
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+// The response to `message` serialized compressed, cut down to `limit` bytes and TC set when it's bigger
+fn respond(resolver: &Resolver, rules: &Rules, message: &[u8], client: SocketAddr, limit: usize) -> Option<Vec<u8>> {
+    let mut response = forward(resolver, rules, message, client)?;
+    let options = SerializeOptions { compress: true };
+    response.truncate_to(limit, &options);
+    response.serialize_with(&options).ok()
+}
+
+/* The response to send `client` for its query `message`, None for responses and messages too
+    short for a header. A query that doesn't parse or doesn't hold exactly one question gets
+    FORMERR, one the upstreams can't answer SERVFAIL.
+ */
+fn forward(resolver: &Resolver, rules: &Rules, message: &[u8], client: SocketAddr) -> Option<DNSPacket> {
+    let header = DNSHeader::deserialize(message).ok()?;
+    if header.flags.is_response {
+        return None;
+    }
+    let mut failure = DNSPacket::with_id(header.id());
+    failure.header.flags.is_response = true;
+    failure.header.flags.recurse_desired = header.flags.recurse_desired;
+    failure.header.flags.recurse_available = true;
+
+    let query = match DNSPacket::deserialize(message) {
+        Ok(query) if query.questions().len() == 1 => query,
+        _ => {
+            tracing::info!(%client, rcode = %ReplyCode::FormErr, "malformed query");
+            failure.set_reply_code(ReplyCode::FormErr);
+            return Some(failure);
+        },
+    };
+    let question = &query.questions()[0];
+    failure.add_question(question.clone());
+
+    // answered here, logged as "blocked" or "overridden" instead of "forwarded"
+    if let Some(action) = rules.lookup(question.name()) {
+        let mut response = failure;
+        let kind = answer_locally(&mut response, question, action, rules.block_with_zero);
+        tracing::info!(
+            %client,
+            name = question.name(),
+            "type" = %question.qtype(),
+            rcode = %response.reply_code(),
+            ms = 0u64,
+            "{}",
+            kind
+        );
+        return Some(response);
+    }
+
+    let start = Instant::now();
+    let mut response = match resolver.query(question.name(), question.qtype()) {
+        Ok((response, _)) => response,
+        Err(err) => {
+            tracing::info!(
+                %client,
+                name = question.name(),
+                "type" = %question.qtype(),
+                rcode = %ReplyCode::ServFail,
+                ms = start.elapsed().as_millis() as u64,
+                %err,
+                "upstream failed"
+            );
+            failure.set_reply_code(ReplyCode::ServFail);
+            return Some(failure);
+        },
+    };
+    tracing::info!(
+        %client,
+        name = question.name(),
+        "type" = %question.qtype(),
+        rcode = %response.reply_code(),
+        ms = start.elapsed().as_millis() as u64,
+        "forwarded"
+    );
+
+    // back under the client's id, with its question as it was asked even if 0x20 changed it upstream
+    response.header.set_id(header.id());
+    response.questions = query.questions.clone();
+    Some(response)
+}
+
+// Fills in the response for a name in the rules, returning what happened for the log
+fn answer_locally(
+    response: &mut DNSPacket,
+    question: &DNSQuestion,
+    action: &Action,
+    block_with_zero: bool,
+) -> &'static str {
+    let zero = [IpAddr::V4(Ipv4Addr::UNSPECIFIED), IpAddr::V6(Ipv6Addr::UNSPECIFIED)];
+    let (addrs, kind): (&[IpAddr], _) = match action {
+        Action::Block if !block_with_zero => {
+            response.set_reply_code(ReplyCode::NxDomain);
+            return "blocked";
+        },
+        Action::Block => (&zero, "blocked"),
+        Action::Override(addrs) => (addrs, "overridden"),
+    };
+    for addr in addrs {
+        let (rtype, data) = match addr {
+            IpAddr::V4(addr) => (RecordType::A, RecordData::A(*addr)),
+            IpAddr::V6(addr) => (RecordType::AAAA, RecordData::Aaaa(*addr)),
+        };
+        if rtype == question.qtype() {
+            let name = String::from(question.name());
+            response.add_answer(DNSRecord { name, rtype, class: RECORD_CLASS, ttl: LOCAL_TTL, data });
+        }
+    }
+    kind
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::IpAddr;
+
+    use crate::proxy::{Action, Rules};
+
+    fn addr(text: &str) -> IpAddr {
+        text.parse().unwrap()
+    }
+
+    #[test]
+    fn exact_and_wildcards() {
+        let mut rules = Rules::new();
+        rules.block("*.ads.example");
+        rules.block("tracker.example.");
+        assert_eq!(rules.lookup("x.ads.example"), Some(&Action::Block));
+        assert_eq!(rules.lookup("deep.x.ADS.example."), Some(&Action::Block));
+        assert_eq!(rules.lookup("Tracker.Example"), Some(&Action::Block));
+        // a wildcard covers what's under the domain, not the domain itself
+        assert_eq!(rules.lookup("ads.example"), None);
+        assert_eq!(rules.lookup("sub.tracker.example"), None);
+        assert_eq!(rules.lookup("example"), None);
+    }
+
+    #[test]
+    fn precedence() {
+        let mut rules = Rules::new();
+        rules.block("*.lan");
+        rules.add_override("printer.lan", addr("192.168.1.50"));
+        rules.add_override("*.office.lan", addr("192.168.2.1"));
+        // an exact override beats the wildcard block, a longer wildcard a shorter one
+        assert_eq!(rules.lookup("printer.lan"), Some(&Action::Override(vec![addr("192.168.1.50")])));
+        assert_eq!(rules.lookup("scanner.office.lan"), Some(&Action::Override(vec![addr("192.168.2.1")])));
+        assert_eq!(rules.lookup("nas.lan"), Some(&Action::Block));
+
+        // overrides win over blocks of the same name, in either order
+        rules.block("printer.lan");
+        rules.add_override("printer.lan", addr("fd00::50"));
+        let both = Action::Override(vec![addr("192.168.1.50"), addr("fd00::50")]);
+        assert_eq!(rules.lookup("printer.lan"), Some(&both));
+        rules.block("nas.lan");
+        rules.add_override("nas.lan", addr("192.168.1.2"));
+        assert_eq!(rules.lookup("nas.lan"), Some(&Action::Override(vec![addr("192.168.1.2")])));
+    }
+
+    #[test]
+    fn hosts_style_blocklist() {
+        let mut rules = Rules::new();
+        let count = rules.load_blocklist("\
+# a comment
+0.0.0.0 ads.example tracker.example # trailing comment
+*.metrics.example
+
+::1 ip6.example
+");
+        assert_eq!((count, rules.len()), (4, 4));
+        assert_eq!(rules.lookup("a.metrics.example"), Some(&Action::Block));
+        assert_eq!(rules.lookup("ip6.example"), Some(&Action::Block));
+        assert_eq!(rules.lookup("0.0.0.0"), None);
+    }
+}
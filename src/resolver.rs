@@ -0,0 +1,1511 @@
+use std::io::Read;
+use std::net::{IpAddr, SocketAddr, UdpSocket};
+#[cfg(unix)]
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{mpsc, Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
+
+use crate::cache::Cache;
+use crate::demux::IdAllocator;
+use crate::error::DnsError;
+use crate::hexdump;
+use crate::hosts::HostsFile;
+use crate::names;
+use crate::packet_ref::DNSPacketRef;
+use crate::packet::{
+    DNSRecord, DNSPacket, DNSQuestion, QueryBuilder, RecordData, RecordType, ReplyCode, Soa, DEFAULT_EDNS_PAYLOAD,
+    RECORD_CLASS, UDP_MESSAGE_SIZE,
+};
+use crate::transport::{self, TcpTransport, UdpTransport};
+#[cfg(feature = "tsig")]
+use crate::tsig::{Tsig, Verifier};
+#[cfg(unix)]
+use crate::transport::UnixTransport;
+#[cfg(feature = "doh")]
+use crate::transport::DohTransport;
+#[cfg(feature = "dot")]
+use crate::transport::TlsTransport;
+
+#[cfg(feature = "dot")]
+pub const DOT_PORT: u16 = 853;
+
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/* Hex dumps of every packet sent and received are trace events on this target,
+    so they can be switched on without the rest of the trace output.
+ */
+pub const WIRE_TARGET: &str = "bkdns::wire";
+
+#[cfg(unix)]
+const RESOLV_CONF: &str = "/etc/resolv.conf";
+
+// Most CNAME hops followed before giving up on a chain
+const MAX_CNAME_CHAIN: usize = 8;
+
+/* How long `lookup_host_dual` waits for the preferred address family once the other has answered,
+    RFC 8305's Resolution Delay
+ */
+pub const DEFAULT_RESOLUTION_DELAY: Duration = Duration::from_millis(50);
+
+// How long a race waits on one socket before checking the other
+const RACE_POLL_INTERVAL: Duration = Duration::from_millis(5);
+
+// Queries sent in 0x20 mode before giving up on getting the casing echoed back
+const DNS0X20_ATTEMPTS: usize = 3;
+
+// Which address family comes first in `lookup_host` results
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IpPreference {
+    Ipv4First,
+    Ipv6First,
+}
+
+// Where `lookup_host` and `lookup_host_dual` found their addresses
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AddressSource {
+    HostsFile,
+    Dns,
+}
+
+// How queries reach the nameserver, each kind has its `transport::Transport` implementation
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TransportKind {
+    Udp,
+    Tcp,
+    // A local stub's stream socket, framed like TCP. `nameservers` aren't used.
+    #[cfg(unix)]
+    Unix(PathBuf),
+    // POSTs to a DNS over HTTPS endpoint URL. `nameservers` aren't used.
+    #[cfg(feature = "doh")]
+    Doh(String),
+    /* TCP framing inside a TLS session to `port` on each nameserver, whose certificate has
+        to be valid for `server_name`.
+     */
+    #[cfg(feature = "dot")]
+    Tls { server_name: String, port: u16 },
+}
+
+impl TransportKind {
+    // Whether queries go to `Resolver::nameservers` rather than an endpoint of their own
+    pub fn uses_nameservers(&self) -> bool {
+        match self {
+            TransportKind::Udp | TransportKind::Tcp => true,
+            #[cfg(feature = "dot")]
+            TransportKind::Tls { .. } => true,
+            #[allow(unreachable_patterns)]
+            _ => false,
+        }
+    }
+
+    // Short name for logs
+    pub fn name(&self) -> &'static str {
+        match self {
+            TransportKind::Udp => "udp",
+            TransportKind::Tcp => "tcp",
+            #[cfg(unix)]
+            TransportKind::Unix(_) => "unix",
+            #[cfg(feature = "doh")]
+            TransportKind::Doh(_) => "doh",
+            #[cfg(feature = "dot")]
+            TransportKind::Tls { .. } => "tls",
+        }
+    }
+}
+
+// What a query told us about the name, derived from the reply code and answer section
+#[derive(Debug, Clone, PartialEq)]
+pub enum QueryOutcome {
+    Answers(Vec<DNSRecord>),
+    // The name exists but has no records of the requested type
+    NoData { soa: Option<Soa> },
+    NxDomain { soa: Option<Soa> },
+    Failure(ReplyCode),
+}
+
+impl QueryOutcome {
+    pub fn from_response(response: &DNSPacket) -> Self {
+        // negative answers carry the zone SOA in the authority section
+        let soa = || response.authority().iter().find_map(|x| match &x.data {
+            RecordData::Soa(soa) => Some(soa.clone()),
+            _ => None,
+        });
+
+        match response.reply_code() {
+            ReplyCode::NoError if response.answers().is_empty() => QueryOutcome::NoData { soa: soa() },
+            ReplyCode::NoError => QueryOutcome::Answers(response.answers().to_vec()),
+            ReplyCode::NxDomain => QueryOutcome::NxDomain { soa: soa() },
+            code => QueryOutcome::Failure(code),
+        }
+    }
+}
+
+pub struct ResolveResult {
+    pub outcome: QueryOutcome,
+    // Time spent waiting on the network, zero when answered from the cache
+    pub elapsed: Duration,
+    // The nameserver that gave the outcome, none from the cache or a Unix socket or DoH endpoint
+    pub server: Option<SocketAddr>,
+    // Nameservers asked, including any that failed before one answered
+    pub attempts: usize,
+    // The question as the server echoed it back, case and all, none from the cache
+    pub question: Option<DNSQuestion>,
+    // How long the answers had been in the cache, none when they came from a nameserver
+    pub age: Option<Duration>,
+}
+
+// A response, which nameserver sent it and how many were asked to get it
+struct Exchange {
+    response: DNSPacket,
+    elapsed: Duration,
+    server: Option<SocketAddr>,
+    attempts: usize,
+}
+
+pub struct Resolver {
+    // Tried in order until one answers, a timeout or network error moves on to the next
+    pub nameservers: Vec<SocketAddr>,
+    pub transport: TransportKind,
+    pub timeout: Duration,
+    pub ip_preference: IpPreference,
+    // How long `lookup_host_dual` gives the `ip_preference` family once the other has answered
+    pub resolution_delay: Duration,
+    // Consulted before the network when set
+    pub cache: Option<Mutex<Cache>>,
+    // Answers `lookup_host` and `lookup_host_dual` before any query is sent, the system's own by default
+    pub hosts: Option<HostsFile>,
+    // Re-query the target when a response stops at a CNAME
+    pub follow_cname: bool,
+    // Randomize the query name's case and require the response to echo it exactly
+    pub dns0x20: bool,
+    // Also move on to the next nameserver when one answers SERVFAIL. NXDOMAIN always stands.
+    pub next_on_servfail: bool,
+    // Ask again over TCP when a UDP query gets SERVFAIL, before moving on to the next nameserver
+    pub retry_tcp_on_servfail: bool,
+    /* Send each UDP query to every nameserver at once and take the first usable response.
+        Other transports still go through the list in order.
+     */
+    pub race: bool,
+    // Start each query one nameserver further down the list, like resolv.conf's `options rotate`
+    pub rotate: bool,
+    // Send an OPT record with the DO bit, asking for RRSIGs alongside the answers
+    pub dnssec_ok: bool,
+    /* Send an OPT record advertising this UDP payload size, and read UDP responses up to it,
+        DEFAULT_EDNS_PAYLOAD to begin with. Unset, queries go without EDNS and responses are held
+        to RFC 1035's 512 bytes, unless `dnssec_ok` still sends the OPT with DEFAULT_EDNS_PAYLOAD.
+     */
+    pub edns_payload: Option<u16>,
+    // Set CD, asking the server not to validate DNSSEC itself
+    pub checking_disabled: bool,
+    // One transaction id for every query instead of a random one, for reproducible captures
+    pub id: Option<u16>,
+    // `query_many` asks again, one at a time, for the questions a response left unanswered
+    pub wait_all: bool,
+    // Sign every query with this key and only take responses the server signed with it too
+    #[cfg(feature = "tsig")]
+    pub tsig: Option<Tsig>,
+    // Trust settings for the Tls transport, the webpki roots when unset
+    #[cfg(feature = "dot")]
+    pub tls_config: Option<Arc<rustls::ClientConfig>>,
+    // Queries started so far, picks the first nameserver with `rotate`
+    rotation: AtomicUsize,
+}
+
+impl Resolver {
+    pub fn new(nameserver: SocketAddr) -> Self {
+        Resolver {
+            nameservers: vec![nameserver],
+            transport: TransportKind::Udp,
+            timeout: DEFAULT_TIMEOUT,
+            ip_preference: IpPreference::Ipv4First,
+            resolution_delay: DEFAULT_RESOLUTION_DELAY,
+            cache: None,
+            hosts: Some(HostsFile::system()),
+            follow_cname: false,
+            dns0x20: false,
+            next_on_servfail: true,
+            retry_tcp_on_servfail: false,
+            race: false,
+            rotate: false,
+            dnssec_ok: false,
+            edns_payload: Some(DEFAULT_EDNS_PAYLOAD),
+            checking_disabled: false,
+            id: None,
+            wait_all: false,
+            #[cfg(feature = "tsig")]
+            tsig: None,
+            #[cfg(feature = "dot")]
+            tls_config: None,
+            rotation: AtomicUsize::new(0),
+        }
+    }
+
+    /* Sends a single recursive query and returns the parsed response, whatever its reply code,
+        along with the time between sending the query and receiving the response.
+     */
+    pub fn query(&self, name: &str, rtype: RecordType) -> Result<(DNSPacket, Duration), DnsError> {
+        self.ask(None, name, rtype).map(|x| (x.response, x.elapsed))
+    }
+
+    fn ask(&self, via: Option<&dyn transport::Transport>, name: &str, rtype: RecordType) -> Result<Exchange, DnsError> {
+        let name = names::validate_name(name)?;
+        if !self.dns0x20 {
+            return self.exchange(via, &name, rtype);
+        }
+
+        // a spoofed reply won't know the casing, drop it and ask again
+        let mut attempts = 0;
+        loop {
+            attempts += 1;
+            let randomized = names::randomize_case(&name, &mut rand::thread_rng());
+            match self.exchange(via, &randomized, rtype) {
+                Err(DnsError::CaseMismatch { .. }) if attempts < DNS0X20_ATTEMPTS => {
+                    tracing::info!(attempts, "the response changed the name's case, asking again");
+                    continue;
+                },
+                result => return result,
+            }
+        }
+    }
+
+    // The recursive query `exchange` sends, with a fresh random id unless `id` is set
+    pub fn build_query(&self, name: &str, rtype: RecordType) -> Result<DNSPacket, DnsError> {
+        self.builder().question(name, rtype).build()
+    }
+
+    fn builder(&self) -> QueryBuilder {
+        let builder = QueryBuilder::new().checking_disabled(self.checking_disabled);
+        let builder = match self.id {
+            Some(id) => builder.id(id),
+            None => builder,
+        };
+        let builder = match self.edns_payload {
+            Some(payload) => builder.edns(payload),
+            None => builder,
+        };
+        builder.dnssec(self.dnssec_ok)
+    }
+
+    // The largest UDP response read: what the query's OPT record advertises, if it has one
+    fn udp_buffer_size(&self) -> usize {
+        match (self.edns_payload, self.dnssec_ok) {
+            (Some(payload), _) => payload as usize,
+            (None, true) => DEFAULT_EDNS_PAYLOAD as usize,
+            (None, false) => UDP_MESSAGE_SIZE,
+        }
+    }
+
+    /* Sends every question in one query. Few recursive resolvers answer more than the first,
+        so with `wait_all` each question left without answers of its own (records of its name
+        and type, or a CNAME for the name) is asked again with `query` and the answers added to
+        the response. The message itself goes out through `send`.
+     */
+    pub fn query_many(&self, questions: &[(&str, RecordType)]) -> Result<(DNSPacket, Duration), DnsError> {
+        let mut builder = self.builder();
+        for (name, rtype) in questions {
+            builder = builder.question(&names::validate_name(name)?, *rtype);
+        }
+        let packet = builder.build()?;
+        let start = Instant::now();
+        let (mut response, _) = self.send(&packet)?;
+
+        if self.wait_all {
+            for question in packet.questions() {
+                let answered = response.answers().iter().any(|x| {
+                    names::eq_ignore_case(&x.name, question.name())
+                        && (x.rtype == question.qtype() || x.rtype == RecordType::CNAME)
+                });
+                if answered {
+                    continue;
+                }
+                tracing::info!(name = question.name(), "type" = %question.qtype(), "unanswered, asking on its own");
+                let (single, _) = self.query(question.name(), question.qtype())?;
+                for record in single.answers() {
+                    response.add_answer(record.clone());
+                }
+            }
+        }
+        Ok((response, start.elapsed()))
+    }
+
+    /* Sends a message built elsewhere, such as an UPDATE, signed when `tsig` is set. The
+        nameservers are tried in order until one answers, without racing, rotation or 0x20.
+     */
+    pub fn send(&self, packet: &DNSPacket) -> Result<(DNSPacket, Duration), DnsError> {
+        let mut bytes = packet.serialize()?;
+        let request_mac = self.sign(&mut bytes)?;
+
+        let start = Instant::now();
+        let mut result = Err(DnsError::NoNameservers);
+        for nameserver in &self.nameservers {
+            result = self.transport_for(&self.transport, *nameserver).query(&bytes).and_then(|reply| {
+                let response = DNSPacket::deserialize(&reply)?;
+                validate_response(packet, &response)?;
+                self.check_signature(&reply, request_mac.as_deref())?;
+                Ok(response)
+            });
+            if !matches!(result, Err(DnsError::Io(_))) || !self.transport.uses_nameservers() {
+                break;
+            }
+            tracing::warn!(server = %nameserver, "no usable answer, trying the next nameserver");
+        }
+        result.map(|response| (response, start.elapsed()))
+    }
+
+    /* Tries the nameservers in order (from a rotating start with `rotate`) until one answers,
+        or asks `via` alone when the caller brought a transport of their own
+     */
+    fn exchange(&self, via: Option<&dyn transport::Transport>, name: &str, rtype: RecordType) -> Result<Exchange, DnsError> {
+        if let Some(via) = via {
+            let (response, elapsed) = self.exchange_via(via, "custom", "custom", name, rtype)?;
+            return Ok(Exchange { response, elapsed, server: None, attempts: 1 });
+        }
+
+        if rtype == RecordType::AXFR && self.transport == TransportKind::Udp {
+            return Err(DnsError::RequiresTcp(rtype));
+        }
+
+        if self.race && self.transport == TransportKind::Udp && self.nameservers.len() > 1 {
+            return self.race(name, rtype);
+        }
+
+        let count = self.nameservers.len();
+        let first = match self.rotate && count > 0 {
+            true => self.rotation.fetch_add(1, Ordering::Relaxed) % count,
+            false => 0,
+        };
+        let mut result = Err(DnsError::NoNameservers);
+        for (attempt, nameserver) in self.nameservers.iter().cycle().skip(first).take(count).enumerate() {
+            result = self.exchange_with(*nameserver, name, rtype).map(|(response, elapsed)| Exchange {
+                response,
+                elapsed,
+                server: self.transport.uses_nameservers().then_some(*nameserver),
+                attempts: attempt + 1,
+            });
+            let next = match &result {
+                Err(DnsError::Io(_)) => true,
+                Ok(exchange) => self.next_on_servfail
+                    && exchange.response.reply_code() == ReplyCode::ServFail,
+                Err(_) => false,
+            };
+            // a Unix socket or DoH endpoint is the only server there is
+            if !self.transport.uses_nameservers() {
+                break;
+            }
+            if !next {
+                break;
+            }
+            tracing::warn!(server = %nameserver, "no usable answer, trying the next nameserver");
+        }
+        result
+    }
+
+    /* Sends the query to every nameserver, each copy with its own id, and returns the first
+        response that matches the copy sent to its server. A SERVFAIL only wins when no other
+        server has anything better before the timeout, and with `next_on_servfail` cleared.
+     */
+    fn race(&self, name: &str, rtype: RecordType) -> Result<Exchange, DnsError> {
+        let span = tracing::info_span!("dns.race", name, "type" = ?rtype, servers = self.nameservers.len());
+        let _entered = span.enter();
+
+        // a socket per address family in use, an IPv4 one can't reach IPv6 servers
+        let mut sockets: Vec<UdpSocket> = Vec::new();
+        for bind_addr in ["0.0.0.0:0", "[::]:0"] {
+            let family_used = self.nameservers.iter().any(|x| x.is_ipv4() == bind_addr.starts_with('0'));
+            if family_used {
+                sockets.push(UdpSocket::bind(bind_addr)?);
+            }
+        }
+
+        let start = Instant::now();
+        let mut ids = IdAllocator::new();
+        // with the MAC of each copy when signed
+        let mut in_flight: Vec<(SocketAddr, DNSPacket, Option<Vec<u8>>)> = Vec::new();
+        for nameserver in &self.nameservers {
+            let mut packet = self.build_query(name, rtype)?;
+            // each server has its own socket address to tell the replies apart by, a pinned id can be shared
+            if self.id.is_none() {
+                packet.header.set_id(ids.allocate()?);
+            }
+            let mut bytes = packet.serialize()?;
+            let request_mac = self.sign(&mut bytes)?;
+            let socket = sockets.iter().find(|x| x.local_addr().is_ok_and(|addr| addr.is_ipv4() == nameserver.is_ipv4()));
+            match socket.map(|x| x.send_to(&bytes, nameserver)) {
+                Some(Ok(_)) => {
+                    tracing::debug!(server = %nameserver, id = packet.header.id, "query sent");
+                    in_flight.push((*nameserver, packet, request_mac));
+                },
+                Some(Err(err)) => tracing::warn!(server = %nameserver, "query not sent: {}", err),
+                None => {},
+            }
+        }
+        let attempts = in_flight.len();
+
+        let deadline = start + self.timeout;
+        let mut fallback: Option<Exchange> = None;
+        let mut last_error = DnsError::NoNameservers;
+        let mut buf = vec![0; self.udp_buffer_size()];
+        while !in_flight.is_empty() {
+            let now = Instant::now();
+            if now >= deadline {
+                break;
+            }
+            // with two sockets neither may block for long, or replies on the other wait
+            let wait = match sockets.len() {
+                1 => deadline - now,
+                _ => (deadline - now).min(RACE_POLL_INTERVAL),
+            };
+            for socket in &sockets {
+                socket.set_read_timeout(Some(wait))?;
+                let (size, peer) = match socket.recv_from(&mut buf) {
+                    Ok(received) => received,
+                    // nothing yet, or an ICMP port unreachable from one server while others may answer
+                    Err(_) => continue,
+                };
+                let response = match DNSPacket::deserialize(&buf[..size]) {
+                    Ok(response) => response,
+                    Err(_) => continue,
+                };
+                // a reply is only held to the query sent to the server it came from
+                let sent_to_peer = in_flight.iter()
+                    .position(|(server, query, _)| *server == peer && query.header.id == response.header.id);
+                let index = match sent_to_peer {
+                    Some(index) => index,
+                    None => continue,
+                };
+                let (server, query, request_mac) = in_flight.remove(index);
+                let valid = validate_response(&query, &response)
+                    .and_then(|_| if self.dns0x20 { validate_case(&query, &response) } else { Ok(()) })
+                    .and_then(|_| self.check_signature(&buf[..size], request_mac.as_deref()));
+                if let Err(err) = valid {
+                    tracing::warn!(server = %server, "unusable response: {}", err);
+                    last_error = err;
+                    continue;
+                }
+
+                let elapsed = start.elapsed();
+                let exchange = Exchange { response, elapsed, server: Some(server), attempts };
+                let servfail = exchange.response.reply_code() == ReplyCode::ServFail;
+                if servfail && self.next_on_servfail {
+                    fallback.get_or_insert(exchange);
+                    continue;
+                }
+                tracing::info!(server = %server, ms = elapsed.as_millis() as u64, "race won");
+                return Ok(exchange);
+            }
+        }
+
+        match fallback {
+            Some(exchange) => Ok(exchange),
+            None if !in_flight.is_empty() => {
+                Err(std::io::Error::new(std::io::ErrorKind::TimedOut, "no nameserver answered in time").into())
+            },
+            None => Err(last_error),
+        }
+    }
+
+    /* One nameserver's response. With `retry_tcp_on_servfail` a SERVFAIL over UDP is asked again
+        over TCP, keeping the SERVFAIL if that can't connect.
+     */
+    fn exchange_with(&self, nameserver: SocketAddr, name: &str, rtype: RecordType) -> Result<(DNSPacket, Duration), DnsError> {
+        let (response, elapsed) = self.exchange_over(&self.transport, nameserver, name, rtype)?;
+        let servfail = response.reply_code() == ReplyCode::ServFail;
+        if !(self.retry_tcp_on_servfail && servfail && self.transport == TransportKind::Udp) {
+            return Ok((response, elapsed));
+        }
+
+        tracing::info!(server = %nameserver, "SERVFAIL over UDP, retrying over TCP");
+        match self.exchange_over(&TransportKind::Tcp, nameserver, name, rtype) {
+            Ok((retried, retry_elapsed)) => Ok((retried, elapsed + retry_elapsed)),
+            Err(err) => {
+                tracing::warn!(server = %nameserver, "TCP retry failed: {}", err);
+                Ok((response, elapsed))
+            },
+        }
+    }
+
+    fn exchange_over(
+        &self,
+        transport: &TransportKind,
+        nameserver: SocketAddr,
+        name: &str,
+        rtype: RecordType,
+    ) -> Result<(DNSPacket, Duration), DnsError> {
+        let via = self.transport_for(transport, nameserver);
+        self.exchange_via(via.as_ref(), transport.name(), &self.server_label(nameserver), name, rtype)
+    }
+
+    fn exchange_via(
+        &self,
+        transport: &dyn transport::Transport,
+        kind: &str,
+        label: &str,
+        name: &str,
+        rtype: RecordType,
+    ) -> Result<(DNSPacket, Duration), DnsError> {
+        let span = tracing::info_span!("dns.query", name, "type" = ?rtype, server = %label);
+        let _entered = span.enter();
+
+        let packet = self.build_query(name, rtype)?;
+        let mut bytes = packet.serialize()?;
+        let request_mac = self.sign(&mut bytes)?;
+        tracing::info!(transport = kind, "sending query");
+        tracing::debug!(bytes = bytes.len(), id = packet.header.id, flags = ?packet.header.flags, "query built");
+        tracing::trace!(target: WIRE_TARGET, "query, {} bytes\n{}", bytes.len(), dump_packet(&bytes));
+
+        let start = Instant::now();
+        let reply = transport.query(&bytes)?;
+        let elapsed = start.elapsed();
+        tracing::info!(bytes = reply.len(), ms = elapsed.as_millis() as u64, "response received");
+        tracing::trace!(target: WIRE_TARGET, "response, {} bytes\n{}", reply.len(), dump_packet(&reply));
+
+        let response = DNSPacket::deserialize(&reply)?;
+        tracing::debug!(id = response.header.id, flags = ?response.header.flags, "response parsed");
+        validate_response(&packet, &response)?;
+        if self.dns0x20 {
+            validate_case(&packet, &response)?;
+        }
+        self.check_signature(&reply, request_mac.as_deref())?;
+        Ok((response, elapsed))
+    }
+
+    /* Outcome of asking for `name`, from the cache if enabled.
+        With `follow_cname`, answers hold every hop of the chain followed by the final records.
+     */
+    pub fn resolve(&self, name: &str, rtype: RecordType) -> Result<ResolveResult, DnsError> {
+        self.resolve_via(None, name, rtype)
+    }
+
+    /* Like `resolve`, over a transport of the caller's own instead of `transport` and `nameservers`.
+        The cache, 0x20 and CNAME following still apply, but there's no server to fail over to
+        and `server` is left unset.
+     */
+    pub fn resolve_with(
+        &self,
+        transport: &dyn transport::Transport,
+        name: &str,
+        rtype: RecordType,
+    ) -> Result<ResolveResult, DnsError> {
+        self.resolve_via(Some(transport), name, rtype)
+    }
+
+    fn resolve_via(
+        &self,
+        via: Option<&dyn transport::Transport>,
+        name: &str,
+        rtype: RecordType,
+    ) -> Result<ResolveResult, DnsError> {
+        let result = self.resolve_once(via, name, rtype)?;
+        match result.outcome {
+            QueryOutcome::Answers(_) if self.follow_cname && rtype != RecordType::CNAME => {
+                self.follow_cnames(via, name, rtype, result)
+            },
+            _ => Ok(result),
+        }
+    }
+
+    /* The server reported is the last one asked, attempts add up over every hop, the question is
+        the first and the age is the oldest of the hops answered from the cache
+     */
+    fn follow_cnames(
+        &self,
+        via: Option<&dyn transport::Transport>,
+        name: &str,
+        rtype: RecordType,
+        first: ResolveResult,
+    ) -> Result<ResolveResult, DnsError> {
+        let ResolveResult { outcome, mut elapsed, mut server, mut attempts, question, mut age } = first;
+        let mut answers = match outcome {
+            QueryOutcome::Answers(answers) => answers,
+            outcome => return Ok(ResolveResult { outcome, elapsed, server, attempts, question, age }),
+        };
+        loop {
+            let chain = aliases(&answers, name);
+            if chain.len() > MAX_CNAME_CHAIN + 1 {
+                return Err(DnsError::CnameLoop(String::from(name)));
+            }
+
+            let target = String::from(*chain.last().unwrap());
+            let resolved = answers.iter()
+                .any(|x| x.rtype == rtype && names::eq_ignore_case(&x.name, &target));
+            if resolved || chain.len() == 1 {
+                return Ok(ResolveResult {
+                    outcome: QueryOutcome::Answers(answers),
+                    elapsed,
+                    server,
+                    attempts,
+                    question,
+                    age,
+                });
+            }
+
+            let hops = chain.len();
+            let result = self.resolve_once(via, &target, rtype)?;
+            elapsed += result.elapsed;
+            attempts += result.attempts;
+            server = result.server.or(server);
+            age = age.max(result.age);
+            match result.outcome {
+                QueryOutcome::Answers(more) => answers.extend(more),
+                outcome => return Ok(ResolveResult { outcome, elapsed, server, attempts, question, age }),
+            }
+            // the target answered without extending the chain or resolving it
+            let chain = aliases(&answers, name);
+            if chain.len() == hops && !answers.iter().any(|x| x.rtype == rtype) {
+                return Err(DnsError::CnameLoop(String::from(name)));
+            }
+        }
+    }
+
+    fn resolve_once(
+        &self,
+        via: Option<&dyn transport::Transport>,
+        name: &str,
+        rtype: RecordType,
+    ) -> Result<ResolveResult, DnsError> {
+        if let Some(cache) = &self.cache {
+            if let Some((answers, age)) = cache.lock().unwrap().get_with_age(name, rtype, RECORD_CLASS) {
+                tracing::debug!(name, "type" = ?rtype, records = answers.len(), ?age, "answered from the cache");
+                return Ok(ResolveResult {
+                    outcome: QueryOutcome::Answers(answers.to_vec()),
+                    elapsed: Duration::ZERO,
+                    server: None,
+                    attempts: 0,
+                    question: None,
+                    age: Some(age),
+                });
+            }
+        }
+
+        if self.cache.is_some() {
+            tracing::debug!(name, "type" = ?rtype, "not in the cache");
+        }
+        let exchange = self.ask(via, name, rtype)?;
+        if let Some(cache) = &self.cache {
+            tracing::debug!(name, "type" = ?rtype, "caching the response");
+            cache.lock().unwrap().insert(&exchange.response);
+        }
+        Ok(ResolveResult {
+            outcome: QueryOutcome::from_response(&exchange.response),
+            elapsed: exchange.elapsed,
+            server: exchange.server,
+            attempts: exchange.attempts,
+            question: exchange.response.questions().first().cloned(),
+            age: None,
+        })
+    }
+
+    // What an exchange with `nameserver` goes over
+    fn transport_for(&self, transport: &TransportKind, nameserver: SocketAddr) -> Box<dyn transport::Transport> {
+        let timeout = self.timeout;
+        match transport {
+            TransportKind::Udp => Box::new(UdpTransport { nameserver, timeout, buffer_size: self.udp_buffer_size() }),
+            TransportKind::Tcp => Box::new(TcpTransport { nameserver, timeout }),
+            #[cfg(unix)]
+            TransportKind::Unix(path) => Box::new(UnixTransport { path: path.clone(), timeout }),
+            #[cfg(feature = "doh")]
+            TransportKind::Doh(url) => Box::new(DohTransport { url: url.clone(), timeout }),
+            #[cfg(feature = "dot")]
+            TransportKind::Tls { server_name, port } => Box::new(TlsTransport {
+                nameserver: SocketAddr::new(nameserver.ip(), *port),
+                server_name: server_name.clone(),
+                config: self.tls_config.clone(),
+                timeout,
+            }),
+        }
+    }
+
+    /* Transfers the whole zone over a stream transport (TCP to the first nameserver unless `transport` is Unix).
+        The records come back in the order sent, starting and ending with the zone's SOA.
+     */
+    pub fn axfr(&self, zone: &str) -> Result<Vec<DNSRecord>, DnsError> {
+        let packet = QueryBuilder::new()
+            .recursion(false)
+            .question(&names::validate_name(zone)?, RecordType::AXFR)
+            .build()?;
+        let mut bytes = packet.serialize()?;
+        let request_mac = self.sign(&mut bytes)?;
+        let verifier = self.verifier(request_mac);
+
+        match &self.transport {
+            #[cfg(unix)]
+            TransportKind::Unix(path) => {
+                let mut stream = transport::connect_unix(path, self.timeout)?;
+                transport::write_tcp_message(&mut stream, &bytes)?;
+                read_transfer(&mut stream, &packet, verifier)
+            },
+            _ => {
+                let nameserver = *self.nameservers.first().ok_or(DnsError::NoNameservers)?;
+                let mut stream = transport::connect_tcp(nameserver, self.timeout)?;
+                transport::write_tcp_message(&mut stream, &bytes)?;
+                read_transfer(&mut stream, &packet, verifier)
+            },
+        }
+    }
+
+    // Signs a serialized query when `tsig` is set, returning the MAC the response has to build on
+    #[cfg(feature = "tsig")]
+    fn sign(&self, bytes: &mut Vec<u8>) -> Result<Option<Vec<u8>>, DnsError> {
+        self.tsig.as_ref().map(|tsig| tsig.sign(bytes, unix_time())).transpose()
+    }
+
+    #[cfg(not(feature = "tsig"))]
+    fn sign(&self, _bytes: &mut Vec<u8>) -> Result<Option<Vec<u8>>, DnsError> {
+        Ok(None)
+    }
+
+    #[cfg(feature = "tsig")]
+    fn check_signature(&self, reply: &[u8], request_mac: Option<&[u8]>) -> Result<(), DnsError> {
+        match self.tsig.as_ref().zip(request_mac) {
+            Some((tsig, request_mac)) => tsig.verify(reply, request_mac, unix_time()),
+            None => Ok(()),
+        }
+    }
+
+    #[cfg(not(feature = "tsig"))]
+    fn check_signature(&self, _reply: &[u8], _request_mac: Option<&[u8]>) -> Result<(), DnsError> {
+        Ok(())
+    }
+
+    // What checks a transfer's messages against the query's MAC, when `tsig` is set
+    #[cfg(feature = "tsig")]
+    fn verifier(&self, request_mac: Option<Vec<u8>>) -> Option<Verifier<'_>> {
+        self.tsig.as_ref().zip(request_mac).map(|(tsig, mac)| tsig.verifier(&mac))
+    }
+
+    #[cfg(not(feature = "tsig"))]
+    fn verifier(&self, _request_mac: Option<Vec<u8>>) -> Option<Verifier> {
+        None
+    }
+
+    /* Resolves both A and AAAA records for `name`, ordered by `ip_preference`, and says where
+        they came from. A name in the hosts file gets its addresses there and nothing is sent.
+     */
+    pub fn lookup_host(&self, name: &str) -> Result<(Vec<IpAddr>, AddressSource), DnsError> {
+        let from_hosts = self.from_hosts(name);
+        if !from_hosts.is_empty() {
+            return Ok((from_hosts, AddressSource::HostsFile));
+        }
+        let v4 = self.lookup_family(name, RecordType::A);
+        let v6 = self.lookup_family(name, RecordType::AAAA);
+
+        let merged = match self.ip_preference {
+            IpPreference::Ipv4First => merge(v4, v6),
+            IpPreference::Ipv6First => merge(v6, v4),
+        };
+        merged.map(|addrs| (addrs, AddressSource::Dns))
+    }
+
+    /* Happy Eyeballs style (RFC 8305 section 3): the A and AAAA lookups run at once, each on a
+        thread of its own. Addresses of the `ip_preference` family are returned as soon as they're
+        back. When the other family answers first it waits `resolution_delay` more for the
+        preferred one, and a preferred family without addresses waits for the other. Whatever is
+        back by then, or by the overall deadline of `timeout` for each nameserver, is returned,
+        starting with the `ip_preference` family and alternating between the two. A lookup still
+        running is left to finish on its own and its result dropped.
+     */
+    pub fn lookup_host_dual(self: &Arc<Self>, name: &str) -> Result<(Vec<IpAddr>, AddressSource), DnsError> {
+        let from_hosts = self.from_hosts(name);
+        if !from_hosts.is_empty() {
+            return Ok((interleave(from_hosts), AddressSource::HostsFile));
+        }
+        let (sender, receiver) = mpsc::channel();
+        for rtype in [RecordType::A, RecordType::AAAA] {
+            let (resolver, name, sender) = (Arc::clone(self), String::from(name), sender.clone());
+            std::thread::spawn(move || {
+                // the receiver is gone when this family took too long
+                let _ = sender.send((rtype, resolver.lookup_family(&name, rtype)));
+            });
+        }
+        drop(sender);
+
+        let start = Instant::now();
+        let mut deadline = start + self.timeout * self.nameservers.len().max(1) as u32;
+        let preferred = match self.ip_preference {
+            IpPreference::Ipv4First => RecordType::A,
+            IpPreference::Ipv6First => RecordType::AAAA,
+        };
+        let (mut v4, mut v6) = (None, None);
+        while v4.is_none() || v6.is_none() {
+            let (rtype, result) = match receiver.recv_timeout(deadline.saturating_duration_since(Instant::now())) {
+                Ok(received) => received,
+                Err(_) => break,
+            };
+            tracing::debug!(name, "type" = ?rtype, ms = start.elapsed().as_millis() as u64, "family resolved");
+            let usable = result.as_ref().is_ok_and(|x| !x.is_empty());
+            match rtype {
+                RecordType::A => v4 = Some(result),
+                _ => v6 = Some(result),
+            }
+            match rtype == preferred {
+                true if usable => break,
+                true => {},
+                false => deadline = deadline.min(Instant::now() + self.resolution_delay),
+            }
+        }
+
+        let missing = || Err(DnsError::from(std::io::Error::new(std::io::ErrorKind::TimedOut, "no answer in time")));
+        let (v4, v6) = (v4.unwrap_or_else(missing), v6.unwrap_or_else(missing));
+        let merged = match self.ip_preference {
+            IpPreference::Ipv4First => merge(v4, v6),
+            IpPreference::Ipv6First => merge(v6, v4),
+        };
+        merged.map(|addrs| (interleave(addrs), AddressSource::Dns))
+    }
+
+    // The addresses `hosts` has for `name`, the `ip_preference` family first
+    pub fn from_hosts(&self, name: &str) -> Vec<IpAddr> {
+        from_hosts(self.hosts.as_ref(), name, self.ip_preference)
+    }
+
+    fn lookup_family(&self, name: &str, rtype: RecordType) -> Result<Vec<IpAddr>, DnsError> {
+        match self.resolve(name, rtype)?.outcome {
+            QueryOutcome::Answers(answers) => Ok(addresses(&answers, name)),
+            QueryOutcome::NoData { .. } => Ok(Vec::new()),
+            QueryOutcome::NxDomain { .. } => Err(DnsError::NxDomain(String::from(name))),
+            QueryOutcome::Failure(code) => Err(DnsError::ReplyCode(code)),
+        }
+    }
+
+    // The nameserver an exchange goes to, as logged
+    fn server_label(&self, nameserver: SocketAddr) -> String {
+        match &self.transport {
+            #[cfg(unix)]
+            TransportKind::Unix(path) => path.display().to_string(),
+            #[cfg(feature = "doh")]
+            TransportKind::Doh(url) => url.clone(),
+            #[cfg(feature = "dot")]
+            TransportKind::Tls { server_name, port } => format!("{} ({})", SocketAddr::new(nameserver.ip(), *port), server_name),
+            _ => nameserver.to_string(),
+        }
+    }
+}
+
+// Sections are marked when the bytes parse, a packet that doesn't is dumped as it is
+fn dump_packet(bytes: &[u8]) -> String {
+    match DNSPacketRef::deserialize(bytes) {
+        Ok(view) => hexdump::hexdump_packet(bytes, view.layout()),
+        Err(_) => hexdump::hexdump(bytes),
+    }
+}
+
+/* Reads AXFR response messages until the SOA that opened the transfer shows up again.
+    With a verifier every message goes through it, and the last has to be signed.
+ */
+fn read_transfer<R: Read>(
+    stream: &mut R,
+    query: &DNSPacket,
+    mut verifier: Option<Verifier>,
+) -> Result<Vec<DNSRecord>, DnsError> {
+    let mut records: Vec<DNSRecord> = Vec::new();
+    loop {
+        let message = transport::read_tcp_message(stream)?;
+        let response = DNSPacket::deserialize(&message)?;
+        validate_response(query, &response)?;
+        // only ever false with tsig, the one build that checks it
+        #[cfg_attr(not(feature = "tsig"), allow(unused_variables))]
+        let signed = match verifier.as_mut() {
+            Some(verifier) => verifier.verify(&message, unix_time())?,
+            None => true,
+        };
+        match response.reply_code() {
+            ReplyCode::NoError => {},
+            ReplyCode::Refused => {
+                let zone = query.questions().first().map(|x| x.name()).unwrap_or_default();
+                return Err(DnsError::TransferRefused(String::from(zone)));
+            },
+            code => return Err(DnsError::ReplyCode(code)),
+        }
+
+        for record in response.answers() {
+            if records.is_empty() && record.rtype != RecordType::SOA {
+                return Err(DnsError::TransferMissingSoa);
+            }
+            records.push(record.clone());
+            if records.len() > 1 && record.rtype == RecordType::SOA {
+                #[cfg(feature = "tsig")]
+                if !signed {
+                    return Err(DnsError::TsigInvalid("the transfer's last message isn't signed"));
+                }
+                return Ok(records);
+            }
+        }
+    }
+}
+
+// Without tsig nothing verifies a transfer, so there is never one of these
+#[cfg(not(feature = "tsig"))]
+enum Verifier {}
+
+#[cfg(not(feature = "tsig"))]
+impl Verifier {
+    fn verify(&mut self, _message: &[u8], _now: u64) -> Result<bool, DnsError> {
+        match *self {}
+    }
+}
+
+// Seconds since the Unix epoch, what TSIG times are in
+fn unix_time() -> u64 {
+    SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).map_or(0, |x| x.as_secs())
+}
+
+// Guards against misrouted packets: the reply must be a response to this query
+pub(crate) fn validate_response(query: &DNSPacket, response: &DNSPacket) -> Result<(), DnsError> {
+    if !response.header.flags.is_response {
+        return Err(DnsError::NotAResponse);
+    }
+    if response.header.id != query.header.id {
+        return Err(DnsError::IdMismatch {
+            expected: query.header.id,
+            received: response.header.id,
+        });
+    }
+    Ok(())
+}
+
+// 0x20 mode: the echoed question must match the randomized casing byte for byte
+fn validate_case(query: &DNSPacket, response: &DNSPacket) -> Result<(), DnsError> {
+    let expected = query.questions().first().map(|x| x.name()).unwrap_or_default();
+    let received = response.questions().first().map(|x| x.name()).unwrap_or_default();
+    if expected != received {
+        return Err(DnsError::CaseMismatch {
+            expected: String::from(expected),
+            received: String::from(received),
+        });
+    }
+    Ok(())
+}
+
+// `name` followed by each CNAME target in the answer section, stopping at a loop
+fn aliases<'a>(answers: &'a [DNSRecord], name: &'a str) -> Vec<&'a str> {
+    let mut chain: Vec<&str> = vec![name];
+    while let Some(target) = answers.iter().find_map(|x| match &x.data {
+        RecordData::Cname(target) if names::eq_ignore_case(&x.name, chain.last().unwrap()) => Some(target),
+        _ => None,
+    }) {
+        if chain.iter().any(|x| names::eq_ignore_case(x, target)) {
+            break;
+        }
+        chain.push(target);
+    }
+    chain
+}
+
+// Collects the addresses for `name` from an answer section, following any CNAMEs
+pub(crate) fn addresses(answers: &[DNSRecord], name: &str) -> Vec<IpAddr> {
+    let aliases = aliases(answers, name);
+    answers.iter()
+        .filter(|x| aliases.iter().any(|alias| names::eq_ignore_case(alias, &x.name)))
+        .filter_map(|x| match x.data {
+            RecordData::A(addr) => Some(IpAddr::V4(addr)),
+            RecordData::Aaaa(addr) => Some(IpAddr::V6(addr)),
+            _ => None,
+        })
+        .collect()
+}
+
+pub(crate) fn from_hosts(hosts: Option<&HostsFile>, name: &str, preference: IpPreference) -> Vec<IpAddr> {
+    let Some(hosts) = hosts else { return Vec::new() };
+    let mut addrs = hosts.lookup(name);
+    if !addrs.is_empty() {
+        tracing::debug!(name, path = %hosts.path().display(), addresses = addrs.len(), "found in the hosts file");
+    }
+    // stable, so each family keeps the file's order
+    addrs.sort_by_key(|x| match preference {
+        IpPreference::Ipv4First => x.is_ipv6(),
+        IpPreference::Ipv6First => x.is_ipv4(),
+    });
+    addrs
+}
+
+// One family failing is fine as long as the other produced addresses
+pub(crate) fn merge(
+    first: Result<Vec<IpAddr>, DnsError>,
+    second: Result<Vec<IpAddr>, DnsError>,
+) -> Result<Vec<IpAddr>, DnsError> {
+    match (first, second) {
+        (Ok(mut first), Ok(second)) => {
+            first.extend(second);
+            Ok(first)
+        },
+        (Ok(addrs), Err(err)) | (Err(err), Ok(addrs)) => match addrs.is_empty() {
+            true => Err(err),
+            false => Ok(addrs),
+        },
+        (Err(err), Err(_)) => Err(err),
+    }
+}
+
+/* The nameservers /etc/resolv.conf lists, in order. Empty when it can't be read or has none,
+    so callers fall back to a default of their own.
+ */
+#[cfg(unix)]
+pub fn system_nameservers() -> Vec<IpAddr> {
+    std::fs::read_to_string(RESOLV_CONF).map(|x| parse_resolv_conf(&x)).unwrap_or_default()
+}
+
+/* The addresses on `nameserver` lines of a resolv.conf. Comments start with # or ; and the
+    other options are ignored, as are link-local addresses with a %zone that IpAddr can't hold.
+ */
+pub fn parse_resolv_conf(text: &str) -> Vec<IpAddr> {
+    text.lines()
+        .map(|x| x.split(['#', ';']).next().unwrap_or_default())
+        .filter_map(|line| {
+            let mut words = line.split_whitespace();
+            match (words.next(), words.next()) {
+                (Some("nameserver"), Some(addr)) => addr.parse().ok(),
+                _ => None,
+            }
+        })
+        .collect()
+}
+
+/* Alternates between the families, starting with the family of the first address (RFC 8305
+    section 4) and keeping the order within each family
+ */
+pub(crate) fn interleave(addrs: Vec<IpAddr>) -> Vec<IpAddr> {
+    let first_is_v6 = addrs.first().is_some_and(|x| x.is_ipv6());
+    let (mut first, mut second): (Vec<IpAddr>, Vec<IpAddr>) = addrs.into_iter().partition(|x| x.is_ipv6() == first_is_v6);
+    let mut interleaved = Vec::with_capacity(first.len() + second.len());
+    first.reverse();
+    second.reverse();
+    while !first.is_empty() || !second.is_empty() {
+        interleaved.extend(first.pop());
+        interleaved.extend(second.pop());
+    }
+    interleaved
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+    use crate::testdata::CNAME_RESPONSE;
+
+    #[test]
+    fn validate_response() {
+        let query = crate::packet::DNSPacket::with_id(0x1234);
+        let response = crate::packet::DNSPacket::deserialize(&CNAME_RESPONSE).unwrap();
+        assert!(crate::resolver::validate_response(&query, &response).is_ok());
+
+        // same packet with QR clear
+        let mut bytes = CNAME_RESPONSE;
+        bytes[2] &= 0x7F;
+        let not_response = crate::packet::DNSPacket::deserialize(&bytes).unwrap();
+        assert!(matches!(
+            crate::resolver::validate_response(&query, &not_response),
+            Err(crate::error::DnsError::NotAResponse)
+        ));
+
+        let other_query = crate::packet::DNSPacket::with_id(0x4321);
+        assert!(matches!(
+            crate::resolver::validate_response(&other_query, &response),
+            Err(crate::error::DnsError::IdMismatch { expected: 0x4321, received: 0x1234 })
+        ));
+    }
+
+    #[test]
+    fn validate_case() {
+        let mut query = crate::packet::DNSPacket::with_id(0x1234);
+        query.add_question(crate::packet::DNSQuestion::new(
+            String::from("wWw.eXaMpLe.CoM"), crate::packet::RecordType::A
+        ).unwrap());
+
+        let mut bytes = CNAME_RESPONSE;
+        bytes[12..29].copy_from_slice(&query.questions()[0].serialize().unwrap()[..17]);
+        let echoed = crate::packet::DNSPacket::deserialize(&bytes).unwrap();
+        assert!(crate::resolver::validate_case(&query, &echoed).is_ok());
+
+        // lowercase echo, as a spoofer guessing the name would send
+        let spoofed = crate::packet::DNSPacket::deserialize(&CNAME_RESPONSE).unwrap();
+        assert!(matches!(
+            crate::resolver::validate_case(&query, &spoofed),
+            Err(crate::error::DnsError::CaseMismatch { .. })
+        ));
+    }
+
+    #[test]
+    fn addresses_follow_cname() {
+        let response = crate::packet::DNSPacket::deserialize(&CNAME_RESPONSE).unwrap();
+        assert_eq!(
+            crate::resolver::addresses(response.answers(), "WWW.example.com"),
+            vec![IpAddr::V4(Ipv4Addr::new(93, 184, 216, 34))]
+        );
+        assert!(crate::resolver::addresses(response.answers(), "other.example.com").is_empty());
+    }
+
+    #[test]
+    fn resolve_uses_cache() {
+        let mut cache = crate::cache::Cache::new();
+        cache.insert(&crate::packet::DNSPacket::deserialize(&CNAME_RESPONSE).unwrap());
+
+        // nothing listens on the discard port, so only a cache hit can succeed
+        let mut resolver = crate::resolver::Resolver::new("127.0.0.1:9".parse().unwrap());
+        resolver.cache = Some(std::sync::Mutex::new(cache));
+        assert_eq!(
+            resolver.lookup_host("www.example.com").unwrap(),
+            (vec![IpAddr::V4(Ipv4Addr::new(93, 184, 216, 34))], crate::resolver::AddressSource::Dns)
+        );
+        // cache hits say how long they've been held
+        let result = resolver.resolve("www.example.com", crate::packet::RecordType::A).unwrap();
+        assert!(result.age.is_some_and(|x| x < std::time::Duration::from_secs(1)));
+    }
+
+    #[test]
+    fn lookup_host_uses_hosts_file() {
+        let path = std::env::temp_dir().join(format!("bkdns-test-{}-resolver.hosts", std::process::id()));
+        std::fs::write(&path, "2001:db8::1 files\n192.0.2.1 files\n192.0.2.2 files\n").unwrap();
+
+        // nothing listens on the discard port, so only the hosts file can answer
+        let mut resolver = crate::resolver::Resolver::new("127.0.0.1:9".parse().unwrap());
+        resolver.timeout = std::time::Duration::from_millis(100);
+        resolver.hosts = Some(crate::hosts::HostsFile::new(&path));
+        let (v4, v6) = (IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1)), IpAddr::V6("2001:db8::1".parse().unwrap()));
+        let second = IpAddr::V4(Ipv4Addr::new(192, 0, 2, 2));
+        let from_hosts = crate::resolver::AddressSource::HostsFile;
+        assert_eq!(resolver.lookup_host("FILES").unwrap(), (vec![v4, second, v6], from_hosts));
+        resolver.ip_preference = crate::resolver::IpPreference::Ipv6First;
+        assert_eq!(resolver.lookup_host("files").unwrap(), (vec![v6, v4, second], from_hosts));
+        assert_eq!(std::sync::Arc::new(resolver).lookup_host_dual("files").unwrap(), (vec![v6, v4, second], from_hosts));
+
+        let mut resolver = crate::resolver::Resolver::new("127.0.0.1:9".parse().unwrap());
+        resolver.timeout = std::time::Duration::from_millis(100);
+        resolver.hosts = None;
+        assert!(resolver.lookup_host("files").is_err());
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn merge_partial_failure() {
+        let v4 = IpAddr::V4(Ipv4Addr::LOCALHOST);
+        let v6 = IpAddr::V6(Ipv6Addr::LOCALHOST);
+
+        assert_eq!(crate::resolver::merge(Ok(vec![v6]), Ok(vec![v4])).unwrap(), vec![v6, v4]);
+        assert_eq!(
+            crate::resolver::merge(
+                Err(crate::error::DnsError::NxDomain(String::from("example.com"))),
+                Ok(vec![v4]),
+            ).unwrap(),
+            vec![v4]
+        );
+        assert!(crate::resolver::merge(
+            Ok(vec![]),
+            Err(crate::error::DnsError::ReplyCode(crate::packet::ReplyCode::ServFail)),
+        ).is_err());
+    }
+
+    #[test]
+    fn parse_resolv_conf() {
+        let text = "\
+# Generated by NetworkManager
+search example.com
+nameserver 192.0.2.53
+nameserver 2001:db8::53 ; the v6 one
+nameserver fe80::1%eth0
+nameserver not-an-address
+  nameserver\t198.51.100.1
+options rotate timeout:2
+";
+        assert_eq!(crate::resolver::parse_resolv_conf(text), [
+            IpAddr::V4(Ipv4Addr::new(192, 0, 2, 53)),
+            IpAddr::V6(Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 0x53)),
+            IpAddr::V4(Ipv4Addr::new(198, 51, 100, 1)),
+        ]);
+        assert!(crate::resolver::parse_resolv_conf("search example.com\n").is_empty());
+    }
+
+    #[test]
+    fn interleave_families() {
+        let v4 = |last: u8| IpAddr::V4(Ipv4Addr::new(192, 0, 2, last));
+        let v6 = |last: u16| IpAddr::V6(Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, last));
+
+        assert_eq!(
+            crate::resolver::interleave(vec![v6(1), v6(2), v6(3), v4(1), v4(2)]),
+            vec![v6(1), v4(1), v6(2), v4(2), v6(3)]
+        );
+        assert_eq!(crate::resolver::interleave(vec![v4(1), v4(2), v6(1)]), vec![v4(1), v6(1), v4(2)]);
+        assert_eq!(crate::resolver::interleave(vec![v4(1), v4(2)]), vec![v4(1), v4(2)]);
+        assert!(crate::resolver::interleave(Vec::new()).is_empty());
+    }
+
+    #[test]
+    fn outcome_from_response() {
+        use crate::resolver::QueryOutcome;
+
+        let answers = crate::packet::DNSPacket::deserialize(&CNAME_RESPONSE).unwrap();
+        assert!(matches!(QueryOutcome::from_response(&answers), QueryOutcome::Answers(x) if x.len() == 2));
+
+        let nodata = crate::packet::DNSPacket::deserialize(&hex_literal::hex!(
+            """
+            12 34 81 80 00 01 00 00 00 00 00 00
+            07 65 78 61 6d 70 6c 65 03 63 6f 6d 00 00 0f 00 01
+            """
+        )).unwrap();
+        assert_eq!(QueryOutcome::from_response(&nodata), QueryOutcome::NoData { soa: None });
+
+        let nxdomain = crate::packet::DNSPacket::deserialize(&crate::testdata::NXDOMAIN_RESPONSE).unwrap();
+        assert!(matches!(
+            QueryOutcome::from_response(&nxdomain),
+            QueryOutcome::NxDomain { soa: Some(soa) } if soa.serial == 2024010101
+        ));
+
+        let servfail = crate::packet::DNSPacket::deserialize(&hex_literal::hex!(
+            "12 34 81 82 00 00 00 00 00 00 00 00"
+        )).unwrap();
+        assert_eq!(
+            QueryOutcome::from_response(&servfail),
+            QueryOutcome::Failure(crate::packet::ReplyCode::ServFail)
+        );
+    }
+
+    // Resolver whose answers can only come from the given cached responses
+    fn cached_resolver(responses: &[&[u8]]) -> crate::resolver::Resolver {
+        let mut cache = crate::cache::Cache::new();
+        for response in responses {
+            cache.insert(&crate::packet::DNSPacket::deserialize(response).unwrap());
+        }
+        let mut resolver = crate::resolver::Resolver::new("127.0.0.1:9".parse().unwrap());
+        resolver.cache = Some(std::sync::Mutex::new(cache));
+        resolver.follow_cname = true;
+        resolver
+    }
+
+    #[test]
+    fn follow_cname_chain() {
+        let resolver = cached_resolver(&[
+            // www.example.com CNAME example.com
+            &hex_literal::hex!(
+                """
+                12 34 81 80 00 01 00 01 00 00 00 00
+                03 77 77 77 07 65 78 61 6d 70 6c 65 03 63 6f 6d 00 00 01 00 01
+                c0 0c 00 05 00 01 00 00 0e 10 00 02 c0 10
+                """
+            ),
+            // example.com A 93.184.216.34
+            &hex_literal::hex!(
+                """
+                12 34 81 80 00 01 00 01 00 00 00 00
+                07 65 78 61 6d 70 6c 65 03 63 6f 6d 00 00 01 00 01
+                c0 0c 00 01 00 01 00 00 0e 10 00 04 5d b8 d8 22
+                """
+            ),
+        ]);
+
+        match resolver.resolve("www.example.com", crate::packet::RecordType::A).unwrap().outcome {
+            crate::resolver::QueryOutcome::Answers(answers) => {
+                assert_eq!(answers.len(), 2);
+                assert_eq!(answers[0].data, crate::packet::RecordData::Cname(String::from("example.com")));
+                assert_eq!(answers[1].data, crate::packet::RecordData::A(Ipv4Addr::new(93, 184, 216, 34)));
+            },
+            outcome => panic!("unexpected outcome {:?}", outcome),
+        }
+    }
+
+    #[test]
+    fn follow_cname_loop() {
+        let resolver = cached_resolver(&[
+            // www.example.com CNAME example.com
+            &hex_literal::hex!(
+                """
+                12 34 81 80 00 01 00 01 00 00 00 00
+                03 77 77 77 07 65 78 61 6d 70 6c 65 03 63 6f 6d 00 00 01 00 01
+                c0 0c 00 05 00 01 00 00 0e 10 00 02 c0 10
+                """
+            ),
+            // example.com CNAME www.example.com
+            &hex_literal::hex!(
+                """
+                12 34 81 80 00 01 00 01 00 00 00 00
+                07 65 78 61 6d 70 6c 65 03 63 6f 6d 00 00 01 00 01
+                c0 0c 00 05 00 01 00 00 0e 10 00 06 03 77 77 77 c0 0c
+                """
+            ),
+        ]);
+
+        assert!(matches!(
+            resolver.resolve("www.example.com", crate::packet::RecordType::A),
+            Err(crate::error::DnsError::CnameLoop(_))
+        ));
+    }
+
+    fn transfer_message(id: u16, records: Vec<crate::packet::DNSRecord>) -> Vec<u8> {
+        let mut packet = crate::packet::DNSPacket::with_id(id);
+        packet.header.flags.is_response = true;
+        for record in records {
+            packet.add_answer(record);
+        }
+        let mut stream = Vec::new();
+        crate::transport::write_tcp_message(&mut stream, &packet.serialize().unwrap()).unwrap();
+        stream
+    }
+
+    #[test]
+    fn read_transfer() {
+        use crate::packet::{DNSRecord, RecordData, RecordType, Soa, RECORD_CLASS};
+
+        let soa = DNSRecord {
+            name: String::from("example.com"),
+            rtype: RecordType::SOA,
+            class: RECORD_CLASS,
+            ttl: 3600,
+            data: RecordData::Soa(Soa {
+                mname: String::from("ns.example.com"),
+                rname: String::from("hostmaster.example.com"),
+                serial: 1, refresh: 7200, retry: 3600, expire: 1209600, minimum: 300,
+            }),
+        };
+        let a = |name: &str| DNSRecord {
+            name: String::from(name),
+            rtype: RecordType::A,
+            class: RECORD_CLASS,
+            ttl: 300,
+            data: RecordData::A(Ipv4Addr::LOCALHOST),
+        };
+        let query = crate::packet::DNSPacket::with_id(0x1234);
+
+        // spread over two messages, anything after the closing SOA is left unread
+        let mut stream = transfer_message(0x1234, vec![soa.clone(), a("www.example.com")]);
+        stream.extend(transfer_message(0x1234, vec![a("mail.example.com"), soa.clone()]));
+        stream.extend(transfer_message(0x1234, vec![a("late.example.com")]));
+        let records = crate::resolver::read_transfer(&mut stream.as_slice(), &query, None).unwrap();
+        assert_eq!(records.len(), 4);
+        assert_eq!(records[0], soa);
+        assert_eq!(records[2].name, "mail.example.com");
+        assert_eq!(records[3], soa);
+
+        let stream = transfer_message(0x1234, vec![a("www.example.com"), soa.clone()]);
+        assert!(matches!(
+            crate::resolver::read_transfer(&mut stream.as_slice(), &query, None),
+            Err(crate::error::DnsError::TransferMissingSoa)
+        ));
+
+        // the connection closing before the second SOA is an error, not a partial zone
+        let stream = transfer_message(0x1234, vec![soa.clone(), a("www.example.com")]);
+        assert!(matches!(
+            crate::resolver::read_transfer(&mut stream.as_slice(), &query, None),
+            Err(crate::error::DnsError::Io(_))
+        ));
+
+        let mut refused = crate::packet::DNSPacket::with_id(0x1234);
+        refused.header.flags.is_response = true;
+        refused.set_reply_code(crate::packet::ReplyCode::Refused);
+        let mut stream = Vec::new();
+        crate::transport::write_tcp_message(&mut stream, &refused.serialize().unwrap()).unwrap();
+        assert!(matches!(
+            crate::resolver::read_transfer(&mut stream.as_slice(), &query, None),
+            Err(crate::error::DnsError::TransferRefused(_))
+        ));
+    }
+
+    #[test]
+    fn axfr_over_udp_rejected() {
+        let resolver = crate::resolver::Resolver::new("127.0.0.1:9".parse().unwrap());
+        assert!(matches!(
+            resolver.query("example.com", crate::packet::RecordType::AXFR),
+            Err(crate::error::DnsError::RequiresTcp(crate::packet::RecordType::AXFR))
+        ));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn query_over_unix_socket() {
+        let path = std::env::temp_dir().join(format!("bkdns-test-{}.sock", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let listener = std::os::unix::net::UnixListener::bind(&path).unwrap();
+
+        // a stub that answers every question with 127.0.0.1
+        let server = std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let query = crate::packet::DNSPacket::deserialize(
+                &crate::transport::read_tcp_message(&mut stream).unwrap()
+            ).unwrap();
+            let mut response = crate::packet::DNSPacket::with_id(query.header.id);
+            response.header.flags.is_response = true;
+            let question = &query.questions()[0];
+            response.add_question(crate::packet::DNSQuestion::new(
+                String::from(question.name()), question.qtype()
+            ).unwrap());
+            response.add_answer(crate::packet::DNSRecord {
+                name: String::from(question.name()),
+                rtype: crate::packet::RecordType::A,
+                class: crate::packet::RECORD_CLASS,
+                ttl: 60,
+                data: crate::packet::RecordData::A(Ipv4Addr::LOCALHOST),
+            });
+            crate::transport::write_tcp_message(&mut stream, &response.serialize().unwrap()).unwrap();
+        });
+
+        let mut resolver = crate::resolver::Resolver::new("127.0.0.1:9".parse().unwrap());
+        resolver.transport = crate::resolver::TransportKind::Unix(path.clone());
+        let (response, _) = resolver.query("example.com", crate::packet::RecordType::A).unwrap();
+        assert_eq!(
+            crate::resolver::addresses(response.answers(), "example.com"),
+            vec![IpAddr::V4(Ipv4Addr::LOCALHOST)]
+        );
+        server.join().unwrap();
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[cfg(feature = "doh")]
+    #[test]
+    fn query_over_doh() {
+        use std::io::{Read, Write};
+
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/dns-query", listener.local_addr().unwrap());
+
+        // a plain HTTP endpoint that answers the POSTed query with 127.0.0.1
+        let server = std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request: Vec<u8> = Vec::new();
+            let mut buf = [0; 1024];
+            let headers_end = loop {
+                let size = stream.read(&mut buf).unwrap();
+                request.extend_from_slice(&buf[..size]);
+                if let Some(index) = request.windows(4).position(|x| x == b"\r\n\r\n") {
+                    break index + 4;
+                }
+            };
+            let headers = String::from_utf8(request[..headers_end].to_vec()).unwrap().to_ascii_lowercase();
+            assert!(headers.starts_with("post /dns-query "));
+            assert!(headers.contains("content-type: application/dns-message"));
+            let length: usize = headers.lines()
+                .find_map(|x| x.strip_prefix("content-length: "))
+                .unwrap()
+                .trim()
+                .parse()
+                .unwrap();
+            while request.len() < headers_end + length {
+                let size = stream.read(&mut buf).unwrap();
+                request.extend_from_slice(&buf[..size]);
+            }
+
+            let query = crate::packet::DNSPacket::deserialize(&request[headers_end..]).unwrap();
+            let mut response = crate::packet::DNSPacket::with_id(query.header.id);
+            response.header.flags.is_response = true;
+            response.add_answer(crate::packet::DNSRecord {
+                name: String::from(query.questions()[0].name()),
+                rtype: crate::packet::RecordType::A,
+                class: crate::packet::RECORD_CLASS,
+                ttl: 60,
+                data: crate::packet::RecordData::A(Ipv4Addr::LOCALHOST),
+            });
+            let body = response.serialize().unwrap();
+            write!(
+                stream,
+                "HTTP/1.1 200 OK\r\nContent-Type: application/dns-message\r\nContent-Length: {}\r\n\r\n",
+                body.len()
+            ).unwrap();
+            stream.write_all(&body).unwrap();
+        });
+
+        let mut resolver = crate::resolver::Resolver::new("127.0.0.1:9".parse().unwrap());
+        resolver.transport = crate::resolver::TransportKind::Doh(url);
+        let (response, _) = resolver.query("example.com", crate::packet::RecordType::A).unwrap();
+        assert_eq!(
+            crate::resolver::addresses(response.answers(), "example.com"),
+            vec![IpAddr::V4(Ipv4Addr::LOCALHOST)]
+        );
+        server.join().unwrap();
+    }
+}
@@ -0,0 +1,124 @@
+/* Comparing answer sets the way DNS defines them: order doesn't matter, names
+    (owners and those inside rdata) compare case-insensitively, and TTLs aren't
+    part of a record's identity.
+ */
+use alloc::vec::Vec;
+
+use crate::names;
+use crate::packet::{DNSRecord, RecordData};
+
+// How the answer set changed between two responses
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Diff {
+    pub added: Vec<DNSRecord>,
+    pub removed: Vec<DNSRecord>,
+    // Records in both whose TTL went up, meaning the answering cache fetched them again
+    pub refreshed: Vec<DNSRecord>,
+}
+
+impl Diff {
+    // Records came or went, a TTL refresh alone doesn't count
+    pub fn changed(&self) -> bool {
+        !self.added.is_empty() || !self.removed.is_empty()
+    }
+}
+
+pub fn diff(old: &[DNSRecord], new: &[DNSRecord]) -> Diff {
+    let find = |records: &[DNSRecord], record: &DNSRecord| records.iter().find(|x| same_record(x, record)).cloned();
+    Diff {
+        added: new.iter().filter(|x| find(old, x).is_none()).cloned().collect(),
+        removed: old.iter().filter(|x| find(new, x).is_none()).cloned().collect(),
+        refreshed: new.iter()
+            .filter(|x| find(old, x).is_some_and(|old| x.ttl > old.ttl))
+            .cloned()
+            .collect(),
+    }
+}
+
+// Same set of records, ignoring order and TTLs
+pub fn same_rrset(a: &[DNSRecord], b: &[DNSRecord]) -> bool {
+    !diff(a, b).changed()
+}
+
+// Same set of records with the same TTL on each
+pub fn same_rrset_with_ttl(a: &[DNSRecord], b: &[DNSRecord]) -> bool {
+    same_rrset(a, b) && a.iter().all(|x| b.iter().any(|y| same_record(x, y) && x.ttl == y.ttl))
+}
+
+pub fn same_record(a: &DNSRecord, b: &DNSRecord) -> bool {
+    a.rtype == b.rtype && a.class == b.class && names::eq_ignore_case(&a.name, &b.name) && same_data(&a.data, &b.data)
+}
+
+fn same_data(a: &RecordData, b: &RecordData) -> bool {
+    match (a, b) {
+        (RecordData::Cname(a), RecordData::Cname(b))
+        | (RecordData::Ns(a), RecordData::Ns(b))
+        | (RecordData::Ptr(a), RecordData::Ptr(b)) => names::eq_ignore_case(a, b),
+        (RecordData::Mx { pref: a_pref, exchange: a }, RecordData::Mx { pref: b_pref, exchange: b }) => {
+            a_pref == b_pref && names::eq_ignore_case(a, b)
+        },
+        (
+            RecordData::Srv { priority: a_priority, weight: a_weight, port: a_port, target: a },
+            RecordData::Srv { priority: b_priority, weight: b_weight, port: b_port, target: b },
+        ) => (a_priority, a_weight, a_port) == (b_priority, b_weight, b_port) && names::eq_ignore_case(a, b),
+        (RecordData::Soa(a), RecordData::Soa(b)) => {
+            names::eq_ignore_case(&a.mname, &b.mname)
+                && names::eq_ignore_case(&a.rname, &b.rname)
+                && (a.serial, a.refresh, a.retry, a.expire, a.minimum) == (b.serial, b.refresh, b.retry, b.expire, b.minimum)
+        },
+        (a, b) => a == b,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::packet::{DNSRecord, RecordData, RecordType, RECORD_CLASS};
+    use crate::testutil::a;
+
+    #[test]
+    fn order_case_and_ttl_ignored() {
+        let old = [a("example.com", 1), a("example.com", 2)];
+        let new = [DNSRecord { ttl: 10, ..a("EXAMPLE.com.", 2) }, DNSRecord { ttl: 20, ..a("example.COM", 1) }];
+        assert!(crate::rrset::same_rrset(&old, &new));
+        assert_eq!(crate::rrset::diff(&old, &new), crate::rrset::Diff::default());
+    }
+
+    #[test]
+    fn added_and_removed() {
+        let old = [a("example.com", 1), a("example.com", 2)];
+        let new = [a("example.com", 2), a("example.com", 3)];
+        let diff = crate::rrset::diff(&old, &new);
+        assert!(diff.changed());
+        assert_eq!(diff.added, [a("example.com", 3)]);
+        assert_eq!(diff.removed, [a("example.com", 1)]);
+    }
+
+    #[test]
+    fn same_rrset_with_ttl() {
+        let old = [a("example.com", 1), a("example.com", 2)];
+        let new = [a("example.com", 2), DNSRecord { ttl: 299, ..a("example.com", 1) }];
+        assert!(crate::rrset::same_rrset(&old, &new));
+        assert!(!crate::rrset::same_rrset_with_ttl(&old, &new));
+        assert!(crate::rrset::same_rrset_with_ttl(&old, &[a("Example.com", 2), a("example.com", 1)]));
+    }
+
+    #[test]
+    fn ttl_reset_is_a_refresh() {
+        let diff = crate::rrset::diff(&[DNSRecord { ttl: 12, ..a("example.com", 1) }], &[a("example.com", 1)]);
+        assert!(!diff.changed());
+        assert_eq!(diff.refreshed, [a("example.com", 1)]);
+    }
+
+    #[test]
+    fn rdata_names_ignore_case() {
+        let cname = |target: &str| DNSRecord {
+            name: String::from("www.example.com"),
+            rtype: RecordType::CNAME,
+            class: RECORD_CLASS,
+            ttl: 300,
+            data: RecordData::Cname(String::from(target)),
+        };
+        assert!(crate::rrset::same_record(&cname("Example.COM"), &cname("example.com")));
+        assert!(!crate::rrset::same_record(&cname("example.org"), &cname("example.com")));
+    }
+}
@@ -0,0 +1,149 @@
+/* Field serializers for the serde feature. Byte strings go out as text so JSON stays readable:
+    base64 for keys and signatures, as zone files write them, hex for unknown rdata, and TXT
+    character-strings with the zone file escapes but not the quotes.
+ */
+#[cfg(feature = "bincode")]
+use alloc::string::ToString;
+use alloc::string::String;
+use alloc::vec::Vec;
+
+use serde::de::Error;
+use serde::{Deserialize, Deserializer, Serializer};
+
+#[cfg(feature = "bincode")]
+use crate::error::DnsError;
+#[cfg(feature = "bincode")]
+use crate::packet::DNSPacket;
+use crate::zonefile::decode_base64;
+
+// Parsed packets stored as bincode, for caches and fixtures rather than anyone reading them
+#[cfg(feature = "bincode")]
+impl DNSPacket {
+    pub fn to_bincode(&self) -> Result<Vec<u8>, DnsError> {
+        bincode::serialize(self).map_err(|err| DnsError::Bincode(err.to_string()))
+    }
+
+    pub fn from_bincode(bytes: &[u8]) -> Result<Self, DnsError> {
+        bincode::deserialize(bytes).map_err(|err| DnsError::Bincode(err.to_string()))
+    }
+}
+
+pub mod base64 {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(bytes: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&crate::zonefile::base64(bytes))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+        decode_base64(&String::deserialize(deserializer)?).ok_or_else(|| D::Error::custom("invalid base64"))
+    }
+}
+
+pub mod hex {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(bytes: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+        let encoded: String = bytes.iter().map(|x| alloc::format!("{:02x}", x)).collect();
+        serializer.serialize_str(&encoded)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+        crate::zonefile::decode_hex(&String::deserialize(deserializer)?).ok_or_else(|| D::Error::custom("invalid hex"))
+    }
+}
+
+pub mod character_strings {
+    use serde::ser::SerializeSeq;
+
+    use super::*;
+
+    pub fn serialize<S: Serializer>(strings: &[Vec<u8>], serializer: S) -> Result<S::Ok, S::Error> {
+        let mut seq = serializer.serialize_seq(Some(strings.len()))?;
+        for string in strings {
+            seq.serialize_element(&crate::zonefile::escape(string))?;
+        }
+        seq.end()
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<Vec<u8>>, D::Error> {
+        Vec::<String>::deserialize(deserializer)?
+            .iter()
+            .map(|x| crate::zonefile::unescape_all(x).ok_or_else(|| D::Error::custom("invalid escape")))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::packet::{DNSPacket, RecordData};
+    use crate::testdata::{A_RESPONSE, DNSSEC_RESPONSE};
+
+    #[test]
+    fn json_strings() {
+        let packet = DNSPacket::deserialize(&A_RESPONSE).unwrap();
+        let json = serde_json::to_value(&packet).unwrap();
+        assert_eq!(json["answers"][0]["data"]["A"], "93.184.216.34");
+
+        // signatures come out as zone files write them
+        let packet = DNSPacket::deserialize(&DNSSEC_RESPONSE).unwrap();
+        let json = serde_json::to_value(&packet).unwrap();
+        let signatures: Vec<_> = packet.answers.iter()
+            .filter_map(|x| match &x.data {
+                RecordData::Rrsig(rrsig) => Some(crate::zonefile::base64(&rrsig.signature)),
+                _ => None,
+            })
+            .collect();
+        assert!(!signatures.is_empty());
+        let json_signatures: Vec<_> = json["answers"].as_array().unwrap().iter()
+            .filter_map(|x| x["data"]["Rrsig"]["signature"].as_str())
+            .collect();
+        assert_eq!(json_signatures, signatures);
+
+        // TXT strings keep bytes that aren't UTF-8
+        let txt = RecordData::Txt(vec![b"v=spf1 -all".to_vec(), vec![0xff, b'"']]);
+        let json = serde_json::to_value(&txt).unwrap();
+        assert_eq!(json["Txt"], serde_json::json!(["v=spf1 -all", "\\255\\\""]));
+        assert_eq!(serde_json::from_value::<RecordData>(json).unwrap(), txt);
+    }
+
+    #[test]
+    fn binary_strings() {
+        assert_eq!(crate::zonefile::decode_base64("AQID"), Some(vec![1, 2, 3]));
+        assert_eq!(crate::zonefile::decode_base64("AQ=="), Some(vec![1]));
+        assert_eq!(crate::zonefile::decode_base64("AQI="), Some(vec![1, 2]));
+        assert_eq!(crate::zonefile::decode_base64("AQ="), None);
+        assert_eq!(crate::zonefile::decode_base64("A*=="), None);
+        assert_eq!(crate::zonefile::decode_hex("00ff7e"), Some(vec![0, 0xFF, 0x7E]));
+        assert_eq!(crate::zonefile::decode_hex("0g"), None);
+    }
+
+    #[cfg(feature = "bincode")]
+    #[test]
+    fn bincode_helpers() {
+        for bytes in [&A_RESPONSE[..], &DNSSEC_RESPONSE] {
+            let packet = DNSPacket::deserialize(bytes).unwrap();
+            let stored = packet.to_bincode().unwrap();
+            assert_eq!(DNSPacket::from_bincode(&stored).unwrap(), packet);
+            assert!(stored.len() < serde_json::to_vec(&packet).unwrap().len());
+            assert!(matches!(
+                DNSPacket::from_bincode(&stored[..stored.len() - 1]),
+                Err(crate::error::DnsError::Bincode(_))
+            ));
+        }
+    }
+
+    proptest::proptest! {
+        #[test]
+        fn json_round_trip(packet in crate::test_support::packet()) {
+            let json = serde_json::to_string(&packet).unwrap();
+            proptest::prop_assert_eq!(serde_json::from_str::<DNSPacket>(&json).unwrap(), packet);
+        }
+
+        #[test]
+        fn bincode_round_trip(packet in crate::test_support::packet()) {
+            let bytes = bincode::serialize(&packet).unwrap();
+            proptest::prop_assert_eq!(bincode::deserialize::<DNSPacket>(&bytes).unwrap(), packet);
+        }
+    }
+}
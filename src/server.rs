@@ -0,0 +1,523 @@
+/* A small authoritative server over UDP and TCP, answering from records loaded with
+    `zonefile::parse`. With an SOA among them the zone is the SOA's owner and everything under
+    it, names outside are refused and negative answers carry the SOA. Without one every name is
+    taken as ours.
+ */
+use std::net::{IpAddr, SocketAddr, TcpListener, TcpStream, UdpSocket};
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+use crate::error::DnsError;
+use crate::names;
+use crate::notify::{self, Notify};
+use crate::packet::{
+    DNSHeader, DNSPacket, DNSQuestion, DNSRecord, RecordData, RecordType, ReplyCode, SerializeOptions,
+    DEFAULT_EDNS_PAYLOAD, MAX_MESSAGE_SIZE,
+};
+use crate::transport::{read_tcp_message, write_tcp_message};
+
+// Most a response can be over UDP when the query didn't offer more with EDNS
+const UDP_RESPONSE_SIZE: usize = 512; // bytes
+
+// Largest UDP message taken in, the payload size our own OPT records advertise
+pub(crate) const UDP_RECV_SIZE: usize = DEFAULT_EDNS_PAYLOAD as usize; // bytes
+
+// TCP connections left idle this long are closed
+const TCP_IDLE_TIMEOUT: Duration = Duration::from_secs(10);
+
+// CNAMEs followed inside the zone before the answer is sent as it is
+const MAX_CNAME_HOPS: usize = 8;
+
+pub struct Zone {
+    records: Vec<DNSRecord>,
+}
+
+impl Zone {
+    pub fn new(records: Vec<DNSRecord>) -> Self {
+        Zone { records }
+    }
+
+    pub fn records(&self) -> &[DNSRecord] {
+        &self.records
+    }
+
+    fn soa(&self) -> Option<&DNSRecord> {
+        self.records.iter().find(|x| x.rtype == RecordType::SOA)
+    }
+
+    // The name of the zone, None without an SOA
+    pub fn origin(&self) -> Option<&str> {
+        self.soa().map(|x| x.name.as_str())
+    }
+
+    pub fn serial(&self) -> Option<u32> {
+        match self.soa()?.data {
+            RecordData::Soa(ref soa) => Some(soa.serial),
+            _ => None,
+        }
+    }
+
+    /* The response to one query message, None when there's nothing to answer: a message too
+        short to hold an id, or one that is itself a response. Anything else that doesn't parse
+        gets FORMERR, opcodes other than QUERY get NOTIMP.
+     */
+    pub fn respond(&self, message: &[u8]) -> Option<DNSPacket> {
+        let id = u16::from_be_bytes([*message.first()?, *message.get(1)?]);
+        let header = DNSHeader::deserialize(message).ok();
+        if header.as_ref().is_some_and(|x| x.flags.is_response) {
+            return None;
+        }
+
+        let mut response = DNSPacket::with_id(id);
+        response.header.flags.is_response = true;
+        if let Some(header) = &header {
+            response.header.flags.opcode = header.flags.opcode;
+            response.header.flags.recurse_desired = header.flags.recurse_desired;
+        }
+        let query = match DNSPacket::deserialize(message) {
+            Ok(query) => query,
+            Err(_) => {
+                response.set_reply_code(ReplyCode::FormErr);
+                return Some(response);
+            },
+        };
+        for question in query.questions() {
+            response.add_question(question.clone());
+        }
+
+        let question = match query.questions() {
+            _ if query.header.flags.opcode != 0 => {
+                response.set_reply_code(ReplyCode::NotImp);
+                return Some(response);
+            },
+            [question] => question,
+            _ => {
+                response.set_reply_code(ReplyCode::FormErr);
+                return Some(response);
+            },
+        };
+        self.answer(question, &mut response);
+        Some(response)
+    }
+
+    fn answer(&self, question: &DNSQuestion, response: &mut DNSPacket) {
+        let soa = self.soa();
+        // zone transfers aren't served
+        if question.qtype() == RecordType::AXFR || soa.is_some_and(|soa| !names::in_zone(question.name(), &soa.name)) {
+            response.set_reply_code(ReplyCode::Refused);
+            return;
+        }
+        response.header.flags.is_authoritative = true;
+
+        let mut name = String::from(question.name());
+        for _ in 0..=MAX_CNAME_HOPS {
+            let found: Vec<&DNSRecord> = self.records.iter()
+                .filter(|x| x.rtype == question.qtype() && names::eq_ignore_case(&x.name, &name))
+                .collect();
+            if !found.is_empty() {
+                for record in found {
+                    response.add_answer(record.clone());
+                }
+                return;
+            }
+            let alias = self.records.iter()
+                .find(|x| x.rtype == RecordType::CNAME && names::eq_ignore_case(&x.name, &name));
+            match alias {
+                Some(alias @ DNSRecord { data: RecordData::Cname(target), .. }) => {
+                    response.add_answer(alias.clone());
+                    name = target.clone();
+                },
+                _ => break,
+            }
+        }
+        // the chain left the zone or ran out, the client carries on from its last target
+        let continues = self.records.iter()
+            .any(|x| x.rtype == RecordType::CNAME && names::eq_ignore_case(&x.name, &name));
+        if continues || soa.is_some_and(|soa| !names::in_zone(&name, &soa.name)) {
+            return;
+        }
+
+        /* a name that exists, if only as the parent of others, has no data of this type. After
+            CNAMEs it's the last target the reply code is about (RFC 6604 section 2).
+         */
+        let exists = self.records.iter().any(|x| names::in_zone(&x.name, &name));
+        if !exists {
+            response.set_reply_code(ReplyCode::NxDomain);
+        }
+        // negative answers can be cached for the SOA's minimum at most (RFC 2308 section 5)
+        if let Some(soa @ DNSRecord { data: RecordData::Soa(fields), .. }) = soa {
+            let mut soa = soa.clone();
+            soa.ttl = soa.ttl.min(fields.minimum);
+            response.add_authority(soa);
+        }
+    }
+}
+
+// Listens on the same address and port for both
+pub struct Server {
+    socket: UdpSocket,
+    listener: TcpListener,
+    zone: Arc<RwLock<Zone>>,
+}
+
+impl Server {
+    pub fn bind(addr: SocketAddr, zone: Zone) -> Result<Self, DnsError> {
+        let socket = UdpSocket::bind(addr)?;
+        // the UDP port, in case `addr` left it to the system
+        let listener = TcpListener::bind(socket.local_addr()?)?;
+        Ok(Server { socket, listener, zone: Arc::new(RwLock::new(zone)) })
+    }
+
+    pub fn serial(&self) -> Option<u32> {
+        self.zone.read().unwrap().serial()
+    }
+
+    // Swaps in new records, queries from then on are answered from them
+    pub fn set_zone(&self, zone: Zone) {
+        *self.zone.write().unwrap() = zone;
+    }
+
+    pub fn local_addr(&self) -> Result<SocketAddr, DnsError> {
+        Ok(self.socket.local_addr()?)
+    }
+
+    // Answers UDP queries one at a time until the socket fails, TCP connections on threads of their own
+    pub fn run(&self) -> Result<(), DnsError> {
+        let zone = self.zone.clone();
+        serve_tcp(self.listener.try_clone()?, move |message, peer| {
+            let response = zone.read().unwrap().respond(message)?;
+            log_failure(response_bytes(response, message, MAX_MESSAGE_SIZE), peer)
+        });
+
+        let mut buf = [0; UDP_RECV_SIZE];
+        loop {
+            let (size, peer) = self.socket.recv_from(&mut buf)?;
+            let message = &buf[..size];
+            let response = match self.zone.read().unwrap().respond(message) {
+                Some(response) => response,
+                None => continue,
+            };
+            if let Some(bytes) = log_failure(response_bytes(response, message, udp_limit(message)), peer) {
+                send_reply(&self.socket, &bytes, peer);
+            }
+        }
+    }
+}
+
+/* Takes NOTIFY messages for one zone on a UDP socket of its own and acknowledges them. Any
+    other message, a NOTIFY for another zone or one from a peer that isn't allowed is REFUSED.
+ */
+pub struct NotifyListener {
+    socket: UdpSocket,
+    zone: String,
+    allowed: Vec<IpAddr>,
+}
+
+impl NotifyListener {
+    pub fn bind(addr: SocketAddr, zone: &str) -> Result<Self, DnsError> {
+        let zone = names::validate_name(zone)?;
+        Ok(NotifyListener { socket: UdpSocket::bind(addr)?, zone, allowed: Vec::new() })
+    }
+
+    // Only peers at these addresses may notify, anyone can when it's empty
+    pub fn with_allowed(mut self, allowed: Vec<IpAddr>) -> Self {
+        self.allowed = allowed;
+        self
+    }
+
+    pub fn local_addr(&self) -> Result<SocketAddr, DnsError> {
+        Ok(self.socket.local_addr()?)
+    }
+
+    // Acknowledges notifies until the socket fails, calling `on_notify` after each is answered
+    pub fn run<F>(&self, mut on_notify: F) -> Result<(), DnsError>
+    where
+        F: FnMut(Notify, SocketAddr),
+    {
+        let mut buf = [0; UDP_RECV_SIZE];
+        loop {
+            let (size, peer) = self.socket.recv_from(&mut buf)?;
+            let Some((response, notify)) = self.acknowledge(&buf[..size], peer.ip()) else { continue };
+            if let Some(bytes) = log_failure(response.serialize(), peer) {
+                send_reply(&self.socket, &bytes, peer);
+            }
+            match notify {
+                Ok(notify) => on_notify(notify, peer),
+                Err(reason) => tracing::warn!(%peer, reason, "NOTIFY refused"),
+            }
+        }
+    }
+
+    /* The response to one message and the notify it carried or why it was refused, None for
+        a message too short to hold an id or a response
+     */
+    fn acknowledge(&self, message: &[u8], peer: IpAddr) -> Option<(DNSPacket, Result<Notify, &'static str>)> {
+        let id = u16::from_be_bytes([*message.first()?, *message.get(1)?]);
+        let header = DNSHeader::deserialize(message).ok();
+        if header.as_ref().is_some_and(|x| x.flags.is_response) {
+            return None;
+        }
+
+        let mut response = DNSPacket::with_id(id);
+        response.header.flags.is_response = true;
+        response.header.flags.opcode = header.map_or(notify::OPCODE_NOTIFY, |x| x.flags.opcode);
+        let notify = DNSPacket::deserialize(message)
+            .map_err(|_| "malformed")
+            .and_then(|packet| {
+                for question in packet.questions() {
+                    response.add_question(question.clone());
+                }
+                notify::parse(&packet)
+            })
+            .and_then(|notify| match names::eq_ignore_case(&notify.zone, &self.zone) {
+                true => Ok(notify),
+                false => Err("not our zone"),
+            })
+            .and_then(|notify| match self.allowed.is_empty() || self.allowed.contains(&peer) {
+                true => Ok(notify),
+                false => Err("peer not allowed"),
+            });
+        if notify.is_err() {
+            response.set_reply_code(ReplyCode::Refused);
+        }
+        Some((response, notify))
+    }
+}
+
+// A reply that can't be sent, to a forged or unreachable source say, is logged and the next message read
+fn send_reply(socket: &UdpSocket, bytes: &[u8], peer: SocketAddr) {
+    if let Err(err) = socket.send_to(bytes, peer) {
+        tracing::warn!(%peer, %err, "couldn't send a response");
+    }
+}
+
+fn log_failure(bytes: Result<Vec<u8>, DnsError>, peer: SocketAddr) -> Option<Vec<u8>> {
+    bytes.map_err(|err| tracing::warn!(%peer, %err, "couldn't serialize a response")).ok()
+}
+
+// The UDP payload size in the query's OPT record, if it has one
+fn edns_payload(query: &[u8]) -> Option<u16> {
+    let query = DNSPacket::deserialize(query).ok()?;
+    query.additional().iter().find(|x| x.rtype == RecordType::OPT).map(|x| x.class)
+}
+
+/* Most a UDP response to `query` can be: the payload its EDNS offers, within what we'd accept
+    ourselves, or 512 bytes without EDNS (RFC 6891 section 6.2.5)
+ */
+pub(crate) fn udp_limit(query: &[u8]) -> usize {
+    edns_payload(query).map_or(UDP_RESPONSE_SIZE, |x| (x as usize).clamp(UDP_RESPONSE_SIZE, UDP_RECV_SIZE))
+}
+
+/* The response serialized within `limit` bytes, cut down with `truncate_to` when it's bigger.
+    Without room for all of the answers TC is set, so a UDP client asks again over TCP.
+ */
+fn response_bytes(mut response: DNSPacket, query: &[u8], limit: usize) -> Result<Vec<u8>, DnsError> {
+    // EDNS in the query is answered with EDNS (RFC 6891 section 7)
+    if edns_payload(query).is_some() {
+        response.add_additional(DNSRecord::opt(DEFAULT_EDNS_PAYLOAD, false));
+    }
+    let options = SerializeOptions { compress: true };
+    response.truncate_to(limit, &options);
+    response.serialize_with(&options)
+}
+
+/* Serves length-prefixed messages on every connection `listener` accepts, each connection on
+    a thread of its own. `handler` answers one message, None sends nothing back. Connections
+    are read until the client closes them or leaves them idle for TCP_IDLE_TIMEOUT.
+ */
+pub(crate) fn serve_tcp<F>(listener: TcpListener, handler: F)
+where
+    F: Fn(&[u8], SocketAddr) -> Option<Vec<u8>> + Send + Sync + 'static,
+{
+    let handler = Arc::new(handler);
+    std::thread::spawn(move || {
+        for stream in listener.incoming() {
+            match stream {
+                Ok(stream) => {
+                    let handler = handler.clone();
+                    std::thread::spawn(move || serve_connection(stream, &*handler));
+                },
+                Err(err) => tracing::warn!(%err, "couldn't accept a TCP connection"),
+            }
+        }
+    });
+}
+
+fn serve_connection<F>(mut stream: TcpStream, handler: &F)
+where
+    F: Fn(&[u8], SocketAddr) -> Option<Vec<u8>>,
+{
+    let Ok(peer) = stream.peer_addr() else { return };
+    if stream.set_read_timeout(Some(TCP_IDLE_TIMEOUT)).is_err() {
+        return;
+    }
+    while let Ok(message) = read_tcp_message(&mut stream) {
+        if let Some(bytes) = handler(&message, peer) {
+            if write_tcp_message(&mut stream, &bytes).is_err() {
+                return;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+
+    use crate::packet::{DNSPacket, DNSRecord, RecordData, RecordType, ReplyCode};
+
+    const ZONE: &str = "\
+example.com. 3600 SOA ns.example.com. admin.example.com. 1 7200 3600 1209600 300
+example.com. 300 A 192.0.2.1
+www.example.com. 300 CNAME example.com.
+gone.example.com. 300 CNAME missing.example.com.
+away.example.com. 300 CNAME www.example.org.
+a.b.example.com. 300 A 192.0.2.2
+";
+
+    fn respond(name: &str, rtype: RecordType) -> DNSPacket {
+        let zone = crate::server::Zone::new(crate::zonefile::parse(ZONE).unwrap());
+        let query = DNSPacket::query(name, rtype).unwrap();
+        zone.respond(&query.serialize().unwrap()).unwrap()
+    }
+
+    #[test]
+    fn answers() {
+        let response = respond("Example.com", RecordType::A);
+        assert!(response.header.flags.is_authoritative && response.header.flags.recurse_desired);
+        assert_eq!(response.questions()[0].name(), "Example.com");
+        assert_eq!(response.answers()[0].data, RecordData::A(Ipv4Addr::new(192, 0, 2, 1)));
+
+        // the alias and what it points to
+        let response = respond("www.example.com", RecordType::A);
+        let types: Vec<RecordType> = response.answers().iter().map(|x| x.rtype).collect();
+        assert_eq!(types, [RecordType::CNAME, RecordType::A]);
+    }
+
+    #[test]
+    fn negative_answers() {
+        let response = respond("example.com", RecordType::MX);
+        assert_eq!(response.reply_code(), ReplyCode::NoError);
+        assert!(response.answers().is_empty());
+        assert_eq!(response.authority()[0].rtype, RecordType::SOA);
+        assert_eq!(response.authority()[0].ttl, 300);
+
+        // an empty non-terminal exists
+        assert_eq!(respond("b.example.com", RecordType::A).reply_code(), ReplyCode::NoError);
+        let response = respond("missing.example.com", RecordType::A);
+        assert_eq!(response.reply_code(), ReplyCode::NxDomain);
+        assert_eq!(response.authority()[0].rtype, RecordType::SOA);
+
+        // an alias to a name the zone doesn't have, the answer is the CNAME and NXDOMAIN
+        let response = respond("gone.example.com", RecordType::A);
+        assert_eq!(response.reply_code(), ReplyCode::NxDomain);
+        assert_eq!(response.answers()[0].rtype, RecordType::CNAME);
+        assert_eq!(response.authority()[0].rtype, RecordType::SOA);
+        // and one out of the zone is left to the client to follow
+        let response = respond("away.example.com", RecordType::A);
+        assert_eq!(response.reply_code(), ReplyCode::NoError);
+        assert_eq!((response.answers().len(), response.authority().len()), (1, 0));
+
+        let response = respond("example.org", RecordType::A);
+        assert_eq!(response.reply_code(), ReplyCode::Refused);
+        assert!(!response.header.flags.is_authoritative);
+        assert_eq!(respond("example.com", RecordType::AXFR).reply_code(), ReplyCode::Refused);
+    }
+
+    #[test]
+    fn bad_queries() {
+        let zone = crate::server::Zone::new(crate::zonefile::parse(ZONE).unwrap());
+        let query = DNSPacket::query("example.com", RecordType::A).unwrap();
+        let bytes = query.serialize().unwrap();
+
+        let response = zone.respond(&bytes[..bytes.len() - 1]).unwrap();
+        assert_eq!((response.header.id(), response.reply_code()), (query.header.id(), ReplyCode::FormErr));
+        assert_eq!(zone.respond(&bytes[..1]), None);
+
+        let mut notify = query.clone();
+        notify.header.flags.opcode = 4;
+        assert_eq!(zone.respond(&notify.serialize().unwrap()).unwrap().reply_code(), ReplyCode::NotImp);
+
+        let mut response = query.clone();
+        response.header.flags.is_response = true;
+        assert_eq!(zone.respond(&response.serialize().unwrap()), None);
+    }
+
+    #[test]
+    fn truncated_over_udp() {
+        let records: Vec<DNSRecord> = (0..80)
+            .map(|x| DNSRecord {
+                name: String::from("example.com"),
+                rtype: RecordType::A,
+                class: crate::packet::RECORD_CLASS,
+                ttl: 300,
+                data: RecordData::A(Ipv4Addr::new(192, 0, 2, x)),
+            })
+            .collect();
+        let zone = crate::server::Zone::new(records);
+        let query = DNSPacket::query("example.com", RecordType::A).unwrap().serialize().unwrap();
+        let limit = crate::server::udp_limit(&query);
+        let bytes = crate::server::response_bytes(zone.respond(&query).unwrap(), &query, limit).unwrap();
+        assert!(bytes.len() <= 512);
+        let response = DNSPacket::deserialize(&bytes).unwrap();
+        assert!(response.header.flags.is_truncated);
+        assert!(!response.answers().is_empty() && response.answers().len() < 80);
+        assert_eq!(response.questions().len(), 1);
+        assert!(response.additional().is_empty());
+
+        // a bigger EDNS payload fits more
+        let query = crate::packet::QueryBuilder::new().question("example.com", RecordType::A).edns(4096).build();
+        let query = query.unwrap().serialize().unwrap();
+        let limit = crate::server::udp_limit(&query);
+        assert_eq!(limit, crate::server::UDP_RECV_SIZE);
+        let bytes = crate::server::response_bytes(zone.respond(&query).unwrap(), &query, limit).unwrap();
+        let larger = DNSPacket::deserialize(&bytes).unwrap();
+        assert!(larger.header.flags.is_truncated);
+        assert!(larger.answers().len() > response.answers().len());
+        // a truncated response to an EDNS query still answers with EDNS
+        assert_eq!(larger.additional().iter().map(|x| x.rtype).collect::<Vec<_>>(), [RecordType::OPT]);
+    }
+
+    #[test]
+    fn serial() {
+        let zone = crate::server::Zone::new(crate::zonefile::parse(ZONE).unwrap());
+        assert_eq!((zone.origin(), zone.serial()), (Some("example.com"), Some(1)));
+        let server = crate::server::Server::bind("127.0.0.1:0".parse().unwrap(), zone).unwrap();
+        server.set_zone(crate::server::Zone::new(crate::zonefile::parse(&ZONE.replace(" 1 7200", " 2 7200")).unwrap()));
+        assert_eq!(server.serial(), Some(2));
+    }
+
+    #[test]
+    fn notifies() {
+        let peer = "192.0.2.53".parse().unwrap();
+        let listener = crate::server::NotifyListener::bind("127.0.0.1:0".parse().unwrap(), "example.com.").unwrap();
+        let acknowledge = |listener: &crate::server::NotifyListener, packet: &DNSPacket| {
+            let (response, notify) = listener.acknowledge(&packet.serialize().unwrap(), peer).unwrap();
+            assert_eq!(
+                (response.header.id(), response.header.flags.opcode, response.questions()),
+                (packet.header.id(), packet.header.flags.opcode, packet.questions())
+            );
+            (response.reply_code(), notify.ok())
+        };
+
+        let notify = crate::notify::message("Example.com", None).unwrap();
+        let (code, notify_seen) = acknowledge(&listener, &notify);
+        assert_eq!((code, notify_seen.unwrap().zone), (ReplyCode::NoError, String::from("example.com")));
+
+        // another zone, not a NOTIFY at all, and not from an allowed peer
+        let other = crate::notify::message("example.org", None).unwrap();
+        assert_eq!(acknowledge(&listener, &other), (ReplyCode::Refused, None));
+        let query = DNSPacket::query("example.com", RecordType::SOA).unwrap();
+        assert_eq!(acknowledge(&listener, &query), (ReplyCode::Refused, None));
+        let listener = listener.with_allowed(vec!["192.0.2.1".parse().unwrap()]);
+        assert_eq!(acknowledge(&listener, &notify), (ReplyCode::Refused, None));
+
+        // garbage is refused too, and responses aren't answered
+        let bytes = notify.serialize().unwrap();
+        let (response, notify) = listener.acknowledge(&bytes[..bytes.len() - 1], peer).unwrap();
+        assert!(notify.is_err() && response.reply_code() == ReplyCode::Refused);
+        let mut response = query.clone();
+        response.header.flags.is_response = true;
+        assert!(listener.acknowledge(&response.serialize().unwrap(), peer).is_none());
+    }
+}
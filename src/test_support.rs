@@ -0,0 +1,108 @@
+/* proptest strategies for valid packets, shared by every module's tests.
+    Each piece has its own strategy so failures shrink down to the one
+    label, record or flag that broke.
+ */
+use std::net::{Ipv4Addr, Ipv6Addr};
+
+use proptest::collection::vec;
+use proptest::prelude::*;
+
+use crate::packet::{
+    DNSRecord, DNSFlags, DNSHeader, DNSPacket, DNSQuestion, Dnskey, RecordData, RecordType, Rrsig, Soa,
+};
+
+pub fn label() -> impl Strategy<Value = String> {
+    "[a-zA-Z0-9]([a-zA-Z0-9-]{0,61}[a-zA-Z0-9])?"
+}
+
+// At most 3 full-length labels, which stays under the 255 byte name limit
+pub fn name() -> impl Strategy<Value = String> {
+    vec(label(), 1..=3).prop_map(|labels| labels.join("."))
+}
+
+pub fn flags() -> impl Strategy<Value = DNSFlags> {
+    any::<u16>().prop_map(DNSFlags::from)
+}
+
+pub fn soa() -> impl Strategy<Value = Soa> {
+    (name(), name(), any::<[u32; 5]>()).prop_map(|(mname, rname, [serial, refresh, retry, expire, minimum])| {
+        Soa { mname, rname, serial, refresh, retry, expire, minimum }
+    })
+}
+
+pub fn rrsig() -> impl Strategy<Value = Rrsig> {
+    (any::<(u16, u8, u8, u32, u32, u32, u16)>(), name(), vec(any::<u8>(), 0..64)).prop_map(
+        |((type_covered, algorithm, labels, original_ttl, expiration, inception, key_tag), signer, signature)| Rrsig {
+            type_covered: RecordType::from_u16(type_covered),
+            algorithm, labels, original_ttl, expiration, inception, key_tag, signer, signature,
+        }
+    )
+}
+
+pub fn dnskey() -> impl Strategy<Value = Dnskey> {
+    (any::<(u16, u8, u8)>(), vec(any::<u8>(), 0..64))
+        .prop_map(|((flags, protocol, algorithm), public_key)| Dnskey { flags, protocol, algorithm, public_key })
+}
+
+// Unknown rdata only round-trips for types the parser doesn't decode
+pub fn unknown_rtype() -> impl Strategy<Value = u16> {
+    any::<u16>().prop_filter("known record type", |x| matches!(RecordType::from_u16(*x), RecordType::Unknown(_)))
+}
+
+pub fn record_data() -> impl Strategy<Value = (RecordType, RecordData)> {
+    prop_oneof![
+        any::<[u8; 4]>().prop_map(|x| (RecordType::A, RecordData::A(Ipv4Addr::from(x)))),
+        any::<[u8; 16]>().prop_map(|x| (RecordType::AAAA, RecordData::Aaaa(Ipv6Addr::from(x)))),
+        name().prop_map(|x| (RecordType::CNAME, RecordData::Cname(x))),
+        name().prop_map(|x| (RecordType::NS, RecordData::Ns(x))),
+        name().prop_map(|x| (RecordType::PTR, RecordData::Ptr(x))),
+        (any::<u16>(), name()).prop_map(|(pref, exchange)| (RecordType::MX, RecordData::Mx { pref, exchange })),
+        (any::<(u16, u16, u16)>(), name()).prop_map(|((priority, weight, port), target)| {
+            (RecordType::SRV, RecordData::Srv { priority, weight, port, target })
+        }),
+        vec(vec(any::<u8>(), 0..=255), 0..4).prop_map(|x| (RecordType::TXT, RecordData::Txt(x))),
+        soa().prop_map(|x| (RecordType::SOA, RecordData::Soa(x))),
+        rrsig().prop_map(|x| (RecordType::RRSIG, RecordData::Rrsig(x))),
+        dnskey().prop_map(|x| (RecordType::DNSKEY, RecordData::Dnskey(x))),
+        (unknown_rtype(), vec(any::<u8>(), 0..32))
+            .prop_map(|(rtype, data)| (RecordType::from_u16(rtype), RecordData::Unknown { rtype, data })),
+    ]
+}
+
+pub fn record() -> impl Strategy<Value = DNSRecord> {
+    (name(), record_data(), any::<u16>(), any::<u32>())
+        .prop_map(|(name, (rtype, data), class, ttl)| DNSRecord { name, rtype, class, ttl, data })
+}
+
+pub fn question() -> impl Strategy<Value = DNSQuestion> {
+    (name(), any::<u16>(), 0..0x8000u16, any::<bool>()).prop_map(|(name, qtype, qclass, unicast_response)| DNSQuestion {
+        name,
+        qtype: RecordType::from_u16(qtype),
+        qclass,
+        unicast_response,
+    })
+}
+
+pub fn packet() -> impl Strategy<Value = DNSPacket> {
+    (
+        any::<u16>(),
+        flags(),
+        vec(question(), 0..=3),
+        vec(record(), 0..=3),
+        vec(record(), 0..=3),
+        vec(record(), 0..=3),
+    ).prop_map(|(id, flags, questions, answers, authority, additional)| DNSPacket {
+        header: DNSHeader {
+            id,
+            flags,
+            question_count: questions.len() as u16,
+            answer_count: answers.len() as u16,
+            authority_count: authority.len() as u16,
+            additional_count: additional.len() as u16,
+        },
+        questions,
+        answers,
+        authority,
+        additional,
+    })
+}
@@ -0,0 +1,287 @@
+/* A mock nameserver for tests: a UDP socket on 127.0.0.1 that answers queries
+    from programmed (name, type) rules, so resolver behavior can be checked
+    without the network. Anything without a rule gets REFUSED.
+    MockTransport skips sockets altogether and hands back canned response bytes.
+ */
+use std::net::{Ipv4Addr, SocketAddr, UdpSocket};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+use crate::error::DnsError;
+use crate::names;
+use crate::packet::{DNSRecord, DNSPacket, DNSQuestion, RecordData, RecordType, ReplyCode, RECORD_CLASS};
+use crate::transport::Transport;
+
+// How often the server thread checks whether it should stop
+const POLL_INTERVAL: Duration = Duration::from_millis(20);
+
+// An A record for `name` at 192.0.2.`last` (RFC 5737's documentation range), class IN and a TTL of 300
+pub fn a(name: &str, last: u8) -> DNSRecord {
+    let data = RecordData::A(Ipv4Addr::new(192, 0, 2, last));
+    DNSRecord { name: String::from(name), rtype: RecordType::A, class: RECORD_CLASS, ttl: 300, data }
+}
+
+// How the server replies to a query matching a rule
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Reply {
+    Normal,
+    // Never answer, the client times out
+    Drop,
+    // Answer with a transaction id one off from the query's
+    WrongId,
+    // Set TC and leave the records out, as a server does when they don't fit
+    Truncated,
+    // Answer normally after waiting
+    Delay(Duration),
+}
+
+#[derive(Debug, Clone)]
+pub struct Rule {
+    pub name: String,
+    pub rtype: RecordType,
+    pub reply_code: ReplyCode,
+    pub answers: Vec<DNSRecord>,
+    pub authority: Vec<DNSRecord>,
+    pub reply: Reply,
+}
+
+impl Rule {
+    // Answers NOERROR with no records until some are added
+    pub fn new(name: &str, rtype: RecordType) -> Self {
+        Rule {
+            name: String::from(name),
+            rtype,
+            reply_code: ReplyCode::NoError,
+            answers: Vec::new(),
+            authority: Vec::new(),
+            reply: Reply::Normal,
+        }
+    }
+
+    pub fn answer(mut self, record: DNSRecord) -> Self {
+        self.answers.push(record);
+        self
+    }
+
+    pub fn authority(mut self, record: DNSRecord) -> Self {
+        self.authority.push(record);
+        self
+    }
+
+    pub fn reply_code(mut self, code: ReplyCode) -> Self {
+        self.reply_code = code;
+        self
+    }
+
+    pub fn reply(mut self, reply: Reply) -> Self {
+        self.reply = reply;
+        self
+    }
+
+    fn matches(&self, question: &DNSQuestion) -> bool {
+        question.qtype() == self.rtype && names::eq_ignore_case(question.name(), &self.name)
+    }
+}
+
+pub struct MockServer {
+    addr: SocketAddr,
+    rules: Arc<Mutex<Vec<Rule>>>,
+    queries: Arc<AtomicUsize>,
+    stop: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl MockServer {
+    // Binds an ephemeral port and starts answering on a background thread
+    pub fn start() -> Result<Self, DnsError> {
+        let socket = UdpSocket::bind("127.0.0.1:0")?;
+        socket.set_read_timeout(Some(POLL_INTERVAL))?;
+        let addr = socket.local_addr()?;
+
+        let rules: Arc<Mutex<Vec<Rule>>> = Arc::new(Mutex::new(Vec::new()));
+        let queries = Arc::new(AtomicUsize::new(0));
+        let stop = Arc::new(AtomicBool::new(false));
+        let thread = {
+            let (rules, queries, stop) = (rules.clone(), queries.clone(), stop.clone());
+            std::thread::spawn(move || serve(socket, &rules, &queries, &stop))
+        };
+
+        Ok(MockServer { addr, rules, queries, stop, thread: Some(thread) })
+    }
+
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    // Rules are tried in the order they were added, the first match wins
+    pub fn add_rule(&self, rule: Rule) {
+        self.rules.lock().unwrap().push(rule);
+    }
+
+    // Number of queries received so far, answered or not
+    pub fn queries(&self) -> usize {
+        self.queries.load(Ordering::SeqCst)
+    }
+}
+
+impl Drop for MockServer {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::SeqCst);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+fn serve(socket: UdpSocket, rules: &Mutex<Vec<Rule>>, queries: &AtomicUsize, stop: &AtomicBool) {
+    let mut buf = [0; 512];
+    while !stop.load(Ordering::SeqCst) {
+        let (size, peer) = match socket.recv_from(&mut buf) {
+            Ok(received) => received,
+            Err(_) => continue,
+        };
+        queries.fetch_add(1, Ordering::SeqCst);
+        let query = match DNSPacket::deserialize(&buf[..size]) {
+            Ok(query) => query,
+            Err(_) => continue,
+        };
+
+        let rule = query.questions().first()
+            .and_then(|question| rules.lock().unwrap().iter().find(|x| x.matches(question)).cloned());
+        let reply = rule.as_ref().map(|x| x.reply).unwrap_or(Reply::Normal);
+        let bytes = match response(&query, rule.as_ref()).serialize() {
+            Ok(bytes) => bytes,
+            Err(_) => continue,
+        };
+
+        match reply {
+            Reply::Drop => {},
+            Reply::Delay(delay) => {
+                // off the server thread, so other queries are still answered meanwhile
+                if let Ok(socket) = socket.try_clone() {
+                    std::thread::spawn(move || {
+                        std::thread::sleep(delay);
+                        let _ = socket.send_to(&bytes, peer);
+                    });
+                }
+            },
+            _ => {
+                let _ = socket.send_to(&bytes, peer);
+            },
+        }
+    }
+}
+
+// The reply for `query`, echoing its question the way it was asked
+fn response(query: &DNSPacket, rule: Option<&Rule>) -> DNSPacket {
+    let id = match rule.map(|x| x.reply) {
+        Some(Reply::WrongId) => query.header.id().wrapping_add(1),
+        _ => query.header.id(),
+    };
+    let mut response = DNSPacket::with_id(id);
+    response.header.flags.is_response = true;
+    response.header.flags.recurse_desired = query.header.flags.recurse_desired;
+    response.header.flags.recurse_available = true;
+    for question in query.questions() {
+        response.add_question(question.clone());
+    }
+
+    let rule = match rule {
+        Some(rule) => rule,
+        None => {
+            response.set_reply_code(ReplyCode::Refused);
+            return response;
+        },
+    };
+    response.set_reply_code(rule.reply_code);
+    if rule.reply == Reply::Truncated {
+        response.header.flags.is_truncated = true;
+        return response;
+    }
+    for record in &rule.answers {
+        response.add_answer(record.clone());
+    }
+    for record in &rule.authority {
+        response.add_authority(record.clone());
+    }
+    response
+}
+
+/* Answers every query with the same bytes, whatever was asked. A recorded response carries
+    the id of the query it was recorded for, `echo_id` copies over the id of each query sent
+    instead so it gets past the resolver's id check.
+ */
+pub struct MockTransport {
+    response: Vec<u8>,
+    echo_id: bool,
+    queries: Mutex<Vec<Vec<u8>>>,
+}
+
+impl MockTransport {
+    pub fn new(response: &[u8]) -> Self {
+        MockTransport { response: response.to_vec(), echo_id: false, queries: Mutex::new(Vec::new()) }
+    }
+
+    pub fn echo_id(mut self) -> Self {
+        self.echo_id = true;
+        self
+    }
+
+    // Every query message received so far, in order
+    pub fn queries(&self) -> Vec<Vec<u8>> {
+        self.queries.lock().unwrap().clone()
+    }
+}
+
+impl Transport for MockTransport {
+    fn query(&self, message: &[u8]) -> Result<Vec<u8>, DnsError> {
+        self.queries.lock().unwrap().push(message.to_vec());
+        let mut response = self.response.clone();
+        if self.echo_id && message.len() >= 2 && response.len() >= 2 {
+            response[..2].copy_from_slice(&message[..2]);
+        }
+        Ok(response)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+
+    use crate::error::DnsError;
+    use crate::packet::{DNSPacket, RecordData, RecordType};
+    use crate::resolver::{QueryOutcome, Resolver};
+    use crate::testdata::CNAME_RESPONSE;
+    use crate::testutil::MockTransport;
+
+    #[test]
+    fn mock_transport_end_to_end() {
+        // nothing listens on the discard port, the mock is the only way an answer comes back
+        let resolver = Resolver::new("127.0.0.1:9".parse().unwrap());
+        let transport = MockTransport::new(&CNAME_RESPONSE).echo_id();
+        let result = resolver.resolve_with(&transport, "www.example.com", RecordType::A).unwrap();
+        let answers = match result.outcome {
+            QueryOutcome::Answers(answers) => answers,
+            outcome => panic!("expected answers, got {:?}", outcome),
+        };
+        assert_eq!(answers[0].data, RecordData::Cname(String::from("example.com")));
+        assert_eq!(answers[1].data, RecordData::A(Ipv4Addr::new(93, 184, 216, 34)));
+
+        // the query went out as the resolver builds it
+        let sent = transport.queries();
+        assert_eq!(sent.len(), 1);
+        let query = DNSPacket::deserialize(&sent[0]).unwrap();
+        assert_eq!((query.questions()[0].name(), query.questions()[0].qtype()), ("www.example.com", RecordType::A));
+        assert!(query.header.flags.recurse_desired);
+
+        // left with its recorded id the response belongs to some other query
+        let transport = MockTransport::new(&CNAME_RESPONSE);
+        let result = resolver.resolve_with(&transport, "www.example.com", RecordType::A);
+        let sent_id = DNSPacket::deserialize(&transport.queries()[0]).unwrap().header.id();
+        if sent_id != 0x1234 {
+            assert!(matches!(result, Err(DnsError::IdMismatch { expected, received: 0x1234 }) if expected == sent_id));
+        }
+    }
+}
@@ -0,0 +1,188 @@
+/* Ways of getting a serialized query to a nameserver and its response back. Each transport
+    handles its own connecting, framing and timeouts. The Resolver builds one per exchange from
+    its `resolver::TransportKind` setting, and `Resolver::resolve_with` takes any other.
+ */
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpStream, UdpSocket};
+#[cfg(unix)]
+use std::os::unix::net::UnixStream;
+#[cfg(unix)]
+use std::path::PathBuf;
+#[cfg(feature = "dot")]
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::error::DnsError;
+#[cfg(feature = "doh")]
+use crate::packet::MAX_MESSAGE_SIZE;
+
+// Media type of DNS messages carried over HTTP (RFC 8484 section 6)
+#[cfg(feature = "doh")]
+const DOH_CONTENT_TYPE: &str = "application/dns-message";
+
+pub trait Transport: Send + Sync {
+    // Sends one query message and returns the response message, unparsed
+    fn query(&self, message: &[u8]) -> Result<Vec<u8>, DnsError>;
+}
+
+// A datagram each way, the default
+pub struct UdpTransport {
+    pub nameserver: SocketAddr,
+    pub timeout: Duration,
+    // Anything longer is cut off, so it matches the payload size the query advertises
+    pub buffer_size: usize,
+}
+
+impl Transport for UdpTransport {
+    fn query(&self, message: &[u8]) -> Result<Vec<u8>, DnsError> {
+        let bind_addr = match self.nameserver {
+            SocketAddr::V4(_) => "0.0.0.0:0",
+            SocketAddr::V6(_) => "[::]:0",
+        };
+        let sock = UdpSocket::bind(bind_addr)?;
+        sock.set_read_timeout(Some(self.timeout))?;
+        sock.connect(self.nameserver)?;
+
+        sock.send(message)?;
+        let mut buf = vec![0; self.buffer_size];
+        let size = sock.recv(&mut buf)?;
+        Ok(buf[..size].to_vec())
+    }
+}
+
+// A connection per query, messages prefixed with their length
+pub struct TcpTransport {
+    pub nameserver: SocketAddr,
+    pub timeout: Duration,
+}
+
+impl Transport for TcpTransport {
+    fn query(&self, message: &[u8]) -> Result<Vec<u8>, DnsError> {
+        let mut stream = connect_tcp(self.nameserver, self.timeout)?;
+        write_tcp_message(&mut stream, message)?;
+        read_tcp_message(&mut stream)
+    }
+}
+
+// A local stub's stream socket, framed like TCP
+#[cfg(unix)]
+pub struct UnixTransport {
+    pub path: PathBuf,
+    pub timeout: Duration,
+}
+
+#[cfg(unix)]
+impl Transport for UnixTransport {
+    fn query(&self, message: &[u8]) -> Result<Vec<u8>, DnsError> {
+        let mut stream = connect_unix(&self.path, self.timeout)?;
+        write_tcp_message(&mut stream, message)?;
+        read_tcp_message(&mut stream)
+    }
+}
+
+// The message goes as the POST body and comes back as the response body, nothing else changes
+#[cfg(feature = "doh")]
+pub struct DohTransport {
+    pub url: String,
+    pub timeout: Duration,
+}
+
+#[cfg(feature = "doh")]
+impl Transport for DohTransport {
+    fn query(&self, message: &[u8]) -> Result<Vec<u8>, DnsError> {
+        let response = ureq::post(&self.url)
+            .timeout(self.timeout)
+            .set("Content-Type", DOH_CONTENT_TYPE)
+            .set("Accept", DOH_CONTENT_TYPE)
+            .send_bytes(message)
+            .map_err(|err| DnsError::Http(err.to_string()))?;
+        if response.content_type() != DOH_CONTENT_TYPE {
+            return Err(DnsError::Http(format!("unexpected content type {}", response.content_type())));
+        }
+
+        let mut body: Vec<u8> = Vec::new();
+        response.into_reader().take(MAX_MESSAGE_SIZE as u64).read_to_end(&mut body)?;
+        Ok(body)
+    }
+}
+
+// TCP framing inside a TLS session, the certificate has to be valid for `server_name`
+#[cfg(feature = "dot")]
+pub struct TlsTransport {
+    pub nameserver: SocketAddr,
+    pub server_name: String,
+    // The webpki roots when unset
+    pub config: Option<Arc<rustls::ClientConfig>>,
+    pub timeout: Duration,
+}
+
+#[cfg(feature = "dot")]
+impl Transport for TlsTransport {
+    fn query(&self, message: &[u8]) -> Result<Vec<u8>, DnsError> {
+        let mut stream = connect_tls(self.nameserver, &self.server_name, self.config.clone(), self.timeout)?;
+        write_tcp_message(&mut stream, message)?;
+        read_tcp_message(&mut stream)
+    }
+}
+
+pub(crate) fn connect_tcp(nameserver: SocketAddr, timeout: Duration) -> Result<TcpStream, DnsError> {
+    let stream = TcpStream::connect_timeout(&nameserver, timeout)?;
+    stream.set_read_timeout(Some(timeout))?;
+    stream.set_write_timeout(Some(timeout))?;
+    Ok(stream)
+}
+
+#[cfg(unix)]
+pub(crate) fn connect_unix(path: &std::path::Path, timeout: Duration) -> Result<UnixStream, DnsError> {
+    let stream = UnixStream::connect(path)?;
+    stream.set_read_timeout(Some(timeout))?;
+    stream.set_write_timeout(Some(timeout))?;
+    Ok(stream)
+}
+
+// A TCP connection with the TLS handshake left to the first read or write
+#[cfg(feature = "dot")]
+fn connect_tls(
+    nameserver: SocketAddr,
+    server_name: &str,
+    config: Option<Arc<rustls::ClientConfig>>,
+    timeout: Duration,
+) -> Result<rustls::StreamOwned<rustls::ClientConnection, TcpStream>, DnsError> {
+    let config = match config {
+        Some(config) => config,
+        None => {
+            let roots = rustls::RootCertStore { roots: webpki_roots::TLS_SERVER_ROOTS.to_vec() };
+            Arc::new(
+                rustls::ClientConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+                    .with_safe_default_protocol_versions()
+                    .map_err(|err| DnsError::Tls(err.to_string()))?
+                    .with_root_certificates(roots)
+                    .with_no_client_auth(),
+            )
+        },
+    };
+    let name = rustls::pki_types::ServerName::try_from(String::from(server_name))
+        .map_err(|_| DnsError::Tls(format!("invalid server name {}", server_name)))?;
+    let connection = rustls::ClientConnection::new(config, name)
+        .map_err(|err| DnsError::Tls(err.to_string()))?;
+    Ok(rustls::StreamOwned::new(connection, connect_tcp(nameserver, timeout)?))
+}
+
+// TCP messages are prefixed with their length as a u16
+pub(crate) fn write_tcp_message<W: Write>(stream: &mut W, message: &[u8]) -> Result<(), DnsError> {
+    let length = u16::try_from(message.len())
+        .map_err(|_| std::io::Error::new(std::io::ErrorKind::InvalidInput, "message too long for TCP"))?;
+    let mut bytes = Vec::with_capacity(2 + message.len());
+    bytes.extend_from_slice(&length.to_be_bytes());
+    bytes.extend_from_slice(message);
+    stream.write_all(&bytes)?;
+    Ok(())
+}
+
+pub(crate) fn read_tcp_message<R: Read>(stream: &mut R) -> Result<Vec<u8>, DnsError> {
+    let mut length = [0; 2];
+    stream.read_exact(&mut length)?;
+    let mut message = vec![0; u16::from_be_bytes(length) as usize];
+    stream.read_exact(&mut message)?;
+    Ok(message)
+}
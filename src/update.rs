@@ -0,0 +1,210 @@
+/* Dynamic updates (RFC 2136). An UPDATE reuses the sections of a query: the question names the
+    zone, the answer section holds prerequisites and the authority section the changes. The class
+    and rdata of each record say what it means. ANY with no rdata stands for a whole RRset (or with
+    type ANY every record of a name) that has to exist or goes, NONE for one that mustn't exist,
+    or with rdata for a single record deleted.
+ */
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+
+use crate::error::DnsError;
+use crate::names;
+use crate::packet::{DNSPacket, DNSQuestion, DNSRecord, RecordClass, RecordData, RecordType};
+
+// The opcode of an UPDATE message, where queries have 0
+pub const OPCODE_UPDATE: u8 = 5;
+
+/// Builds an UPDATE message for a zone, prerequisites and changes each added in order.
+///
+/// ```
+/// use std::net::Ipv4Addr;
+///
+/// use bkdns::packet::{DNSRecord, RecordData, RecordType, RECORD_CLASS};
+/// use bkdns::update::{UpdateBuilder, OPCODE_UPDATE};
+///
+/// let www = DNSRecord {
+///     name: String::from("www.example.com"),
+///     rtype: RecordType::A,
+///     class: RECORD_CLASS,
+///     ttl: 300,
+///     data: RecordData::A(Ipv4Addr::new(192, 0, 2, 1)),
+/// };
+/// let update = UpdateBuilder::new("example.com")
+///     .require_absent_rrset("www.example.com", RecordType::CNAME)
+///     .delete_rrset("www.example.com", RecordType::A)
+///     .add_record(www)
+///     .build()
+///     .unwrap();
+/// assert_eq!(update.header.flags.opcode, OPCODE_UPDATE);
+/// assert_eq!(update.questions()[0].qtype(), RecordType::SOA);
+/// assert_eq!((update.answers().len(), update.authority().len()), (1, 2));
+/// ```
+#[derive(Debug, Clone)]
+pub struct UpdateBuilder {
+    id: Option<u16>,
+    zone: String,
+    prerequisites: Vec<DNSRecord>,
+    updates: Vec<DNSRecord>,
+}
+
+impl UpdateBuilder {
+    pub fn new(zone: &str) -> Self {
+        UpdateBuilder { id: None, zone: String::from(zone), prerequisites: Vec::new(), updates: Vec::new() }
+    }
+
+    // Random when unset, and 0 without std, like a query's
+    pub fn id(mut self, id: u16) -> Self {
+        self.id = Some(id);
+        self
+    }
+
+    // The name has records of `rtype`, whatever their data
+    pub fn require_rrset(mut self, name: &str, rtype: RecordType) -> Self {
+        self.prerequisites.push(empty(name, rtype, RecordClass::ANY));
+        self
+    }
+
+    pub fn require_absent_rrset(mut self, name: &str, rtype: RecordType) -> Self {
+        self.prerequisites.push(empty(name, rtype, RecordClass::NONE));
+        self
+    }
+
+    // The name has records of any type
+    pub fn require_name(mut self, name: &str) -> Self {
+        self.prerequisites.push(empty(name, RecordType::ANY, RecordClass::ANY));
+        self
+    }
+
+    pub fn require_absent_name(mut self, name: &str) -> Self {
+        self.prerequisites.push(empty(name, RecordType::ANY, RecordClass::NONE));
+        self
+    }
+
+    // Adds the record to its RRset, as it is
+    pub fn add_record(mut self, record: DNSRecord) -> Self {
+        self.updates.push(record);
+        self
+    }
+
+    pub fn delete_rrset(mut self, name: &str, rtype: RecordType) -> Self {
+        self.updates.push(empty(name, rtype, RecordClass::ANY));
+        self
+    }
+
+    // Every record of the name, of every type
+    pub fn delete_name(mut self, name: &str) -> Self {
+        self.updates.push(empty(name, RecordType::ANY, RecordClass::ANY));
+        self
+    }
+
+    // Just the record with this type and data, its class and TTL are ignored
+    pub fn delete_record(mut self, record: DNSRecord) -> Self {
+        self.updates.push(DNSRecord { class: RecordClass::NONE.value(), ttl: 0, ..record });
+        self
+    }
+
+    // Names outside the zone are refused with NOTZONE by the server, so they're caught here first
+    pub fn build(self) -> Result<DNSPacket, DnsError> {
+        #[cfg(feature = "std")]
+        let id = self.id.unwrap_or_else(rand::random::<u16>);
+        #[cfg(not(feature = "std"))]
+        let id = self.id.unwrap_or(0);
+
+        let mut packet = DNSPacket::with_id(id);
+        packet.header.flags.opcode = OPCODE_UPDATE;
+        packet.add_question(DNSQuestion::new(names::validate_name(&self.zone)?, RecordType::SOA)?);
+        for record in self.prerequisites.iter().chain(&self.updates) {
+            if !names::in_zone(&record.name, &self.zone) {
+                return Err(DnsError::NotInZone { name: record.name.clone(), zone: self.zone });
+            }
+        }
+        for record in self.prerequisites {
+            packet.add_answer(record);
+        }
+        for record in self.updates {
+            packet.add_authority(record);
+        }
+        Ok(packet)
+    }
+}
+
+/* `name` as written relative to `zone`, the way nsupdate and zone files take them: `@` is the
+    zone itself, a trailing dot makes a name absolute, anything else has the zone appended
+    unless it's already in the zone.
+ */
+pub fn qualify(name: &str, zone: &str) -> String {
+    let zone = zone.trim_end_matches('.');
+    match name {
+        "@" => String::from(zone),
+        name if name.ends_with('.') => String::from(name.trim_end_matches('.')),
+        name if names::in_zone(name, zone) => String::from(name),
+        name => format!("{}.{}", name, zone),
+    }
+}
+
+// A record with no TTL or rdata, the class saying what it stands for
+fn empty(name: &str, rtype: RecordType, class: RecordClass) -> DNSRecord {
+    DNSRecord {
+        name: String::from(name),
+        rtype,
+        class: class.value(),
+        ttl: 0,
+        data: RecordData::Unknown { rtype: rtype.value(), data: Vec::new() },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use hex_literal::hex;
+
+    use crate::packet::{DNSPacket, RecordType};
+    use crate::testutil::a;
+    use crate::update::UpdateBuilder;
+
+    #[test]
+    fn build() {
+        let update = UpdateBuilder::new("example.com.")
+            .id(0x1234)
+            .require_name("www.example.com")
+            .require_absent_rrset("www.example.com", RecordType::CNAME)
+            .delete_rrset("old.example.com", RecordType::A)
+            .delete_record(a("www.example.com", 1))
+            .add_record(a("www.example.com", 2))
+            .build()
+            .unwrap();
+        assert_eq!(update.serialize().unwrap(), hex!(
+            // id, opcode 5 and nothing else set, 1 zone, 2 prerequisites, 3 updates
+            "1234 2800 0001 0002 0003 0000"
+            // zone: example.com SOA IN
+            "076578616d706c6503636f6d00 0006 0001"
+            // www.example.com ANY ANY, ttl 0, no rdata: the name is in use
+            "03777777076578616d706c6503636f6d00 00ff 00ff 00000000 0000"
+            // www.example.com CNAME NONE: no CNAME there
+            "03777777076578616d706c6503636f6d00 0005 00fe 00000000 0000"
+            // old.example.com A ANY: delete the RRset
+            "036f6c64076578616d706c6503636f6d00 0001 00ff 00000000 0000"
+            // www.example.com A NONE 192.0.2.1: delete the record
+            "03777777076578616d706c6503636f6d00 0001 00fe 00000000 0004 c0000201"
+            // www.example.com 300 A IN 192.0.2.2: add it
+            "03777777076578616d706c6503636f6d00 0001 0001 0000012c 0004 c0000202"
+        ));
+        // and it parses back, the empty records included
+        assert_eq!(DNSPacket::deserialize(&update.serialize().unwrap()).unwrap(), update);
+
+        assert!(matches!(
+            UpdateBuilder::new("example.com").add_record(a("www.example.org", 1)).build(),
+            Err(crate::error::DnsError::NotInZone { .. })
+        ));
+    }
+
+    #[test]
+    fn qualify() {
+        use crate::update::qualify;
+
+        assert_eq!(qualify("www", "example.com."), "www.example.com");
+        assert_eq!(qualify("@", "example.com"), "example.com");
+        assert_eq!(qualify("www.Example.com", "example.com"), "www.Example.com");
+        assert_eq!(qualify("ns.example.net.", "example.com"), "ns.example.net");
+    }
+}
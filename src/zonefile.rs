@@ -0,0 +1,501 @@
+/* Master file (RFC 1035 section 5) rendering of records, as BIND and
+    most other tools read and write them: `name. TTL CLASS TYPE rdata`.
+    `parse` reads the same form back, without $ORIGIN, $TTL or relative names.
+ */
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+
+use crate::error::DnsError;
+use crate::names;
+use crate::packet::{self, DNSRecord, DNSPacket, RecordClass, RecordData, RecordType, Soa};
+
+impl DNSPacket {
+    // Every record of the answer, authority and additional sections, one per line
+    pub fn to_zonefile(&self) -> String {
+        self.answers().iter()
+            .chain(self.authority())
+            .chain(self.additional())
+            .map(|x| record(x) + "\n")
+            .collect()
+    }
+}
+
+pub fn record(record: &DNSRecord) -> String {
+    format!(
+        "{}\t{}\t{}\t{}\t{}",
+        fqdn(&record.name),
+        record.ttl,
+        class_name(record.class),
+        record.rtype,
+        rdata(&record.data)
+    )
+}
+
+fn class_name(class: u16) -> String {
+    RecordClass::from_u16(class).to_string()
+}
+
+// Names are written fully qualified so they don't pick up the file's $ORIGIN
+pub fn fqdn(name: &str) -> String {
+    match packet::strip_root(name) {
+        "" => String::from("."),
+        name => format!("{}.", name),
+    }
+}
+
+// The rdata in presentation format
+pub fn rdata(data: &RecordData) -> String {
+    match data {
+        RecordData::A(addr) => addr.to_string(),
+        RecordData::Aaaa(addr) => addr.to_string(),
+        RecordData::Cname(name) | RecordData::Ns(name) | RecordData::Ptr(name) => fqdn(name),
+        RecordData::Mx { pref, exchange } => format!("{} {}", pref, fqdn(exchange)),
+        RecordData::Srv { priority, weight, port, target } => {
+            format!("{} {} {} {}", priority, weight, port, fqdn(target))
+        },
+        RecordData::Txt(strings) => strings.iter().map(|x| quote(x)).collect::<Vec<String>>().join(" "),
+        RecordData::Soa(soa) => format!(
+            "{} {} (\n\t\t\t\t{}\t; serial\n\t\t\t\t{}\t; refresh\n\t\t\t\t{}\t; retry\n\t\t\t\t{}\t; expire\n\t\t\t\t{}\t; minimum\n\t\t\t\t)",
+            fqdn(&soa.mname), fqdn(&soa.rname), soa.serial, soa.refresh, soa.retry, soa.expire, soa.minimum
+        ),
+        RecordData::Rrsig(rrsig) => format!(
+            "{} {} {} {} {} {} {} {} {}",
+            rrsig.type_covered,
+            rrsig.algorithm,
+            rrsig.labels,
+            rrsig.original_ttl,
+            signature_time(rrsig.expiration),
+            signature_time(rrsig.inception),
+            rrsig.key_tag,
+            fqdn(&rrsig.signer),
+            base64(&rrsig.signature)
+        ),
+        RecordData::Dnskey(dnskey) => format!(
+            "{} {} {} {}", dnskey.flags, dnskey.protocol, dnskey.algorithm, base64(&dnskey.public_key)
+        ),
+        RecordData::Unknown { data, .. } if data.is_empty() => String::from("\\# 0"),
+        RecordData::Unknown { data, .. } => {
+            let hex: String = data.iter().map(|x| format!("{:02x}", x)).collect();
+            format!("\\# {} {}", data.len(), hex)
+        },
+    }
+}
+
+// An RRSIG time as YYYYMMDDHHmmSS in UTC (RFC 4034 section 3.2)
+fn signature_time(seconds: u32) -> String {
+    let (days, time) = (seconds as u64 / 86400, seconds as u64 % 86400);
+    // days since 1970-01-01 to a civil date, counting years from March so leap days come last
+    let shifted = days + 719468;
+    let era = shifted / 146097;
+    let day_of_era = shifted % 146097;
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_from_march = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_from_march + 2) / 5 + 1;
+    let month = if month_from_march < 10 { month_from_march + 3 } else { month_from_march - 9 };
+    let year = era * 400 + year_of_era + u64::from(month <= 2);
+    format!("{:04}{:02}{:02}{:02}{:02}{:02}", year, month, day, time / 3600, time / 60 % 60, time % 60)
+}
+
+pub(crate) const BASE64_ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+// Standard padded base64, how keys and signatures are written
+pub(crate) fn base64(bytes: &[u8]) -> String {
+    let mut encoded = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let group = chunk.iter().enumerate().fold(0u32, |acc, (index, byte)| acc | (*byte as u32) << (16 - 8 * index));
+        // 3 bytes make 4 characters, a short chunk has its missing ones padded
+        for index in 0..4 {
+            match index <= chunk.len() {
+                true => encoded.push(BASE64_ALPHABET[(group >> (18 - 6 * index) & 0x3F) as usize] as char),
+                false => encoded.push('='),
+            }
+        }
+    }
+    encoded
+}
+
+// Padded base64, None on a stray character or a length that isn't a multiple of 4
+#[cfg(any(feature = "serde", feature = "tsig"))]
+pub(crate) fn decode_base64(text: &str) -> Option<Vec<u8>> {
+    if !text.len().is_multiple_of(4) {
+        return None;
+    }
+    let mut decoded = Vec::with_capacity(text.len() / 4 * 3);
+    for chunk in text.as_bytes().chunks(4) {
+        let padding = chunk.iter().rev().take_while(|x| **x == b'=').count();
+        if padding > 2 {
+            return None;
+        }
+        let mut group = 0u32;
+        for (index, byte) in chunk[..4 - padding].iter().enumerate() {
+            let value = BASE64_ALPHABET.iter().position(|x| x == byte)? as u32;
+            group |= value << (18 - 6 * index);
+        }
+        decoded.extend_from_slice(&group.to_be_bytes()[1..4 - padding]);
+    }
+    Some(decoded)
+}
+
+// A quoted character-string
+fn quote(string: &[u8]) -> String {
+    format!("\"{}\"", escape(string))
+}
+
+// A character-string's bytes as text, with quotes, backslashes and anything but printable ASCII escaped
+pub(crate) fn escape(string: &[u8]) -> String {
+    let mut escaped = String::new();
+    for byte in string {
+        match byte {
+            b'"' | b'\\' => {
+                escaped.push('\\');
+                escaped.push(*byte as char);
+            },
+            0x20..=0x7E => escaped.push(*byte as char),
+            _ => escaped.push_str(&format!("\\{:03}", byte)),
+        }
+    }
+    escaped
+}
+
+// The bytes `escape` wrote, None on a stray backslash
+#[cfg(feature = "serde")]
+pub(crate) fn unescape_all(text: &str) -> Option<Vec<u8>> {
+    let mut bytes: Vec<u8> = Vec::new();
+    let mut chars = text.chars().peekable();
+    while let Some(chr) = chars.next() {
+        match chr {
+            '\\' => bytes.push(unescape(&mut chars)?),
+            chr => bytes.extend_from_slice(chr.encode_utf8(&mut [0; 4]).as_bytes()),
+        }
+    }
+    Some(bytes)
+}
+
+/* Records from master file text, one per line or spread over several inside parentheses,
+    with `;` comments. Every record is `name TTL [class] type rdata`, names taken as fully
+    qualified. Rdata is read for the types `rdata` writes, other than RRSIG and DNSKEY, and
+    in the generic `\# length hex` form for any type.
+ */
+pub fn parse(text: &str) -> Result<Vec<DNSRecord>, DnsError> {
+    entries(text)?
+        .into_iter()
+        .map(|(line, tokens)| {
+            parse_record(&tokens).map_err(|message| DnsError::ZoneSyntax { line, message })
+        })
+        .collect()
+}
+
+// A word of an entry, quoted ones already unescaped into `bytes`
+struct Token {
+    text: String,
+    bytes: Vec<u8>,
+    quoted: bool,
+}
+
+// Each entry's tokens with the line it starts on, counting from 1
+fn entries(text: &str) -> Result<Vec<(usize, Vec<Token>)>, DnsError> {
+    let mut entries: Vec<(usize, Vec<Token>)> = Vec::new();
+    let mut tokens: Vec<Token> = Vec::new();
+    let mut start = 0;
+    let mut open = false;
+    for (index, line) in text.lines().enumerate() {
+        let number = index + 1;
+        if !open {
+            start = number;
+        }
+        let syntax = |message: &str| DnsError::ZoneSyntax { line: number, message: String::from(message) };
+        let mut chars = line.chars().peekable();
+        while let Some(chr) = chars.next() {
+            match chr {
+                ';' => break,
+                '(' if open => return Err(syntax("nested parentheses")),
+                '(' => open = true,
+                ')' if !open => return Err(syntax("unbalanced parentheses")),
+                ')' => open = false,
+                '"' => {
+                    let mut bytes: Vec<u8> = Vec::new();
+                    loop {
+                        match chars.next() {
+                            None => return Err(syntax("unterminated quoted string")),
+                            Some('"') => break,
+                            Some('\\') => bytes.push(unescape(&mut chars).ok_or_else(|| syntax("invalid escape"))?),
+                            Some(chr) => bytes.extend_from_slice(chr.encode_utf8(&mut [0; 4]).as_bytes()),
+                        }
+                    }
+                    // only TXT takes the bytes as they are, a quoted word anywhere else has to be text
+                    let text = String::from_utf8_lossy(&bytes).into_owned();
+                    tokens.push(Token { text, bytes, quoted: true });
+                },
+                chr if chr.is_whitespace() => {},
+                chr => {
+                    let mut text = String::from(chr);
+                    while let Some(chr) = chars.next_if(|x| !x.is_whitespace() && !"();\"".contains(*x)) {
+                        text.push(chr);
+                    }
+                    tokens.push(Token { bytes: text.clone().into_bytes(), text, quoted: false });
+                },
+            }
+        }
+        if !open && !tokens.is_empty() {
+            entries.push((start, core::mem::take(&mut tokens)));
+        }
+    }
+    if open {
+        return Err(DnsError::ZoneSyntax { line: start, message: String::from("unbalanced parentheses") });
+    }
+    Ok(entries)
+}
+
+// The byte after a backslash: the next character itself or three decimal digits
+fn unescape(chars: &mut core::iter::Peekable<core::str::Chars>) -> Option<u8> {
+    let first = chars.next()?;
+    if !first.is_ascii_digit() {
+        return u8::try_from(first).ok();
+    }
+    let digits = [first, chars.next()?, chars.next()?];
+    digits.iter().collect::<String>().parse().ok()
+}
+
+fn parse_record(tokens: &[Token]) -> Result<DNSRecord, String> {
+    let (owner, ttl, rest) = match tokens {
+        [owner, ttl, rest @ ..] => (owner, ttl, rest),
+        _ => return Err(String::from("expected a name, TTL, type and rdata")),
+    };
+    let name = parse_name(&owner.text)?;
+    let ttl: u32 = ttl.text.parse().map_err(|_| format!("invalid TTL {}", ttl.text))?;
+    // the class is optional, IN if left out
+    let (class, rest) = match rest {
+        [class, rest @ ..] if class.text.parse::<RecordClass>().is_ok() && class.text.parse::<RecordType>().is_err() => {
+            (class.text.parse::<RecordClass>().unwrap().value(), rest)
+        },
+        rest => (packet::RECORD_CLASS, rest),
+    };
+    let (rtype, rdata) = match rest {
+        [rtype, rdata @ ..] => (rtype.text.parse::<RecordType>().map_err(|err| err.to_string())?, rdata),
+        [] => return Err(String::from("missing record type")),
+    };
+    let data = parse_rdata(rtype, rdata)?;
+    Ok(DNSRecord { name, rtype, class, ttl, data })
+}
+
+fn parse_name(name: &str) -> Result<String, String> {
+    match name {
+        "." => Ok(String::new()),
+        name => names::validate_name(name).map_err(|err| err.to_string()),
+    }
+}
+
+fn parse_rdata(rtype: RecordType, tokens: &[Token]) -> Result<RecordData, String> {
+    let words: Vec<&str> = tokens.iter().map(|x| x.text.as_str()).collect();
+    let number = |word: &str| word.parse::<u32>().map_err(|_| format!("invalid number {}", word));
+    // a quoted \# is a TXT string like any other
+    let generic = tokens.first().is_some_and(|x| !x.quoted);
+    let data = match (rtype, words.as_slice()) {
+        (_, ["\\#", length, hex @ ..]) if generic => {
+            let data = decode_hex(&hex.concat()).ok_or_else(|| String::from("invalid hex in generic rdata"))?;
+            if data.len().to_string() != *length {
+                return Err(format!("generic rdata is {} bytes, not {}", data.len(), length));
+            }
+            if data.len() > u16::MAX as usize {
+                return Err(String::from("generic rdata is longer than 65535 bytes"));
+            }
+            RecordData::Unknown { rtype: rtype.value(), data }
+        },
+        (RecordType::A, [addr]) => RecordData::A(addr.parse().map_err(|_| format!("invalid IPv4 address {}", addr))?),
+        (RecordType::AAAA, [addr]) => RecordData::Aaaa(addr.parse().map_err(|_| format!("invalid IPv6 address {}", addr))?),
+        (RecordType::CNAME, [name]) => RecordData::Cname(parse_name(name)?),
+        (RecordType::NS, [name]) => RecordData::Ns(parse_name(name)?),
+        (RecordType::PTR, [name]) => RecordData::Ptr(parse_name(name)?),
+        (RecordType::MX, [pref, exchange]) => RecordData::Mx {
+            pref: pref.parse().map_err(|_| format!("invalid preference {}", pref))?,
+            exchange: parse_name(exchange)?,
+        },
+        (RecordType::SRV, [priority, weight, port, target]) => RecordData::Srv {
+            priority: priority.parse().map_err(|_| format!("invalid priority {}", priority))?,
+            weight: weight.parse().map_err(|_| format!("invalid weight {}", weight))?,
+            port: port.parse().map_err(|_| format!("invalid port {}", port))?,
+            target: parse_name(target)?,
+        },
+        (RecordType::TXT, strings) if !strings.is_empty() => {
+            if let Some(long) = tokens.iter().find(|x| x.bytes.len() > 255) {
+                return Err(format!("TXT string is {} bytes, more than 255", long.bytes.len()));
+            }
+            RecordData::Txt(tokens.iter().map(|x| x.bytes.clone()).collect())
+        },
+        (RecordType::SOA, [mname, rname, serial, refresh, retry, expire, minimum]) => RecordData::Soa(Soa {
+            mname: parse_name(mname)?,
+            rname: parse_name(rname)?,
+            serial: number(serial)?,
+            refresh: number(refresh)?,
+            retry: number(retry)?,
+            expire: number(expire)?,
+            minimum: number(minimum)?,
+        }),
+        (RecordType::A | RecordType::AAAA | RecordType::CNAME | RecordType::NS | RecordType::PTR
+            | RecordType::MX | RecordType::SRV | RecordType::TXT | RecordType::SOA, _) => {
+            return Err(format!("wrong number of rdata fields for {}", rtype));
+        },
+        _ => return Err(format!("only the generic \\# form is read for {} rdata", rtype)),
+    };
+    Ok(data)
+}
+
+pub(crate) fn decode_hex(text: &str) -> Option<Vec<u8>> {
+    if !text.len().is_multiple_of(2) {
+        return None;
+    }
+    text.as_bytes()
+        .chunks(2)
+        .map(|x| u8::from_str_radix(core::str::from_utf8(x).ok()?, 16).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{Ipv4Addr, Ipv6Addr};
+
+    use crate::packet::{DNSRecord, RecordData, RecordType, Soa, RECORD_CLASS};
+
+    fn record(name: &str, rtype: RecordType, data: RecordData) -> DNSRecord {
+        DNSRecord { name: String::from(name), rtype, class: RECORD_CLASS, ttl: 3600, data }
+    }
+
+    #[test]
+    fn to_zonefile() {
+        let mut packet = crate::packet::DNSPacket::with_id(0x1234);
+        packet.add_answer(record("example.com", RecordType::A, RecordData::A(Ipv4Addr::new(93, 184, 216, 34))));
+        packet.add_answer(record("example.com", RecordType::AAAA, RecordData::Aaaa(Ipv6Addr::LOCALHOST)));
+        packet.add_answer(record("www.example.com", RecordType::CNAME, RecordData::Cname(String::from("example.com"))));
+        packet.add_answer(record("example.com", RecordType::MX, RecordData::Mx {
+            pref: 10, exchange: String::from("mail.example.com"),
+        }));
+        packet.add_answer(record("example.com", RecordType::TXT, RecordData::Txt(vec![
+            b"v=spf1 -all".to_vec(), b"say \"hi\"".to_vec(), vec![0xff, 0x00, b'x'],
+        ])));
+        packet.add_authority(record("example.com", RecordType::NS, RecordData::Ns(String::from("a.iana-servers.net"))));
+        packet.add_authority(record("example.com", RecordType::SOA, RecordData::Soa(Soa {
+            mname: String::from("ns.icann.org"),
+            rname: String::from("noc.dns.icann.org"),
+            serial: 2024010101, refresh: 7200, retry: 3600, expire: 1209600, minimum: 3600,
+        })));
+        packet.add_additional(record("1.0.0.127.in-addr.arpa", RecordType::PTR, RecordData::Ptr(String::from("localhost"))));
+        packet.add_additional(record("example.com", RecordType::Unknown(99), RecordData::Unknown {
+            rtype: 99, data: vec![0xde, 0xad],
+        }));
+
+        assert_eq!(
+            packet.to_zonefile(),
+            "example.com.\t3600\tIN\tA\t93.184.216.34\n\
+             example.com.\t3600\tIN\tAAAA\t::1\n\
+             www.example.com.\t3600\tIN\tCNAME\texample.com.\n\
+             example.com.\t3600\tIN\tMX\t10 mail.example.com.\n\
+             example.com.\t3600\tIN\tTXT\t\"v=spf1 -all\" \"say \\\"hi\\\"\" \"\\255\\000x\"\n\
+             example.com.\t3600\tIN\tNS\ta.iana-servers.net.\n\
+             example.com.\t3600\tIN\tSOA\tns.icann.org. noc.dns.icann.org. (\n\
+             \t\t\t\t2024010101\t; serial\n\
+             \t\t\t\t7200\t; refresh\n\
+             \t\t\t\t3600\t; retry\n\
+             \t\t\t\t1209600\t; expire\n\
+             \t\t\t\t3600\t; minimum\n\
+             \t\t\t\t)\n\
+             1.0.0.127.in-addr.arpa.\t3600\tIN\tPTR\tlocalhost.\n\
+             example.com.\t3600\tIN\tTYPE99\t\\# 2 dead\n"
+        );
+    }
+
+    #[test]
+    fn dnssec_records() {
+        let rrsig = record("example.com", RecordType::RRSIG, RecordData::Rrsig(crate::packet::Rrsig {
+            type_covered: RecordType::A,
+            algorithm: 13,
+            labels: 2,
+            original_ttl: 300,
+            expiration: 1767225600,
+            inception: 1709251199,
+            key_tag: 2371,
+            signer: String::from("example.com"),
+            signature: b"sig".to_vec(),
+        }));
+        assert_eq!(
+            crate::zonefile::record(&rrsig),
+            "example.com.\t3600\tIN\tRRSIG\tA 13 2 300 20260101000000 20240229235959 2371 example.com. c2ln"
+        );
+
+        let dnskey = record("example.com", RecordType::DNSKEY, RecordData::Dnskey(crate::packet::Dnskey {
+            flags: 257, protocol: 3, algorithm: 13, public_key: vec![0xff, 0x00, 0x10, 0x20],
+        }));
+        assert_eq!(crate::zonefile::record(&dnskey), "example.com.\t3600\tIN\tDNSKEY\t257 3 13 /wAQIA==");
+    }
+
+    #[test]
+    fn zonefile_names_and_classes() {
+        let mut root = record(".", RecordType::A, RecordData::A(Ipv4Addr::LOCALHOST));
+        root.class = 3;
+        assert_eq!(crate::zonefile::record(&root), ".\t3600\tCH\tA\t127.0.0.1");
+        root.class = 42;
+        assert_eq!(crate::zonefile::record(&root), ".\t3600\tCLASS42\tA\t127.0.0.1");
+
+        let fqdn = record("example.com.", RecordType::Unknown(99), RecordData::Unknown { rtype: 99, data: vec![] });
+        assert_eq!(crate::zonefile::record(&fqdn), "example.com.\t3600\tIN\tTYPE99\t\\# 0");
+    }
+
+    #[test]
+    fn parse_what_record_writes() {
+        let records = vec![
+            record("example.com", RecordType::A, RecordData::A(Ipv4Addr::new(93, 184, 216, 34))),
+            record("example.com", RecordType::AAAA, RecordData::Aaaa(Ipv6Addr::LOCALHOST)),
+            record("www.example.com", RecordType::CNAME, RecordData::Cname(String::from("example.com"))),
+            record("example.com", RecordType::MX, RecordData::Mx { pref: 10, exchange: String::from("mail.example.com") }),
+            record("_sip._udp.example.com", RecordType::SRV, RecordData::Srv {
+                priority: 10, weight: 60, port: 5060, target: String::from("sip.example.com"),
+            }),
+            record("example.com", RecordType::TXT, RecordData::Txt(vec![
+                b"v=spf1 -all".to_vec(), b"say \"hi\" \\ ;not a comment".to_vec(), "caf\u{e9}".as_bytes().to_vec(),
+                vec![0xff, 0xfe],
+            ])),
+            record("example.com", RecordType::SOA, RecordData::Soa(Soa {
+                mname: String::from("ns.icann.org"),
+                rname: String::from("noc.dns.icann.org"),
+                serial: 2024010101, refresh: 7200, retry: 3600, expire: 1209600, minimum: 3600,
+            })),
+            record("", RecordType::NS, RecordData::Ns(String::from("a.root-servers.net"))),
+            record("example.com", RecordType::Unknown(99), RecordData::Unknown { rtype: 99, data: vec![0xde, 0xad] }),
+        ];
+        let text: String = records.iter().map(|x| crate::zonefile::record(x) + "\n").collect();
+        assert_eq!(crate::zonefile::parse(&text).unwrap(), records);
+    }
+
+    #[test]
+    fn parse_by_hand() {
+        let text = "\
+; a comment line
+example.com. 300 A 192.0.2.1 ; the class can be left out
+
+Example.COM 60 IN TXT \"\\\\#\" 0 \"\\065\"
+";
+        let records = crate::zonefile::parse(text).unwrap();
+        assert_eq!(records[0].data, RecordData::A(Ipv4Addr::new(192, 0, 2, 1)));
+        assert_eq!((records[0].class, records[0].ttl), (RECORD_CLASS, 300));
+        assert_eq!(records[1].name, "example.com");
+        assert_eq!(records[1].data, RecordData::Txt(vec![b"\\#".to_vec(), b"0".to_vec(), b"A".to_vec()]));
+    }
+
+    #[test]
+    fn parse_errors() {
+        let line_of = |text: &str| match crate::zonefile::parse(text) {
+            Err(crate::error::DnsError::ZoneSyntax { line, .. }) => line,
+            other => panic!("{:?}", other),
+        };
+        assert_eq!(line_of("example.com. 300 A 192.0.2.1\nexample.com. soon A 192.0.2.1"), 2);
+        assert_eq!(line_of("example.com. 300 A 192.0.2.300"), 1);
+        assert_eq!(line_of("example.com. 300 MX 10"), 1);
+        assert_eq!(line_of("example.com. 300 BOGUS 1"), 1);
+        assert_eq!(line_of("example.com. 300 TXT \"open"), 1);
+        assert_eq!(line_of("\n\nexample.com. 300 SOA a. b. ( 1 2 3\n4 5"), 3);
+        assert_eq!(line_of("example.com. 300 TYPE99 \\# 3 dead"), 1);
+        assert_eq!(line_of("example.com. 300 DNSKEY 256 3 13 AAAA"), 1);
+        assert_eq!(line_of("bad..name 300 A 192.0.2.1"), 1);
+    }
+}
@@ -0,0 +1,388 @@
+use std::io::{Read, Write};
+use std::net::{TcpListener, UdpSocket};
+use std::process::Command;
+
+use bkdns::packet::{DNSPacket, DNSRecord, RecordData, RecordType, ReplyCode, RECORD_CLASS};
+use bkdns::server::{NotifyListener, Server, Zone};
+use bkdns::testutil::{a, MockServer, Reply, Rule};
+
+// The bkdns binary looking up `args` with a nameserver on `port` of 127.0.0.1
+fn run(port: u16, args: &[&str]) -> std::process::Output {
+    Command::new(env!("CARGO_BIN_EXE_bkdns"))
+        .args(["@127.0.0.1", "-p", &port.to_string()])
+        .args(args)
+        .output()
+        .unwrap()
+}
+
+fn status(port: u16, args: &[&str]) -> i32 {
+    run(port, args).status.code().unwrap()
+}
+
+#[test]
+fn answer_and_no_data() {
+    let server = MockServer::start().unwrap();
+    server.add_rule(Rule::new("example.com", RecordType::A).answer(a("example.com", 1)));
+    server.add_rule(Rule::new("example.com", RecordType::MX));
+    assert_eq!(status(server.addr().port(), &["example.com"]), 0);
+    assert_eq!(status(server.addr().port(), &["example.com", "MX"]), 0);
+}
+
+#[test]
+fn usage_error() {
+    assert_eq!(status(53, &["example.com", "bogus"]), 1);
+    assert_eq!(status(53, &["--strict-ttl", "example.com"]), 1);
+    assert_eq!(status(53, &["foo..com"]), 1);
+}
+
+#[test]
+fn network_failure() {
+    // nothing listens on a port just given back, so the query is refused
+    let port = UdpSocket::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+    assert_eq!(status(port, &["example.com"]), 2);
+}
+
+#[test]
+fn nxdomain() {
+    let server = MockServer::start().unwrap();
+    server.add_rule(Rule::new("missing.example.com", RecordType::A).reply_code(ReplyCode::NxDomain));
+    assert_eq!(status(server.addr().port(), &["missing.example.com"]), 3);
+    assert_eq!(status(server.addr().port(), &["missing.example.com", "--short"]), 3);
+}
+
+#[test]
+fn server_error() {
+    let server = MockServer::start().unwrap();
+    server.add_rule(Rule::new("example.com", RecordType::A).reply_code(ReplyCode::ServFail));
+    // no rule at all is REFUSED
+    assert_eq!(status(server.addr().port(), &["example.com"]), 4);
+    assert_eq!(status(server.addr().port(), &["example.org"]), 4);
+}
+
+#[test]
+fn bad_response() {
+    let server = MockServer::start().unwrap();
+    server.add_rule(Rule::new("example.com", RecordType::A).answer(a("example.com", 1)).reply(Reply::WrongId));
+    assert_eq!(status(server.addr().port(), &["example.com"]), 5);
+}
+
+#[test]
+fn repeated_queries() {
+    let server = MockServer::start().unwrap();
+    server.add_rule(Rule::new("example.com", RecordType::A).answer(a("example.com", 1)));
+    let output = run(server.addr().port(), &["example.com", "--count", "3"]);
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(server.queries(), 3);
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.contains("3: 1 records, "));
+    assert!(stdout.contains("3 queries, 3 answered, 0 failed\nmin/avg/max = "));
+
+    // nobody answers, so nothing to time and the status is a network failure
+    let port = UdpSocket::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+    let output = run(port, &["example.com", "--count", "2"]);
+    assert_eq!(output.status.code(), Some(2));
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.ends_with("2 queries, 0 answered, 2 failed\n"));
+}
+
+#[test]
+fn all_types() {
+    let server = MockServer::start().unwrap();
+    server.add_rule(Rule::new("example.com", RecordType::A).answer(a("example.com", 1)));
+    server.add_rule(Rule::new("example.com", RecordType::MX));
+    server.add_rule(Rule::new("example.com", RecordType::TXT).reply(Reply::WrongId));
+
+    // every type is reported, the failed TXT query and REFUSED ones included
+    let output = run(server.addr().port(), &["example.com", "--all"]);
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let headings: Vec<&str> = stdout.lines().filter(|x| x.starts_with(";; ")).collect();
+    assert_eq!(headings, [";; A", ";; AAAA", ";; MX", ";; NS", ";; TXT", ";; SOA"]);
+    assert!(stdout.contains("192.0.2.1"), "{}", stdout);
+    assert!(stdout.contains("No records found for example.com"), "{}", stdout);
+    assert!(stdout.contains("Failed to resolve domain: Response id"), "{}", stdout);
+    assert!(stdout.contains("Server returned error reply code Refused"), "{}", stdout);
+    assert_eq!(output.status.code(), Some(5));
+}
+
+#[test]
+fn json_output() {
+    let server = MockServer::start().unwrap();
+    server.add_rule(Rule::new("example.com", RecordType::A).answer(a("example.com", 1)));
+    let output = run(server.addr().port(), &["example.com", "--output", "json"]);
+    assert_eq!(output.status.code(), Some(0));
+
+    let response: bkdns::packet::DNSPacket = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(response.answers(), [a("example.com", 1)]);
+    let json: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(json["answers"][0]["data"]["A"], "192.0.2.1");
+}
+
+#[test]
+fn question_section() {
+    let server = MockServer::start().unwrap();
+    server.add_rule(Rule::new("example.com", RecordType::A).answer(a("example.com", 1)));
+    let output = run(server.addr().port(), &["example.com"]);
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.starts_with(";; QUESTION SECTION:\n;example.com.\t\tIN\tA\n\n"), "{}", stdout);
+}
+
+#[test]
+fn reverse_lookup() {
+    let server = MockServer::start().unwrap();
+    let ptr = DNSRecord {
+        name: String::from("9.9.9.9.in-addr.arpa"),
+        rtype: RecordType::PTR,
+        class: RECORD_CLASS,
+        ttl: 300,
+        data: RecordData::Ptr(String::from("dns9.quad9.net")),
+    };
+    server.add_rule(Rule::new("9.9.9.9.in-addr.arpa", RecordType::PTR).answer(ptr));
+    let output = run(server.addr().port(), &["9.9.9.9", "--short"]);
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(String::from_utf8(output.stdout).unwrap().trim_end(), "dns9.quad9.net");
+}
+
+/* The bkdns binary looking up `args` with $BKDNS_HOSTS naming a file called `file` that has
+    files.test, and no nameserver unless `args` gives one
+ */
+fn run_with_hosts(file: &str, args: &[&str]) -> (i32, String) {
+    let path = std::env::temp_dir().join(format!("bkdns-cli-{}-{}.hosts", std::process::id(), file));
+    std::fs::write(&path, "192.0.2.10 files.test\n2001:db8::10 files.test\n").unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_bkdns"))
+        .env("BKDNS_HOSTS", &path)
+        .args(args)
+        .output()
+        .unwrap();
+    std::fs::remove_file(&path).unwrap();
+    (output.status.code().unwrap(), String::from_utf8(output.stdout).unwrap())
+}
+
+#[test]
+fn hosts_file() {
+    let (code, stdout) = run_with_hosts("answer", &["files.test"]);
+    assert_eq!(code, 0);
+    assert_eq!(stdout, "files.test\t0\tIN\tA\t192.0.2.10\t; from hosts file\n");
+    let (code, stdout) = run_with_hosts("short", &["files.test", "AAAA", "--short"]);
+    assert_eq!((code, stdout.as_str()), (0, "2001:db8::10\n"));
+    let (code, stdout) = run_with_hosts("both", &["files.test", "--both"]);
+    assert_eq!(code, 0);
+    assert_eq!(stdout, "192.0.2.10\t; from hosts file\n2001:db8::10\t; from hosts file\n");
+}
+
+#[test]
+fn hosts_file_skipped_for_a_nameserver_given() {
+    let server = MockServer::start().unwrap();
+    server.add_rule(Rule::new("files.test", RecordType::A).answer(a("files.test", 1)));
+    let port = server.addr().port().to_string();
+    let given = [&["files.test", "@127.0.0.1"][..], &["files.test", "--server", "127.0.0.1"], &["127.0.0.1", "files.test"]];
+    for args in given {
+        let (code, stdout) = run_with_hosts("server", &[args, &["-p", &port]].concat());
+        assert_eq!(code, 0, "{:?}", args);
+        assert!(stdout.contains("files.test\t300\tIN\tA\t192.0.2.1"), "{}", stdout);
+        assert!(!stdout.contains("from hosts file"), "{}", stdout);
+    }
+    assert_eq!(server.queries(), 3);
+}
+
+#[test]
+fn no_hosts() {
+    // the system's nameservers on a port nothing answers, .test never resolves either way
+    let port = UdpSocket::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port().to_string();
+    let (code, stdout) = run_with_hosts("no-hosts", &["files.test", "--no-hosts", "-p", &port]);
+    assert_ne!(code, 0);
+    assert!(!stdout.contains("from hosts file"), "{}", stdout);
+}
+
+// `bkdns axfr` against 127.0.0.1:`port`
+fn axfr(port: u16) -> std::process::Output {
+    Command::new(env!("CARGO_BIN_EXE_bkdns"))
+        .args(["axfr", "example.com", "@127.0.0.1", "-p", &port.to_string()])
+        .output()
+        .unwrap()
+}
+
+#[test]
+fn zone_transfer() {
+    let soa = bkdns::zonefile::parse(
+        "example.com. 3600 SOA ns.example.com. admin.example.com. 1 7200 3600 1209600 300\n",
+    ).unwrap().remove(0);
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let zone = [vec![soa.clone(), a("www.example.com", 1)], vec![a("mail.example.com", 1), soa]];
+    std::thread::spawn(move || {
+        let (mut stream, _) = listener.accept().unwrap();
+        let mut length = [0; 2];
+        stream.read_exact(&mut length).unwrap();
+        let mut query = vec![0; u16::from_be_bytes(length) as usize];
+        stream.read_exact(&mut query).unwrap();
+        let query = DNSPacket::deserialize(&query).unwrap();
+
+        // the zone in two messages
+        for records in zone {
+            let mut message = DNSPacket::with_id(query.header.id());
+            message.header.flags.is_response = true;
+            message.add_question(query.questions()[0].clone());
+            for record in records {
+                message.add_answer(record);
+            }
+            let bytes = message.serialize().unwrap();
+            stream.write_all(&(bytes.len() as u16).to_be_bytes()).unwrap();
+            stream.write_all(&bytes).unwrap();
+        }
+    });
+
+    let output = axfr(port);
+    assert_eq!(output.status.code(), Some(0));
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    // the SOA's fields carry on over indented lines
+    let lines: Vec<&str> = stdout.lines().filter(|x| !x.starts_with('\t')).collect();
+    assert_eq!(lines.len(), 4);
+    assert!(lines[0].contains("SOA") && lines[3].contains("SOA"));
+    assert_eq!(lines[2], "mail.example.com.\t300\tIN\tA\t192.0.2.1");
+}
+
+#[test]
+fn zone_transfer_refused() {
+    let zone = Zone::new(bkdns::zonefile::parse("example.com. 300 A 192.0.2.1\n").unwrap());
+    let server = Server::bind("127.0.0.1:0".parse().unwrap(), zone).unwrap();
+    let port = server.local_addr().unwrap().port();
+    std::thread::spawn(move || server.run());
+
+    let output = axfr(port);
+    assert_eq!(output.status.code(), Some(4));
+    assert!(String::from_utf8(output.stdout).unwrap().contains("refused to transfer example.com"));
+}
+
+// `bkdns serial example.com` asking each of `ports` on 127.0.0.1
+fn serial(ports: &[u16], extra: &[&str]) -> (i32, String) {
+    let output = Command::new(env!("CARGO_BIN_EXE_bkdns"))
+        .args(["serial", "example.com"])
+        .args(ports.iter().map(|x| format!("@127.0.0.1:{}", x)))
+        .args(extra)
+        .output()
+        .unwrap();
+    (output.status.code().unwrap(), String::from_utf8(output.stdout).unwrap())
+}
+
+fn soa(serial: u32) -> DNSRecord {
+    let mut record = bkdns::zonefile::parse(
+        "example.com. 3600 SOA ns.example.com. admin.example.com. 1 7200 3600 1209600 300\n",
+    ).unwrap().remove(0);
+    if let RecordData::Soa(soa) = &mut record.data {
+        soa.serial = serial;
+    }
+    record
+}
+
+#[test]
+fn serials() {
+    let servers: Vec<MockServer> = [2024010102, 2024010102, 2024010101]
+        .into_iter()
+        .map(|serial| {
+            let server = MockServer::start().unwrap();
+            server.add_rule(Rule::new("example.com", RecordType::SOA).answer(soa(serial)));
+            server
+        })
+        .collect();
+    let ports: Vec<u16> = servers.iter().map(|x| x.addr().port()).collect();
+
+    let (code, stdout) = serial(&ports[..2], &[]);
+    assert_eq!(code, 0, "{}", stdout);
+    assert!(stdout.ends_with("2 of 2 servers have serial 2024010102\n"), "{}", stdout);
+    let (code, _) = serial(&ports[..2], &["--expect-serial", "2024010103"]);
+    assert_eq!(code, 1);
+
+    // a lagging and an unreachable server are both reported, the run goes on
+    let unreachable = UdpSocket::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+    let (code, stdout) = serial(&[ports[0], ports[2], unreachable], &[]);
+    assert_eq!(code, 1);
+    let lines: Vec<&str> = stdout.lines().collect();
+    assert!(lines[1].ends_with("2024010102  in sync"), "{}", stdout);
+    assert!(lines[2].ends_with("2024010101  behind by 1"), "{}", stdout);
+    assert!(lines[3].ends_with("-           unreachable"), "{}", stdout);
+    assert_eq!(lines[4], "1 of 3 servers have serial 2024010102");
+}
+
+#[test]
+fn dynamic_update() {
+    let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let port = socket.local_addr().unwrap().port();
+    let received = std::thread::spawn(move || {
+        let mut messages = Vec::new();
+        // the first update is accepted, the second fails its prerequisite
+        for code in [ReplyCode::NoError, ReplyCode::NxRrset] {
+            let mut buf = [0; 512];
+            let (size, client) = socket.recv_from(&mut buf).unwrap();
+            let update = DNSPacket::deserialize(&buf[..size]).unwrap();
+            let mut response = DNSPacket::with_id(update.header.id());
+            response.header.flags.is_response = true;
+            response.header.flags.opcode = update.header.flags.opcode;
+            response.add_question(update.questions()[0].clone());
+            response.set_reply_code(code);
+            socket.send_to(&response.serialize().unwrap(), client).unwrap();
+
+            let records = |x: &[DNSRecord]| -> Vec<(String, RecordType, u16, u32)> {
+                x.iter().map(|x| (x.name.clone(), x.rtype, x.class, x.ttl)).collect()
+            };
+            messages.push((update.header.flags.opcode, records(update.answers()), records(update.authority())));
+        }
+        messages
+    });
+
+    let update = |args: &[&str]| {
+        let output = Command::new(env!("CARGO_BIN_EXE_bkdns"))
+            .args(["update", "example.com", &format!("@127.0.0.1:{}", port)])
+            .args(args)
+            .output()
+            .unwrap();
+        (output.status.code().unwrap(), String::from_utf8(output.stdout).unwrap())
+    };
+    let (code, stdout) = update(&["--require-absent", "www CNAME", "--delete", "old A", "--add", "www 300 A 192.0.2.1"]);
+    assert_eq!((code, stdout.trim_end()), (0, "Update of example.com: NOERROR"));
+    let (code, stdout) = update(&["--require", "www A", "--delete", "www.example.com. A 192.0.2.1"]);
+    assert_eq!((code, stdout.trim_end()), (4, "Update of example.com: NXRRSET"));
+
+    let www = String::from("www.example.com");
+    assert_eq!(received.join().unwrap(), [
+        (5, vec![(www.clone(), RecordType::CNAME, 254, 0)], vec![
+            (String::from("old.example.com"), RecordType::A, 255, 0),
+            (www.clone(), RecordType::A, RECORD_CLASS, 300),
+        ]),
+        (5, vec![(www.clone(), RecordType::A, 255, 0)], vec![(www, RecordType::A, 254, 0)]),
+    ]);
+
+    // names have to be in the zone
+    let (code, stdout) = update(&["--add", "www.example.org. 300 A 192.0.2.1"]);
+    assert_eq!(code, 1);
+    assert!(stdout.contains("not in the zone"));
+}
+
+#[test]
+fn notify() {
+    let primary = MockServer::start().unwrap();
+    primary.add_rule(Rule::new("example.com", RecordType::SOA).answer(soa(2024010102)));
+    let secondary = NotifyListener::bind("127.0.0.1:0".parse().unwrap(), "example.com").unwrap()
+        .with_allowed(vec!["127.0.0.1".parse().unwrap()]);
+    let port = secondary.local_addr().unwrap().port();
+    let (sender, notified) = std::sync::mpsc::channel();
+    std::thread::spawn(move || secondary.run(|notify, _| sender.send(notify).unwrap()));
+
+    let notify = |zone: &str| {
+        let output = Command::new(env!("CARGO_BIN_EXE_bkdns"))
+            .args(["notify", zone, &format!("@127.0.0.1:{}", port)])
+            .args(["--primary", &primary.addr().to_string()])
+            .output()
+            .unwrap();
+        (output.status.code().unwrap(), String::from_utf8(output.stdout).unwrap())
+    };
+    let (code, stdout) = notify("example.com");
+    assert_eq!(code, 0, "{}", stdout);
+    assert!(stdout.starts_with("NOTIFY for example.com (serial 2024010102) to 127.0.0.1:"), "{}", stdout);
+    assert!(stdout.contains(": NOERROR, "));
+    assert_eq!(notified.recv().unwrap().serial, Some(2024010102));
+
+    // the secondary doesn't have this one, and the primary has no SOA for it
+    let (code, stdout) = notify("example.org");
+    assert_eq!(code, 4);
+    assert!(stdout.contains("(no SOA found)") && stdout.contains(": REFUSED, "), "{}", stdout);
+}
@@ -0,0 +1,186 @@
+use std::net::{Ipv4Addr, SocketAddr, UdpSocket};
+use std::time::Duration;
+
+use bkdns::packet::{DNSPacket, QueryBuilder, RecordData, RecordType, ReplyCode};
+use bkdns::proxy::{Proxy, Rules};
+use bkdns::resolver::{Resolver, TransportKind};
+use bkdns::testutil::{a, MockServer, Reply, Rule};
+
+/* A proxy in front of `upstream` that asks it with id 0x5555 whatever the client used, so a
+    reply relayed under the client's id shows the id was rewritten both ways
+ */
+fn start(upstream: &MockServer) -> SocketAddr {
+    start_with(upstream, Rules::new())
+}
+
+fn start_with(upstream: &MockServer, rules: Rules) -> SocketAddr {
+    start_proxy(upstream, |proxy| proxy.with_rules(rules))
+}
+
+fn start_proxy(upstream: &MockServer, setup: impl FnOnce(Proxy) -> Proxy) -> SocketAddr {
+    let mut resolver = Resolver::new(upstream.addr());
+    resolver.timeout = Duration::from_millis(200);
+    resolver.id = Some(0x5555);
+    let proxy = setup(Proxy::bind("127.0.0.1:0".parse().unwrap(), resolver).unwrap());
+    let addr = proxy.local_addr().unwrap();
+    std::thread::spawn(move || proxy.run());
+    addr
+}
+
+fn client() -> UdpSocket {
+    let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+    socket.set_read_timeout(Some(Duration::from_secs(2))).unwrap();
+    socket
+}
+
+fn receive(socket: &UdpSocket) -> DNSPacket {
+    let mut buf = [0; 1024];
+    let size = socket.recv(&mut buf).unwrap();
+    DNSPacket::deserialize(&buf[..size]).unwrap()
+}
+
+#[test]
+fn relays_under_the_client_id() {
+    let upstream = MockServer::start().unwrap();
+    upstream.add_rule(Rule::new("example.com", RecordType::A).answer(a("example.com", 1)));
+    let proxy = start(&upstream);
+
+    let socket = client();
+    let query = QueryBuilder::new().id(0x0042).question("Example.COM", RecordType::A).build().unwrap();
+    socket.send_to(&query.serialize().unwrap(), proxy).unwrap();
+    let response = receive(&socket);
+    assert_eq!(response.header.id(), 0x0042);
+    assert_eq!(response.questions()[0].name(), "Example.COM");
+    assert_eq!(response.answers(), [a("example.com", 1)]);
+    assert_eq!(upstream.queries(), 1);
+}
+
+#[test]
+fn big_answers_over_tcp() {
+    let upstream = MockServer::start().unwrap();
+    for (name, count) in [("some.example.com", 25), ("many.example.com", 30)] {
+        let mut rule = Rule::new(name, RecordType::A);
+        for last in 0..count {
+            rule = rule.answer(a(name, last));
+        }
+        upstream.add_rule(rule);
+    }
+    let proxy = start(&upstream);
+
+    // 25 answers fit in 512 bytes once their names are compressed, 30 don't
+    let socket = client();
+    let query = QueryBuilder::new().id(3).question("some.example.com", RecordType::A).build().unwrap();
+    socket.send_to(&query.serialize().unwrap(), proxy).unwrap();
+    let response = receive(&socket);
+    assert!(!response.header.flags.is_truncated && response.answers().len() == 25);
+    let query = QueryBuilder::new().id(4).question("many.example.com", RecordType::A).build().unwrap();
+    socket.send_to(&query.serialize().unwrap(), proxy).unwrap();
+    let response = receive(&socket);
+    assert!(response.header.flags.is_truncated && response.answers().len() < 30);
+
+    let mut resolver = Resolver::new(proxy);
+    resolver.transport = TransportKind::Tcp;
+    let (response, _) = resolver.query("many.example.com", RecordType::A).unwrap();
+    assert!(!response.header.flags.is_truncated);
+    assert_eq!(response.answers().len(), 30);
+}
+
+#[test]
+fn failures() {
+    let upstream = MockServer::start().unwrap();
+    upstream.add_rule(Rule::new("down.example.com", RecordType::A).reply(Reply::Drop));
+    let proxy = start(&upstream);
+    let socket = client();
+
+    let query = QueryBuilder::new().id(7).question("down.example.com", RecordType::A).build().unwrap();
+    socket.send_to(&query.serialize().unwrap(), proxy).unwrap();
+    let response = receive(&socket);
+    assert_eq!((response.header.id(), response.reply_code()), (7, ReplyCode::ServFail));
+
+    let bytes = query.serialize().unwrap();
+    socket.send_to(&bytes[..bytes.len() - 2], proxy).unwrap();
+    assert_eq!(receive(&socket).reply_code(), ReplyCode::FormErr);
+}
+
+#[test]
+fn queries_beyond_the_limit_dropped() {
+    let upstream = MockServer::start().unwrap();
+    upstream.add_rule(Rule::new("down.example.com", RecordType::A).reply(Reply::Drop));
+    upstream.add_rule(Rule::new("example.com", RecordType::A).answer(a("example.com", 1)));
+    let proxy = start_proxy(&upstream, |proxy| proxy.with_max_in_flight(1));
+
+    // the first holds the only slot until its upstream query times out, the second isn't answered
+    let (first, second) = (client(), client());
+    let query = |id: u16, name: &str| QueryBuilder::new().id(id).question(name, RecordType::A).build().unwrap();
+    first.send_to(&query(1, "down.example.com").serialize().unwrap(), proxy).unwrap();
+    std::thread::sleep(Duration::from_millis(20));
+    second.send_to(&query(2, "example.com").serialize().unwrap(), proxy).unwrap();
+    assert_eq!(receive(&first).reply_code(), ReplyCode::ServFail);
+    let mut buf = [0; 512];
+    second.set_read_timeout(Some(Duration::from_millis(300))).unwrap();
+    assert!(second.recv(&mut buf).is_err());
+
+    // and once it's answered the slot is free again
+    second.send_to(&query(3, "example.com").serialize().unwrap(), proxy).unwrap();
+    second.set_read_timeout(Some(Duration::from_secs(2))).unwrap();
+    assert_eq!(receive(&second).answers(), [a("example.com", 1)]);
+}
+
+#[test]
+fn slow_answers_dont_block_others() {
+    let upstream = MockServer::start().unwrap();
+    upstream.add_rule(Rule::new("slow.example.com", RecordType::A)
+        .answer(a("slow.example.com", 1))
+        .reply(Reply::Delay(Duration::from_millis(150))));
+    upstream.add_rule(Rule::new("fast.example.com", RecordType::A).answer(a("fast.example.com", 2)));
+    let proxy = start(&upstream);
+
+    let (slow, fast) = (client(), client());
+    let query = |id: u16, name: &str| QueryBuilder::new().id(id).question(name, RecordType::A).build().unwrap();
+    slow.send_to(&query(1, "slow.example.com").serialize().unwrap(), proxy).unwrap();
+    std::thread::sleep(Duration::from_millis(20));
+    fast.send_to(&query(2, "fast.example.com").serialize().unwrap(), proxy).unwrap();
+
+    // the fast answer isn't queued behind the slow one
+    fast.set_read_timeout(Some(Duration::from_millis(100))).unwrap();
+    assert_eq!(receive(&fast).answers(), [a("fast.example.com", 2)]);
+    assert_eq!(receive(&slow).answers(), [a("slow.example.com", 1)]);
+}
+
+#[test]
+fn blocked_and_overridden_names_stay_local() {
+    let upstream = MockServer::start().unwrap();
+    let mut rules = Rules::new();
+    rules.load_blocklist("0.0.0.0 *.ads.example\n");
+    rules.add_override("banner.ads.example", "192.0.2.50".parse().unwrap());
+    let proxy = start_with(&upstream, rules);
+    let socket = client();
+    let ask = |name: &str, rtype: RecordType| {
+        let query = QueryBuilder::new().id(9).question(name, rtype).build().unwrap();
+        socket.send_to(&query.serialize().unwrap(), proxy).unwrap();
+        receive(&socket)
+    };
+
+    assert_eq!(ask("x.ads.example", RecordType::A).reply_code(), ReplyCode::NxDomain);
+    // the exact override wins over the wildcard block
+    let response = ask("banner.ads.example", RecordType::A);
+    assert_eq!(response.answers()[0].data, RecordData::A(Ipv4Addr::new(192, 0, 2, 50)));
+    let response = ask("banner.ads.example", RecordType::AAAA);
+    assert_eq!((response.reply_code(), response.answers().len()), (ReplyCode::NoError, 0));
+    assert_eq!(upstream.queries(), 0);
+}
+
+#[test]
+fn blocked_with_zero_addresses() {
+    let upstream = MockServer::start().unwrap();
+    let mut rules = Rules::new();
+    rules.block("tracker.example");
+    rules.block_with_zero = true;
+    let proxy = start_with(&upstream, rules);
+    let socket = client();
+    let query = QueryBuilder::new().question("tracker.example", RecordType::A).build().unwrap();
+    socket.send_to(&query.serialize().unwrap(), proxy).unwrap();
+    let response = receive(&socket);
+    assert_eq!(response.reply_code(), ReplyCode::NoError);
+    assert_eq!(response.answers()[0].data, RecordData::A(Ipv4Addr::UNSPECIFIED));
+}
@@ -0,0 +1,605 @@
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use bkdns::cache::Cache;
+use bkdns::error::DnsError;
+use bkdns::packet::{DNSPacket, DNSQuestion, DNSRecord, RecordData, RecordType, ReplyCode, Soa, RECORD_CLASS};
+use bkdns::resolver::{AddressSource, IpPreference, QueryOutcome, Resolver};
+use bkdns::testutil::{a, MockServer, Reply, Rule};
+use bkdns::transport::{Transport, UdpTransport};
+
+fn record(name: &str, rtype: RecordType, data: RecordData) -> DNSRecord {
+    DNSRecord { name: String::from(name), rtype, class: RECORD_CLASS, ttl: 300, data }
+}
+
+// A resolver for `server` that gives up quickly, so dropped queries don't stall the suite
+fn resolver(server: &MockServer) -> Resolver {
+    let mut resolver = Resolver::new(server.addr());
+    resolver.timeout = Duration::from_millis(200);
+    resolver
+}
+
+#[test]
+fn answers() {
+    let server = MockServer::start().unwrap();
+    server.add_rule(Rule::new("example.com", RecordType::A).answer(a("example.com", 1)));
+
+    let result = resolver(&server).resolve("example.com", RecordType::A).unwrap();
+    assert_eq!(result.outcome, QueryOutcome::Answers(vec![a("example.com", 1)]));
+    assert_eq!(server.queries(), 1);
+}
+
+#[test]
+fn unknown_name_refused() {
+    let server = MockServer::start().unwrap();
+    let result = resolver(&server).resolve("example.com", RecordType::A).unwrap();
+    assert_eq!(result.outcome, QueryOutcome::Failure(ReplyCode::Refused));
+}
+
+#[test]
+fn nxdomain_with_soa() {
+    let soa = Soa {
+        mname: String::from("ns.example.com"),
+        rname: String::from("hostmaster.example.com"),
+        serial: 1, refresh: 7200, retry: 3600, expire: 1209600, minimum: 300,
+    };
+    let server = MockServer::start().unwrap();
+    server.add_rule(Rule::new("missing.example.com", RecordType::A)
+        .reply_code(ReplyCode::NxDomain)
+        .authority(record("example.com", RecordType::SOA, RecordData::Soa(soa.clone()))));
+
+    let result = resolver(&server).resolve("missing.example.com", RecordType::A).unwrap();
+    assert_eq!(result.outcome, QueryOutcome::NxDomain { soa: Some(soa) });
+}
+
+#[test]
+fn no_answer_times_out() {
+    let server = MockServer::start().unwrap();
+    server.add_rule(Rule::new("example.com", RecordType::A).reply(Reply::Drop));
+
+    assert!(matches!(resolver(&server).query("example.com", RecordType::A), Err(DnsError::Io(_))));
+    assert_eq!(server.queries(), 1);
+}
+
+#[test]
+fn wrong_id_rejected() {
+    let server = MockServer::start().unwrap();
+    server.add_rule(Rule::new("example.com", RecordType::A).answer(a("example.com", 1)).reply(Reply::WrongId));
+
+    match resolver(&server).query("example.com", RecordType::A) {
+        Err(DnsError::IdMismatch { expected, received }) => assert_eq!(received, expected.wrapping_add(1)),
+        result => panic!("expected an id mismatch, got {:?}", result.map(|x| x.0)),
+    }
+}
+
+#[test]
+fn truncated_reply() {
+    let server = MockServer::start().unwrap();
+    server.add_rule(Rule::new("example.com", RecordType::A).answer(a("example.com", 1)).reply(Reply::Truncated));
+
+    let (response, _) = resolver(&server).query("example.com", RecordType::A).unwrap();
+    assert!(response.header.flags.is_truncated);
+    assert!(response.answers().is_empty());
+}
+
+#[test]
+fn delayed_reply() {
+    let server = MockServer::start().unwrap();
+    server.add_rule(Rule::new("example.com", RecordType::A)
+        .answer(a("example.com", 1))
+        .reply(Reply::Delay(Duration::from_millis(50))));
+
+    let result = resolver(&server).resolve("example.com", RecordType::A).unwrap();
+    assert!(result.elapsed >= Duration::from_millis(50));
+    assert_eq!(result.outcome, QueryOutcome::Answers(vec![a("example.com", 1)]));
+
+    // past the timeout it's as good as dropped
+    server.add_rule(Rule::new("slow.example.com", RecordType::A).reply(Reply::Delay(Duration::from_millis(400))));
+    assert!(resolver(&server).query("slow.example.com", RecordType::A).is_err());
+}
+
+#[test]
+fn follow_cname_across_queries() {
+    let server = MockServer::start().unwrap();
+    let cname = record("www.example.com", RecordType::CNAME, RecordData::Cname(String::from("example.com")));
+    server.add_rule(Rule::new("www.example.com", RecordType::A).answer(cname.clone()));
+    server.add_rule(Rule::new("example.com", RecordType::A).answer(a("example.com", 1)));
+
+    let mut resolver = resolver(&server);
+    resolver.follow_cname = true;
+    let result = resolver.resolve("www.example.com", RecordType::A).unwrap();
+    assert_eq!(result.outcome, QueryOutcome::Answers(vec![cname, a("example.com", 1)]));
+    assert_eq!(server.queries(), 2);
+}
+
+#[test]
+fn cache_skips_the_network() {
+    let server = MockServer::start().unwrap();
+    server.add_rule(Rule::new("example.com", RecordType::A).answer(a("example.com", 1)));
+
+    let mut resolver = resolver(&server);
+    resolver.cache = Some(Mutex::new(Cache::new()));
+    resolver.resolve("example.com", RecordType::A).unwrap();
+    let cached = resolver.resolve("example.com", RecordType::A).unwrap();
+    assert_eq!(cached.elapsed, Duration::ZERO);
+    assert_eq!(server.queries(), 1);
+}
+
+#[test]
+fn dns0x20_echoed_case() {
+    let server = MockServer::start().unwrap();
+    server.add_rule(Rule::new("example.com", RecordType::A).answer(a("example.com", 1)));
+
+    let mut resolver = resolver(&server);
+    resolver.dns0x20 = true;
+    assert!(resolver.query("example.com", RecordType::A).is_ok());
+}
+
+#[test]
+fn falls_back_to_next_nameserver() {
+    let down = MockServer::start().unwrap();
+    down.add_rule(Rule::new("example.com", RecordType::A).reply(Reply::Drop));
+    let up = MockServer::start().unwrap();
+    up.add_rule(Rule::new("example.com", RecordType::A).answer(a("example.com", 2)));
+
+    let mut resolver = resolver(&down);
+    resolver.nameservers.push(up.addr());
+    let result = resolver.resolve("example.com", RecordType::A).unwrap();
+    assert_eq!(result.outcome, QueryOutcome::Answers(vec![a("example.com", 2)]));
+    assert_eq!((down.queries(), up.queries()), (1, 1));
+    assert_eq!((result.server, result.attempts), (Some(up.addr()), 2));
+}
+
+#[test]
+fn servfail_falls_back_unless_disabled() {
+    let broken = MockServer::start().unwrap();
+    broken.add_rule(Rule::new("example.com", RecordType::A).reply_code(ReplyCode::ServFail));
+    let up = MockServer::start().unwrap();
+    up.add_rule(Rule::new("example.com", RecordType::A).answer(a("example.com", 2)));
+
+    let mut resolver = resolver(&broken);
+    resolver.nameservers.push(up.addr());
+    let result = resolver.resolve("example.com", RecordType::A).unwrap();
+    assert_eq!(result.outcome, QueryOutcome::Answers(vec![a("example.com", 2)]));
+    assert_eq!(up.queries(), 1);
+
+    resolver.next_on_servfail = false;
+    let result = resolver.resolve("example.com", RecordType::A).unwrap();
+    assert_eq!(result.outcome, QueryOutcome::Failure(ReplyCode::ServFail));
+    assert_eq!(result.server, Some(broken.addr()));
+    assert_eq!(up.queries(), 1);
+}
+
+#[test]
+fn nxdomain_does_not_fall_back() {
+    let first = MockServer::start().unwrap();
+    first.add_rule(Rule::new("missing.example.com", RecordType::A).reply_code(ReplyCode::NxDomain));
+    let second = MockServer::start().unwrap();
+    second.add_rule(Rule::new("missing.example.com", RecordType::A).answer(a("missing.example.com", 1)));
+
+    let mut resolver = resolver(&first);
+    resolver.nameservers.push(second.addr());
+    let result = resolver.resolve("missing.example.com", RecordType::A).unwrap();
+    assert_eq!(result.outcome, QueryOutcome::NxDomain { soa: None });
+    assert_eq!((result.server, result.attempts), (Some(first.addr()), 1));
+    assert_eq!(second.queries(), 0);
+}
+
+#[test]
+fn rotate_spreads_the_first_nameserver() {
+    let servers: Vec<MockServer> = (0..3).map(|_| MockServer::start().unwrap()).collect();
+    for server in &servers {
+        server.add_rule(Rule::new("example.com", RecordType::A).answer(a("example.com", 1)));
+    }
+
+    let mut resolver = resolver(&servers[0]);
+    resolver.nameservers = servers.iter().map(|x| x.addr()).collect();
+    resolver.resolve("example.com", RecordType::A).unwrap();
+    assert_eq!(servers.iter().map(|x| x.queries()).collect::<Vec<usize>>(), [1, 0, 0]);
+
+    resolver.rotate = true;
+    let answered: Vec<_> = (0..3).map(|_| resolver.resolve("example.com", RecordType::A).unwrap().server).collect();
+    assert_eq!(answered, servers.iter().map(|x| Some(x.addr())).collect::<Vec<_>>());
+    assert_eq!(servers.iter().map(|x| x.queries()).collect::<Vec<usize>>(), [2, 1, 1]);
+}
+
+#[test]
+fn rotate_still_fails_over() {
+    let up = MockServer::start().unwrap();
+    up.add_rule(Rule::new("example.com", RecordType::A).answer(a("example.com", 1)));
+    let down = MockServer::start().unwrap();
+    down.add_rule(Rule::new("example.com", RecordType::A).reply(Reply::Drop));
+
+    // the second query starts at the dead server and wraps around to the first
+    let mut resolver = resolver(&up);
+    resolver.nameservers.push(down.addr());
+    resolver.rotate = true;
+    resolver.resolve("example.com", RecordType::A).unwrap();
+    let result = resolver.resolve("example.com", RecordType::A).unwrap();
+    assert_eq!((result.server, result.attempts), (Some(up.addr()), 2));
+}
+
+#[test]
+fn race_takes_the_fastest() {
+    let slow = MockServer::start().unwrap();
+    slow.add_rule(Rule::new("example.com", RecordType::A)
+        .answer(a("example.com", 1))
+        .reply(Reply::Delay(Duration::from_millis(300))));
+    let fast = MockServer::start().unwrap();
+    fast.add_rule(Rule::new("example.com", RecordType::A).answer(a("example.com", 2)));
+
+    let mut resolver = resolver(&slow);
+    resolver.nameservers.push(fast.addr());
+    resolver.race = true;
+    let result = resolver.resolve("example.com", RecordType::A).unwrap();
+    assert_eq!(result.outcome, QueryOutcome::Answers(vec![a("example.com", 2)]));
+    assert_eq!((result.server, result.attempts), (Some(fast.addr()), 2));
+    assert!(result.elapsed < Duration::from_millis(300));
+    assert_eq!((slow.queries(), fast.queries()), (1, 1));
+}
+
+#[test]
+fn race_ignores_unusable_replies() {
+    let wrong = MockServer::start().unwrap();
+    wrong.add_rule(Rule::new("example.com", RecordType::A).reply(Reply::WrongId));
+    let down = MockServer::start().unwrap();
+    down.add_rule(Rule::new("example.com", RecordType::A).reply(Reply::Drop));
+    let up = MockServer::start().unwrap();
+    up.add_rule(Rule::new("example.com", RecordType::A)
+        .answer(a("example.com", 1))
+        .reply(Reply::Delay(Duration::from_millis(50))));
+
+    let mut resolver = resolver(&wrong);
+    resolver.nameservers.extend([down.addr(), up.addr()]);
+    resolver.race = true;
+    let result = resolver.resolve("example.com", RecordType::A).unwrap();
+    assert_eq!(result.outcome, QueryOutcome::Answers(vec![a("example.com", 1)]));
+    assert_eq!(result.server, Some(up.addr()));
+
+    resolver.nameservers = vec![wrong.addr(), down.addr()];
+    assert!(matches!(resolver.query("example.com", RecordType::A), Err(DnsError::Io(_))));
+}
+
+fn aaaa(name: &str, last: u16) -> DNSRecord {
+    record(name, RecordType::AAAA, RecordData::Aaaa(Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, last)))
+}
+
+#[test]
+fn dual_interleaves_families() {
+    let server = MockServer::start().unwrap();
+    server.add_rule(Rule::new("example.com", RecordType::A).answer(a("example.com", 1)).answer(a("example.com", 2)));
+    server.add_rule(Rule::new("example.com", RecordType::AAAA)
+        .answer(aaaa("example.com", 1))
+        .reply(Reply::Delay(Duration::from_millis(20))));
+
+    let mut resolver = resolver(&server);
+    resolver.ip_preference = IpPreference::Ipv6First;
+    let (addrs, source) = Arc::new(resolver).lookup_host_dual("example.com").unwrap();
+    assert_eq!(source, AddressSource::Dns);
+    let expected: Vec<IpAddr> = vec![
+        Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 1).into(),
+        Ipv4Addr::new(192, 0, 2, 1).into(),
+        Ipv4Addr::new(192, 0, 2, 2).into(),
+    ];
+    assert_eq!(addrs, expected);
+}
+
+#[test]
+fn dual_stops_waiting_after_the_resolution_delay() {
+    let server = MockServer::start().unwrap();
+    server.add_rule(Rule::new("example.com", RecordType::A).answer(a("example.com", 1)));
+    server.add_rule(Rule::new("example.com", RecordType::AAAA).reply(Reply::Drop));
+
+    // the preferred family never answers, the A records are all there is after 50ms
+    let mut resolver = resolver(&server);
+    resolver.timeout = Duration::from_secs(2);
+    resolver.ip_preference = IpPreference::Ipv6First;
+    let start = Instant::now();
+    let (addrs, _) = Arc::new(resolver).lookup_host_dual("example.com").unwrap();
+    assert_eq!(addrs, vec![IpAddr::from(Ipv4Addr::new(192, 0, 2, 1))]);
+    assert!(start.elapsed() < Duration::from_secs(1), "{:?}", start.elapsed());
+
+    // neither family answering runs into the timeout
+    server.add_rule(Rule::new("dead.example.com", RecordType::A).reply(Reply::Drop));
+    server.add_rule(Rule::new("dead.example.com", RecordType::AAAA).reply(Reply::Drop));
+    let resolver = Arc::new(self::resolver(&server));
+    assert!(matches!(resolver.lookup_host_dual("dead.example.com"), Err(DnsError::Io(_))));
+}
+
+#[test]
+fn dual_returns_the_preferred_family_at_once() {
+    let server = MockServer::start().unwrap();
+    server.add_rule(Rule::new("example.com", RecordType::A).answer(a("example.com", 1)));
+    server.add_rule(Rule::new("example.com", RecordType::AAAA)
+        .answer(aaaa("example.com", 1))
+        .reply(Reply::Delay(Duration::from_millis(500))));
+
+    // the A records are preferred, nothing waits on the AAAA lookup
+    let mut resolver = resolver(&server);
+    resolver.timeout = Duration::from_secs(2);
+    resolver.resolution_delay = Duration::from_secs(1);
+    let start = Instant::now();
+    let (addrs, _) = Arc::new(resolver).lookup_host_dual("example.com").unwrap();
+    assert_eq!(addrs, vec![IpAddr::from(Ipv4Addr::new(192, 0, 2, 1))]);
+    assert!(start.elapsed() < Duration::from_millis(400), "{:?}", start.elapsed());
+}
+
+#[test]
+fn every_nameserver_down() {
+    let down = MockServer::start().unwrap();
+    down.add_rule(Rule::new("example.com", RecordType::A).reply(Reply::Drop));
+
+    let mut resolver = resolver(&down);
+    resolver.nameservers.push(down.addr());
+    assert!(matches!(resolver.query("example.com", RecordType::A), Err(DnsError::Io(_))));
+    assert_eq!(down.queries(), 2);
+
+    resolver.nameservers.clear();
+    assert!(matches!(resolver.query("example.com", RecordType::A), Err(DnsError::NoNameservers)));
+}
+
+// Everything a test's tracing subscriber wrote, as text
+#[derive(Clone, Default)]
+struct Captured(Arc<Mutex<Vec<u8>>>);
+
+impl std::io::Write for Captured {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Captured {
+    fn text(&self) -> String {
+        String::from_utf8(self.0.lock().unwrap().clone()).unwrap()
+    }
+}
+
+fn capture_logs<F: FnOnce()>(level: tracing::Level, f: F) -> String {
+    let captured = Captured::default();
+    let writer = captured.clone();
+    let subscriber = tracing_subscriber::fmt()
+        .with_writer(move || writer.clone())
+        .with_max_level(level)
+        .without_time()
+        .finish();
+    tracing::subscriber::with_default(subscriber, f);
+    captured.text()
+}
+
+#[test]
+fn query_spans_and_events() {
+    let down = MockServer::start().unwrap();
+    down.add_rule(Rule::new("example.com", RecordType::A).reply(Reply::Drop));
+    let up = MockServer::start().unwrap();
+    up.add_rule(Rule::new("example.com", RecordType::A).answer(a("example.com", 2)));
+
+    let mut resolver = resolver(&down);
+    resolver.nameservers.push(up.addr());
+    resolver.cache = Some(Mutex::new(Cache::new()));
+    let logs = capture_logs(tracing::Level::DEBUG, || {
+        resolver.resolve("example.com", RecordType::A).unwrap();
+        resolver.resolve("example.com", RecordType::A).unwrap();
+    });
+
+    let span = format!("dns.query{{name=\"example.com\" type=A server={}}}", up.addr());
+    assert!(logs.lines().any(|x| x.contains(&span) && x.contains("response received")), "{}", logs);
+    assert!(logs.contains(&format!("no usable answer, trying the next nameserver server={}", down.addr())), "{}", logs);
+    assert!(logs.contains("answered from the cache"), "{}", logs);
+    // wire dumps are trace events
+    assert!(!logs.contains("00000000"), "{}", logs);
+}
+
+#[test]
+fn wire_dumps_at_trace() {
+    let server = MockServer::start().unwrap();
+    server.add_rule(Rule::new("example.com", RecordType::A).answer(a("example.com", 1)));
+
+    let logs = capture_logs(tracing::Level::TRACE, || {
+        resolver(&server).query("example.com", RecordType::A).unwrap();
+    });
+    assert!(logs.contains("; question section"), "{}", logs);
+    assert!(logs.contains("; answer section"), "{}", logs);
+}
+
+#[test]
+fn dnssec_ok_adds_opt_record() {
+    let server = MockServer::start().unwrap();
+    let mut resolver = resolver(&server);
+    let query = resolver.build_query("example.com", RecordType::A).unwrap();
+    assert_eq!((query.additional()[0].rtype, query.additional()[0].ttl), (RecordType::OPT, 0));
+    resolver.edns_payload = None;
+    assert!(resolver.build_query("example.com", RecordType::A).unwrap().additional().is_empty());
+
+    resolver.dnssec_ok = true;
+    let query = resolver.build_query("example.com", RecordType::A).unwrap();
+    let opt = &query.additional()[0];
+    assert_eq!(opt.rtype, RecordType::OPT);
+    assert_eq!(opt.ttl, bkdns::packet::EDNS_DNSSEC_OK);
+    assert_eq!(opt.class, bkdns::packet::DEFAULT_EDNS_PAYLOAD);
+}
+
+#[test]
+fn edns_payload_sizes_the_udp_buffer() {
+    // 40 uncompressed A records, about 1100 bytes
+    let server = MockServer::start().unwrap();
+    let mut rule = Rule::new("example.com", RecordType::A);
+    for last in 1..=40 {
+        rule = rule.answer(a("example.com", last));
+    }
+    server.add_rule(rule);
+
+    let mut resolver = resolver(&server);
+    resolver.edns_payload = Some(512);
+    let query = resolver.build_query("example.com", RecordType::A).unwrap();
+    assert_eq!((query.additional()[0].rtype, query.additional()[0].class), (RecordType::OPT, 512));
+    // the mock server ignores the OPT record, so a response too big for it is cut off
+    assert!(resolver.resolve("example.com", RecordType::A).is_err());
+
+    resolver.edns_payload = Some(1232);
+    match resolver.resolve("example.com", RecordType::A).unwrap().outcome {
+        QueryOutcome::Answers(answers) => assert_eq!(answers.len(), 40),
+        outcome => panic!("{:?}", outcome),
+    }
+}
+
+// Answers one query over TCP on `addr` with `answer`, as a server whose UDP path is broken might
+fn answer_once_over_tcp(addr: std::net::SocketAddr, answer: DNSRecord) -> std::thread::JoinHandle<()> {
+    use std::io::{Read, Write};
+
+    let listener = std::net::TcpListener::bind(addr).unwrap();
+    std::thread::spawn(move || {
+        let (mut stream, _) = listener.accept().unwrap();
+        let mut length = [0; 2];
+        stream.read_exact(&mut length).unwrap();
+        let mut query = vec![0; u16::from_be_bytes(length) as usize];
+        stream.read_exact(&mut query).unwrap();
+        let query = DNSPacket::deserialize(&query).unwrap();
+
+        let mut response = DNSPacket::with_id(query.header.id());
+        response.header.flags.is_response = true;
+        let question = &query.questions()[0];
+        response.add_question(DNSQuestion::new(String::from(question.name()), question.qtype()).unwrap());
+        response.add_answer(answer);
+        let bytes = response.serialize().unwrap();
+        stream.write_all(&(bytes.len() as u16).to_be_bytes()).unwrap();
+        stream.write_all(&bytes).unwrap();
+    })
+}
+
+#[test]
+fn servfail_retried_over_tcp() {
+    let server = MockServer::start().unwrap();
+    server.add_rule(Rule::new("example.com", RecordType::A).reply_code(ReplyCode::ServFail));
+
+    let mut resolver = resolver(&server);
+    let result = resolver.resolve("example.com", RecordType::A).unwrap();
+    assert_eq!(result.outcome, QueryOutcome::Failure(ReplyCode::ServFail));
+
+    resolver.retry_tcp_on_servfail = true;
+    let tcp = answer_once_over_tcp(server.addr(), a("example.com", 3));
+    let result = resolver.resolve("example.com", RecordType::A).unwrap();
+    assert_eq!(result.outcome, QueryOutcome::Answers(vec![a("example.com", 3)]));
+    tcp.join().unwrap();
+
+    // nothing listening over TCP leaves the SERVFAIL
+    let result = resolver.resolve("example.com", RecordType::A).unwrap();
+    assert_eq!(result.outcome, QueryOutcome::Failure(ReplyCode::ServFail));
+}
+
+// Answers every query in memory with one record for the question's name, www.example.com is an alias
+struct Answering(Mutex<Vec<Vec<u8>>>);
+
+impl Transport for Answering {
+    fn query(&self, message: &[u8]) -> Result<Vec<u8>, DnsError> {
+        self.0.lock().unwrap().push(message.to_vec());
+        let query = DNSPacket::deserialize(message)?;
+        let mut response = DNSPacket::with_id(query.header.id());
+        response.header.flags.is_response = true;
+        let question = &query.questions()[0];
+        response.add_question(DNSQuestion::new(String::from(question.name()), question.qtype())?);
+        match question.name() {
+            "www.example.com" => response.add_answer(www_alias()),
+            name => response.add_answer(a(name, 7)),
+        }
+        response.serialize()
+    }
+}
+
+#[test]
+fn resolve_with_a_transport_of_our_own() {
+    // the resolver's own nameserver is never asked
+    let server = MockServer::start().unwrap();
+    let transport = Answering(Mutex::new(Vec::new()));
+    let result = resolver(&server).resolve_with(&transport, "example.com", RecordType::A).unwrap();
+    assert_eq!(result.outcome, QueryOutcome::Answers(vec![a("example.com", 7)]));
+    assert_eq!((result.server, result.attempts), (None, 1));
+    assert_eq!((transport.0.lock().unwrap().len(), server.queries()), (1, 0));
+
+    // the built-in ones work the same way
+    server.add_rule(Rule::new("example.com", RecordType::A).answer(a("example.com", 1)));
+    let udp = UdpTransport { nameserver: server.addr(), timeout: Duration::from_millis(200), buffer_size: 512 };
+    let result = resolver(&server).resolve_with(&udp, "example.com", RecordType::A).unwrap();
+    assert_eq!(result.outcome, QueryOutcome::Answers(vec![a("example.com", 1)]));
+}
+
+fn www_alias() -> DNSRecord {
+    record("www.example.com", RecordType::CNAME, RecordData::Cname(String::from("example.com")))
+}
+
+#[test]
+fn resolve_with_follows_cnames_and_caches() {
+    let server = MockServer::start().unwrap();
+    let transport = Answering(Mutex::new(Vec::new()));
+    let mut resolver = resolver(&server);
+    resolver.follow_cname = true;
+    resolver.cache = Some(Mutex::new(Cache::new()));
+    let result = resolver.resolve_with(&transport, "www.example.com", RecordType::A).unwrap();
+    assert_eq!(result.outcome, QueryOutcome::Answers(vec![www_alias(), a("example.com", 7)]));
+    assert_eq!((result.server, result.attempts), (None, 2));
+
+    // both hops are cached now, the transport isn't asked again
+    let cached = resolver.resolve_with(&transport, "www.example.com", RecordType::A).unwrap();
+    assert_eq!(cached.outcome, result.outcome);
+    assert_eq!(cached.elapsed, Duration::ZERO);
+    assert_eq!((transport.0.lock().unwrap().len(), server.queries()), (2, 0));
+}
+
+#[test]
+fn pinned_transaction_id() {
+    let server = MockServer::start().unwrap();
+    let transport = Answering(Mutex::new(Vec::new()));
+    let mut resolver = resolver(&server);
+    resolver.id = Some(0x1234);
+    for _ in 0..2 {
+        resolver.resolve_with(&transport, "example.com", RecordType::A).unwrap();
+    }
+    let ids: Vec<u16> = transport.0.lock().unwrap().iter().map(|x| u16::from_be_bytes([x[0], x[1]])).collect();
+    assert_eq!(ids, [0x1234, 0x1234]);
+}
+
+#[test]
+fn wait_all_asks_for_the_rest() {
+    // like most resolvers, the mock answers only the first question of a query
+    let server = MockServer::start().unwrap();
+    server.add_rule(Rule::new("a.example.com", RecordType::A).answer(a("a.example.com", 1)));
+    server.add_rule(Rule::new("b.example.com", RecordType::A).answer(a("b.example.com", 2)));
+    server.add_rule(Rule::new("c.example.com", RecordType::AAAA));
+    let questions = [
+        ("a.example.com", RecordType::A),
+        ("b.example.com", RecordType::A),
+        ("c.example.com", RecordType::AAAA),
+    ];
+
+    let mut resolver = resolver(&server);
+    let (response, _) = resolver.query_many(&questions).unwrap();
+    assert_eq!(response.questions().len(), 3);
+    assert_eq!(response.answers(), [a("a.example.com", 1)]);
+    assert_eq!(server.queries(), 1);
+
+    // a question with no data still gets asked, and adds nothing
+    resolver.wait_all = true;
+    let (response, _) = resolver.query_many(&questions).unwrap();
+    assert_eq!(response.answers(), [a("a.example.com", 1), a("b.example.com", 2)]);
+    assert_eq!(response.header.answer_count(), 2);
+    assert_eq!(server.queries(), 4);
+}
+
+#[test]
+fn signed_queries_need_signed_responses() {
+    let server = MockServer::start().unwrap();
+    server.add_rule(Rule::new("example.com", RecordType::A).answer(a("example.com", 1)));
+    let mut resolver = resolver(&server);
+    resolver.tsig = Some("test-key:hmac-sha256:AAECAwQFBgcICQoLDA0ODxAREhMUFRYXGBkaGxwdHh8=".parse().unwrap());
+
+    // the mock doesn't sign, which is as bad as a wrong signature
+    assert!(matches!(resolver.query("example.com", RecordType::A), Err(DnsError::TsigInvalid(_))));
+    resolver.tsig = None;
+    assert!(resolver.query("example.com", RecordType::A).is_ok());
+    assert_eq!(server.queries(), 2);
+}
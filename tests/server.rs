@@ -0,0 +1,96 @@
+use std::net::{Ipv4Addr, SocketAddr};
+use std::time::Duration;
+
+use bkdns::packet::{RecordData, RecordType, ReplyCode};
+use bkdns::resolver::{QueryOutcome, Resolver, TransportKind};
+use bkdns::server::{Server, Zone};
+
+const ZONE: &str = "\
+example.com.      3600 IN SOA   ns.example.com. admin.example.com. ( 2024010101 7200 3600 1209600 300 )
+example.com.      300  IN A     192.0.2.1
+example.com.      300  IN MX    10 mail.example.com.
+www.example.com.  300  IN CNAME example.com.
+";
+
+// A server for ZONE on an ephemeral port, left running for the rest of the test binary
+fn start() -> SocketAddr {
+    serve(ZONE)
+}
+
+fn serve(text: &str) -> SocketAddr {
+    let zone = Zone::new(bkdns::zonefile::parse(text).unwrap());
+    let server = Server::bind("127.0.0.1:0".parse().unwrap(), zone).unwrap();
+    let addr = server.local_addr().unwrap();
+    std::thread::spawn(move || server.run());
+    addr
+}
+
+fn resolver(addr: SocketAddr) -> Resolver {
+    let mut resolver = Resolver::new(addr);
+    resolver.timeout = Duration::from_millis(500);
+    resolver
+}
+
+#[test]
+fn answers_from_the_zone() {
+    let resolver = resolver(start());
+    let (response, _) = resolver.query("www.example.com", RecordType::A).unwrap();
+    assert!(response.header.flags.is_authoritative);
+    let data: Vec<&RecordData> = response.answers().iter().map(|x| &x.data).collect();
+    assert_eq!(data, [&RecordData::Cname(String::from("example.com")), &RecordData::A(Ipv4Addr::new(192, 0, 2, 1))]);
+
+    let result = resolver.resolve("example.com", RecordType::MX).unwrap();
+    assert!(matches!(result.outcome, QueryOutcome::Answers(answers) if answers.len() == 1));
+}
+
+#[test]
+fn negative_answers_carry_the_soa() {
+    let resolver = resolver(start());
+    let result = resolver.resolve("example.com", RecordType::AAAA).unwrap();
+    assert!(matches!(result.outcome, QueryOutcome::NoData { soa: Some(soa) } if soa.serial == 2024010101));
+
+    let result = resolver.resolve("nope.example.com", RecordType::A).unwrap();
+    assert!(matches!(result.outcome, QueryOutcome::NxDomain { soa: Some(_) }));
+
+    let result = resolver.resolve("example.org", RecordType::A).unwrap();
+    assert_eq!(result.outcome, QueryOutcome::Failure(ReplyCode::Refused));
+}
+
+#[test]
+fn formerr_for_garbage() {
+    let addr = start();
+    let socket = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+    socket.set_read_timeout(Some(Duration::from_millis(500))).unwrap();
+    socket.send_to(&[0xab, 0xcd, 0x01, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x07], addr).unwrap();
+    let mut buf = [0; 512];
+    let size = socket.recv(&mut buf).unwrap();
+    let response = bkdns::packet::DNSPacket::deserialize(&buf[..size]).unwrap();
+    assert_eq!((response.header.id(), response.reply_code()), (0xabcd, ReplyCode::FormErr));
+}
+
+#[test]
+fn truncated_over_udp_whole_over_tcp() {
+    // ten 200 byte strings, well over 512 bytes
+    let mut text = String::from(ZONE);
+    for index in 0..10 {
+        text.push_str(&format!("big.example.com. 300 IN TXT \"{}{}\"\n", index, "x".repeat(199)));
+    }
+    let addr = serve(&text);
+
+    let socket = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+    socket.set_read_timeout(Some(Duration::from_millis(500))).unwrap();
+    let query = bkdns::packet::DNSPacket::query("big.example.com", RecordType::TXT).unwrap();
+    socket.send_to(&query.serialize().unwrap(), addr).unwrap();
+    let mut buf = [0; 1024];
+    let size = socket.recv(&mut buf).unwrap();
+    assert!(size <= 512);
+    let response = bkdns::packet::DNSPacket::deserialize(&buf[..size]).unwrap();
+    assert!(response.header.flags.is_truncated);
+    assert!(response.answers().len() < 10);
+
+    let mut resolver = resolver(addr);
+    resolver.transport = TransportKind::Tcp;
+    let (response, _) = resolver.query("big.example.com", RecordType::TXT).unwrap();
+    assert!(!response.header.flags.is_truncated);
+    assert_eq!(response.answers().len(), 10);
+}
@@ -0,0 +1,93 @@
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpListener};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+use bkdns::error::DnsError;
+use bkdns::packet::{DNSPacket, DNSQuestion, RecordType};
+use bkdns::resolver::{QueryOutcome, Resolver, TransportKind};
+use bkdns::testutil::a;
+
+// The name on the test server's self-signed certificate
+const SERVER_NAME: &str = "dns.test";
+
+fn provider() -> Arc<rustls::crypto::CryptoProvider> {
+    Arc::new(rustls::crypto::ring::default_provider())
+}
+
+/* A DoT server on 127.0.0.1 answering one connection with an A record for the question,
+    and a client config that trusts only its certificate.
+ */
+fn start_server() -> (SocketAddr, Arc<rustls::ClientConfig>, JoinHandle<()>) {
+    let certified = rcgen::generate_simple_self_signed(vec![String::from(SERVER_NAME)]).unwrap();
+    let cert = certified.cert.der().clone();
+    let key = rustls::pki_types::PrivatePkcs8KeyDer::from(certified.key_pair.serialize_der());
+
+    let server_config = rustls::ServerConfig::builder_with_provider(provider())
+        .with_safe_default_protocol_versions()
+        .unwrap()
+        .with_no_client_auth()
+        .with_single_cert(vec![cert.clone()], key.into())
+        .unwrap();
+    let mut roots = rustls::RootCertStore::empty();
+    roots.add(cert).unwrap();
+    let client_config = rustls::ClientConfig::builder_with_provider(provider())
+        .with_safe_default_protocol_versions()
+        .unwrap()
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let server = std::thread::spawn(move || {
+        let (stream, _) = listener.accept().unwrap();
+        let connection = rustls::ServerConnection::new(Arc::new(server_config)).unwrap();
+        let mut stream = rustls::StreamOwned::new(connection, stream);
+
+        // a client that rejects the certificate hangs up during the handshake
+        let mut length = [0; 2];
+        if stream.read_exact(&mut length).is_err() {
+            return;
+        }
+        let mut query = vec![0; u16::from_be_bytes(length) as usize];
+        stream.read_exact(&mut query).unwrap();
+        let query = DNSPacket::deserialize(&query).unwrap();
+
+        let mut response = DNSPacket::with_id(query.header.id());
+        response.header.flags.is_response = true;
+        let question = &query.questions()[0];
+        response.add_question(DNSQuestion::new(String::from(question.name()), question.qtype()).unwrap());
+        response.add_answer(a(question.name(), 1));
+        let bytes = response.serialize().unwrap();
+        stream.write_all(&(bytes.len() as u16).to_be_bytes()).unwrap();
+        stream.write_all(&bytes).unwrap();
+        stream.flush().unwrap();
+    });
+
+    (addr, Arc::new(client_config), server)
+}
+
+fn resolver(addr: SocketAddr, config: Arc<rustls::ClientConfig>, server_name: &str) -> Resolver {
+    let mut resolver = Resolver::new(addr);
+    resolver.timeout = Duration::from_secs(2);
+    resolver.transport = TransportKind::Tls { server_name: String::from(server_name), port: addr.port() };
+    resolver.tls_config = Some(config);
+    resolver
+}
+
+#[test]
+fn query_over_tls() {
+    let (addr, config, server) = start_server();
+    let result = resolver(addr, config, SERVER_NAME).resolve("example.com", RecordType::A).unwrap();
+    assert_eq!(result.outcome, QueryOutcome::Answers(vec![a("example.com", 1)]));
+    server.join().unwrap();
+}
+
+#[test]
+fn certificate_for_another_name_rejected() {
+    let (addr, config, server) = start_server();
+    let result = resolver(addr, config, "other.test").resolve("example.com", RecordType::A);
+    assert!(matches!(result, Err(DnsError::Io(_))), "{:?}", result.map(|x| x.outcome));
+    server.join().unwrap();
+}